@@ -0,0 +1,356 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::Error;
+use clap::{ArgGroup, Parser};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+use rs_image_processing_service::config::inference_backend::inference_backend;
+use rs_image_processing_service::config::settings::SETTINGS;
+use rs_image_processing_service::pipeline::general_pipeline::general_pipeline::{GeneralFaceExtractionResult, GeneralPipeline};
+use rs_image_processing_service::pipeline::model_config::config::FaceQualityClass;
+use rs_image_processing_service::pipeline::model_config::pipeline_config::StageKind;
+use rs_image_processing_service::utils::image_files::image_files;
+
+/// Runs the general pipeline of the service over an image archive, to re-embed the enrollments
+/// after a model upgrade. Models, pipeline and inference backend come from the service config, so
+/// the results match the extraction endpoint.
+///
+/// Each image gives one JSON line in `--output`. With `--embeddings` the embeddings go to that file
+/// as rows of little-endian f32 and the line holds the row instead. Images already in `--output` are
+/// skipped, so an interrupted run is resumed by starting it again with the same arguments.
+#[derive(Debug, Parser)]
+#[command(group(ArgGroup::new("source").required(true).args(["input", "manifest"])))]
+struct Args {
+    /// Directory searched recursively for images
+    #[arg(long)]
+    input: Option<PathBuf>,
+    /// File with one image path per line, relative paths are resolved from its directory
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+    /// JSONL results
+    #[arg(long)]
+    output: PathBuf,
+    /// Binary embedding file
+    #[arg(long)]
+    embeddings: Option<PathBuf>,
+    /// Extract with the enrollment rules, as `is_enroll` does on the endpoint
+    #[arg(long)]
+    enroll: bool,
+    /// Images extracted at the same time
+    #[arg(long, default_value_t = 8)]
+    concurrency: usize,
+    /// Seconds between progress reports
+    #[arg(long, default_value_t = 10)]
+    progress_interval: u64,
+}
+
+/// One line of the output.
+#[derive(Debug, Serialize, Deserialize)]
+struct ExtractionRecord {
+    path: String,
+    face_count: i32,
+    face_quality: Option<FaceQualityClass>,
+    quality_score: Option<f32>,
+    /// `None` without a face, or when it went to the embedding file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    embedding: Option<Vec<f32>>,
+    /// Row of the embedding in the embedding file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    embedding_row: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    embedding_dim: Option<usize>,
+    error: Option<String>,
+}
+
+/// Appends the records, and the embeddings when they go to their own file.
+struct RecordWriter {
+    output: BufWriter<File>,
+    embeddings: Option<BufWriter<File>>,
+    embedding_rows: u64,
+}
+
+impl RecordWriter {
+    /// Opens the files for appending and drops what an interruption left half written. Returns the
+    /// writer with the paths already extracted.
+    fn open(output_path: &Path, embeddings_path: Option<&Path>) -> Result<(Self, HashSet<String>), Error> {
+        let mut done: HashSet<String> = HashSet::new();
+        let mut embedding_rows = 0u64;
+        let mut embedding_bytes = 0u64;
+
+        let output = OpenOptions::new().create(true).append(true).open(output_path)?;
+        let content = fs::read(output_path)?;
+        let complete = content.iter().rposition(|byte| *byte == b'\n').map(|end| end + 1).unwrap_or(0);
+        for line in std::str::from_utf8(&content[..complete])?.lines().filter(|line| !line.trim().is_empty()) {
+            let record: ExtractionRecord = match serde_json::from_str(line) {
+                Ok(record) => {record}
+                Err(e) => return Err(Error::msg(format!("unreadable line in {:?}: {e}", output_path)))
+            };
+            if let (Some(row), Some(dim)) = (record.embedding_row, record.embedding_dim) {
+                embedding_rows = embedding_rows.max(row + 1);
+                embedding_bytes = embedding_bytes.max((row + 1) * dim as u64 * 4);
+            }
+            done.insert(record.path);
+        }
+        output.set_len(complete as u64)?;
+
+        let embeddings = match embeddings_path {
+            None => None,
+            Some(path) => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                // A row written before its line was not recorded
+                file.set_len(embedding_bytes)?;
+                Some(BufWriter::new(file))
+            }
+        };
+
+        Ok((RecordWriter {
+            output: BufWriter::new(output),
+            embeddings,
+            embedding_rows,
+        }, done))
+    }
+
+    /// Writes the embedding before the line, so a recorded row is always complete.
+    fn write(&mut self, mut record: ExtractionRecord) -> Result<(), Error> {
+        if let Some(embeddings) = self.embeddings.as_mut() {
+            if let Some(embedding) = record.embedding.take() {
+                let bytes: Vec<u8> = embedding.iter().flat_map(|value| value.to_le_bytes()).collect();
+                embeddings.write_all(&bytes)?;
+                embeddings.flush()?;
+                record.embedding_row = Some(self.embedding_rows);
+                record.embedding_dim = Some(embedding.len());
+                self.embedding_rows += 1;
+            }
+        }
+        serde_json::to_writer(&mut self.output, &record)?;
+        self.output.write_all(b"\n")?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let args = Args::parse();
+
+    let images = match (&args.input, &args.manifest) {
+        (Some(input), _) => image_files(input, true)?,
+        (None, Some(manifest)) => manifest_files(manifest)?,
+        (None, None) => return Err(Error::msg("either --input or --manifest is required")),
+    };
+    let (mut writer, done) = RecordWriter::open(&args.output, args.embeddings.as_deref())?;
+    let pending = pending_images(images, &done);
+    println!("{} images already extracted, {} to go", done.len(), pending.len());
+
+    let configured_backend = inference_backend(&SETTINGS).await?;
+    let general_pipeline = Arc::new(GeneralPipeline::new(Arc::clone(&configured_backend.inference_backend), &SETTINGS.models, &SETTINGS.pipelines.general).await?);
+
+    let total = pending.len();
+    let mut pending = pending.into_iter();
+    let mut tasks: JoinSet<ExtractionRecord> = JoinSet::new();
+    let mut extracted = 0usize;
+    let mut failed = 0usize;
+    let start = Instant::now();
+    let mut last_report = Instant::now();
+    loop {
+        while tasks.len() < args.concurrency.max(1) {
+            let Some(path) = pending.next() else { break };
+            let general_pipeline = Arc::clone(&general_pipeline);
+            let is_enroll = Some(args.enroll);
+            tasks.spawn(async move {
+                let path_key = path.display().to_string();
+                let result = match tokio::fs::read(&path).await {
+                    Ok(im_bytes) => general_pipeline.extract(&im_bytes, is_enroll).await,
+                    Err(e) => Err(Error::from(e)),
+                };
+                to_record(path_key, result)
+            });
+        }
+        let record = match tasks.join_next().await {
+            None => break,
+            Some(joined) => joined?,
+        };
+        if let Some(error) = &record.error {
+            eprintln!("failed to extract {}: {error}", record.path);
+            failed += 1;
+        }
+        writer.write(record)?;
+        extracted += 1;
+
+        if last_report.elapsed() >= Duration::from_secs(args.progress_interval) {
+            last_report = Instant::now();
+            let rate = extracted as f64 / start.elapsed().as_secs_f64().max(f64::EPSILON);
+            let remaining = Duration::from_secs_f64((total - extracted) as f64 / rate.max(f64::EPSILON));
+            println!("{extracted}/{total} images, {failed} failed, {rate:.1} images/s, {remaining:.0?} left");
+        }
+    }
+
+    println!("extracted {extracted} images in {:.2?}, {failed} failed", start.elapsed());
+    configured_backend.release().await;
+    Ok(())
+}
+
+/// The images not extracted yet, each listed once.
+fn pending_images(images: Vec<PathBuf>, done: &HashSet<String>) -> Vec<PathBuf> {
+    let mut seen: HashSet<String> = HashSet::new();
+    images.into_iter()
+        .filter(|path| {
+            let path_key = path.display().to_string();
+            !done.contains(&path_key) && seen.insert(path_key)
+        })
+        .collect()
+}
+
+/// The record of one image, an error leaves the extraction fields empty. The embedding is only
+/// written when a face was selected and the extraction ran for it, never the no-face placeholder.
+fn to_record(path: String, result: Result<GeneralFaceExtractionResult, Error>) -> ExtractionRecord {
+    match result {
+        Ok(result) => {
+            let embedding = result.facial_feature
+                .filter(|feature| result.stages_run.contains(&StageKind::Extraction) && feature.iter().any(|value| *value != 0.0))
+                .map(|feature| feature.to_vec());
+            ExtractionRecord {
+                path,
+                face_count: result.face_count,
+                face_quality: result.face_quality,
+                quality_score: result.quality_score,
+                embedding,
+                embedding_row: None,
+                embedding_dim: None,
+                error: None,
+            }
+        }
+        Err(e) => ExtractionRecord {
+            path,
+            face_count: 0,
+            face_quality: None,
+            quality_score: None,
+            embedding: None,
+            embedding_row: None,
+            embedding_dim: None,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+/// Paths listed in the manifest in its order, blank lines and `#` comments skipped.
+fn manifest_files(manifest: &Path) -> Result<Vec<PathBuf>, Error> {
+    let base = manifest.parent().unwrap_or(Path::new(""));
+    let files = fs::read_to_string(manifest)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base.join(line))
+        .collect();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::fs;
+    use std::path::PathBuf;
+    use ndarray::Array1;
+    use uuid::Uuid;
+    use rs_image_processing_service::pipeline::general_pipeline::general_pipeline::GeneralFaceExtractionResult;
+    use rs_image_processing_service::pipeline::model_config::pipeline_config::StageKind;
+    use super::{pending_images, to_record, ExtractionRecord, RecordWriter};
+
+    fn extraction_record(path: &str, embedding: Option<Vec<f32>>) -> ExtractionRecord {
+        ExtractionRecord {
+            path: path.to_string(),
+            face_count: 1,
+            face_quality: None,
+            quality_score: None,
+            embedding,
+            embedding_row: None,
+            embedding_dim: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_resume() {
+        let dir = std::env::temp_dir().join(format!("batch-extract-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("results.jsonl");
+        let embeddings = dir.join("embeddings.f32");
+
+        let (mut writer, done) = RecordWriter::open(&output, Some(&embeddings)).unwrap();
+        assert!(done.is_empty());
+        writer.write(extraction_record("a.jpg", Some(vec![1.0, 2.0]))).unwrap();
+        writer.write(extraction_record("b.jpg", None)).unwrap();
+        drop(writer);
+
+        // An interruption left a row without its line and half a line
+        let mut row = fs::read(&embeddings).unwrap();
+        row.extend_from_slice(&[0u8; 8]);
+        fs::write(&embeddings, row).unwrap();
+        let mut lines = fs::read_to_string(&output).unwrap();
+        lines.push_str("{\"path\":\"c.jp");
+        fs::write(&output, lines).unwrap();
+
+        let (mut writer, done) = RecordWriter::open(&output, Some(&embeddings)).unwrap();
+        assert_eq!(done, HashSet::from(["a.jpg".to_string(), "b.jpg".to_string()]));
+        assert_eq!(fs::metadata(&embeddings).unwrap().len(), 8);
+        assert!(fs::read_to_string(&output).unwrap().ends_with("}\n"));
+
+        let images = ["a.jpg", "b.jpg", "c.jpg", "c.jpg"].map(PathBuf::from).to_vec();
+        let pending = pending_images(images, &done);
+        assert_eq!(pending, vec![PathBuf::from("c.jpg")]);
+        writer.write(extraction_record("c.jpg", Some(vec![3.0, 4.0]))).unwrap();
+        drop(writer);
+
+        let records: Vec<ExtractionRecord> = fs::read_to_string(&output).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let paths: Vec<&str> = records.iter().map(|record| record.path.as_str()).collect();
+        assert_eq!(paths, vec!["a.jpg", "b.jpg", "c.jpg"]);
+        assert_eq!(records[2].embedding_row, Some(1));
+        assert!(records.iter().all(|record| record.embedding.is_none()));
+        let values: Vec<f32> = fs::read(&embeddings).unwrap()
+            .chunks(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        assert_eq!(values, vec![1.0, 2.0, 3.0, 4.0]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_no_embedding_without_extraction() {
+        let result = GeneralFaceExtractionResult {
+            face_count: 1,
+            face_quality: None,
+            quality_score: None,
+            head_pose: None,
+            image_quality: None,
+            facial_feature: Some(Array1::from(vec![0.6, 0.8])),
+            stages_run: vec![StageKind::Detection, StageKind::Selection],
+            stages_skipped: vec![StageKind::Extraction],
+        };
+        assert!(to_record("a.jpg".to_string(), Ok(result.clone())).embedding.is_none());
+
+        // Without a selected face the extraction runs over no face and leaves the zero placeholder
+        let no_face = GeneralFaceExtractionResult {
+            face_count: 0,
+            facial_feature: Some(Array1::zeros(512)),
+            stages_run: vec![StageKind::Detection, StageKind::Selection, StageKind::Extraction],
+            stages_skipped: vec![],
+            ..result.clone()
+        };
+        assert!(to_record("a.jpg".to_string(), Ok(no_face)).embedding.is_none());
+
+        let extracted = GeneralFaceExtractionResult {
+            stages_run: vec![StageKind::Detection, StageKind::Selection, StageKind::Extraction],
+            stages_skipped: vec![],
+            ..result
+        };
+        assert_eq!(to_record("a.jpg".to_string(), Ok(extracted)).embedding, Some(vec![0.6, 0.8]));
+    }
+}
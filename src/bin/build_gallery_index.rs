@@ -0,0 +1,122 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use clap::Parser;
+use rs_image_processing_service::repository::face_repository::Identity;
+use rs_image_processing_service::repository::file_face_repository::FileFaceRepository;
+use rs_image_processing_service::repository::hnsw_index::{HnswIndex, HnswParams};
+
+/// Builds the HNSW snapshot of a gallery file offline, so the service does not rebuild it at startup.
+#[derive(Debug, Parser)]
+struct Args {
+    /// Gallery file written by the service
+    #[arg(long)]
+    gallery: PathBuf,
+    /// Output index snapshot, matches `gallery.index.path`
+    #[arg(long)]
+    output: PathBuf,
+    #[arg(long, default_value_t = 16)]
+    m: usize,
+    #[arg(long, default_value_t = 200)]
+    ef_construction: usize,
+    #[arg(long, default_value_t = 64)]
+    ef_search: usize,
+    /// Number of enrolled embeddings used as queries to measure recall, 0 disables the evaluation
+    #[arg(long, default_value_t = 0)]
+    eval_queries: usize,
+    #[arg(long, default_value_t = 10)]
+    top_k: usize,
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let args = Args::parse();
+
+    let identities = FileFaceRepository::load_identities(&args.gallery)?;
+    let params = HnswParams {
+        m: args.m,
+        ef_construction: args.ef_construction,
+        ef_search: args.ef_search,
+    };
+
+    let start = Instant::now();
+    let index = FileFaceRepository::build_index(&identities, params)?;
+    println!(
+        "built index with {} vectors from {} identities in {:.2?}",
+        index.len(),
+        identities.len(),
+        start.elapsed()
+    );
+
+    index.snapshot(&args.output)?;
+    println!("wrote snapshot to {:?}", args.output);
+
+    if args.eval_queries > 0 {
+        evaluate(&index, &identities, args.eval_queries, args.top_k)?;
+    }
+    Ok(())
+}
+
+/// Compares the index against an exact scan, recall@k is the share of exact top-k keys found by the index.
+fn evaluate(index: &HnswIndex, identities: &[Identity], eval_queries: usize, top_k: usize) -> Result<(), anyhow::Error> {
+    let queries: Vec<&Vec<f32>> = identities
+        .iter()
+        .flat_map(|identity| identity.embeddings.iter())
+        .take(eval_queries)
+        .collect();
+    if queries.is_empty() {
+        return Ok(())
+    }
+
+    let mut exact_elapsed = Duration::ZERO;
+    let truths: Vec<Vec<String>> = queries
+        .iter()
+        .map(|query| {
+            let start = Instant::now();
+            let truth = exact_search(identities, query, top_k);
+            exact_elapsed += start.elapsed();
+            truth
+        })
+        .collect();
+    println!("exact scan mean latency={:.2?}", exact_elapsed / queries.len() as u32);
+
+    for ef_search in [16, 32, 64, 128, 256] {
+        let mut found = 0;
+        let mut expected = 0;
+        let mut elapsed = Duration::ZERO;
+        for (query, truth) in queries.iter().zip(truths.iter()) {
+            let start = Instant::now();
+            let approx = index.search(query, top_k, Some(ef_search))?;
+            elapsed += start.elapsed();
+
+            expected += truth.len();
+            found += truth
+                .iter()
+                .filter(|key| approx.iter().any(|(approx_key, _)| approx_key == *key))
+                .count();
+        }
+        println!(
+            "ef_search={:<4} recall@{}={:.4} mean latency={:.2?}",
+            ef_search,
+            top_k,
+            found as f64 / usize::max(expected, 1) as f64,
+            elapsed / queries.len() as u32
+        );
+    }
+    Ok(())
+}
+
+/// Enrolled embeddings are L2-normalised, so the dot product is the cosine similarity.
+fn exact_search(identities: &[Identity], query: &[f32], top_k: usize) -> Vec<String> {
+    let mut scores: Vec<(&str, f32)> = identities
+        .iter()
+        .map(|identity| {
+            let best = identity.embeddings
+                .iter()
+                .map(|embedding| embedding.iter().zip(query.iter()).map(|(a, b)| a * b).sum::<f32>())
+                .fold(f32::MIN, f32::max);
+            (identity.identity_id.as_str(), best)
+        })
+        .collect();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scores.truncate(top_k);
+    scores.into_iter().map(|(identity_id, _)| identity_id.to_string()).collect()
+}
@@ -0,0 +1,297 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::Error;
+use clap::Parser;
+use ndarray::Array1;
+use serde::Serialize;
+use rs_image_processing_service::config::inference_backend::inference_backend;
+use rs_image_processing_service::config::settings::SETTINGS;
+use rs_image_processing_service::pipeline::antispoofing_pipeline::antispoofing_pipeline::AntiSpoofingPipeline;
+use rs_image_processing_service::pipeline::general_pipeline::general_pipeline::GeneralPipeline;
+use rs_image_processing_service::pipeline::model_config::config::FaceQualityClass;
+use rs_image_processing_service::pipeline::model_config::pipeline_config::RequestedStage;
+use rs_image_processing_service::pipeline::utils::utils::{cosine_similarity, l2_normalize};
+use rs_image_processing_service::utils::image_files::image_files;
+use rs_image_processing_service::utils::roc::{equal_error_rate, error_rates, point_at_far, Acceptance, OperatingPoint};
+
+const EMBEDDING_STAGES: &[RequestedStage] = &[RequestedStage::Embedding];
+const LIVENESS_STAGES: &[RequestedStage] = &[RequestedStage::Liveness];
+const QUALITY_STAGES: &[RequestedStage] = &[RequestedStage::Quality];
+
+/// Measures the error rates of the configured models on a labelled dataset and recommends the
+/// thresholds that meet a target false accept rate.
+///
+/// The dataset directory holds any of
+/// `verification/<identity>/*.jpg` with one person per directory,
+/// `anti_spoofing/live/*.jpg` and `anti_spoofing/spoof/*.jpg`,
+/// `face_quality/good/*.jpg` and `face_quality/bad/*.jpg`.
+/// Models, pipelines and the inference backend come from the service config.
+#[derive(Debug, Parser)]
+struct Args {
+    #[arg(long)]
+    dataset: PathBuf,
+    /// Directory for report.json and the <task>_curve.csv files
+    #[arg(long)]
+    output: PathBuf,
+    /// False accept rates the error rates are reported at
+    #[arg(long, value_delimiter = ',', default_values_t = vec![0.01, 0.001, 0.0001])]
+    far: Vec<f64>,
+    /// False accept rate the verification threshold is recommended for
+    #[arg(long, default_value_t = 0.001)]
+    verification_far: f64,
+    /// Share of spoofs accepted (APCER) the anti-spoofing threshold is recommended for
+    #[arg(long, default_value_t = 0.01)]
+    anti_spoofing_far: f64,
+    /// Share of bad quality faces accepted the face quality threshold is recommended for
+    #[arg(long, default_value_t = 0.05)]
+    face_quality_far: f64,
+    /// Impostor pairs beyond this many are sampled evenly
+    #[arg(long, default_value_t = 1_000_000)]
+    max_impostor_pairs: usize,
+}
+
+/// Scores of one task, the genuine ones should be accepted and the impostor ones rejected.
+#[derive(Debug, Default)]
+struct TaskScores {
+    genuine: Vec<f32>,
+    impostor: Vec<f32>,
+    /// Images without a usable face, left out of the scores
+    failed_images: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+struct TaskReport {
+    /// Config key the recommended threshold goes to
+    config_key: &'static str,
+    current_threshold: Option<f32>,
+    genuine_count: usize,
+    impostor_count: usize,
+    failed_images: Vec<PathBuf>,
+    eer: Option<f64>,
+    eer_threshold: Option<f32>,
+    /// Operating point at each requested false accept rate
+    operating_points: Vec<OperatingPoint>,
+    target_far: f64,
+    recommended: Option<OperatingPoint>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct CalibrationReport {
+    verification: Option<TaskReport>,
+    anti_spoofing: Option<TaskReport>,
+    face_quality: Option<TaskReport>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let args = Args::parse();
+    fs::create_dir_all(&args.output)?;
+
+    let configured_backend = inference_backend(&SETTINGS).await?;
+    let inference_backend = Arc::clone(&configured_backend.inference_backend);
+    // The quality threshold is what is being calibrated, so a good face is never downgraded by it
+    let mut models_cfg = SETTINGS.models.clone();
+    models_cfg.face_quality.threshold = 0.0;
+
+    let mut report = CalibrationReport::default();
+    let verification_dir = args.dataset.join("verification");
+    let face_quality_dir = args.dataset.join("face_quality");
+    if verification_dir.is_dir() || face_quality_dir.is_dir() {
+        let general_pipeline = GeneralPipeline::new(Arc::clone(&inference_backend), &models_cfg, &SETTINGS.pipelines.general).await?;
+        if verification_dir.is_dir() {
+            let scores = verification_scores(&general_pipeline, &verification_dir, args.max_impostor_pairs).await?;
+            let current_threshold = Some(SETTINGS.verification.threshold);
+            report.verification = Some(evaluate(&args, "verification", "verification.threshold", current_threshold, scores, Acceptance::AtOrAbove, args.verification_far)?);
+        }
+        if face_quality_dir.is_dir() {
+            let scores = face_quality_scores(&general_pipeline, &face_quality_dir).await?;
+            let current_threshold = Some(SETTINGS.models.face_quality.threshold);
+            report.face_quality = Some(evaluate(&args, "face_quality", "models.face_quality.threshold", current_threshold, scores, Acceptance::AtOrAbove, args.face_quality_far)?);
+        }
+    }
+    let anti_spoofing_dir = args.dataset.join("anti_spoofing");
+    if anti_spoofing_dir.is_dir() {
+        let antispoofing_pipeline = AntiSpoofingPipeline::new(Arc::clone(&inference_backend), &models_cfg, &SETTINGS.pipelines.antispoofing).await?;
+        let scores = anti_spoofing_scores(&antispoofing_pipeline, &anti_spoofing_dir).await?;
+        let current_threshold = Some(SETTINGS.models.face_anti_spoofing.threshold);
+        report.anti_spoofing = Some(evaluate(&args, "anti_spoofing", "models.face_anti_spoofing.threshold", current_threshold, scores, Acceptance::Above, args.anti_spoofing_far)?);
+    }
+
+    let report_path = args.output.join("report.json");
+    fs::write(&report_path, serde_json::to_vec_pretty(&report)?)?;
+    println!("wrote report to {:?}", report_path);
+    configured_backend.release().await;
+    Ok(())
+}
+
+/// Cosine similarity of every pair of embeddings, genuine within an identity directory and impostor across them.
+async fn verification_scores(pipeline: &GeneralPipeline, dir: &Path, max_impostor_pairs: usize) -> Result<TaskScores, Error> {
+    let mut scores = TaskScores::default();
+    let mut embeddings: Vec<(usize, Array1<f32>)> = vec![];
+    for (identity, identity_dir) in sub_dirs(dir)?.into_iter().enumerate() {
+        for path in image_files(&identity_dir, false)? {
+            let result = match pipeline.extract_with_stages(&fs::read(&path)?, Some(false), Some(EMBEDDING_STAGES)).await {
+                Ok(result) => {result}
+                Err(e) => {
+                    eprintln!("failed to extract {:?}: {e}", path);
+                    scores.failed_images.push(path);
+                    continue
+                }
+            };
+            // Without a selected face the feature is the zero placeholder, a skipped extraction leaves none
+            match result.facial_feature {
+                Some(feature) if feature.iter().any(|value| *value != 0.0) => {
+                    embeddings.push((identity, l2_normalize(&feature)));
+                }
+                _ => scores.failed_images.push(path),
+            }
+        }
+    }
+
+    let pairs = embeddings.len() * embeddings.len().saturating_sub(1) / 2;
+    let genuine_pairs: usize = (0..embeddings.len())
+        .map(|i| embeddings[i + 1..].iter().filter(|(identity, _)| *identity == embeddings[i].0).count())
+        .sum();
+    let stride = (pairs - genuine_pairs).div_ceil(max_impostor_pairs.max(1)).max(1);
+    let mut impostor_pairs = 0;
+    for (i, (identity, embedding)) in embeddings.iter().enumerate() {
+        for (other_identity, other_embedding) in embeddings[i + 1..].iter() {
+            if identity == other_identity {
+                scores.genuine.push(cosine_similarity(embedding, other_embedding));
+                continue
+            }
+            if impostor_pairs % stride == 0 {
+                scores.impostor.push(cosine_similarity(embedding, other_embedding));
+            }
+            impostor_pairs += 1;
+        }
+    }
+    println!(
+        "verification: {} embeddings, {} genuine and {} impostor pairs, {} images without a face",
+        embeddings.len(), scores.genuine.len(), scores.impostor.len(), scores.failed_images.len()
+    );
+    Ok(scores)
+}
+
+/// Calibrated live score of the selected face, genuine for `live` and impostor for `spoof`.
+async fn anti_spoofing_scores(pipeline: &AntiSpoofingPipeline, dir: &Path) -> Result<TaskScores, Error> {
+    let mut scores = TaskScores::default();
+    for (label, genuine) in [("live", true), ("spoof", false)] {
+        let label_dir = dir.join(label);
+        if !label_dir.is_dir() {
+            continue
+        }
+        for path in image_files(&label_dir, false)? {
+            let result = match pipeline.extract(&fs::read(&path)?, Some(true), Some(false), Some(LIVENESS_STAGES)).await {
+                Ok(result) => {result}
+                Err(e) => {
+                    eprintln!("failed to extract {:?}: {e}", path);
+                    scores.failed_images.push(path);
+                    continue
+                }
+            };
+            match result.anti_spoofing_score {
+                Some(score) if genuine => scores.genuine.push(score.live_score),
+                Some(score) => scores.impostor.push(score.live_score),
+                None => scores.failed_images.push(path),
+            }
+        }
+    }
+    println!(
+        "anti_spoofing: {} live and {} spoof images, {} without a face",
+        scores.genuine.len(), scores.impostor.len(), scores.failed_images.len()
+    );
+    Ok(scores)
+}
+
+/// Good class probability of the selected face, genuine for `good` and impostor for `bad`. A face
+/// whose most likely class is not good is rejected at any threshold and scores 0.
+async fn face_quality_scores(pipeline: &GeneralPipeline, dir: &Path) -> Result<TaskScores, Error> {
+    let mut scores = TaskScores::default();
+    for (label, genuine) in [("good", true), ("bad", false)] {
+        let label_dir = dir.join(label);
+        if !label_dir.is_dir() {
+            continue
+        }
+        for path in image_files(&label_dir, false)? {
+            let result = match pipeline.extract_with_stages(&fs::read(&path)?, Some(false), Some(QUALITY_STAGES)).await {
+                Ok(result) => {result}
+                Err(e) => {
+                    eprintln!("failed to extract {:?}: {e}", path);
+                    scores.failed_images.push(path);
+                    continue
+                }
+            };
+            if result.face_count == 0 {
+                scores.failed_images.push(path);
+                continue
+            }
+            let score = match result.face_quality {
+                Some(FaceQualityClass::Good) => result.quality_score.unwrap_or(0.0),
+                _ => 0.0,
+            };
+            if genuine {
+                scores.genuine.push(score);
+            } else {
+                scores.impostor.push(score);
+            }
+        }
+    }
+    println!(
+        "face_quality: {} good and {} bad images, {} without a face",
+        scores.genuine.len(), scores.impostor.len(), scores.failed_images.len()
+    );
+    Ok(scores)
+}
+
+/// Writes the ROC/DET points of the task to `<name>_curve.csv` and summarises them. `acceptance` is
+/// the comparison the service makes with `config_key`.
+fn evaluate(args: &Args, name: &str, config_key: &'static str, current_threshold: Option<f32>, scores: TaskScores, acceptance: Acceptance, target_far: f64) -> Result<TaskReport, Error> {
+    let points = error_rates(&scores.genuine, &scores.impostor, acceptance);
+
+    let mut csv = String::from("threshold,far,frr,tpr\n");
+    for point in points.iter() {
+        csv.push_str(&format!("{},{},{},{}\n", point.threshold, point.far, point.frr, 1.0 - point.frr));
+    }
+    let curve_path = args.output.join(format!("{name}_curve.csv"));
+    fs::write(&curve_path, csv)?;
+
+    let eer = equal_error_rate(&points);
+    let recommended = point_at_far(&points, target_far);
+    if let Some((eer, point)) = eer {
+        println!("{name}: eer={:.4} at threshold {:.4}", eer, point.threshold);
+    }
+    match recommended {
+        Some(point) => println!(
+            "{name}: {config_key}={:.4} gives far={:.5} frr={:.5} (target far {target_far}, current {:?})",
+            point.threshold, point.far, point.frr, current_threshold
+        ),
+        None => println!("{name}: no threshold reaches far {target_far}"),
+    }
+
+    Ok(TaskReport {
+        config_key,
+        current_threshold,
+        genuine_count: scores.genuine.len(),
+        impostor_count: scores.impostor.len(),
+        failed_images: scores.failed_images,
+        eer: eer.map(|(eer, _)| eer),
+        eer_threshold: eer.map(|(_, point)| point.threshold),
+        operating_points: args.far.iter().filter_map(|far| point_at_far(&points, *far)).collect(),
+        target_far,
+        recommended,
+    })
+}
+
+fn sub_dirs(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut dirs: Vec<PathBuf> = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            dirs.push(path);
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
@@ -0,0 +1,111 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use anyhow::Error;
+use log::info;
+use crate::config::settings::Settings;
+use crate::pipeline::inference_backend::batching_backend::BatchingBackend;
+use crate::pipeline::inference_backend::inference_backend::{InferenceBackend, InferenceBackendKind};
+use crate::pipeline::inference_backend::onnx_backend::OnnxBackend;
+use crate::pipeline::inference_backend::triton_backend::TritonBackend;
+use crate::pipeline::triton_client::recorder::{InferRecorder, RecordMode};
+use crate::pipeline::triton_client::shared_memory::{SharedMemoryOptions, TritonTransport};
+
+/// The inference backend of the settings, the server and the CLIs all build it here.
+pub struct ConfiguredInferenceBackend {
+    pub inference_backend: Arc<dyn InferenceBackend>,
+    /// The Triton backend that registered shared-memory regions
+    shared_memory_backend: Option<TritonBackend>,
+}
+
+impl ConfiguredInferenceBackend {
+    /// Unregisters the Triton shared-memory regions, to be called before exiting.
+    pub async fn release(&self) {
+        if let Some(triton_backend) = &self.shared_memory_backend {
+            triton_backend.release_shared_memory().await;
+        }
+    }
+}
+
+/// Builds the backend of `[inference]`, Triton with its recording, shared memory and batching
+/// settings unless ONNX Runtime is configured.
+pub async fn inference_backend(settings: &Settings) -> Result<ConfiguredInferenceBackend, Error> {
+    let backend_kind = match &settings.inference {
+        None => InferenceBackendKind::Triton,
+        Some(inference) => match InferenceBackendKind::from_str(&inference.backend) {
+            Ok(backend_kind) => {backend_kind}
+            Err(e) => return Err(e)
+        },
+    };
+    info!("using {:?} inference backend", backend_kind);
+
+    match backend_kind {
+        InferenceBackendKind::Triton => triton_backend(settings).await,
+        InferenceBackendKind::Onnx => {
+            let onnx = match settings.inference.as_ref().and_then(|inference| inference.onnx.clone()) {
+                Some(onnx) => onnx,
+                None => return Err(Error::msg("inference_backend - missing [inference.onnx] settings for the onnx backend")),
+            };
+            let onnx_backend = match OnnxBackend::new(&onnx.model_dir, onnx.intra_threads.unwrap_or(4)) {
+                Ok(onnx_backend) => {onnx_backend}
+                Err(e) => return Err(e)
+            };
+            Ok(ConfiguredInferenceBackend {
+                inference_backend: Arc::new(onnx_backend),
+                shared_memory_backend: None,
+            })
+        }
+    }
+}
+
+async fn triton_backend(settings: &Settings) -> Result<ConfiguredInferenceBackend, Error> {
+    let host = settings.triton.faceid_host.as_str();
+    let port = settings.triton.faceid_grpc_port.to_string();
+    let record_mode = match &settings.triton.recording {
+        None => RecordMode::Off,
+        Some(recording) => match RecordMode::from_str(&recording.mode) {
+            Ok(record_mode) => {record_mode}
+            Err(e) => return Err(e)
+        },
+    };
+    let triton_backend = match (record_mode, &settings.triton.recording) {
+        (RecordMode::Off, _) | (_, None) => TritonBackend::new(host, &port).await,
+        (_, Some(recording)) => {
+            info!("triton traffic {} mode with fixtures in {}", record_mode, recording.fixture_dir);
+            TritonBackend::new_with_recorder(host, &port, InferRecorder::new(record_mode, &recording.fixture_dir)).await
+        }
+    };
+    let mut triton_backend = match triton_backend {
+        Ok(triton_backend) => {triton_backend}
+        Err(e) => return Err(e)
+    };
+
+    // Recorded fixtures hash the input bytes, so shared memory only applies to live traffic
+    let mut shared_memory_backend: Option<TritonBackend> = None;
+    if let (RecordMode::Off, Some(shared_memory)) = (record_mode, &settings.triton.shared_memory) {
+        let transport = match TritonTransport::from_str(&shared_memory.transport) {
+            Ok(transport) => {transport}
+            Err(e) => return Err(e)
+        };
+        if transport == TritonTransport::SystemSharedMemory {
+            triton_backend = triton_backend.with_shared_memory(&SharedMemoryOptions {
+                prefix: shared_memory.prefix.clone(),
+                pool_size: shared_memory.pool_size,
+                region_size: shared_memory.region_size,
+            }).await;
+            shared_memory_backend = Some(triton_backend.clone());
+        }
+    }
+
+    let triton_backend: Arc<dyn InferenceBackend> = Arc::new(triton_backend);
+    let inference_backend: Arc<dyn InferenceBackend> = match &settings.batching {
+        Some(batching) if batching.enabled => {
+            info!("batching triton requests up to {} rows or {}ms", batching.max_batch_size, batching.max_wait_ms);
+            Arc::new(BatchingBackend::new(triton_backend, batching))
+        }
+        _ => triton_backend,
+    };
+    Ok(ConfiguredInferenceBackend {
+        inference_backend,
+        shared_memory_backend,
+    })
+}
@@ -1,2 +1,3 @@
 mod parameter;
-pub mod settings;
\ No newline at end of file
+pub mod settings;
+pub mod inference_backend;
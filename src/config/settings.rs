@@ -2,6 +2,9 @@ use config::{Config, ConfigError, Environment, File, FileFormat};
 use once_cell::sync::Lazy;
 use serde::Deserialize;
 use std::{env, fmt};
+use crate::pipeline::inference_backend::batching_backend::DynamicBatchingConfig;
+use crate::pipeline::model_config::config::ModelsConfig;
+use crate::pipeline::model_config::pipeline_config::PipelinesConfig;
 
 pub static SETTINGS: Lazy<Settings> = Lazy::new(|| Settings::new().expect("Failed to setup settings"));
 
@@ -18,10 +21,65 @@ pub struct Server {
     pub request_timeout: Option<u64>,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct TritonRecording {
+    pub mode: String,
+    pub fixture_dir: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TritonSharedMemory {
+    pub transport: String,
+    pub prefix: String,
+    pub pool_size: usize,
+    pub region_size: usize,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Triton {
     pub faceid_host: String,
     pub faceid_grpc_port: u16,
+    pub recording: Option<TritonRecording>,
+    pub shared_memory: Option<TritonSharedMemory>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Onnx {
+    pub model_dir: String,
+    pub intra_threads: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Inference {
+    pub backend: String,
+    pub onnx: Option<Onnx>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Verification {
+    pub threshold: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LivenessChallenge {
+    pub session_ttl_secs: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GalleryIndex {
+    pub enabled: bool,
+    pub path: String,
+    pub m: Option<usize>,
+    pub ef_construction: Option<usize>,
+    pub ef_search: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Gallery {
+    pub path: String,
+    /// Changes between two rewrites of the gallery file and index snapshot
+    pub snapshot_every: Option<usize>,
+    pub index: Option<GalleryIndex>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -42,6 +100,13 @@ pub struct Settings {
     pub server: Server,
     pub logger: Option<Logger>,
     pub triton: Triton,
+    pub inference: Option<Inference>,
+    pub batching: Option<DynamicBatchingConfig>,
+    pub verification: Verification,
+    pub liveness_challenge: Option<LivenessChallenge>,
+    pub gallery: Gallery,
+    pub models: ModelsConfig,
+    pub pipelines: PipelinesConfig,
     pub tracer: Tracer,
     pub app: App,
 }
@@ -61,7 +126,19 @@ impl Settings {
             builder = builder.set_override("server.port", port)?;
         }
 
-        builder.build()?.try_deserialize()
+        let settings: Settings = builder.build()?.try_deserialize()?;
+        if let Err(e) = settings.models.validate() {
+            return Err(ConfigError::Message(e.to_string()))
+        }
+        if let Err(e) = settings.pipelines.validate() {
+            return Err(ConfigError::Message(e.to_string()))
+        }
+        if let Some(batching) = &settings.batching {
+            if let Err(e) = batching.validate() {
+                return Err(ConfigError::Message(e.to_string()))
+            }
+        }
+        Ok(settings)
     }
 }
 
@@ -11,6 +11,7 @@ use crate::config::settings::SETTINGS;
 use crate::error::errors::ResponseCode;
 use crate::logger::logger::LoggerExtraFields;
 use crate::models::antispoofing_model::{AntiSpoofingExtractionInput, AntiSpoofingExtractionResultOutput};
+use crate::pipeline::model_config::pipeline_config::RequestedStage;
 use crate::response::common_response::{BaseResponse, GeneralResponseBuilder, GeneralResponseResult};
 use crate::state::antispoofing_state::AntiSpoofingState;
 
@@ -27,6 +28,7 @@ pub async fn antispoofing_extract(headers: HeaderMap, State(state): State<AntiSp
     let request_id: String = request_id_header.parse().unwrap();
     let mut is_enroll: Option<bool> = Some(false);
     let mut spoofing_check: Option<bool> = Some(false);
+    let mut stages: Option<Vec<RequestedStage>> = None;
 
     extra_fields::set_extra_fields(LoggerExtraFields {
         request_id: request_id.clone(),
@@ -116,6 +118,28 @@ pub async fn antispoofing_extract(headers: HeaderMap, State(state): State<AntiSp
                     }
                 }
             }
+            "stages" => {
+                let value = field.text().await.unwrap();
+                match RequestedStage::parse_list(&value) {
+                    Ok(val) => {
+                        stages = Some(val);
+                    }
+                    Err(e) => {
+                        error!("failed to retrieves stages value [{value}] from request: {e}");
+                        return Ok(GeneralResponseBuilder::new()
+                            .status_code(StatusCode::BAD_REQUEST)
+                            .body(BaseResponse {
+                                data: None,
+                                response_message: "invalid stages value".to_string(),
+                                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                                is_success: false,
+                                request_id: request_id.clone(),
+                            })
+                            .build()
+                        )
+                    }
+                }
+            }
             _ => {}
         }
     }
@@ -123,6 +147,7 @@ pub async fn antispoofing_extract(headers: HeaderMap, State(state): State<AntiSp
         im_bytes,
         is_enroll,
         spoofing_check,
+        stages,
     };
     child.end();
 
@@ -0,0 +1,118 @@
+use axum::debug_handler;
+use axum::extract::{Multipart, State};
+use bytes::Bytes;
+use ecs_logger::extra_fields;
+use http::{HeaderMap, StatusCode};
+use log::{error, info};
+use opentelemetry::global;
+use opentelemetry::trace::Tracer;
+use crate::config::settings::SETTINGS;
+use crate::error::errors::ResponseCode;
+use crate::logger::logger::LoggerExtraFields;
+use crate::models::compliance_model::{ComplianceInput, ComplianceResultOutput};
+use crate::response::common_response::{BaseResponse, GeneralResponseBuilder, GeneralResponseResult};
+use crate::state::compliance_state::ComplianceState;
+
+#[debug_handler(state=ComplianceState)]
+pub async fn check_compliance(headers: HeaderMap, State(state): State<ComplianceState>, mut payload: Multipart) -> GeneralResponseResult<BaseResponse<ComplianceResultOutput>> {
+    let tracer = global::tracer(SETTINGS.app.name.clone());
+    let parent_ctx = opentelemetry::Context::new();
+    let _span = tracer
+        .span_builder("portrait-compliance")
+        .start_with_context(&tracer, &parent_ctx);
+
+    let request_id_header = headers.get("x-request-id").unwrap().to_str().unwrap();
+    let request_id: String = request_id_header.parse().unwrap();
+    let mut im_bytes: Bytes = Bytes::new();
+
+    extra_fields::set_extra_fields(LoggerExtraFields {
+        request_id: request_id.clone(),
+    }).unwrap();
+
+    info!("received portrait compliance request");
+
+    while let Some(field) = payload.next_field().await.unwrap() {
+        let name = field.name().unwrap().to_string();
+        if name != "images" {
+            continue
+        }
+        match field.bytes().await {
+            Ok(data) => {
+                if data.len() == 0 {
+                    return Ok(GeneralResponseBuilder::new()
+                        .status_code(StatusCode::BAD_REQUEST)
+                        .body(BaseResponse {
+                            data: None,
+                            response_message: "image is empty".to_string(),
+                            response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                            is_success: false,
+                            request_id: request_id.clone(),
+                        })
+                        .build()
+                    )
+                }
+                im_bytes = data;
+            }
+            Err(e) => {
+                error!("failed to retrieves image from request: {e}");
+                return Ok(GeneralResponseBuilder::new()
+                    .status_code(StatusCode::BAD_REQUEST)
+                    .body(BaseResponse {
+                        data: None,
+                        response_message: "failed to process image".to_string(),
+                        response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                        is_success: false,
+                        request_id: request_id.clone(),
+                    })
+                    .build()
+                )
+            }
+        };
+    }
+
+    if im_bytes.is_empty() {
+        return Ok(GeneralResponseBuilder::new()
+            .status_code(StatusCode::BAD_REQUEST)
+            .body(BaseResponse {
+                data: None,
+                response_message: "images is required".to_string(),
+                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                is_success: false,
+                request_id: request_id.clone(),
+            })
+            .build()
+        )
+    }
+
+    let result = match state.compliance_service.check(ComplianceInput { im_bytes }).await {
+        Ok(result) => {result}
+        Err(e) => {
+            error!("failed to check portrait compliance: {e}");
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: "internal server error".to_string(),
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeServer),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    };
+    info!("completed checking portrait compliance");
+
+    extra_fields::clear_extra_fields();
+    return Ok(GeneralResponseBuilder::new()
+        .status_code(StatusCode::OK)
+        .body(BaseResponse {
+            data: Some(result),
+            response_message: "OK".to_string(),
+            response_code: ResponseCode::response_code(ResponseCode::CodeOK),
+            is_success: true,
+            request_id: request_id.clone(),
+        })
+        .build()
+    )
+}
@@ -0,0 +1,504 @@
+use axum::debug_handler;
+use axum::extract::{Multipart, Path, Query, State};
+use bytes::Bytes;
+use ecs_logger::extra_fields;
+use http::{HeaderMap, StatusCode};
+use log::{error, info};
+use opentelemetry::global;
+use opentelemetry::trace::Tracer;
+use crate::config::settings::SETTINGS;
+use crate::error::errors::ResponseCode;
+use crate::logger::logger::LoggerExtraFields;
+use crate::models::gallery_model::{GalleryDeleteResultOutput, GalleryEnrollInput, GalleryEnrollResultOutput, GalleryListQuery, GalleryListResultOutput, GallerySearchInput, GallerySearchResultOutput};
+use crate::repository::face_repository::SearchParams;
+use crate::response::common_response::{BaseResponse, GeneralResponseBuilder, GeneralResponseResult, ResponsePagination};
+use crate::state::gallery_state::GalleryState;
+
+const DEFAULT_LIST_LIMIT: usize = 100;
+const DEFAULT_SEARCH_TOP_K: usize = 5;
+
+#[debug_handler(state=GalleryState)]
+pub async fn gallery_enroll(headers: HeaderMap, State(state): State<GalleryState>, mut payload: Multipart) -> GeneralResponseResult<BaseResponse<GalleryEnrollResultOutput>> {
+    let tracer = global::tracer(SETTINGS.app.name.clone());
+    let parent_ctx = opentelemetry::Context::new();
+    let _span = tracer
+        .span_builder("gallery-enroll")
+        .start_with_context(&tracer, &parent_ctx);
+
+    let request_id_header = headers.get("x-request-id").unwrap().to_str().unwrap();
+    let request_id: String = request_id_header.parse().unwrap();
+    let mut identity_id = String::new();
+    let mut images: Vec<Bytes> = vec![];
+    let mut embeddings: Vec<Vec<f32>> = vec![];
+
+    extra_fields::set_extra_fields(LoggerExtraFields {
+        request_id: request_id.clone(),
+    }).unwrap();
+
+    info!("received gallery enroll request");
+
+    while let Some(field) = payload.next_field().await.unwrap() {
+        let name = field.name().unwrap().to_string();
+        match name.as_str() {
+            "identity_id" => {
+                identity_id = field.text().await.unwrap().trim().to_string();
+            }
+            "images" => {
+                match field.bytes().await {
+                    Ok(data) => {
+                        if data.len() == 0 {
+                            return Ok(GeneralResponseBuilder::new()
+                                .status_code(StatusCode::BAD_REQUEST)
+                                .body(BaseResponse {
+                                    data: None,
+                                    response_message: "image is empty".to_string(),
+                                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                                    is_success: false,
+                                    request_id: request_id.clone(),
+                                })
+                                .build()
+                            )
+                        }
+                        images.push(data);
+                    }
+                    Err(e) => {
+                        error!("failed to retrieves image from request: {e}");
+                        return Ok(GeneralResponseBuilder::new()
+                            .status_code(StatusCode::BAD_REQUEST)
+                            .body(BaseResponse {
+                                data: None,
+                                response_message: "failed to process image".to_string(),
+                                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                                is_success: false,
+                                request_id: request_id.clone(),
+                            })
+                            .build()
+                        )
+                    }
+                };
+            }
+            "embeddings" => {
+                let value = field.text().await.unwrap();
+                match serde_json::from_str::<Vec<Vec<f32>>>(&value) {
+                    Ok(val) => {
+                        embeddings.extend(val);
+                    }
+                    Err(e) => {
+                        error!("failed to retrieves embeddings value from request: {e}");
+                        return Ok(GeneralResponseBuilder::new()
+                            .status_code(StatusCode::BAD_REQUEST)
+                            .body(BaseResponse {
+                                data: None,
+                                response_message: "invalid embeddings value".to_string(),
+                                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                                is_success: false,
+                                request_id: request_id.clone(),
+                            })
+                            .build()
+                        )
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if identity_id.is_empty() || (images.is_empty() && embeddings.is_empty()) {
+        return Ok(GeneralResponseBuilder::new()
+            .status_code(StatusCode::BAD_REQUEST)
+            .body(BaseResponse {
+                data: None,
+                response_message: "identity_id and at least one image or embedding are required".to_string(),
+                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                is_success: false,
+                request_id: request_id.clone(),
+            })
+            .build()
+        )
+    }
+
+    // Checked before the repository so nothing of a bad request reaches the gallery log
+    for embedding in embeddings.iter() {
+        if let Err(message) = state.gallery_service.check_embedding("embeddings", embedding) {
+            error!("invalid embeddings: {message}");
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::BAD_REQUEST)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: message,
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    }
+
+    let input = GalleryEnrollInput {
+        identity_id,
+        images,
+        embeddings,
+    };
+
+    let result = match state.gallery_service.enroll(input).await {
+        Ok(result) => {result}
+        Err(e) => {
+            error!("failed to enroll identity: {e}");
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: "internal server error".to_string(),
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeServer),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    };
+    info!("completed gallery enrollment");
+    extra_fields::clear_extra_fields();
+
+    if result.enrolled_count == 0 {
+        return Ok(GeneralResponseBuilder::new()
+            .status_code(StatusCode::UNPROCESSABLE_ENTITY)
+            .body(BaseResponse {
+                data: Some(result),
+                response_message: "no image is suitable for enrollment".to_string(),
+                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeValidation),
+                is_success: false,
+                request_id: request_id.clone(),
+            })
+            .build()
+        )
+    }
+
+    return Ok(GeneralResponseBuilder::new()
+        .status_code(StatusCode::OK)
+        .body(BaseResponse {
+            data: Some(result),
+            response_message: "OK".to_string(),
+            response_code: ResponseCode::response_code(ResponseCode::CodeOK),
+            is_success: true,
+            request_id: request_id.clone(),
+        })
+        .build()
+    )
+}
+
+#[debug_handler(state=GalleryState)]
+pub async fn gallery_list(headers: HeaderMap, State(state): State<GalleryState>, Query(query): Query<GalleryListQuery>) -> GeneralResponseResult<BaseResponse<GalleryListResultOutput>> {
+    let request_id_header = headers.get("x-request-id").unwrap().to_str().unwrap();
+    let request_id: String = request_id_header.parse().unwrap();
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(DEFAULT_LIST_LIMIT);
+
+    let (result, total) = match state.gallery_service.list(offset, limit) {
+        Ok((result, total)) => {(result, total)}
+        Err(e) => {
+            error!("failed to list identities: {e}");
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: "internal server error".to_string(),
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeServer),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    };
+
+    return Ok(GeneralResponseBuilder::new()
+        .status_code(StatusCode::OK)
+        .pagination(ResponsePagination {
+            count: total as u64,
+            offset: offset as u64,
+            limit: limit as u32,
+        })
+        .body(BaseResponse {
+            data: Some(result),
+            response_message: "OK".to_string(),
+            response_code: ResponseCode::response_code(ResponseCode::CodeOK),
+            is_success: true,
+            request_id: request_id.clone(),
+        })
+        .build()
+    )
+}
+
+#[debug_handler(state=GalleryState)]
+pub async fn gallery_delete(headers: HeaderMap, State(state): State<GalleryState>, Path(identity_id): Path<String>) -> GeneralResponseResult<BaseResponse<GalleryDeleteResultOutput>> {
+    let request_id_header = headers.get("x-request-id").unwrap().to_str().unwrap();
+    let request_id: String = request_id_header.parse().unwrap();
+
+    let deleted = match state.gallery_service.delete(&identity_id) {
+        Ok(deleted) => {deleted}
+        Err(e) => {
+            error!("failed to delete identity {identity_id}: {e}");
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: "internal server error".to_string(),
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeServer),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    };
+
+    if !deleted {
+        return Ok(GeneralResponseBuilder::new()
+            .status_code(StatusCode::NOT_FOUND)
+            .body(BaseResponse {
+                data: None,
+                response_message: "identity not found".to_string(),
+                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                is_success: false,
+                request_id: request_id.clone(),
+            })
+            .build()
+        )
+    }
+    info!("deleted identity {identity_id}");
+
+    return Ok(GeneralResponseBuilder::new()
+        .status_code(StatusCode::OK)
+        .body(BaseResponse {
+            data: Some(GalleryDeleteResultOutput { identity_id }),
+            response_message: "OK".to_string(),
+            response_code: ResponseCode::response_code(ResponseCode::CodeOK),
+            is_success: true,
+            request_id: request_id.clone(),
+        })
+        .build()
+    )
+}
+
+#[debug_handler(state=GalleryState)]
+pub async fn gallery_search(headers: HeaderMap, State(state): State<GalleryState>, mut payload: Multipart) -> GeneralResponseResult<BaseResponse<GallerySearchResultOutput>> {
+    let tracer = global::tracer(SETTINGS.app.name.clone());
+    let parent_ctx = opentelemetry::Context::new();
+    let _span = tracer
+        .span_builder("gallery-search")
+        .start_with_context(&tracer, &parent_ctx);
+
+    let request_id_header = headers.get("x-request-id").unwrap().to_str().unwrap();
+    let request_id: String = request_id_header.parse().unwrap();
+    let mut im_bytes: Option<Bytes> = None;
+    let mut embedding: Option<Vec<f32>> = None;
+    let mut top_k = DEFAULT_SEARCH_TOP_K;
+    let mut search_params = SearchParams::default();
+
+    extra_fields::set_extra_fields(LoggerExtraFields {
+        request_id: request_id.clone(),
+    }).unwrap();
+
+    info!("received gallery search request");
+
+    while let Some(field) = payload.next_field().await.unwrap() {
+        let name = field.name().unwrap().to_string();
+        match name.as_str() {
+            "images" => {
+                match field.bytes().await {
+                    Ok(data) => {
+                        if data.len() == 0 {
+                            return Ok(GeneralResponseBuilder::new()
+                                .status_code(StatusCode::BAD_REQUEST)
+                                .body(BaseResponse {
+                                    data: None,
+                                    response_message: "image is empty".to_string(),
+                                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                                    is_success: false,
+                                    request_id: request_id.clone(),
+                                })
+                                .build()
+                            )
+                        }
+                        im_bytes = Some(data);
+                    }
+                    Err(e) => {
+                        error!("failed to retrieves image from request: {e}");
+                        return Ok(GeneralResponseBuilder::new()
+                            .status_code(StatusCode::BAD_REQUEST)
+                            .body(BaseResponse {
+                                data: None,
+                                response_message: "failed to process image".to_string(),
+                                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                                is_success: false,
+                                request_id: request_id.clone(),
+                            })
+                            .build()
+                        )
+                    }
+                };
+            }
+            "embedding" => {
+                let value = field.text().await.unwrap();
+                match serde_json::from_str::<Vec<f32>>(&value) {
+                    Ok(val) => {
+                        embedding = Some(val);
+                    }
+                    Err(e) => {
+                        error!("failed to retrieves embedding value from request: {e}");
+                        return Ok(GeneralResponseBuilder::new()
+                            .status_code(StatusCode::BAD_REQUEST)
+                            .body(BaseResponse {
+                                data: None,
+                                response_message: "invalid embedding value".to_string(),
+                                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                                is_success: false,
+                                request_id: request_id.clone(),
+                            })
+                            .build()
+                        )
+                    }
+                }
+            }
+            "top_k" => {
+                let value = field.text().await.unwrap();
+                match value.parse::<usize>() {
+                    Ok(val) if val > 0 => {
+                        top_k = val;
+                    }
+                    _ => {
+                        error!("failed to retrieves top_k value [{value}] from request");
+                        return Ok(GeneralResponseBuilder::new()
+                            .status_code(StatusCode::BAD_REQUEST)
+                            .body(BaseResponse {
+                                data: None,
+                                response_message: "invalid top_k value".to_string(),
+                                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                                is_success: false,
+                                request_id: request_id.clone(),
+                            })
+                            .build()
+                        )
+                    }
+                }
+            }
+            "ef_search" => {
+                let value = field.text().await.unwrap();
+                match value.parse::<usize>() {
+                    Ok(val) if val > 0 => {
+                        search_params.ef_search = Some(val);
+                    }
+                    _ => {
+                        error!("failed to retrieves ef_search value [{value}] from request");
+                        return Ok(GeneralResponseBuilder::new()
+                            .status_code(StatusCode::BAD_REQUEST)
+                            .body(BaseResponse {
+                                data: None,
+                                response_message: "invalid ef_search value".to_string(),
+                                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                                is_success: false,
+                                request_id: request_id.clone(),
+                            })
+                            .build()
+                        )
+                    }
+                }
+            }
+            "exact" => {
+                let value = field.text().await.unwrap();
+                match value.parse::<bool>() {
+                    Ok(val) => {
+                        search_params.exact = val;
+                    }
+                    Err(e) => {
+                        error!("failed to retrieves exact value from request: {e}");
+                        return Ok(GeneralResponseBuilder::new()
+                            .status_code(StatusCode::BAD_REQUEST)
+                            .body(BaseResponse {
+                                data: None,
+                                response_message: "invalid exact value".to_string(),
+                                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                                is_success: false,
+                                request_id: request_id.clone(),
+                            })
+                            .build()
+                        )
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if im_bytes.is_none() && embedding.is_none() {
+        return Ok(GeneralResponseBuilder::new()
+            .status_code(StatusCode::BAD_REQUEST)
+            .body(BaseResponse {
+                data: None,
+                response_message: "an image or an embedding is required".to_string(),
+                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                is_success: false,
+                request_id: request_id.clone(),
+            })
+            .build()
+        )
+    }
+
+    if let Some(embedding) = &embedding {
+        if let Err(message) = state.gallery_service.check_embedding("embedding", embedding) {
+            error!("invalid embedding: {message}");
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::BAD_REQUEST)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: message,
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    }
+
+    let input = GallerySearchInput {
+        im_bytes,
+        embedding,
+        top_k,
+        search_params,
+    };
+
+    let result = match state.gallery_service.search(input).await {
+        Ok(result) => {result}
+        Err(e) => {
+            error!("failed to search gallery: {e}");
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: "internal server error".to_string(),
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeServer),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    };
+    info!("completed gallery search");
+
+    extra_fields::clear_extra_fields();
+    return Ok(GeneralResponseBuilder::new()
+        .status_code(StatusCode::OK)
+        .body(BaseResponse {
+            data: Some(result),
+            response_message: "OK".to_string(),
+            response_code: ResponseCode::response_code(ResponseCode::CodeOK),
+            is_success: true,
+            request_id: request_id.clone(),
+        })
+        .build()
+    )
+}
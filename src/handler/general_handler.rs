@@ -16,13 +16,14 @@ use uuid::Uuid;
 use crate::config::settings::SETTINGS;
 use crate::error::errors::ResponseCode;
 use crate::logger::logger::LoggerExtraFields;
-use crate::models::general_model::{GeneralExtractionInput, GeneralExtractionResultOutput};
+use crate::models::general_model::{GeneralExtractionInput, GeneralExtractionMode, GeneralExtractionOutput};
 use crate::pipeline::general_pipeline::general_pipeline::{GeneralPipeline, GeneralFaceExtractionResult};
+use crate::pipeline::model_config::pipeline_config::RequestedStage;
 use crate::response::common_response::{BaseResponse, ResponsePagination, GeneralResponse, GeneralResponseBuilder, GeneralResponseResult};
 use crate::state::general_state::GeneralState;
 
 #[debug_handler(state=GeneralState)]
-pub async fn general_extract(headers: HeaderMap, State(state): State<GeneralState>, mut payload: Multipart) -> GeneralResponseResult<BaseResponse<GeneralExtractionResultOutput>> {
+pub async fn general_extract(headers: HeaderMap, State(state): State<GeneralState>, mut payload: Multipart) -> GeneralResponseResult<BaseResponse<GeneralExtractionOutput>> {
     let tracer = global::tracer(SETTINGS.app.name.clone());
     let parent_ctx = opentelemetry::Context::new();
     let span = tracer
@@ -33,6 +34,8 @@ pub async fn general_extract(headers: HeaderMap, State(state): State<GeneralStat
     let mut im_bytes: Bytes = Bytes::new();
     let request_id: String = request_id_header.parse().unwrap();
     let mut is_enroll: Option<bool> = Some(false);
+    let mut mode = GeneralExtractionMode::Single;
+    let mut stages: Option<Vec<RequestedStage>> = None;
 
     extra_fields::set_extra_fields(LoggerExtraFields {
         request_id: request_id.clone(),
@@ -99,15 +102,61 @@ pub async fn general_extract(headers: HeaderMap, State(state): State<GeneralStat
                     }
                 }
             }
+            "mode" => {
+                let value = field.text().await.unwrap();
+                match value.parse::<GeneralExtractionMode>() {
+                    Ok(val) => {
+                        mode = val;
+                    }
+                    Err(e) => {
+                        error!("failed to retrieves mode value [{value}] from request: {e}");
+                        return Ok(GeneralResponseBuilder::new()
+                            .status_code(StatusCode::BAD_REQUEST)
+                            .body(BaseResponse {
+                                data: None,
+                                response_message: "invalid mode value".to_string(),
+                                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                                is_success: false,
+                                request_id: request_id.clone(),
+                            })
+                            .build()
+                        )
+                    }
+                }
+            }
+            "stages" => {
+                let value = field.text().await.unwrap();
+                match RequestedStage::parse_list(&value) {
+                    Ok(val) => {
+                        stages = Some(val);
+                    }
+                    Err(e) => {
+                        error!("failed to retrieves stages value [{value}] from request: {e}");
+                        return Ok(GeneralResponseBuilder::new()
+                            .status_code(StatusCode::BAD_REQUEST)
+                            .body(BaseResponse {
+                                data: None,
+                                response_message: "invalid stages value".to_string(),
+                                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                                is_success: false,
+                                request_id: request_id.clone(),
+                            })
+                            .build()
+                        )
+                    }
+                }
+            }
             _ => {}
         }
     }
     let input = GeneralExtractionInput {
         im_bytes,
         is_enroll,
+        mode,
+        stages,
     };
 
-    let result = match state.general_service.extract_general_image(input).await {
+    let result = match state.general_service.extract(input).await {
         Ok(result) => {result}
         Err(e) => {
             error!("failed to extract face: {e}");
@@ -0,0 +1,259 @@
+use axum::debug_handler;
+use axum::extract::{Multipart, Path, State};
+use bytes::Bytes;
+use ecs_logger::extra_fields;
+use http::{HeaderMap, StatusCode};
+use log::{error, info};
+use opentelemetry::global;
+use opentelemetry::trace::Tracer;
+use crate::config::settings::SETTINGS;
+use crate::error::errors::ResponseCode;
+use crate::logger::logger::LoggerExtraFields;
+use crate::models::liveness_model::{LivenessChallengeInput, LivenessChallengeOutput, LivenessChallengeResultOutput, LivenessInput, LivenessResultOutput, LivenessSource};
+use crate::response::common_response::{BaseResponse, GeneralResponseBuilder, GeneralResponseResult};
+use crate::state::liveness_state::LivenessState;
+
+#[debug_handler(state=LivenessState)]
+pub async fn check_liveness(headers: HeaderMap, State(state): State<LivenessState>, mut payload: Multipart) -> GeneralResponseResult<BaseResponse<LivenessResultOutput>> {
+    let tracer = global::tracer(SETTINGS.app.name.clone());
+    let parent_ctx = opentelemetry::Context::new();
+    let _span = tracer
+        .span_builder("liveness-check")
+        .start_with_context(&tracer, &parent_ctx);
+
+    let request_id_header = headers.get("x-request-id").unwrap().to_str().unwrap();
+    let request_id: String = request_id_header.parse().unwrap();
+
+    extra_fields::set_extra_fields(LoggerExtraFields {
+        request_id: request_id.clone(),
+    }).unwrap();
+
+    info!("received liveness request");
+
+    let source = match read_liveness_source(&mut payload).await {
+        Ok(source) => {source}
+        Err(message) => {
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::BAD_REQUEST)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: message,
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    };
+
+    let frames = match state.liveness_service.decode_frames(&source) {
+        Ok(frames) => {frames}
+        Err(message) => {
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::BAD_REQUEST)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: message,
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    };
+    drop(source);
+
+    let result = match state.liveness_service.check(LivenessInput { frames }).await {
+        Ok(result) => {result}
+        Err(e) => {
+            error!("failed to check liveness: {e}");
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: "internal server error".to_string(),
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeServer),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    };
+    info!("completed checking liveness");
+
+    extra_fields::clear_extra_fields();
+    return Ok(GeneralResponseBuilder::new()
+        .status_code(StatusCode::OK)
+        .body(BaseResponse {
+            data: Some(result),
+            response_message: "OK".to_string(),
+            response_code: ResponseCode::response_code(ResponseCode::CodeOK),
+            is_success: true,
+            request_id: request_id.clone(),
+        })
+        .build()
+    )
+}
+
+#[debug_handler(state=LivenessState)]
+pub async fn start_liveness_challenge(headers: HeaderMap, State(state): State<LivenessState>) -> GeneralResponseResult<BaseResponse<LivenessChallengeOutput>> {
+    let request_id_header = headers.get("x-request-id").unwrap().to_str().unwrap();
+    let request_id: String = request_id_header.parse().unwrap();
+
+    extra_fields::set_extra_fields(LoggerExtraFields {
+        request_id: request_id.clone(),
+    }).unwrap();
+
+    let result = state.liveness_service.start_challenge();
+
+    extra_fields::clear_extra_fields();
+    return Ok(GeneralResponseBuilder::new()
+        .status_code(StatusCode::OK)
+        .body(BaseResponse {
+            data: Some(result),
+            response_message: "OK".to_string(),
+            response_code: ResponseCode::response_code(ResponseCode::CodeOK),
+            is_success: true,
+            request_id: request_id.clone(),
+        })
+        .build()
+    )
+}
+
+#[debug_handler(state=LivenessState)]
+pub async fn verify_liveness_challenge(headers: HeaderMap, State(state): State<LivenessState>, Path(session_id): Path<String>, mut payload: Multipart) -> GeneralResponseResult<BaseResponse<LivenessChallengeResultOutput>> {
+    let tracer = global::tracer(SETTINGS.app.name.clone());
+    let parent_ctx = opentelemetry::Context::new();
+    let _span = tracer
+        .span_builder("liveness-challenge-verify")
+        .start_with_context(&tracer, &parent_ctx);
+
+    let request_id_header = headers.get("x-request-id").unwrap().to_str().unwrap();
+    let request_id: String = request_id_header.parse().unwrap();
+
+    extra_fields::set_extra_fields(LoggerExtraFields {
+        request_id: request_id.clone(),
+    }).unwrap();
+
+    info!("received liveness challenge response for session {session_id}");
+
+    let source = match read_liveness_source(&mut payload).await {
+        Ok(source) => {source}
+        Err(message) => {
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::BAD_REQUEST)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: message,
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    };
+
+    let frames = match state.liveness_service.decode_frames(&source) {
+        Ok(frames) => {frames}
+        Err(message) => {
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::BAD_REQUEST)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: message,
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    };
+    drop(source);
+
+    let result = match state.liveness_service.verify_challenge(LivenessChallengeInput { session_id: session_id.clone(), frames }).await {
+        Ok(Some(result)) => {result}
+        Ok(None) => {
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::NOT_FOUND)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: format!("liveness challenge session {session_id} is unknown, used or expired"),
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+        Err(e) => {
+            error!("failed to verify liveness challenge: {e}");
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: "internal server error".to_string(),
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeServer),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    };
+    info!("completed verifying liveness challenge");
+
+    extra_fields::clear_extra_fields();
+    return Ok(GeneralResponseBuilder::new()
+        .status_code(StatusCode::OK)
+        .body(BaseResponse {
+            data: Some(result),
+            response_message: "OK".to_string(),
+            response_code: ResponseCode::response_code(ResponseCode::CodeOK),
+            is_success: true,
+            request_id: request_id.clone(),
+        })
+        .build()
+    )
+}
+
+/// Reads the repeated `frames` fields or a single `clip` field, the error is the message for the client.
+async fn read_liveness_source(payload: &mut Multipart) -> Result<LivenessSource, String> {
+    let mut frames: Vec<Bytes> = vec![];
+    let mut clip: Option<Bytes> = None;
+
+    while let Some(field) = payload.next_field().await.unwrap() {
+        let name = field.name().unwrap().to_string();
+        match name.as_str() {
+            "frames" | "clip" => {
+                match field.bytes().await {
+                    Ok(data) => {
+                        if data.len() == 0 {
+                            return Err(format!("{name} is empty"))
+                        }
+                        if name == "frames" {
+                            frames.push(data);
+                        } else {
+                            clip = Some(data);
+                        }
+                    }
+                    Err(e) => {
+                        error!("failed to retrieves {name} from request: {e}");
+                        return Err("failed to process image".to_string())
+                    }
+                };
+            }
+            _ => {}
+        }
+    }
+
+    match (frames.is_empty(), clip) {
+        (false, None) => Ok(LivenessSource::Frames(frames)),
+        (true, Some(clip)) => Ok(LivenessSource::Clip(clip)),
+        _ => Err("either frames or a clip is required, not both".to_string()),
+    }
+}
@@ -1,2 +1,6 @@
 pub mod general_handler;
-pub mod antispoofing_handler;
\ No newline at end of file
+pub mod antispoofing_handler;
+pub mod verification_handler;
+pub mod gallery_handler;
+pub mod compliance_handler;
+pub mod liveness_handler;
@@ -0,0 +1,188 @@
+use axum::debug_handler;
+use axum::extract::{Multipart, State};
+use bytes::Bytes;
+use ecs_logger::extra_fields;
+use http::{HeaderMap, StatusCode};
+use log::{error, info};
+use opentelemetry::global;
+use opentelemetry::trace::Tracer;
+use crate::config::settings::SETTINGS;
+use crate::error::errors::ResponseCode;
+use crate::logger::logger::LoggerExtraFields;
+use crate::models::verification_model::{VerificationInput, VerificationResultOutput, VerificationTarget};
+use crate::response::common_response::{BaseResponse, GeneralResponseBuilder, GeneralResponseResult};
+use crate::state::verification_state::VerificationState;
+
+#[debug_handler(state=VerificationState)]
+pub async fn verify(headers: HeaderMap, State(state): State<VerificationState>, mut payload: Multipart) -> GeneralResponseResult<BaseResponse<VerificationResultOutput>> {
+    let tracer = global::tracer(SETTINGS.app.name.clone());
+    let parent_ctx = opentelemetry::Context::new();
+    let _span = tracer
+        .span_builder("face-verification")
+        .start_with_context(&tracer, &parent_ctx);
+
+    let request_id_header = headers.get("x-request-id").unwrap().to_str().unwrap();
+    let request_id: String = request_id_header.parse().unwrap();
+    let mut source_im_bytes: Bytes = Bytes::new();
+    let mut target_im_bytes: Bytes = Bytes::new();
+    let mut target_embedding: Option<Vec<f32>> = None;
+
+    extra_fields::set_extra_fields(LoggerExtraFields {
+        request_id: request_id.clone(),
+    }).unwrap();
+
+    info!("received face verification request");
+
+    while let Some(field) = payload.next_field().await.unwrap() {
+        let name = field.name().unwrap().to_string();
+        match name.as_str() {
+            "source_image" | "target_image" => {
+                match field.bytes().await {
+                    Ok(data) => {
+                        if data.len() == 0 {
+                            return Ok(GeneralResponseBuilder::new()
+                                .status_code(StatusCode::BAD_REQUEST)
+                                .body(BaseResponse {
+                                    data: None,
+                                    response_message: format!("{name} is empty"),
+                                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                                    is_success: false,
+                                    request_id: request_id.clone(),
+                                })
+                                .build()
+                            )
+                        }
+                        if name == "source_image" {
+                            source_im_bytes = data;
+                        } else {
+                            target_im_bytes = data;
+                        }
+                    }
+                    Err(e) => {
+                        error!("failed to retrieves {name} from request: {e}");
+                        return Ok(GeneralResponseBuilder::new()
+                            .status_code(StatusCode::BAD_REQUEST)
+                            .body(BaseResponse {
+                                data: None,
+                                response_message: "failed to process image".to_string(),
+                                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                                is_success: false,
+                                request_id: request_id.clone(),
+                            })
+                            .build()
+                        )
+                    }
+                };
+            }
+            "target_embedding" => {
+                let value = field.text().await.unwrap();
+                match serde_json::from_str::<Vec<f32>>(&value) {
+                    Ok(val) => {
+                        target_embedding = Some(val);
+                    }
+                    Err(e) => {
+                        error!("failed to retrieves target_embedding value from request: {e}");
+                        return Ok(GeneralResponseBuilder::new()
+                            .status_code(StatusCode::BAD_REQUEST)
+                            .body(BaseResponse {
+                                data: None,
+                                response_message: "invalid embedding value".to_string(),
+                                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                                is_success: false,
+                                request_id: request_id.clone(),
+                            })
+                            .build()
+                        )
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let target = match (target_im_bytes.is_empty(), target_embedding) {
+        (false, None) => VerificationTarget::Image(target_im_bytes),
+        (true, Some(embedding)) => VerificationTarget::Embedding(embedding),
+        _ => {
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::BAD_REQUEST)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: "exactly one of target_image or target_embedding is required".to_string(),
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    };
+
+    if source_im_bytes.is_empty() {
+        return Ok(GeneralResponseBuilder::new()
+            .status_code(StatusCode::BAD_REQUEST)
+            .body(BaseResponse {
+                data: None,
+                response_message: "source_image is required".to_string(),
+                response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                is_success: false,
+                request_id: request_id.clone(),
+            })
+            .build()
+        )
+    }
+
+    if let VerificationTarget::Embedding(embedding) = &target {
+        if let Err(message) = state.verification_service.check_embedding(embedding) {
+            error!("invalid target_embedding: {message}");
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::BAD_REQUEST)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: message,
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeInput),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    }
+
+    let input = VerificationInput {
+        source_im_bytes,
+        target,
+    };
+
+    let result = match state.verification_service.verify(input).await {
+        Ok(result) => {result}
+        Err(e) => {
+            error!("failed to verify faces: {e}");
+            return Ok(GeneralResponseBuilder::new()
+                .status_code(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(BaseResponse {
+                    data: None,
+                    response_message: "internal server error".to_string(),
+                    response_code: ResponseCode::response_code(ResponseCode::ErrorCodeServer),
+                    is_success: false,
+                    request_id: request_id.clone(),
+                })
+                .build()
+            )
+        }
+    };
+    info!("completed verifying faces");
+
+    extra_fields::clear_extra_fields();
+    return Ok(GeneralResponseBuilder::new()
+        .status_code(StatusCode::OK)
+        .body(BaseResponse {
+            data: Some(result),
+            response_message: "OK".to_string(),
+            response_code: ResponseCode::response_code(ResponseCode::CodeOK),
+            is_success: true,
+            request_id: request_id.clone(),
+        })
+        .build()
+    )
+}
@@ -0,0 +1,14 @@
+pub mod routes;
+pub mod utils;
+pub mod logger;
+pub mod config;
+pub mod response;
+pub mod error;
+pub mod models;
+pub mod middleware;
+pub mod state;
+pub mod repository;
+pub mod handler;
+pub mod service;
+pub mod pipeline;
+pub mod tracer;
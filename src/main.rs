@@ -1,19 +1,3 @@
-mod routes;
-mod utils;
-mod logger;
-mod config;
-mod response;
-mod error;
-mod models;
-mod middleware;
-mod state;
-mod repository;
-mod handler;
-mod service;
-mod pipeline;
-
-mod tracer;
-
 use std::env;
 use std::sync::Arc;
 use anyhow::Error;
@@ -21,20 +5,24 @@ use axum::{
     routing::get,
     Router,
 };
-use log::info;
+use log::{error, info};
 use opentelemetry::global;
 use opentelemetry::global::shutdown_tracer_provider;
 use tokio::signal;
-use crate::logger::logger::setup_logger;
-use config::settings::SETTINGS;
-use crate::pipeline::general_pipeline::general_pipeline::GeneralPipeline;
-use crate::pipeline::antispoofing_pipeline::antispoofing_pipeline::AntiSpoofingPipeline;
-use crate::routes::root::{root_routes, RouterState};
+use rs_image_processing_service::logger::logger::setup_logger;
+use rs_image_processing_service::config::inference_backend::inference_backend;
+use rs_image_processing_service::config::settings::SETTINGS;
+use rs_image_processing_service::pipeline::general_pipeline::general_pipeline::GeneralPipeline;
+use rs_image_processing_service::pipeline::antispoofing_pipeline::antispoofing_pipeline::AntiSpoofingPipeline;
+use rs_image_processing_service::repository::face_repository::FaceRepository;
+use rs_image_processing_service::repository::file_face_repository::{FileFaceRepository, FileFaceRepositoryIndexOptions};
+use rs_image_processing_service::repository::hnsw_index::HnswParams;
+use rs_image_processing_service::routes::root::{root_routes, RouterState};
 
 
 #[cfg(not(target_env = "msvc"))]
 use tikv_jemallocator::Jemalloc;
-use crate::tracer::tracer::init_tracer_provider;
+use rs_image_processing_service::tracer::tracer::init_tracer_provider;
 
 #[cfg(not(target_env = "msvc"))]
 #[global_allocator]
@@ -46,22 +34,42 @@ async fn main() {
     setup_logger();
     let addr = format!("0.0.0.0:{}", SETTINGS.server.http_port);
 
+    // Setup inference backend
+    let configured_backend = inference_backend(&SETTINGS)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to init inference backend: {}", e.to_string()));
+    let inference_backend = Arc::clone(&configured_backend.inference_backend);
+
     // Setup pipeline
-    let general_pipeline = GeneralPipeline::new(
-        SETTINGS.triton.faceid_host.as_str(),
-        SETTINGS.triton.faceid_grpc_port.to_string().as_str(),
-    )
+    let general_pipeline = GeneralPipeline::new(Arc::clone(&inference_backend), &SETTINGS.models, &SETTINGS.pipelines.general)
         .await
         .unwrap_or_else(|e| panic!("Failed to init general pipeline client: {}", e.to_string()));
 
-    let antispoofing_pipeline = AntiSpoofingPipeline::new(
-        SETTINGS.triton.faceid_host.as_str(),
-        SETTINGS.triton.faceid_grpc_port.to_string().as_str(),
-    )
+    let antispoofing_pipeline = AntiSpoofingPipeline::new(Arc::clone(&inference_backend), &SETTINGS.models, &SETTINGS.pipelines.antispoofing)
         .await
         .unwrap_or_else(|e| panic!("Failed to init anti-spoofing pipeline client: {}", e.to_string()));
     info!("completed initializing pipelines");
 
+    // Setup face gallery
+    let index_options = match &SETTINGS.gallery.index {
+        Some(index) if index.enabled => {
+            let default_params = HnswParams::default();
+            Some(FileFaceRepositoryIndexOptions {
+                path: index.path.clone().into(),
+                params: HnswParams {
+                    m: index.m.unwrap_or(default_params.m),
+                    ef_construction: index.ef_construction.unwrap_or(default_params.ef_construction),
+                    ef_search: index.ef_search.unwrap_or(default_params.ef_search),
+                },
+            })
+        }
+        _ => None,
+    };
+    let face_repository: Arc<dyn FaceRepository> = Arc::new(
+        FileFaceRepository::open(&SETTINGS.gallery.path, SETTINGS.gallery.snapshot_every.unwrap_or(100), index_options)
+            .unwrap_or_else(|e| panic!("Failed to open face gallery: {}", e.to_string()))
+    );
+
     // Setup tracing
     let tracer_provider = init_tracer_provider().expect("Failed to initialize tracer provider.");
     global::set_tracer_provider(tracer_provider.clone());
@@ -71,13 +79,17 @@ async fn main() {
         .await
         .unwrap_or_else(|e| panic!("Failed to create new listener: {}", e.to_string()));
     info!("starting api server on {:?}", addr);
-    let router_state = RouterState::new(general_pipeline, antispoofing_pipeline);
+    let router_state = RouterState::new(general_pipeline, antispoofing_pipeline, Arc::clone(&face_repository));
 
     axum::serve(listener, root_routes(router_state))
         .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap_or_else(|e| panic!("Failed to start api server: {}", e.to_string()));
 
+    if let Err(e) = face_repository.flush() {
+        error!("failed to flush face gallery: {e}");
+    }
+    configured_backend.release().await;
     shutdown_tracer_provider();
 }
 
@@ -1,6 +1,10 @@
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 use crate::pipeline::model_config::config::{FaceAntiSpoofingClass, FaceQualityClass};
+use crate::pipeline::model_config::pipeline_config::{RequestedStage, StageKind};
+use crate::pipeline::module::face_antispoofing::AntiSpoofingScore;
+use crate::pipeline::module::head_pose::HeadPose;
+use crate::pipeline::module::image_quality::ImageQualityReport;
 
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -8,7 +12,12 @@ pub struct AntiSpoofingExtractionResultOutput {
     pub face_count: i32,
     pub face_quality: Option<FaceQualityClass>,
     pub spoofing_check: Option<FaceAntiSpoofingClass>,
+    pub anti_spoofing_score: Option<AntiSpoofingScore>,
+    pub head_pose: Option<HeadPose>,
+    pub image_quality: Option<ImageQualityReport>,
     pub facial_feature: Option<Vec<f32>>,
+    pub stages_run: Vec<StageKind>,
+    pub stages_skipped: Vec<StageKind>,
 }
 
 impl Default for AntiSpoofingExtractionResultOutput {
@@ -17,7 +26,12 @@ impl Default for AntiSpoofingExtractionResultOutput {
             face_count: 0,
             face_quality: None,
             spoofing_check: None,
+            anti_spoofing_score: None,
+            head_pose: None,
+            image_quality: None,
             facial_feature: None,
+            stages_run: vec![],
+            stages_skipped: vec![],
         }
     }
 }
@@ -27,4 +41,6 @@ pub struct AntiSpoofingExtractionInput {
     pub im_bytes: Bytes,
     pub is_enroll: Option<bool>,
     pub spoofing_check: Option<bool>,
+    /// Stages the client asked for, every stage runs when `None`
+    pub stages: Option<Vec<RequestedStage>>,
 }
\ No newline at end of file
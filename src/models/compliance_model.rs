@@ -0,0 +1,16 @@
+use bytes::Bytes;
+use serde::Serialize;
+use crate::pipeline::module::portrait_compliance::ComplianceCheck;
+
+
+#[derive(Clone, Serialize)]
+pub struct ComplianceResultOutput {
+    pub compliant: bool,
+    pub face_count: i32,
+    pub checks: Vec<ComplianceCheck>,
+}
+
+#[derive(Clone)]
+pub struct ComplianceInput {
+    pub im_bytes: Bytes,
+}
@@ -0,0 +1,55 @@
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use crate::repository::face_repository::{IdentityMatch, IdentitySummary, SearchParams};
+
+
+#[derive(Clone, Serialize)]
+pub struct GalleryRejectedImageOutput {
+    pub index: usize,
+    pub reason: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct GalleryEnrollResultOutput {
+    pub identity_id: String,
+    pub enrolled_count: usize,
+    pub embedding_count: usize,
+    pub rejected: Vec<GalleryRejectedImageOutput>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct GalleryListResultOutput {
+    pub identities: Vec<IdentitySummary>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct GalleryDeleteResultOutput {
+    pub identity_id: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct GallerySearchResultOutput {
+    pub face_count: Option<i32>,
+    pub matches: Vec<IdentityMatch>,
+}
+
+#[derive(Clone)]
+pub struct GalleryEnrollInput {
+    pub identity_id: String,
+    pub images: Vec<Bytes>,
+    pub embeddings: Vec<Vec<f32>>,
+}
+
+#[derive(Clone)]
+pub struct GallerySearchInput {
+    pub im_bytes: Option<Bytes>,
+    pub embedding: Option<Vec<f32>>,
+    pub top_k: usize,
+    pub search_params: SearchParams,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct GalleryListQuery {
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
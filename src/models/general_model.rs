@@ -1,6 +1,10 @@
+use std::str::FromStr;
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 use crate::pipeline::model_config::config::FaceQualityClass;
+use crate::pipeline::model_config::pipeline_config::{RequestedStage, StageKind};
+use crate::pipeline::module::head_pose::HeadPose;
+use crate::pipeline::module::image_quality::ImageQualityReport;
 
 
 #[derive(Clone, Serialize)]
@@ -8,7 +12,11 @@ pub struct GeneralExtractionResultOutput {
     pub face_count: i32,
     pub face_quality: Option<FaceQualityClass>,
     pub quality_score: Option<f32>,
+    pub head_pose: Option<HeadPose>,
+    pub image_quality: Option<ImageQualityReport>,
     pub facial_feature: Option<Vec<f32>>,
+    pub stages_run: Vec<StageKind>,
+    pub stages_skipped: Vec<StageKind>,
 }
 
 impl Default for GeneralExtractionResultOutput {
@@ -17,7 +25,67 @@ impl Default for GeneralExtractionResultOutput {
             face_count: 0,
             face_quality: None,
             quality_score: None,
+            head_pose: None,
+            image_quality: None,
             facial_feature: None,
+            stages_run: vec![],
+            stages_skipped: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct GeneralFaceOutput {
+    pub bbox: Vec<f32>,
+    pub landmarks: Option<Vec<Vec<f32>>>,
+    pub detection_score: f32,
+    pub face_quality: Option<FaceQualityClass>,
+    pub quality_score: Option<f32>,
+    pub head_pose: Option<HeadPose>,
+    pub image_quality: Option<ImageQualityReport>,
+    pub facial_feature: Option<Vec<f32>>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct GeneralMultiFaceExtractionResultOutput {
+    pub face_count: i32,
+    pub faces: Vec<GeneralFaceOutput>,
+    pub stages_run: Vec<StageKind>,
+    pub stages_skipped: Vec<StageKind>,
+}
+
+impl Default for GeneralMultiFaceExtractionResultOutput {
+    fn default() -> Self {
+        GeneralMultiFaceExtractionResultOutput {
+            face_count: 0,
+            faces: vec![],
+            stages_run: vec![],
+            stages_skipped: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(untagged)]
+pub enum GeneralExtractionOutput {
+    Single(GeneralExtractionResultOutput),
+    All(GeneralMultiFaceExtractionResultOutput),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeneralExtractionMode {
+    Single,
+    All,
+}
+
+impl FromStr for GeneralExtractionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "single" => Ok(GeneralExtractionMode::Single),
+            "all" => Ok(GeneralExtractionMode::All),
+            _ => Err(format!("unsupported extraction mode: {s}")),
         }
     }
 }
@@ -26,4 +94,7 @@ impl Default for GeneralExtractionResultOutput {
 pub struct GeneralExtractionInput {
     pub im_bytes: Bytes,
     pub is_enroll: Option<bool>,
-}
\ No newline at end of file
+    pub mode: GeneralExtractionMode,
+    /// Stages the client asked for, every stage runs when `None`
+    pub stages: Option<Vec<RequestedStage>>,
+}
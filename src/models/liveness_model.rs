@@ -0,0 +1,61 @@
+use bytes::Bytes;
+use opencv::core::Mat;
+use serde::Serialize;
+use crate::pipeline::module::liveness_challenge::LivenessChallenge;
+
+
+#[derive(Clone, Serialize)]
+pub struct LivenessResultOutput {
+    pub is_live: bool,
+    pub confidence: f32,
+    pub frame_count: usize,
+    pub tracked_frames: usize,
+    pub anti_spoofing_score: Option<f32>,
+    pub micro_motion: f32,
+    pub blink_detected: bool,
+    pub bbox_consistency: f32,
+}
+
+#[derive(Clone)]
+pub enum LivenessSource {
+    /// Burst of still images in capture order
+    Frames(Vec<Bytes>),
+    /// Multi-page image holding the frames, such as a TIFF burst. Video containers are not decoded,
+    /// a client recording video sends its frames instead
+    Clip(Bytes),
+}
+
+#[derive(Clone)]
+pub struct LivenessInput {
+    /// Decoded frames in capture order
+    pub frames: Vec<Mat>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct LivenessChallengeOutput {
+    pub session_id: String,
+    pub challenge: LivenessChallenge,
+    pub instruction: String,
+    /// Unix time in seconds from which the session is refused
+    pub expires_at: u64,
+}
+
+#[derive(Clone)]
+pub struct LivenessChallengeInput {
+    pub session_id: String,
+    /// Decoded frames in capture order
+    pub frames: Vec<Mat>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct LivenessChallengeResultOutput {
+    pub session_id: String,
+    pub challenge: LivenessChallenge,
+    pub passed: bool,
+    pub challenge_completed: bool,
+    pub response: Option<f32>,
+    pub frame_count: usize,
+    pub tracked_frames: usize,
+    pub spoofing_passed: bool,
+    pub anti_spoofing_score: Option<f32>,
+}
@@ -1,2 +1,6 @@
 pub mod general_model;
-pub mod antispoofing_model;
\ No newline at end of file
+pub mod antispoofing_model;
+pub mod verification_model;
+pub mod gallery_model;
+pub mod compliance_model;
+pub mod liveness_model;
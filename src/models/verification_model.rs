@@ -0,0 +1,32 @@
+use bytes::Bytes;
+use serde::Serialize;
+use crate::pipeline::model_config::config::FaceQualityClass;
+
+
+#[derive(Clone, Serialize)]
+pub struct VerificationFaceOutput {
+    pub face_count: i32,
+    pub face_quality: Option<FaceQualityClass>,
+    pub quality_score: Option<f32>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct VerificationResultOutput {
+    pub similarity: Option<f32>,
+    pub threshold: f32,
+    pub is_match: bool,
+    pub source: VerificationFaceOutput,
+    pub target: Option<VerificationFaceOutput>,
+}
+
+#[derive(Clone)]
+pub enum VerificationTarget {
+    Image(Bytes),
+    Embedding(Vec<f32>),
+}
+
+#[derive(Clone)]
+pub struct VerificationInput {
+    pub source_im_bytes: Bytes,
+    pub target: VerificationTarget,
+}
@@ -1,27 +1,24 @@
+use std::sync::Arc;
 use anyhow::Error;
 use ndarray::Array1;
+use opencv::core::Mat;
 use serde::{Deserialize, Serialize};
-use crate::pipeline::model_config::config::{FaceAlignmentConfig, FaceAntiSpoofingClass, FaceAntiSpoofingConfig, FaceDetectionConfig, FaceIdentificationConfig, FaceQualityAssessmentConfig, FaceQualityClass, FaceQualityConfig, FaceSelectionConfig, match_face_anti_spoofing, match_face_quality};
-use crate::pipeline::module::face_alignment::FaceAlignment;
-use crate::pipeline::module::face_antispoofing::FaceAntiSpoofing;
-use crate::pipeline::module::face_detection::RetinaFaceDetection;
-use crate::pipeline::module::face_extraction::FaceExtraction;
-use crate::pipeline::module::face_quality::FaceQuality;
-use crate::pipeline::module::face_quality_assessment::FaceQualityAssessment;
-use crate::pipeline::module::face_selection::FaceSelection;
-use crate::pipeline::triton_client::client::triton::{ModelConfigRequest, ModelConfigResponse};
-use crate::pipeline::triton_client::client::TritonInferenceClient;
-use crate::pipeline::utils::utils::byte_data_to_opencv;
+use crate::pipeline::model_config::config::{FaceAntiSpoofingClass, FaceQualityClass, ModelsConfig};
+use crate::pipeline::model_config::pipeline_config::{PipelineDefinition, RequestedStage, StageKind};
+use crate::pipeline::inference_backend::inference_backend::InferenceBackend;
+use crate::pipeline::module::face_antispoofing::AntiSpoofingScore;
+use crate::pipeline::module::head_pose::HeadPose;
+use crate::pipeline::module::image_quality::ImageQualityReport;
+use crate::pipeline::module::liveness_challenge::{LivenessChallenge, LivenessChallengeCheck, LivenessChallengeReport};
+use crate::pipeline::module::temporal_liveness::{TemporalLiveness, TemporalLivenessReport};
+use crate::pipeline::stage_pipeline::pipeline_stage::{FaceContext, PipelineOptions};
+use crate::pipeline::stage_pipeline::stage_pipeline::{StagePipeline, StagePipelineBuilder};
 
 #[derive(Clone)]
 pub struct AntiSpoofingPipeline {
-    face_detection: RetinaFaceDetection,
-    face_selection: FaceSelection,
-    face_alignment: FaceAlignment,
-    face_quality: FaceQuality,
-    face_quality_assessment: FaceQualityAssessment,
-    face_anti_spoofing: FaceAntiSpoofing,
-    face_extraction: FaceExtraction,
+    pipeline: StagePipeline,
+    temporal_liveness: TemporalLiveness,
+    liveness_challenge: LivenessChallengeCheck,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +27,11 @@ pub struct AntiSpoofingFaceExtractionResult {
     pub facial_feature: Option<Array1<f32>>,
     pub face_quality: Option<FaceQualityClass>,
     pub spoofing_check: Option<FaceAntiSpoofingClass>,
+    pub anti_spoofing_score: Option<AntiSpoofingScore>,
+    pub head_pose: Option<HeadPose>,
+    pub image_quality: Option<ImageQualityReport>,
+    pub stages_run: Vec<StageKind>,
+    pub stages_skipped: Vec<StageKind>,
 }
 
 impl AntiSpoofingFaceExtractionResult {
@@ -39,285 +41,186 @@ impl AntiSpoofingFaceExtractionResult {
             facial_feature: None,
             face_quality: Some(FaceQualityClass::Good),
             spoofing_check: Some(FaceAntiSpoofingClass::Real),
+            anti_spoofing_score: None,
+            head_pose: None,
+            image_quality: None,
+            stages_run: vec![],
+            stages_skipped: vec![],
         }
     }
 }
 
 impl AntiSpoofingPipeline {
     pub async fn new(
-        triton_host: &str,
-        triton_port: &str,
+        inference_backend: Arc<dyn InferenceBackend>,
+        models_cfg: &ModelsConfig,
+        pipeline_definition: &PipelineDefinition,
     ) -> Result<Self, Error> {
-
-        // Init model config
-        let face_detection_cfg = FaceDetectionConfig::new();
-        let face_selection_cfg = FaceSelectionConfig::new();
-        let face_align_cfg = FaceAlignmentConfig::new();
-        let face_quality_cfg = FaceQualityConfig::new();
-        let face_extraction_cfg = FaceIdentificationConfig::new();
-        let face_anti_spoofing_cfg = FaceAntiSpoofingConfig::new();
-        let face_quality_assessment_cfg = FaceQualityAssessmentConfig::new();
-
-        // Init triton client
-        let triton_infer_client = match TritonInferenceClient::new(triton_host, triton_port).await {
-            Ok(triton_infer_client) => triton_infer_client,
-            Err(e) => {
-                return Err(Error::from(e))
-            }
-        };
-
-        // Query face detection model config
-        let face_detection_model_config = match triton_infer_client
-            .model_config(ModelConfigRequest {
-                name: face_detection_cfg.model_name.to_string(),
-                version: "".to_string(),
-            }).await {
-            Ok(model_config_resp) => {model_config_resp}
-            Err(e) => return Err(Error::from(e))
-        };
-
-        // Query face quality model config
-        let face_quality_model_config = match triton_infer_client
-            .model_config(ModelConfigRequest {
-                name: face_quality_cfg.model_name.to_string(),
-                version: "".to_string(),
-            }).await {
-            Ok(model_config_resp) => {model_config_resp}
-            Err(e) => return Err(Error::from(e))
-        };
-
-        // Query face extraction model config
-        let face_extraction_model_config = match triton_infer_client
-            .model_config(ModelConfigRequest {
-                name: face_extraction_cfg.model_name.to_string(),
-                version: "".to_string(),
-            }).await {
-            Ok(model_config_resp) => {model_config_resp}
-            Err(e) => return Err(Error::from(e))
-        };
-
-        // Query face anti-spoofing model config
-        let mut antispoofing_model_config: Vec<ModelConfigResponse> = vec![];
-        for model_name in &face_anti_spoofing_cfg.model_name {
-            let face_antispoofing_model_config = match triton_infer_client
-                .model_config(ModelConfigRequest {
-                    name: model_name.to_owned(),
-                    version: "".to_string(),
-                }).await {
-                Ok(model_config_resp) => {model_config_resp}
-                Err(e) => {
-                    return Err(Error::from(e))
-                }
-            };
-            antispoofing_model_config.push(face_antispoofing_model_config)
-        }
-
-        // Query face quality assessment model config
-        let face_quality_assessment_model_config = match triton_infer_client
-            .model_config(ModelConfigRequest {
-                name: face_quality_assessment_cfg.model_name.to_string(),
-                version: "".to_string(),
-            }).await {
-            Ok(model_config_resp) => {model_config_resp}
-            Err(e) => return Err(Error::from(e))
-        };
-
-
-        // face detection model
-        let face_detection = match RetinaFaceDetection::new(
-            triton_infer_client.clone(),
-            face_detection_model_config,
-            face_detection_cfg.model_name,
-            face_detection_cfg.image_size,
-            face_detection_cfg.max_batch_size,
-            face_detection_cfg.confidence_threshold,
-            face_detection_cfg.iou_threshold,
-        ).await {
-            Ok(face_detection) => {face_detection}
-            Err(e) => {
-                return Err(Error::from(e))
-            }
-        };
-
-        // face selection model
-        let face_selection = FaceSelection::new(
-            face_selection_cfg.margin_center_left_ratio,
-            face_selection_cfg.margin_center_right_ratio,
-            face_selection_cfg.margin_edge_ratio,
-            face_selection_cfg.minimum_face_ratio,
-        ).await;
-
-        // Face alignment model
-        let face_alignment = FaceAlignment::new(
-            face_align_cfg.image_size,
-            face_align_cfg.standard_landmarks
-        );
-
-        // Face quality model
-        let face_quality = match FaceQuality::new(
-            triton_infer_client.clone(),
-            face_quality_model_config,
-            face_quality_cfg.model_name,
-            face_quality_cfg.image_size,
-            face_quality_cfg.threshold,
-        ).await {
-            Ok(face_quality) => {face_quality}
-            Err(e) => {
-                return Err(Error::from(e))
-            }
-        };
-
-        // Face extraction model
-        let face_extraction = match FaceExtraction::new(
-            triton_infer_client.clone(),
-            face_extraction_model_config,
-            face_extraction_cfg.model_name,
-            face_extraction_cfg.image_size,
-            face_extraction_cfg.batch_size,
-        ).await {
-            Ok(face_extraction) => {face_extraction}
-            Err(e) => {
-                return Err(Error::from(e))
-            }
-        };
-
-        // Face anti-spoofing model
-        let face_anti_spoofing = match FaceAntiSpoofing::new(
-            triton_infer_client.clone(),
-            antispoofing_model_config,
-            face_anti_spoofing_cfg.model_name,
-            face_anti_spoofing_cfg.image_size,
-            face_anti_spoofing_cfg.scale,
-            face_anti_spoofing_cfg.batch_size,
-            face_anti_spoofing_cfg.threshold,
-        ).await {
-            Ok(face_anti_spoofing) => {face_anti_spoofing}
-            Err(e) => {
-                return Err(Error::from(e))
-            }
+        match pipeline_definition.validate("antispoofing") {
+            Ok(_) => {}
+            Err(e) => return Err(e)
         };
 
-        // Face quality assessment model
-        let face_quality_assessment = match FaceQualityAssessment::new(
-            triton_infer_client.clone(),
-            face_quality_assessment_model_config,
-            face_quality_assessment_cfg.model_name,
-            face_quality_assessment_cfg.image_size,
-            face_quality_assessment_cfg.batch_size,
-            face_quality_assessment_cfg.threshold,
-        ).await {
-            Ok(face_quality_assessment) => {face_quality_assessment}
+        let pipeline = match StagePipelineBuilder::new(inference_backend, models_cfg).build(pipeline_definition).await {
+            Ok(pipeline) => {pipeline}
             Err(e) => {
                 return Err(Error::from(e))
             }
         };
 
         Ok(AntiSpoofingPipeline {
-            face_detection,
-            face_selection,
-            face_alignment,
-            face_quality,
-            face_quality_assessment,
-            face_anti_spoofing,
-            face_extraction,
+            pipeline,
+            temporal_liveness: TemporalLiveness::new(models_cfg.liveness.clone()),
+            liveness_challenge: LivenessChallengeCheck::new(models_cfg.liveness_challenge.clone(), &models_cfg.liveness),
         })
     }
 
-    pub async fn extract(&self, im_bytes: &[u8], is_spoofing_check: Option<bool>, is_enroll: Option<bool>) -> Result<AntiSpoofingFaceExtractionResult, Error> {
+    /// `stages` limits the run to the requested stages, every stage runs when it is `None`.
+    pub async fn extract(&self, im_bytes: &[u8], is_spoofing_check: Option<bool>, is_enroll: Option<bool>, stages: Option<&[RequestedStage]>) -> Result<AntiSpoofingFaceExtractionResult, Error> {
+        let options = PipelineOptions {
+            enroll: is_enroll.unwrap_or(false),
+            spoofing_check: is_spoofing_check.unwrap_or(false),
+            stages: stages.map(RequestedStage::stage_kinds),
+            ..Default::default()
+        };
 
         let mut antispoofing_extraction_result = AntiSpoofingFaceExtractionResult::new();
-
-        let spoofing_check = is_spoofing_check.unwrap_or(false);
-        let enroll = is_enroll.unwrap_or(false);
-
-        let image = match byte_data_to_opencv(im_bytes) {
-            Ok(image) => {image}
+        let ctx = match self.pipeline.run(im_bytes, options).await {
+            Ok(ctx) => {ctx}
             Err(e) => {
                 return Err(Error::from(e))
             }
         };
-
-        let (detections, key_points)  = match self.face_detection.call(image.clone()).await {
-            Ok((detections, key_points)) => {(detections, key_points)}
-            Err(e) => {
-                return Err(Error::from(e))
+        antispoofing_extraction_result.face_count = ctx.face_count;
+        antispoofing_extraction_result.stages_run = ctx.stages_run;
+        antispoofing_extraction_result.stages_skipped = ctx.stages_skipped;
+
+        // No default liveness verdict when the client did not ask for one
+        if let Some(stages) = &ctx.options.stages {
+            if !stages.contains(&StageKind::AntiSpoofing) {
+                antispoofing_extraction_result.spoofing_check = None;
             }
-        };
-
-        let face_count = detections.dim().0 as i32;
-        antispoofing_extraction_result.face_count = face_count;
+        }
 
-        let (selected_face_box, selected_face_point) = match self.face_selection.call(&image.clone(), detections, key_points, is_enroll) {
-            Ok((selected_face_box, selected_face_point)) => {(selected_face_box, selected_face_point)}
-            Err(e) => {
-                return Err(Error::from(e))
+        if let Some(face) = ctx.faces.into_iter().next() {
+            if face.spoofing_check.is_some() {
+                antispoofing_extraction_result.spoofing_check = face.spoofing_check;
             }
-        };
-
-        if let Some(_selected_face_box) = selected_face_box {
-            if spoofing_check {
-                let model_spoofing_result = match self.face_anti_spoofing.call(image.clone(), _selected_face_box.clone()).await {
-                    Ok(model_spoofing_result) => {model_spoofing_result}
-                    Err(e) => {
-                        return Err(Error::from(e))
-                    }
-                };
-                antispoofing_extraction_result.spoofing_check = Some(match_face_anti_spoofing(model_spoofing_result[0].to_vec()[0] as usize));
+            if face.face_quality_verdict.is_some() {
+                antispoofing_extraction_result.face_quality = face.face_quality_verdict;
             }
+            antispoofing_extraction_result.anti_spoofing_score = face.anti_spoofing_score;
+            antispoofing_extraction_result.head_pose = face.head_pose;
+            antispoofing_extraction_result.image_quality = face.image_quality;
+            antispoofing_extraction_result.facial_feature = face.facial_feature;
+        }
+        Ok(antispoofing_extraction_result)
+    }
 
+    /// Scores every face of each frame for spoofing and fuses the scores of the face followed across
+    /// the frames with its motion. Frames beyond `max_frames` of the liveness config are ignored.
+    pub async fn check_liveness(&self, frames: Vec<Mat>) -> Result<TemporalLivenessReport, Error> {
+        if frames.is_empty() {
+            return Err(Error::msg("antispoofing_pipeline - liveness needs at least one frame"))
+        }
+        let frame_faces = match self.run_frames(frames, self.temporal_liveness.max_frames(), &[RequestedStage::Liveness]).await {
+            Ok(frame_faces) => {frame_faces}
+            Err(e) => return Err(e)
+        };
+        self.temporal_liveness.call(&frame_faces)
+    }
 
-            let aligned_face_image = match self.face_alignment.call(&image, Some(_selected_face_box.clone()), selected_face_point) {
-                Ok(aligned_face_image) => {aligned_face_image}
-                Err(e) => {
-                    return Err(Error::from(e))
-                }
-            };
-
-            let aligned_img_arr = aligned_face_image;
+    /// Checks that the face followed across the frames performs `challenge` and is classified real in
+    /// each of them. Frames beyond `max_frames` of the liveness challenge config are ignored.
+    pub async fn verify_challenge(&self, challenge: LivenessChallenge, frames: Vec<Mat>) -> Result<LivenessChallengeReport, Error> {
+        if frames.is_empty() {
+            return Err(Error::msg("antispoofing_pipeline - liveness challenge needs at least one frame"))
+        }
+        let frame_faces = match self.run_frames(frames, self.liveness_challenge.max_frames(), &[RequestedStage::HeadPose, RequestedStage::Liveness]).await {
+            Ok(frame_faces) => {frame_faces}
+            Err(e) => return Err(e)
+        };
+        self.liveness_challenge.call(challenge, &frame_faces)
+    }
 
-            let (quality_score, quality_class) = match self.face_quality.call(aligned_img_arr.clone()).await {
-                Ok((quality_score, quality_class)) => {(quality_score, quality_class)}
-                Err(e) => {
-                    return Err(Error::from(e))
-                }
-            };
+    /// Runs `stages` with the spoofing check on every face of the first `max_frames` frames.
+    async fn run_frames(&self, frames: Vec<Mat>, max_frames: usize, stages: &[RequestedStage]) -> Result<Vec<(Mat, Vec<FaceContext>)>, Error> {
+        let options = PipelineOptions {
+            spoofing_check: true,
+            all_faces: true,
+            stages: Some(RequestedStage::stage_kinds(stages)),
+            ..Default::default()
+        };
 
-            // face quality assessment
-            let (quality_assessment_score, quality_assessment_class) = match self.face_quality_assessment.call(aligned_img_arr.clone()).await {
-                Ok((quality_assessment_score, quality_assessment_class)) => {(quality_assessment_score[0], quality_assessment_class[0])}
+        let mut frame_faces: Vec<(Mat, Vec<FaceContext>)> = Vec::with_capacity(frames.len().min(max_frames));
+        for image in frames.into_iter().take(max_frames) {
+            let ctx = match self.pipeline.run_image(image, options.clone()).await {
+                Ok(ctx) => {ctx}
                 Err(e) => {
                     return Err(Error::from(e))
                 }
             };
+            frame_faces.push((ctx.image, ctx.faces));
+        }
+        Ok(frame_faces)
+    }
+}
 
-            if !enroll {
-                if match_face_quality(quality_class[0].to_owned()) == FaceQualityClass::WearingMask {
-                    antispoofing_extraction_result.face_quality = Some(match_face_quality(quality_class[0].to_owned()));
-                    return Ok(antispoofing_extraction_result)
-                } else {
-                    let facial_feature = match self.face_extraction.call(aligned_img_arr.clone()).await {
-                        Ok(facial_feature) => {facial_feature}
-                        Err(e) => {
-                            return Err(Error::from(e))
-                        }
-                    };
-                    antispoofing_extraction_result.facial_feature = Some(facial_feature[0].to_owned().into_shape((facial_feature[0].len(),)).unwrap());
-                }
-            } else {
-                if match_face_quality(quality_class[0].to_owned()) == FaceQualityClass::Good && match_face_quality(quality_assessment_class.to_owned() as usize) == FaceQualityClass::Good {
-                    let facial_feature = match self.face_extraction.call(aligned_img_arr.clone()).await {
-                        Ok(facial_feature) => {facial_feature}
-                        Err(e) => {
-                            return Err(Error::from(e))
-                        }
-                    };
-                    antispoofing_extraction_result.facial_feature = Some(facial_feature[0].to_owned().into_shape((facial_feature[0].len(),)).unwrap());
-                } else {
-                    antispoofing_extraction_result.face_quality = Some(FaceQualityClass::Bad)
-                }
-            }
 
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::pipeline::antispoofing_pipeline::antispoofing_pipeline::AntiSpoofingPipeline;
+    use crate::pipeline::inference_backend::triton_backend::TritonBackend;
+    use crate::pipeline::model_config::config::{FaceAntiSpoofingClass, FaceQualityClass, ModelsConfig};
+    use crate::pipeline::model_config::pipeline_config::PipelineDefinition;
+    use crate::pipeline::module::liveness_challenge::LivenessChallenge;
+    use crate::pipeline::triton_client::mock_server::{MockTritonServer, synthetic_image_bytes};
+    use crate::pipeline::utils::utils::byte_data_to_opencv;
+
+    #[tokio::test]
+    async fn test_pipeline() {
+        let server = MockTritonServer::new()
+            .with_face_models()
+            .start().await.unwrap();
+        let inference_backend = Arc::new(TritonBackend::new(&server.host(), &server.port()).await.unwrap());
+        let antispoofing_pipeline = AntiSpoofingPipeline::new(inference_backend, &ModelsConfig::default(), &PipelineDefinition::antispoofing()).await.unwrap();
+        let im_bytes = synthetic_image_bytes(640, 480).unwrap();
+
+        let result = antispoofing_pipeline.extract(&im_bytes, Some(true), Some(true), None).await.unwrap();
+        assert_eq!(result.face_count, 1);
+        assert_eq!(result.spoofing_check, Some(FaceAntiSpoofingClass::Real));
+        let score = result.anti_spoofing_score.unwrap();
+        assert_eq!(score.scale_scores.len(), 4);
+        assert!(score.scale_scores.iter().all(|scale_score| (scale_score - 0.9).abs() < 1e-6));
+        assert_eq!(score.scale_weights.len(), 4);
+        assert!((score.raw_score - 0.9).abs() < 1e-6);
+        assert_eq!(score.live_score, score.raw_score);
+        assert_eq!(result.face_quality, Some(FaceQualityClass::Good));
+        assert_eq!(result.facial_feature.unwrap().len(), 512);
+
+        // The scripted face never moves, so only the anti-spoofing and box consistency cues score
+        let frames: Vec<_> = (0..3).map(|_| byte_data_to_opencv(&im_bytes).unwrap()).collect();
+        let report = antispoofing_pipeline.check_liveness(frames).await.unwrap();
+        assert_eq!((report.frame_count, report.tracked_frames), (3, 3));
+        assert!((report.anti_spoofing_score.unwrap() - 0.9).abs() < 1e-4);
+        assert_eq!(report.micro_motion, 0.0);
+        assert!((report.bbox_consistency - 1.0).abs() < 1e-6);
+        assert!(antispoofing_pipeline.check_liveness(vec![]).await.is_err());
+
+        // The scripted face is real and frontal but never turns its head
+        let frames: Vec<_> = (0..3).map(|_| byte_data_to_opencv(&im_bytes).unwrap()).collect();
+        let report = antispoofing_pipeline.verify_challenge(LivenessChallenge::TurnHeadLeft, frames).await.unwrap();
+        assert_eq!((report.frame_count, report.tracked_frames), (3, 3));
+        assert!(report.spoofing_passed);
+        assert!(report.response.is_some());
+        assert!(!report.challenge_completed);
+        assert!(!report.passed);
+        assert!(antispoofing_pipeline.verify_challenge(LivenessChallenge::Blink, vec![]).await.is_err());
+
+        let model_names: Vec<String> = server.infer_requests().into_iter().map(|request| request.model_name).collect();
+        for model_name in ["miniFAS_4", "miniFAS_2_7", "miniFAS_2", "miniFAS_1", "face_quality_assetment", "face_identification"] {
+            assert!(model_names.iter().any(|name| name == model_name), "{model_name} was not called");
         }
-        Ok(antispoofing_extraction_result)
     }
-}
\ No newline at end of file
+}
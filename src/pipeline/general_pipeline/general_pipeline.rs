@@ -1,23 +1,21 @@
+use std::sync::Arc;
 use anyhow::Error;
-use ndarray::{Array1};
+use ndarray::{Array1, s};
 use serde::{Deserialize, Serialize};
-use crate::pipeline::model_config::config::{FaceAlignmentConfig, FaceDetectionConfig, FaceIdentificationConfig, FaceQualityClass, FaceQualityConfig, FaceSelectionConfig, match_face_quality};
-use crate::pipeline::module::face_alignment::FaceAlignment;
-use crate::pipeline::module::face_detection::RetinaFaceDetection;
-use crate::pipeline::module::face_extraction::FaceExtraction;
-use crate::pipeline::module::face_quality::FaceQuality;
-use crate::pipeline::module::face_selection::FaceSelection;
-use crate::pipeline::triton_client::client::triton::ModelConfigRequest;
-use crate::pipeline::triton_client::client::TritonInferenceClient;
-use crate::pipeline::utils::utils::byte_data_to_opencv;
+use crate::pipeline::model_config::config::{FaceQualityClass, ModelsConfig};
+use crate::pipeline::model_config::pipeline_config::{PipelineDefinition, RequestedStage, StageKind};
+use crate::pipeline::inference_backend::inference_backend::InferenceBackend;
+use crate::pipeline::module::head_pose::HeadPose;
+use crate::pipeline::module::image_quality::ImageQualityReport;
+use crate::pipeline::module::portrait_compliance::{PortraitCompliance, PortraitComplianceReport};
+use crate::pipeline::stage_pipeline::pipeline_stage::PipelineOptions;
+use crate::pipeline::stage_pipeline::stage_pipeline::{StagePipeline, StagePipelineBuilder};
 
 #[derive(Clone)]
 pub struct GeneralPipeline {
-    face_detection: RetinaFaceDetection,
-    face_selection: FaceSelection,
-    face_alignment: FaceAlignment,
-    face_quality: FaceQuality,
-    face_extraction: FaceExtraction,
+    pipeline: StagePipeline,
+    portrait_compliance: PortraitCompliance,
+    embedding_dim: Option<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,7 +23,13 @@ pub struct GeneralFaceExtractionResult {
     pub face_count: i32,
     pub face_quality: Option<FaceQualityClass>,
     pub quality_score: Option<f32>,
+    pub head_pose: Option<HeadPose>,
+    pub image_quality: Option<ImageQualityReport>,
+    /// 512 zeros without a selected face, `None` when the request left out the embedding or a rule
+    /// skipped the extraction
     pub facial_feature: Option<Array1<f32>>,
+    pub stages_run: Vec<StageKind>,
+    pub stages_skipped: Vec<StageKind>,
 }
 
 impl GeneralFaceExtractionResult {
@@ -34,199 +38,258 @@ impl GeneralFaceExtractionResult {
             face_count: 0,
             face_quality: None,
             quality_score: None,
+            head_pose: None,
+            image_quality: None,
             facial_feature: Some(Array1::<f32>::default(512)),
+            stages_run: vec![],
+            stages_skipped: vec![],
         }
     }
 }
 
-impl GeneralPipeline {
-    pub async fn new(
-        triton_host: &str,
-        triton_port: &str,
-    ) -> Result<Self, Error> {
-
-        // Init model config
-        let face_detection_cfg = FaceDetectionConfig::new();
-        let face_selection_cfg = FaceSelectionConfig::new();
-        let face_align_cfg = FaceAlignmentConfig::new();
-        let face_quality_cfg = FaceQualityConfig::new();
-        let face_extraction_cfg = FaceIdentificationConfig::new();
-
-        // Init triton client
-        let triton_infer_client = match TritonInferenceClient::new(triton_host, triton_port).await {
-            Ok(triton_infer_client) => triton_infer_client,
-            Err(e) => {
-                return Err(Error::from(e))
-            }
-        };
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeneralDetectedFace {
+    pub bbox: Vec<f32>,
+    pub landmarks: Option<Vec<Vec<f32>>>,
+    pub detection_score: f32,
+    pub face_quality: Option<FaceQualityClass>,
+    pub quality_score: Option<f32>,
+    pub head_pose: Option<HeadPose>,
+    pub image_quality: Option<ImageQualityReport>,
+    pub facial_feature: Option<Array1<f32>>,
+}
 
-        // Query face detection model config
-        let face_detection_model_config = match triton_infer_client
-            .model_config(ModelConfigRequest {
-                name: face_detection_cfg.model_name.to_string(),
-                version: "".to_string(),
-            }).await {
-            Ok(model_config_resp) => {model_config_resp}
-            Err(e) => return Err(Error::from(e))
-        };
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeneralMultiFaceExtractionResult {
+    pub face_count: i32,
+    pub faces: Vec<GeneralDetectedFace>,
+    pub stages_run: Vec<StageKind>,
+    pub stages_skipped: Vec<StageKind>,
+}
 
-        // Query face quality model config
-        let face_quality_model_config = match triton_infer_client
-            .model_config(ModelConfigRequest {
-                name: face_quality_cfg.model_name.to_string(),
-                version: "".to_string(),
-            }).await {
-            Ok(model_config_resp) => {model_config_resp}
-            Err(e) => return Err(Error::from(e))
-        };
+impl GeneralMultiFaceExtractionResult {
+    fn new() -> GeneralMultiFaceExtractionResult {
+        GeneralMultiFaceExtractionResult {
+            face_count: 0,
+            faces: vec![],
+            stages_run: vec![],
+            stages_skipped: vec![],
+        }
+    }
+}
 
-        // Query face extraction model config
-        let face_extraction_model_config = match triton_infer_client
-            .model_config(ModelConfigRequest {
-                name: face_extraction_cfg.model_name.to_string(),
-                version: "".to_string(),
-            }).await {
-            Ok(model_config_resp) => {model_config_resp}
-            Err(e) => return Err(Error::from(e))
+impl GeneralPipeline {
+    pub async fn new(
+        inference_backend: Arc<dyn InferenceBackend>,
+        models_cfg: &ModelsConfig,
+        pipeline_definition: &PipelineDefinition,
+    ) -> Result<Self, Error> {
+        match pipeline_definition.validate("general") {
+            Ok(_) => {}
+            Err(e) => return Err(e)
         };
 
-
-        // face detection model
-        let face_detection = match RetinaFaceDetection::new(
-            triton_infer_client.clone(),
-            face_detection_model_config,
-            face_detection_cfg.model_name,
-            face_detection_cfg.image_size,
-            face_detection_cfg.max_batch_size,
-            face_detection_cfg.confidence_threshold,
-            face_detection_cfg.iou_threshold,
-        ).await {
-            Ok(face_detection) => {face_detection}
-            Err(e) => {
-                return Err(Error::from(e))
+        // Caller supplied embeddings are checked against the output length of the identification model
+        let embedding_dim = if pipeline_definition.stages.contains(&StageKind::Extraction) {
+            match inference_backend.model_spec(&models_cfg.face_identification.model_name).await {
+                Ok(model_spec) => {
+                    model_spec.outputs.first()
+                        .and_then(|output| output.dims.last())
+                        .filter(|dim| **dim > 0)
+                        .map(|dim| *dim as usize)
+                }
+                Err(e) => return Err(Error::from(e))
             }
+        } else {
+            None
         };
 
-        // face selection model
-        let face_selection = FaceSelection::new(
-            face_selection_cfg.margin_center_left_ratio,
-            face_selection_cfg.margin_center_right_ratio,
-            face_selection_cfg.margin_edge_ratio,
-            face_selection_cfg.minimum_face_ratio,
-        ).await;
-
-        // Face alignment model
-        let face_alignment = FaceAlignment::new(
-            face_align_cfg.image_size,
-            face_align_cfg.standard_landmarks
-        );
-
-        // Face quality model
-        let face_quality = match FaceQuality::new(
-            triton_infer_client.clone(),
-            face_quality_model_config,
-            face_quality_cfg.model_name,
-            face_quality_cfg.image_size,
-            face_quality_cfg.threshold,
-        ).await {
-            Ok(face_quality) => {face_quality}
+        let pipeline = match StagePipelineBuilder::new(inference_backend, models_cfg).build(pipeline_definition).await {
+            Ok(pipeline) => {pipeline}
             Err(e) => {
                 return Err(Error::from(e))
             }
         };
 
-        // Face extraction model
-        let face_extraction = match FaceExtraction::new(
-            triton_infer_client.clone(),
-            face_extraction_model_config,
-            face_extraction_cfg.model_name,
-            face_extraction_cfg.image_size,
-            face_extraction_cfg.batch_size,
-        ).await {
-            Ok(face_extraction) => {face_extraction}
-            Err(e) => {
-                return Err(Error::from(e))
-            }
-        };
+        let portrait_compliance = PortraitCompliance::new(&models_cfg.portrait_compliance, &models_cfg.face_selection).await;
 
         Ok(GeneralPipeline {
-            face_detection,
-            face_selection,
-            face_alignment,
-            face_quality,
-            face_extraction,
+            pipeline,
+            portrait_compliance,
+            embedding_dim,
         })
     }
 
-    pub async fn extract(&self, im_bytes: &[u8], is_enroll: Option<bool>) -> Result<GeneralFaceExtractionResult, Error> {
-        let enroll = is_enroll.unwrap_or(false);
+    /// Length of the facial feature, `None` when the pipeline does not extract one.
+    pub fn embedding_dim(&self) -> Option<usize> {
+        self.embedding_dim
+    }
 
-        let mut general_extraction_result = GeneralFaceExtractionResult::new();
-        let image = match byte_data_to_opencv(im_bytes) {
-            Ok(image) => {image}
-            Err(e) => {
-                return Err(Error::from(e))
+    /// Checks an embedding sent by a caller in `field` against the features this pipeline extracts,
+    /// the error is the message returned to the caller.
+    pub fn check_embedding(&self, field: &str, embedding: &[f32]) -> Result<(), String> {
+        if let Some(embedding_dim) = self.embedding_dim {
+            if embedding.len() != embedding_dim {
+                return Err(format!("{field} must have {embedding_dim} values, got {}", embedding.len()))
             }
+        }
+        let norm = embedding.iter().map(|value| value * value).sum::<f32>().sqrt();
+        if !norm.is_finite() || norm <= f32::EPSILON {
+            return Err(format!("{field} must be finite and non-zero"))
+        }
+        Ok(())
+    }
+
+    pub async fn extract(&self, im_bytes: &[u8], is_enroll: Option<bool>) -> Result<GeneralFaceExtractionResult, Error> {
+        self.extract_with_stages(im_bytes, is_enroll, None).await
+    }
+
+    /// Like `extract` but only runs the stages needed for `stages`, every stage when `None`.
+    pub async fn extract_with_stages(&self, im_bytes: &[u8], is_enroll: Option<bool>, stages: Option<&[RequestedStage]>) -> Result<GeneralFaceExtractionResult, Error> {
+        let options = PipelineOptions {
+            enroll: is_enroll.unwrap_or(false),
+            stages: stages.map(RequestedStage::stage_kinds),
+            ..Default::default()
         };
 
-        let (detections, key_points)  = match self.face_detection.call(image.clone()).await {
-            Ok((detections, key_points)) => {(detections, key_points)}
+        let mut general_extraction_result = GeneralFaceExtractionResult::new();
+        let ctx = match self.pipeline.run(im_bytes, options).await {
+            Ok(ctx) => {ctx}
             Err(e) => {
                 return Err(Error::from(e))
             }
         };
+        general_extraction_result.face_count = ctx.face_count;
+        general_extraction_result.stages_run = ctx.stages_run;
+        general_extraction_result.stages_skipped = ctx.stages_skipped;
 
-        let face_count = detections.dim().0 as i32;
-        general_extraction_result.face_count = face_count;
+        // No feature at all, rather than a placeholder, when the extraction did not run
+        if !general_extraction_result.stages_run.contains(&StageKind::Extraction) {
+            general_extraction_result.facial_feature = None;
+        }
 
-        let (selected_face_box, selected_face_point) = match self.face_selection.call(&image, detections, key_points, Some(enroll)) {
-            Ok((selected_face_box, selected_face_point)) => {(selected_face_box, selected_face_point)}
+        if let Some(face) = ctx.faces.into_iter().next() {
+            let extraction_skipped = face.is_skipped(StageKind::Extraction);
+            general_extraction_result.face_quality = face.face_quality_verdict.or(face.face_quality);
+            general_extraction_result.quality_score = face.quality_score;
+            general_extraction_result.head_pose = face.head_pose;
+            general_extraction_result.image_quality = face.image_quality;
+            // A rule skipping the extraction for this face leaves no feature to compare
+            general_extraction_result.facial_feature = if extraction_skipped {
+                None
+            } else {
+                face.facial_feature
+            };
+        }
+
+        Ok(general_extraction_result)
+    }
+
+    /// Extracts every detected face instead of only the one picked by `FaceSelection`.
+    pub async fn extract_all(&self, im_bytes: &[u8], stages: Option<&[RequestedStage]>) -> Result<GeneralMultiFaceExtractionResult, Error> {
+        let options = PipelineOptions {
+            all_faces: true,
+            stages: stages.map(RequestedStage::stage_kinds),
+            ..Default::default()
+        };
+
+        let mut multi_face_result = GeneralMultiFaceExtractionResult::new();
+        let ctx = match self.pipeline.run(im_bytes, options).await {
+            Ok(ctx) => {ctx}
             Err(e) => {
                 return Err(Error::from(e))
             }
         };
+        multi_face_result.face_count = ctx.face_count;
+        multi_face_result.stages_run = ctx.stages_run;
+        multi_face_result.stages_skipped = ctx.stages_skipped;
 
-        if selected_face_box.is_some() {
-            let aligned_face_image = match self.face_alignment.call(&image, selected_face_box.clone(), selected_face_point) {
-                Ok(aligned_face_image) => {aligned_face_image}
-                Err(e) => {
-                    return Err(Error::from(e))
-                }
-            };
-
-            let (quality_score, quality_class) = match self.face_quality.call(aligned_face_image.clone()).await {
-                Ok((quality_score, quality_class)) => {(quality_score, quality_class)}
-                Err(e) => {
-                    return Err(Error::from(e))
-                }
-            };
-
-            let facial_feature = match self.face_extraction.call(aligned_face_image).await {
-                Ok(facial_feature) => {facial_feature}
-                Err(e) => {
-                    return Err(Error::from(e))
-                }
-            };
-            general_extraction_result.facial_feature = Some(facial_feature[0].to_owned().into_shape((facial_feature[0].len(),)).unwrap());
-            general_extraction_result.face_count = face_count;
-            general_extraction_result.face_quality = Some(match_face_quality(quality_class[0].to_owned()));
-            general_extraction_result.quality_score = Some(quality_score[0]);
-            drop(facial_feature);
+        for face in ctx.faces.into_iter() {
+            multi_face_result.faces.push(GeneralDetectedFace {
+                bbox: face.detection.slice(s![0..4]).to_vec(),
+                landmarks: face.landmarks.map(|points| points.outer_iter().map(|point| point.to_vec()).collect()),
+                detection_score: face.detection[4],
+                face_quality: face.face_quality_verdict.or(face.face_quality),
+                quality_score: face.quality_score,
+                head_pose: face.head_pose,
+                image_quality: face.image_quality,
+                facial_feature: face.facial_feature,
+            });
         }
 
+        Ok(multi_face_result)
+    }
 
-        Ok(general_extraction_result)
+    /// Checks the image against the ID portrait rules, every face is detected and the largest one is judged.
+    pub async fn check_compliance(&self, im_bytes: &[u8]) -> Result<PortraitComplianceReport, Error> {
+        let options = PipelineOptions {
+            all_faces: true,
+            stages: Some(RequestedStage::stage_kinds(&[RequestedStage::HeadPose, RequestedStage::ImageQuality, RequestedStage::Quality])),
+            ..Default::default()
+        };
+
+        let ctx = match self.pipeline.run(im_bytes, options).await {
+            Ok(ctx) => {ctx}
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+        self.portrait_compliance.call(&ctx.image, ctx.face_count, &ctx.faces)
     }
 }
 
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
     use crate::pipeline::general_pipeline::general_pipeline::GeneralPipeline;
-    use crate::pipeline::triton_client::client::TritonInferenceClient;
+    use crate::pipeline::inference_backend::triton_backend::TritonBackend;
+    use crate::pipeline::model_config::config::{FaceQualityClass, ModelsConfig};
+    use crate::pipeline::model_config::pipeline_config::{PipelineDefinition, RequestedStage, StageKind};
+    use crate::pipeline::triton_client::mock_server::{MockTritonServer, synthetic_image_bytes};
 
     #[tokio::test]
     async fn test_pipeline() {
+        let server = MockTritonServer::new()
+            .with_face_models()
+            .start().await.unwrap();
+        let inference_backend = Arc::new(TritonBackend::new(&server.host(), &server.port()).await.unwrap());
+        let general_pipeline = GeneralPipeline::new(inference_backend, &ModelsConfig::default(), &PipelineDefinition::general()).await.unwrap();
+        let im_bytes = synthetic_image_bytes(640, 480).unwrap();
+        assert_eq!(general_pipeline.embedding_dim(), Some(512));
+
+        let result = general_pipeline.extract(&im_bytes, Some(false)).await.unwrap();
+        assert_eq!(result.face_count, 1);
+        assert_eq!(result.face_quality, Some(FaceQualityClass::Good));
+        assert!(result.head_pose.unwrap().is_within(5.0, 5.0, 5.0));
+        assert!(result.image_quality.is_some());
+        let facial_feature = result.facial_feature.unwrap();
+        assert_eq!(facial_feature.len(), 512);
+        assert!((facial_feature.dot(&facial_feature) - 1.0).abs() < 1e-4);
+
+        let detect_only = general_pipeline.extract_with_stages(&im_bytes, Some(false), Some(&[RequestedStage::Detect][..])).await.unwrap();
+        assert_eq!(detect_only.face_count, 1);
+        assert!(detect_only.facial_feature.is_none());
+
+        let multi_face_result = general_pipeline.extract_all(&im_bytes, None).await.unwrap();
+        assert_eq!(multi_face_result.face_count, 1);
+        assert_eq!(multi_face_result.faces.len(), 1);
+        assert_eq!(multi_face_result.faces[0].bbox.len(), 4);
+        assert_eq!(multi_face_result.faces[0].landmarks.as_ref().unwrap().len(), 5);
+        assert_eq!(multi_face_result.faces[0].facial_feature.as_ref().unwrap().len(), 512);
+
+        let compliance_report = general_pipeline.check_compliance(&im_bytes).await.unwrap();
+        assert_eq!(compliance_report.face_count, 1);
+        assert_eq!(compliance_report.checks.len(), 9);
+        assert!(compliance_report.checks.iter().all(|check| check.passed == check.reason.is_none()));
 
+        // Detection, quality and extraction for each full call, the compliance check needs no embedding
+        let model_names: Vec<String> = server.infer_requests().into_iter().map(|request| request.model_name).collect();
+        assert_eq!(model_names, vec![
+            "face_detection_retina", "face_quality", "face_identification",
+            "face_detection_retina", "face_quality", "face_identification",
+            "face_detection_retina",
+            "face_detection_retina", "face_quality",
+        ]);
     }
 }
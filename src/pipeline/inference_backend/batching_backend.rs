@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::Error;
+use async_trait::async_trait;
+use log::debug;
+use serde::Deserialize;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{timeout_at, Instant};
+use crate::pipeline::inference_backend::inference_backend::{InferenceBackend, ModelSpec, Tensor};
+
+/// How long a model waits for concurrent requests and how many rows it sends at most.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct BatchPolicy {
+    pub max_batch_size: i32,
+    pub max_wait_ms: u64,
+}
+
+/// The `[batching]` section of the config file.
+///
+/// `max_batch_size` and `max_wait_ms` apply to every model without an entry in `models`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DynamicBatchingConfig {
+    pub enabled: bool,
+    pub max_batch_size: i32,
+    pub max_wait_ms: u64,
+    #[serde(default)]
+    pub models: HashMap<String, BatchPolicy>,
+}
+
+impl Default for DynamicBatchingConfig {
+    fn default() -> Self {
+        DynamicBatchingConfig {
+            enabled: false,
+            max_batch_size: 8,
+            max_wait_ms: 2,
+            models: HashMap::new(),
+        }
+    }
+}
+
+impl DynamicBatchingConfig {
+    pub fn policy(&self, model_name: &str) -> BatchPolicy {
+        match self.models.get(model_name) {
+            None => BatchPolicy {
+                max_batch_size: self.max_batch_size,
+                max_wait_ms: self.max_wait_ms,
+            },
+            Some(policy) => *policy,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut errors: Vec<String> = vec![];
+        if self.max_batch_size < 1 {
+            errors.push(format!("batching.max_batch_size must be at least 1, got {}", self.max_batch_size));
+        }
+        for (model_name, policy) in self.models.iter() {
+            if policy.max_batch_size < 1 {
+                errors.push(format!("batching.models.{model_name}.max_batch_size must be at least 1, got {}", policy.max_batch_size));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(Error::msg(format!("invalid batching config: {}", errors.join("; "))))
+        }
+        Ok(())
+    }
+}
+
+struct PendingInfer {
+    inputs: Vec<Tensor>,
+    batch_size: i64,
+    respond_to: oneshot::Sender<Result<Vec<Tensor>, Error>>,
+}
+
+/// `InferenceBackend` that coalesces concurrent requests to the same model into one call.
+///
+/// Requests are concatenated along their first dimension until the batch is full or the oldest
+/// request has waited `max_wait_ms`, the outputs are then split back row-wise. Models whose spec
+/// has no batch dimension or allows a single row are passed straight to the inner backend.
+#[derive(Debug)]
+pub struct BatchingBackend {
+    inner: Arc<dyn InferenceBackend>,
+    config: DynamicBatchingConfig,
+    queues: Mutex<HashMap<String, Option<mpsc::UnboundedSender<PendingInfer>>>>,
+}
+
+impl BatchingBackend {
+    pub fn new(inner: Arc<dyn InferenceBackend>, config: &DynamicBatchingConfig) -> Self {
+        BatchingBackend {
+            inner,
+            config: config.clone(),
+            queues: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Queue of the batching worker of `model_name`, started on first use, `None` when the model cannot batch.
+    async fn queue(&self, model_name: &str) -> Result<Option<mpsc::UnboundedSender<PendingInfer>>, Error> {
+        let cached = self.queues.lock().unwrap().get(model_name).cloned();
+        if let Some(queue) = cached {
+            return Ok(queue)
+        }
+
+        let model_spec = match self.inner.model_spec(model_name).await {
+            Ok(model_spec) => {model_spec}
+            Err(e) => return Err(Error::from(e))
+        };
+        let policy = self.config.policy(model_name);
+        let max_batch_size = match model_spec.max_batch_size {
+            0 => 0,
+            model_max_batch_size => i32::min(policy.max_batch_size, model_max_batch_size),
+        };
+
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.entry(model_name.to_string()).or_insert_with(|| {
+            if max_batch_size <= 1 {
+                return None
+            }
+            debug!("batching requests to {model_name} up to {max_batch_size} rows or {}ms", policy.max_wait_ms);
+            let (sender, receiver) = mpsc::unbounded_channel();
+            tokio::spawn(run_batcher(
+                Arc::clone(&self.inner),
+                model_name.to_string(),
+                max_batch_size as i64,
+                Duration::from_millis(policy.max_wait_ms),
+                receiver,
+            ));
+            Some(sender)
+        });
+        Ok(queue.clone())
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for BatchingBackend {
+    async fn model_spec(&self, model_name: &str) -> Result<ModelSpec, Error> {
+        self.inner.model_spec(model_name).await
+    }
+
+    async fn infer(&self, model_name: &str, inputs: Vec<Tensor>) -> Result<Vec<Tensor>, Error> {
+        let queue = match self.queue(model_name).await {
+            Ok(queue) => {queue}
+            Err(e) => return Err(e)
+        };
+        let batch_size = inputs.first().and_then(|input| input.shape.first()).cloned().unwrap_or(0);
+        let queue = match queue {
+            Some(queue) if batch_size > 0 => queue,
+            _ => return self.inner.infer(model_name, inputs).await,
+        };
+
+        let (respond_to, response) = oneshot::channel();
+        if queue.send(PendingInfer { inputs, batch_size, respond_to }).is_err() {
+            return Err(Error::msg(format!("batching_backend - batching worker of {model_name} has stopped")))
+        }
+        match response.await {
+            Ok(result) => result,
+            Err(_) => Err(Error::msg(format!("batching_backend - batch of {model_name} was dropped"))),
+        }
+    }
+}
+
+/// Collects the requests of one model into batches and runs every batch on its own task.
+async fn run_batcher(
+    inner: Arc<dyn InferenceBackend>,
+    model_name: String,
+    max_batch_size: i64,
+    max_wait: Duration,
+    mut receiver: mpsc::UnboundedReceiver<PendingInfer>,
+) {
+    let mut carry: Option<PendingInfer> = None;
+    loop {
+        let first = match carry.take() {
+            Some(pending) => pending,
+            None => match receiver.recv().await {
+                None => return,
+                Some(pending) => pending,
+            },
+        };
+
+        let deadline = Instant::now() + max_wait;
+        let mut batch_size = first.batch_size;
+        let mut batch = vec![first];
+        while batch_size < max_batch_size {
+            let pending = match timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(pending)) => pending,
+                Ok(None) | Err(_) => break,
+            };
+            // A request that does not fit opens the next batch
+            if batch_size + pending.batch_size > max_batch_size || !can_merge(&batch[0].inputs, &pending.inputs) {
+                carry = Some(pending);
+                break
+            }
+            batch_size += pending.batch_size;
+            batch.push(pending);
+        }
+
+        tokio::spawn(run_batch(Arc::clone(&inner), model_name.clone(), batch));
+    }
+}
+
+async fn run_batch(inner: Arc<dyn InferenceBackend>, model_name: String, mut batch: Vec<PendingInfer>) {
+    if batch.len() == 1 {
+        let pending = batch.remove(0);
+        let result = inner.infer(&model_name, pending.inputs).await;
+        let _ = pending.respond_to.send(result);
+        return
+    }
+
+    let batch_sizes: Vec<i64> = batch.iter().map(|pending| pending.batch_size).collect();
+    let mut requests: Vec<Vec<Tensor>> = Vec::with_capacity(batch.len());
+    let mut responders: Vec<oneshot::Sender<Result<Vec<Tensor>, Error>>> = Vec::with_capacity(batch.len());
+    for pending in batch.into_iter() {
+        requests.push(pending.inputs);
+        responders.push(pending.respond_to);
+    }
+
+    let outputs = match inner.infer(&model_name, concat_inputs(requests)).await {
+        Ok(outputs) => {outputs}
+        Err(e) => return fail_all(responders, &e)
+    };
+    let outputs = match split_outputs(outputs, &batch_sizes) {
+        Ok(outputs) => {outputs}
+        Err(e) => return fail_all(responders, &e)
+    };
+    for (respond_to, outputs) in responders.into_iter().zip(outputs.into_iter()) {
+        let _ = respond_to.send(Ok(outputs));
+    }
+}
+
+fn fail_all(responders: Vec<oneshot::Sender<Result<Vec<Tensor>, Error>>>, e: &Error) {
+    for respond_to in responders.into_iter() {
+        let _ = respond_to.send(Err(Error::msg(format!("batching_backend - batched inference failed: {e}"))));
+    }
+}
+
+/// Requests merge when they send the same inputs with the same dimensions past the first.
+fn can_merge(first: &[Tensor], other: &[Tensor]) -> bool {
+    first.len() == other.len() && first.iter().zip(other.iter()).all(|(a, b)| {
+        a.name == b.name && a.shape.len() == b.shape.len() && a.shape[1..] == b.shape[1..]
+    })
+}
+
+fn concat_inputs(requests: Vec<Vec<Tensor>>) -> Vec<Tensor> {
+    let mut merged: Vec<Tensor> = vec![];
+    for inputs in requests.into_iter() {
+        if merged.is_empty() {
+            merged = inputs;
+            continue
+        }
+        for (merged_input, input) in merged.iter_mut().zip(inputs.into_iter()) {
+            merged_input.shape[0] += input.shape[0];
+            merged_input.data.extend(input.data);
+        }
+    }
+    merged
+}
+
+/// Cuts every output along its first dimension into one slice per request.
+fn split_outputs(outputs: Vec<Tensor>, batch_sizes: &[i64]) -> Result<Vec<Vec<Tensor>>, Error> {
+    let total: i64 = batch_sizes.iter().sum();
+    let mut split: Vec<Vec<Tensor>> = batch_sizes.iter().map(|_| Vec::with_capacity(outputs.len())).collect();
+    for output in outputs.into_iter() {
+        if output.shape.first() != Some(&total) {
+            return Err(Error::msg(format!("batching_backend - output {} has shape {:?} for a batch of {total}", output.name, output.shape)))
+        }
+        let row_len = output.data.len() / total as usize;
+        let mut offset = 0;
+        for (idx, batch_size) in batch_sizes.iter().enumerate() {
+            let len = *batch_size as usize * row_len;
+            let mut shape = output.shape.clone();
+            shape[0] = *batch_size;
+            split[idx].push(Tensor::new(&output.name, shape, output.data[offset..offset + len].to_vec()));
+            offset += len;
+        }
+    }
+    Ok(split)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use crate::pipeline::inference_backend::batching_backend::{BatchPolicy, BatchingBackend, DynamicBatchingConfig};
+    use crate::pipeline::inference_backend::inference_backend::{InferenceBackend, Tensor};
+    use crate::pipeline::inference_backend::triton_backend::TritonBackend;
+    use crate::pipeline::triton_client::mock_server::MockTritonServer;
+
+    #[tokio::test]
+    async fn test_batching() {
+        let server = MockTritonServer::new()
+            .with_face_models()
+            .start().await.unwrap();
+        let inference_backend = Arc::new(TritonBackend::new(&server.host(), &server.port()).await.unwrap());
+        let config = DynamicBatchingConfig {
+            enabled: true,
+            models: HashMap::from([("face_identification".to_string(), BatchPolicy { max_batch_size: 8, max_wait_ms: 200 })]),
+            ..Default::default()
+        };
+        let batching_backend = BatchingBackend::new(inference_backend, &config);
+
+        let input = |value: f32| vec![Tensor::new("data", vec![1, 3, 112, 112], vec![value; 3 * 112 * 112])];
+        let (first, second, third) = tokio::join!(
+            batching_backend.infer("face_identification", input(0.0)),
+            batching_backend.infer("face_identification", input(1.0)),
+            batching_backend.infer("face_identification", input(2.0)),
+        );
+
+        // The model allows 4 rows, the three requests travel together and get their own row back
+        for (outputs, value) in [(first, 0.0), (second, 1.0), (third, 2.0)] {
+            let outputs = outputs.unwrap();
+            assert_eq!(outputs[0].shape, vec![1, 512]);
+            assert!(outputs[0].data.iter().all(|feature| *feature == 1.0 + value));
+        }
+        let requests = server.infer_requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].inputs[0].shape, vec![3, 3, 112, 112]);
+
+        // face_quality allows a single row and is never queued
+        let outputs = batching_backend.infer("face_quality", vec![Tensor::new("input", vec![1, 3, 112, 112], vec![0.0; 3 * 112 * 112])]).await.unwrap();
+        assert_eq!(outputs[0].shape, vec![1, 4]);
+        assert_eq!(server.infer_requests().len(), 2);
+    }
+}
@@ -0,0 +1,131 @@
+use std::fmt::Debug;
+use std::str::FromStr;
+use anyhow::Error;
+use async_trait::async_trait;
+use ndarray::{ArrayD, Dimension, IxDyn};
+
+/// Name, element type and dimensions of a model input or output, `-1` marks a dynamic dimension.
+#[derive(Debug, Clone)]
+pub struct TensorSpec {
+    pub name: String,
+    pub datatype: String,
+    pub dims: Vec<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ModelSpec {
+    pub name: String,
+    pub max_batch_size: i32,
+    pub inputs: Vec<TensorSpec>,
+    pub outputs: Vec<TensorSpec>,
+}
+
+impl ModelSpec {
+    pub fn input(&self, idx: usize) -> Result<&TensorSpec, Error> {
+        match self.inputs.get(idx) {
+            None => Err(Error::msg(format!("inference_backend - model {} has no input {}", self.name, idx))),
+            Some(input) => Ok(input),
+        }
+    }
+
+    pub fn output(&self, idx: usize) -> Result<&TensorSpec, Error> {
+        match self.outputs.get(idx) {
+            None => Err(Error::msg(format!("inference_backend - model {} has no output {}", self.name, idx))),
+            Some(output) => Ok(output),
+        }
+    }
+}
+
+/// Named dense FP32 tensor in row-major order, the unit exchanged with every backend.
+#[derive(Debug, Clone)]
+pub struct Tensor {
+    pub name: String,
+    pub shape: Vec<i64>,
+    pub data: Vec<f32>,
+}
+
+impl Tensor {
+    pub fn new(name: &str, shape: Vec<i64>, data: Vec<f32>) -> Self {
+        Tensor {
+            name: name.to_string(),
+            shape,
+            data,
+        }
+    }
+
+    pub fn from_array<D: Dimension>(name: &str, array: ndarray::Array<f32, D>) -> Self {
+        let shape = array.shape().iter().map(|d| *d as i64).collect();
+        let data = array.as_standard_layout().iter().cloned().collect();
+        Tensor::new(name, shape, data)
+    }
+
+    /// Reshapes the data into an array with the dimensionality the caller expects.
+    pub fn into_array<D: Dimension>(self) -> Result<ndarray::Array<f32, D>, Error> {
+        let shape: Vec<usize> = self.shape.iter().map(|d| *d as usize).collect();
+        let array = match ArrayD::from_shape_vec(IxDyn(&shape), self.data) {
+            Ok(array) => {array}
+            Err(e) => return Err(Error::from(e))
+        };
+        match array.into_dimensionality::<D>() {
+            Ok(array) => Ok(array),
+            Err(e) => Err(Error::from(e))
+        }
+    }
+}
+
+/// Removes the output with the given name from the backend response.
+pub fn take_output(outputs: &mut Vec<Tensor>, name: &str) -> Result<Tensor, Error> {
+    match outputs.iter().position(|output| output.name == name) {
+        None => Err(Error::msg(format!("inference_backend - no output named {name}"))),
+        Some(idx) => Ok(outputs.swap_remove(idx)),
+    }
+}
+
+/// Runs a model on named input tensors and returns its named output tensors.
+///
+/// Modules only depend on this trait, so the same pipeline runs against a Triton server or
+/// in-process with ONNX Runtime.
+#[async_trait]
+pub trait InferenceBackend: Send + Sync + Debug {
+    /// Loads or queries the model and describes its inputs and outputs.
+    async fn model_spec(&self, model_name: &str) -> Result<ModelSpec, Error>;
+
+    async fn infer(&self, model_name: &str, inputs: Vec<Tensor>) -> Result<Vec<Tensor>, Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InferenceBackendKind {
+    Triton,
+    Onnx,
+}
+
+impl FromStr for InferenceBackendKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "triton" => Ok(InferenceBackendKind::Triton),
+            "onnx" => Ok(InferenceBackendKind::Onnx),
+            _ => Err(Error::msg(format!("inference_backend - unknown backend {s}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array2, Ix2};
+    use crate::pipeline::inference_backend::inference_backend::{take_output, Tensor};
+
+    #[test]
+    fn test_tensor_roundtrip() {
+        let array = Array2::from_shape_vec((2, 3), vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0]).unwrap();
+        let tensor = Tensor::from_array("fc1", array.clone().reversed_axes());
+        assert_eq!(tensor.shape, vec![3, 2]);
+        assert_eq!(tensor.data, vec![0.0, 3.0, 1.0, 4.0, 2.0, 5.0]);
+
+        let mut outputs = vec![Tensor::new("other", vec![1], vec![0.0]), tensor];
+        let output = take_output(&mut outputs, "fc1").unwrap();
+        assert_eq!(output.into_array::<Ix2>().unwrap(), array.reversed_axes());
+        assert!(take_output(&mut outputs, "fc1").is_err());
+    }
+}
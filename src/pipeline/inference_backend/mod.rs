@@ -0,0 +1,4 @@
+pub mod inference_backend;
+pub mod triton_backend;
+pub mod onnx_backend;
+pub mod batching_backend;
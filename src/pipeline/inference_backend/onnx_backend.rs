@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use anyhow::Error;
+use async_trait::async_trait;
+use log::info;
+use ort::session::builder::GraphOptimizationLevel;
+use ort::session::{Session, SessionInputValue};
+use ort::tensor::TensorElementType;
+use ort::value::ValueType;
+use crate::pipeline::inference_backend::inference_backend::{InferenceBackend, ModelSpec, Tensor, TensorSpec};
+
+/// `InferenceBackend` running the models in-process on the CPU with ONNX Runtime.
+///
+/// Models are looked up in a Triton-style repository (`<model_dir>/<model_name>/1/model.onnx`) or as
+/// `<model_dir>/<model_name>.onnx`, so the same directory can be shared with a Triton deployment.
+pub struct OnnxBackend {
+    model_dir: PathBuf,
+    intra_threads: usize,
+    sessions: RwLock<HashMap<String, Arc<Session>>>,
+}
+
+impl fmt::Debug for OnnxBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sessions = self.sessions.read().unwrap();
+        f.debug_struct("OnnxBackend")
+            .field("model_dir", &self.model_dir)
+            .field("intra_threads", &self.intra_threads)
+            .field("models", &sessions.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl OnnxBackend {
+    pub fn new(model_dir: impl AsRef<Path>, intra_threads: usize) -> Result<Self, Error> {
+        let model_dir = model_dir.as_ref().to_path_buf();
+        if !model_dir.is_dir() {
+            return Err(Error::msg(format!("onnx_backend - model directory {:?} does not exist", model_dir)))
+        }
+        Ok(OnnxBackend {
+            model_dir,
+            intra_threads,
+            sessions: RwLock::new(HashMap::new()),
+        })
+    }
+
+    fn model_path(&self, model_name: &str) -> Result<PathBuf, Error> {
+        let candidates = [
+            self.model_dir.join(model_name).join("1").join("model.onnx"),
+            self.model_dir.join(format!("{model_name}.onnx")),
+        ];
+        match candidates.iter().find(|path| path.is_file()) {
+            None => Err(Error::msg(format!("onnx_backend - no onnx file found for model {model_name}"))),
+            Some(path) => Ok(path.to_owned()),
+        }
+    }
+
+    fn session(&self, model_name: &str) -> Result<Arc<Session>, Error> {
+        if let Some(session) = self.sessions.read().unwrap().get(model_name) {
+            return Ok(Arc::clone(session))
+        }
+
+        let model_path = match self.model_path(model_name) {
+            Ok(model_path) => {model_path}
+            Err(e) => return Err(e)
+        };
+        let session = match Session::builder()
+            .and_then(|builder| builder.with_optimization_level(GraphOptimizationLevel::Level3))
+            .and_then(|builder| builder.with_intra_threads(self.intra_threads))
+            .and_then(|builder| builder.commit_from_file(&model_path)) {
+            Ok(session) => {Arc::new(session)}
+            Err(e) => return Err(Error::from(e))
+        };
+        info!("loaded onnx model {model_name} from {:?}", model_path);
+
+        let mut sessions = self.sessions.write().unwrap();
+        let session = sessions.entry(model_name.to_string()).or_insert(session);
+        Ok(Arc::clone(session))
+    }
+}
+
+/// Names the element type the way Triton does, e.g. `FP32` instead of ONNX Runtime's `f32`.
+fn triton_datatype(ty: &TensorElementType) -> String {
+    match ty {
+        TensorElementType::Bool => "BOOL".to_string(),
+        TensorElementType::Float32 => "FP32".to_string(),
+        TensorElementType::Float64 => "FP64".to_string(),
+        TensorElementType::Uint8 => "UINT8".to_string(),
+        TensorElementType::Int8 => "INT8".to_string(),
+        TensorElementType::Int32 => "INT32".to_string(),
+        TensorElementType::Int64 => "INT64".to_string(),
+        ty => format!("{ty}").to_uppercase(),
+    }
+}
+
+fn tensor_spec(name: &str, value_type: &ValueType) -> TensorSpec {
+    match value_type {
+        ValueType::Tensor { ty, dimensions, .. } => TensorSpec {
+            name: name.to_string(),
+            datatype: triton_datatype(ty),
+            dims: dimensions.to_owned(),
+        },
+        _ => TensorSpec {
+            name: name.to_string(),
+            datatype: "".to_string(),
+            dims: vec![],
+        },
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for OnnxBackend {
+    async fn model_spec(&self, model_name: &str) -> Result<ModelSpec, Error> {
+        let session = match self.session(model_name) {
+            Ok(session) => {session}
+            Err(e) => return Err(e)
+        };
+
+        Ok(ModelSpec {
+            name: model_name.to_string(),
+            // ONNX models carry the batch dimension in their input shape
+            max_batch_size: 0,
+            inputs: session.inputs.iter().map(|input| tensor_spec(&input.name, &input.input_type)).collect(),
+            outputs: session.outputs.iter().map(|output| tensor_spec(&output.name, &output.output_type)).collect(),
+        })
+    }
+
+    async fn infer(&self, model_name: &str, inputs: Vec<Tensor>) -> Result<Vec<Tensor>, Error> {
+        let session = match self.session(model_name) {
+            Ok(session) => {session}
+            Err(e) => return Err(e)
+        };
+
+        // Session::run blocks for the whole forward pass, keep it off the async workers
+        let outputs = tokio::task::spawn_blocking(move || -> Result<Vec<Tensor>, Error> {
+            let mut session_inputs: Vec<(String, SessionInputValue)> = Vec::with_capacity(inputs.len());
+            for input in inputs {
+                let value = match ort::value::Tensor::from_array((input.shape, input.data)) {
+                    Ok(value) => {value}
+                    Err(e) => return Err(Error::from(e))
+                };
+                session_inputs.push((input.name, value.into()));
+            }
+
+            let session_outputs = match session.run(session_inputs) {
+                Ok(session_outputs) => {session_outputs}
+                Err(e) => return Err(Error::from(e))
+            };
+
+            let mut outputs: Vec<Tensor> = Vec::with_capacity(session.outputs.len());
+            for output in session.outputs.iter() {
+                let value = match session_outputs.get(&output.name) {
+                    None => return Err(Error::msg(format!("onnx_backend - missing output {}", output.name))),
+                    Some(value) => value,
+                };
+                let (shape, data) = match value.try_extract_raw_tensor::<f32>() {
+                    Ok((shape, data)) => {(shape, data)}
+                    Err(e) => return Err(Error::from(e))
+                };
+                outputs.push(Tensor::new(&output.name, shape.to_vec(), data.to_vec()));
+            }
+            Ok(outputs)
+        }).await;
+
+        match outputs {
+            Ok(outputs) => outputs,
+            Err(e) => Err(Error::from(e)),
+        }
+    }
+}
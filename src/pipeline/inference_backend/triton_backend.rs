@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use anyhow::Error;
+use async_trait::async_trait;
+use log::{info, warn};
+use crate::pipeline::inference_backend::inference_backend::{InferenceBackend, ModelSpec, Tensor, TensorSpec};
+use crate::pipeline::triton_client::client::triton::model_infer_request::{InferInputTensor, InferRequestedOutputTensor};
+use crate::pipeline::triton_client::client::triton::{ModelConfigRequest, ModelInferRequest, ModelInferResponse};
+use crate::pipeline::triton_client::client::TritonInferenceClient;
+use crate::pipeline::triton_client::recorder::InferRecorder;
+use crate::pipeline::triton_client::shared_memory::{shared_memory_parameters, SharedMemoryOptions, SharedMemoryPool, SharedMemoryRegion};
+use crate::pipeline::triton_client::tensor_encoding::{decode_raw, encode_raw, TensorDataType};
+
+/// `InferenceBackend` served by a remote Triton Inference Server over gRPC.
+///
+/// Inputs travel as raw little-endian bytes in the datatype the model config declares, through
+/// system shared memory when a pool is set up and the request fits in a region.
+#[derive(Debug, Clone)]
+pub struct TritonBackend {
+    triton_infer_client: TritonInferenceClient,
+    model_specs: Arc<RwLock<HashMap<String, ModelSpec>>>,
+    shared_memory: Option<Arc<SharedMemoryPool>>,
+}
+
+impl TritonBackend {
+    pub async fn new(triton_host: &str, triton_port: &str) -> Result<Self, Error> {
+        let triton_infer_client = match TritonInferenceClient::new(triton_host, triton_port).await {
+            Ok(triton_infer_client) => triton_infer_client,
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+        Ok(TritonBackend {
+            triton_infer_client,
+            model_specs: Arc::new(RwLock::new(HashMap::new())),
+            shared_memory: None,
+        })
+    }
+
+    /// Same as `new`, with the Triton traffic recorded to or replayed from golden fixtures.
+    pub async fn new_with_recorder(triton_host: &str, triton_port: &str, recorder: InferRecorder) -> Result<Self, Error> {
+        let triton_infer_client = match TritonInferenceClient::new_with_recorder(triton_host, triton_port, recorder).await {
+            Ok(triton_infer_client) => triton_infer_client,
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+        Ok(TritonBackend {
+            triton_infer_client,
+            model_specs: Arc::new(RwLock::new(HashMap::new())),
+            shared_memory: None,
+        })
+    }
+
+    /// Moves tensors through system shared-memory regions registered with a co-located Triton.
+    ///
+    /// When the regions cannot be set up, for instance because Triton runs on another host, the
+    /// backend keeps sending the tensors in the gRPC messages.
+    pub async fn with_shared_memory(mut self, options: &SharedMemoryOptions) -> Self {
+        match SharedMemoryPool::new(self.triton_infer_client.clone(), options).await {
+            Ok(shared_memory) => {
+                info!("triton tensors go through {} shared memory regions", options.pool_size);
+                self.shared_memory = Some(Arc::new(shared_memory));
+            }
+            Err(e) => {
+                warn!("failed to set up triton shared memory, falling back to grpc payloads: {e}");
+            }
+        }
+        self
+    }
+
+    /// Unregisters the shared-memory regions, to be called on shutdown.
+    pub async fn release_shared_memory(&self) {
+        if let Some(shared_memory) = &self.shared_memory {
+            shared_memory.release().await;
+        }
+    }
+
+    async fn infer_shared_memory(
+        &self,
+        model_spec: &ModelSpec,
+        infer_inputs: &[InferInputTensor],
+        raw_input_contents: &[Vec<u8>],
+        region: &SharedMemoryRegion,
+    ) -> Result<Vec<Tensor>, Error> {
+        let mut offset = 0;
+        let mut inputs: Vec<InferInputTensor> = Vec::with_capacity(infer_inputs.len());
+        for (input, raw_input) in infer_inputs.iter().zip(raw_input_contents.iter()) {
+            match region.write(offset, raw_input) {
+                Ok(_) => {}
+                Err(e) => return Err(e)
+            };
+            let mut input = input.clone();
+            input.parameters = shared_memory_parameters(region.name(), offset, raw_input.len());
+            inputs.push(input);
+            offset += raw_input.len();
+        }
+
+        // Outputs whose size the model config fixes are written to the region too, the others come back in the response
+        let batch_size = infer_inputs.first().and_then(|input| input.shape.first()).cloned().unwrap_or(1);
+        let mut shared_outputs: HashMap<String, usize> = HashMap::new();
+        let mut outputs: Vec<InferRequestedOutputTensor> = Vec::with_capacity(model_spec.outputs.len());
+        for output_spec in model_spec.outputs.iter() {
+            let mut output = InferRequestedOutputTensor {
+                name: output_spec.name.to_string(),
+                parameters: Default::default(),
+            };
+            if let Some(byte_size) = output_byte_size(output_spec, model_spec.max_batch_size, batch_size) {
+                if offset + byte_size <= region.byte_size() {
+                    output.parameters = shared_memory_parameters(region.name(), offset, byte_size);
+                    shared_outputs.insert(output_spec.name.to_string(), offset);
+                    offset += byte_size;
+                }
+            }
+            outputs.push(output);
+        }
+
+        let model_request = ModelInferRequest {
+            model_name: model_spec.name.to_string(),
+            model_version: "".to_string(),
+            id: "".to_string(),
+            parameters: Default::default(),
+            inputs,
+            outputs,
+            raw_input_contents: vec![],
+        };
+        let model_out = match self.triton_infer_client.model_infer(model_request).await {
+            Ok(model_out) => model_out,
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+        decode_outputs(&model_out, &shared_outputs, Some(region))
+    }
+}
+
+/// Bytes an output takes for `batch_size` rows, `None` when the model config leaves a dimension open.
+fn output_byte_size(output_spec: &TensorSpec, max_batch_size: i32, batch_size: i64) -> Option<usize> {
+    let datatype = match output_spec.datatype.parse::<TensorDataType>() {
+        Ok(datatype) => datatype,
+        Err(_) => return None,
+    };
+    if output_spec.dims.iter().any(|dim| *dim < 0) {
+        return None
+    }
+    let mut elements: i64 = output_spec.dims.iter().product();
+    if max_batch_size > 0 {
+        elements *= batch_size;
+    }
+    Some(elements as usize * datatype.byte_size())
+}
+
+/// Reads the outputs written to `region` at the offsets of `shared_outputs`, the others from `raw_output_contents`.
+fn decode_outputs(model_out: &ModelInferResponse, shared_outputs: &HashMap<String, usize>, region: Option<&SharedMemoryRegion>) -> Result<Vec<Tensor>, Error> {
+    let mut outputs: Vec<Tensor> = Vec::with_capacity(model_out.outputs.len());
+    let mut raw_idx = 0;
+    for output in model_out.outputs.iter() {
+        let datatype = match output.datatype.parse::<TensorDataType>() {
+            Ok(datatype) => {datatype}
+            Err(e) => return Err(e)
+        };
+        let decoded = match (shared_outputs.get(&output.name), region) {
+            (Some(offset), Some(region)) => {
+                let byte_size = output.shape.iter().product::<i64>() as usize * datatype.byte_size();
+                match region.read(*offset, byte_size) {
+                    Ok(u8_array) => decode_raw(&u8_array, datatype),
+                    Err(e) => Err(e),
+                }
+            }
+            _ => {
+                let u8_array: &[u8] = match model_out.raw_output_contents.get(raw_idx) {
+                    None => return Err(Error::msg(format!("triton_backend - output {} has no content", output.name))),
+                    Some(u8_array) => u8_array,
+                };
+                raw_idx += 1;
+                decode_raw(u8_array, datatype)
+            }
+        };
+        let data = match decoded {
+            Ok(data) => {data}
+            Err(e) => return Err(e)
+        };
+        outputs.push(Tensor::new(&output.name, output.shape.to_owned(), data));
+    }
+    Ok(outputs)
+}
+
+#[async_trait]
+impl InferenceBackend for TritonBackend {
+    async fn model_spec(&self, model_name: &str) -> Result<ModelSpec, Error> {
+        let model_config_resp = match self.triton_infer_client
+            .model_config(ModelConfigRequest {
+                name: model_name.to_string(),
+                version: "".to_string(),
+            }).await {
+            Ok(model_config_resp) => {model_config_resp}
+            Err(e) => return Err(Error::from(e))
+        };
+
+        let model_cfg = match model_config_resp.config {
+            None => {
+                return Err(Error::msg(format!("triton_backend - model config of {model_name} is empty")))
+            }
+            Some(model_cfg) => {model_cfg}
+        };
+
+        let model_spec = ModelSpec {
+            name: model_cfg.name.to_string(),
+            max_batch_size: model_cfg.max_batch_size,
+            inputs: model_cfg.input.iter().map(|input| TensorSpec {
+                name: input.name.to_string(),
+                datatype: input.data_type().as_str_name()[5..].to_uppercase(),
+                dims: input.dims.to_owned(),
+            }).collect(),
+            outputs: model_cfg.output.iter().map(|output| TensorSpec {
+                name: output.name.to_string(),
+                datatype: output.data_type().as_str_name()[5..].to_uppercase(),
+                dims: output.dims.to_owned(),
+            }).collect(),
+        };
+        self.model_specs.write().unwrap().insert(model_name.to_string(), model_spec.clone());
+        Ok(model_spec)
+    }
+
+    async fn infer(&self, model_name: &str, inputs: Vec<Tensor>) -> Result<Vec<Tensor>, Error> {
+        let cached_spec = self.model_specs.read().unwrap().get(model_name).cloned();
+        let model_spec = match cached_spec {
+            Some(model_spec) => model_spec,
+            None => match self.model_spec(model_name).await {
+                Ok(model_spec) => {model_spec}
+                Err(e) => return Err(e)
+            },
+        };
+
+        let mut infer_inputs: Vec<InferInputTensor> = Vec::with_capacity(inputs.len());
+        let mut raw_input_contents: Vec<Vec<u8>> = Vec::with_capacity(inputs.len());
+        for input in inputs.into_iter() {
+            let datatype = match model_spec.inputs.iter().find(|spec| spec.name == input.name) {
+                None => TensorDataType::Fp32,
+                Some(spec) => match spec.datatype.parse::<TensorDataType>() {
+                    Ok(datatype) => {datatype}
+                    Err(e) => return Err(e)
+                },
+            };
+            raw_input_contents.push(encode_raw(&input.data, datatype));
+            infer_inputs.push(InferInputTensor {
+                name: input.name,
+                datatype: datatype.as_str().to_string(),
+                shape: input.shape,
+                parameters: Default::default(),
+                contents: None,
+            });
+        }
+
+        if let Some(shared_memory) = &self.shared_memory {
+            let input_bytes: usize = raw_input_contents.iter().map(|raw_input| raw_input.len()).sum();
+            // Requests that do not fit, or arrive while every region is busy, go over grpc
+            if input_bytes <= shared_memory.region_size() {
+                if let Some(region) = shared_memory.acquire() {
+                    match self.infer_shared_memory(&model_spec, &infer_inputs, &raw_input_contents, &region).await {
+                        Ok(outputs) => return Ok(outputs),
+                        Err(e) => warn!("triton_backend - shared memory inference of {model_name} failed, retrying over grpc: {e}"),
+                    }
+                }
+            }
+        }
+
+        let model_request = ModelInferRequest {
+            model_name: model_name.to_owned(),
+            model_version: "".to_string(),
+            id: "".to_string(),
+            parameters: Default::default(),
+            inputs: infer_inputs,
+            outputs: Default::default(),
+            raw_input_contents,
+        };
+
+        let model_out = match self.triton_infer_client.model_infer(model_request).await {
+            Ok(model_out) => model_out,
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+        decode_outputs(&model_out, &HashMap::new(), None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::inference_backend::inference_backend::{InferenceBackend, Tensor};
+    use crate::pipeline::inference_backend::triton_backend::TritonBackend;
+    use crate::pipeline::triton_client::client::triton::DataType;
+    use crate::pipeline::triton_client::mock_server::{fp32_infer_response, fp32_model_config, input_values, MockTritonServer};
+
+    #[tokio::test]
+    async fn test_raw_inputs() {
+        let mut model_config = fp32_model_config("echo_fp16", 1, vec![("input", vec![1, 4])], vec![("output", vec![1, 4])]);
+        model_config.input[0].data_type = DataType::TypeFp16 as i32;
+        let server = MockTritonServer::new()
+            .with_model_config(model_config)
+            .with_infer_handler("echo_fp16", |request| {
+                let values = input_values(request, 0).unwrap();
+                Ok(fp32_infer_response(&request.model_name, vec![("output", vec![1, 4], values)]))
+            })
+            .start().await.unwrap();
+        let backend = TritonBackend::new(&server.host(), &server.port()).await.unwrap();
+
+        let outputs = backend.infer("echo_fp16", vec![Tensor::new("input", vec![1, 4], vec![0.5, -1.0, 2.0, 0.25])]).await.unwrap();
+        assert_eq!(outputs[0].data, vec![0.5, -1.0, 2.0, 0.25]);
+
+        // The input went over the wire as half precision bytes, not as typed contents
+        let requests = server.infer_requests();
+        assert_eq!(requests[0].inputs[0].datatype, "FP16");
+        assert!(requests[0].inputs[0].contents.is_none());
+        assert_eq!(requests[0].raw_input_contents[0].len(), 8);
+    }
+}
@@ -1,8 +1,10 @@
 mod rcnn;
 mod processing;
 pub mod triton_client;
+pub mod inference_backend;
 mod module;
 pub mod general_pipeline;
 pub mod antispoofing_pipeline;
+pub mod stage_pipeline;
 mod utils;
 pub mod model_config;
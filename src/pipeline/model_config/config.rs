@@ -1,6 +1,10 @@
 use std::vec;
+use anyhow::Error;
 use ndarray::Array2;
 use serde::{Deserialize, Serialize};
+use crate::pipeline::processing::calibration::CalibrationConfig;
+use crate::pipeline::processing::nms::NmsConfig;
+use crate::pipeline::processing::preprocess::{ChannelOrder, Normalization};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum FaceQualityClass {
@@ -34,92 +38,607 @@ pub fn match_face_anti_spoofing(q: usize) -> FaceAntiSpoofingClass {
     }
 }
 
-#[derive(Debug)]
+/// Every model and threshold of the pipelines, read from the `[models.*]` sections of the config file.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ModelsConfig {
+    pub face_detection: FaceDetectionConfig,
+    pub face_selection: FaceSelectionConfig,
+    pub face_alignment: FaceAlignmentConfig,
+    pub face_quality: FaceQualityConfig,
+    pub face_identification: FaceIdentificationConfig,
+    pub face_anti_spoofing: FaceAntiSpoofingConfig,
+    pub face_quality_assessment: FaceQualityAssessmentConfig,
+    #[serde(default)]
+    pub head_pose: HeadPoseConfig,
+    #[serde(default)]
+    pub image_quality: ImageQualityConfig,
+    #[serde(default)]
+    pub portrait_compliance: PortraitComplianceConfig,
+    #[serde(default)]
+    pub liveness: LivenessConfig,
+    #[serde(default)]
+    pub liveness_challenge: LivenessChallengeConfig,
+}
+
+impl ModelsConfig {
+    /// Rejects values the pipelines cannot run with, so a bad deployment fails at startup
+    /// instead of on the first request.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut errors: Vec<String> = vec![];
+
+        let detection = &self.face_detection;
+        check_model_name(&mut errors, "face_detection", &detection.model_name);
+        check_image_size(&mut errors, "face_detection", detection.image_size);
+        check_batch_size(&mut errors, "face_detection.max_batch_size", detection.max_batch_size);
+        check_unit_interval(&mut errors, "face_detection.confidence_threshold", detection.confidence_threshold);
+        check_unit_interval(&mut errors, "face_detection.iou_threshold", detection.iou_threshold);
+        errors.extend(detection.normalization.errors("face_detection.normalization"));
+        if detection.pad_color.iter().any(|c| !(0.0..=255.0).contains(c)) {
+            errors.push(format!("face_detection.pad_color must be within [0, 255], got {:?}", detection.pad_color));
+        }
+        let tiling = &detection.tiling;
+        if tiling.enabled {
+            if tiling.tile_sizes.is_empty() || tiling.tile_sizes.iter().any(|tile_size| *tile_size <= 0) {
+                errors.push(format!("face_detection.tiling.tile_sizes must list positive sizes, got {:?}", tiling.tile_sizes));
+            }
+            if !(0.0..1.0).contains(&tiling.overlap) {
+                errors.push(format!("face_detection.tiling.overlap must be within [0, 1), got {}", tiling.overlap));
+            }
+        }
+        let nms = &detection.nms;
+        if nms.sigma <= 0.0 {
+            errors.push(format!("face_detection.nms.sigma must be positive, got {}", nms.sigma));
+        }
+        check_unit_interval(&mut errors, "face_detection.nms.min_score", nms.min_score);
+        if nms.top_k == Some(0) {
+            errors.push("face_detection.nms.top_k must be at least 1".to_string());
+        }
+
+        let selection = &self.face_selection;
+        check_unit_interval(&mut errors, "face_selection.margin_center_left_ratio", selection.margin_center_left_ratio);
+        check_unit_interval(&mut errors, "face_selection.margin_center_right_ratio", selection.margin_center_right_ratio);
+        check_unit_interval(&mut errors, "face_selection.margin_edge_ratio", selection.margin_edge_ratio);
+        check_unit_interval(&mut errors, "face_selection.minimum_face_ratio", selection.minimum_face_ratio);
+        if selection.minimum_width_height_ratio > selection.maximum_width_height_ratio {
+            errors.push(format!(
+                "face_selection.minimum_width_height_ratio {} is greater than maximum_width_height_ratio {}",
+                selection.minimum_width_height_ratio, selection.maximum_width_height_ratio
+            ));
+        }
+
+        let head_pose = &self.head_pose;
+        for (key, max_angle) in [("max_yaw", head_pose.max_yaw), ("max_pitch", head_pose.max_pitch), ("max_roll", head_pose.max_roll)] {
+            if !(max_angle > 0.0 && max_angle <= 180.0) {
+                errors.push(format!("head_pose.{key} must be within (0, 180] degrees, got {max_angle}"));
+            }
+        }
+
+        let image_quality = &self.image_quality;
+        if image_quality.min_brightness > image_quality.max_brightness {
+            errors.push(format!(
+                "image_quality.min_brightness {} is greater than max_brightness {}",
+                image_quality.min_brightness, image_quality.max_brightness
+            ));
+        }
+        check_unit_interval(&mut errors, "image_quality.max_overexposed_ratio", image_quality.max_overexposed_ratio);
+        check_unit_interval(&mut errors, "image_quality.max_underexposed_ratio", image_quality.max_underexposed_ratio);
+        if image_quality.underexposed_level >= image_quality.overexposed_level {
+            errors.push(format!(
+                "image_quality.underexposed_level {} must be below overexposed_level {}",
+                image_quality.underexposed_level, image_quality.overexposed_level
+            ));
+        }
+
+        let compliance = &self.portrait_compliance;
+        check_unit_interval(&mut errors, "portrait_compliance.max_center_offset_ratio", compliance.max_center_offset_ratio);
+        for (key, (min_ratio, max_ratio)) in [
+            ("eye_line_ratio", (compliance.min_eye_line_ratio, compliance.max_eye_line_ratio)),
+            ("head_height_ratio", (compliance.min_head_height_ratio, compliance.max_head_height_ratio)),
+        ] {
+            check_unit_interval(&mut errors, &format!("portrait_compliance.min_{key}"), min_ratio);
+            check_unit_interval(&mut errors, &format!("portrait_compliance.max_{key}"), max_ratio);
+            if min_ratio > max_ratio {
+                errors.push(format!("portrait_compliance.min_{key} {min_ratio} is greater than max_{key} {max_ratio}"));
+            }
+        }
+        for (key, max_angle) in [("max_yaw", compliance.max_yaw), ("max_pitch", compliance.max_pitch), ("max_roll", compliance.max_roll)] {
+            if !(max_angle > 0.0 && max_angle <= 180.0) {
+                errors.push(format!("portrait_compliance.{key} must be within (0, 180] degrees, got {max_angle}"));
+            }
+        }
+        if compliance.max_background_std < 0.0 {
+            errors.push(format!("portrait_compliance.max_background_std must not be negative, got {}", compliance.max_background_std));
+        }
+        check_unit_interval(&mut errors, "portrait_compliance.min_background_ratio", compliance.min_background_ratio);
+        check_unit_interval(&mut errors, "portrait_compliance.max_lighting_imbalance", compliance.max_lighting_imbalance);
+
+        let liveness = &self.liveness;
+        if liveness.min_frames < 2 || liveness.min_frames > liveness.max_frames {
+            errors.push(format!(
+                "liveness.min_frames must be at least 2 and at most max_frames {}, got {}",
+                liveness.max_frames, liveness.min_frames
+            ));
+        }
+        check_unit_interval(&mut errors, "liveness.min_track_iou", liveness.min_track_iou);
+        check_unit_interval(&mut errors, "liveness.blink_ratio", liveness.blink_ratio);
+        check_unit_interval(&mut errors, "liveness.threshold", liveness.threshold);
+        if liveness.target_micro_motion <= 0.0 {
+            errors.push(format!("liveness.target_micro_motion must be positive, got {}", liveness.target_micro_motion));
+        }
+        let weights = [liveness.anti_spoofing_weight, liveness.micro_motion_weight, liveness.blink_weight, liveness.bbox_consistency_weight];
+        if weights.iter().any(|weight| *weight < 0.0) || weights.iter().sum::<f32>() <= 0.0 {
+            errors.push(format!("liveness weights must not be negative and must not all be zero, got {:?}", weights));
+        }
+
+        let challenge = &self.liveness_challenge;
+        if challenge.min_frames < 2 || challenge.min_frames > challenge.max_frames {
+            errors.push(format!(
+                "liveness_challenge.min_frames must be at least 2 and at most max_frames {}, got {}",
+                challenge.max_frames, challenge.min_frames
+            ));
+        }
+        if !(challenge.max_start_angle > 0.0 && challenge.max_start_angle < challenge.min_turn_angle && challenge.min_turn_angle <= 90.0) {
+            errors.push(format!(
+                "liveness_challenge.max_start_angle {} must be positive and below min_turn_angle {}, which is at most 90 degrees",
+                challenge.max_start_angle, challenge.min_turn_angle
+            ));
+        }
+        if challenge.min_smile_widening <= 1.0 {
+            errors.push(format!("liveness_challenge.min_smile_widening must be greater than 1, got {}", challenge.min_smile_widening));
+        }
+
+        let alignment = &self.face_alignment;
+        check_image_size(&mut errors, "face_alignment", alignment.image_size);
+        if alignment.standard_landmarks.len() != 5 {
+            errors.push(format!("face_alignment.standard_landmarks must have 5 points, got {}", alignment.standard_landmarks.len()));
+        }
+
+        let quality = &self.face_quality;
+        check_model_name(&mut errors, "face_quality", &quality.model_name);
+        check_image_size(&mut errors, "face_quality", quality.image_size);
+        check_batch_size(&mut errors, "face_quality.batch_size", quality.batch_size);
+        check_unit_interval(&mut errors, "face_quality.threshold", quality.threshold);
+
+        let identification = &self.face_identification;
+        check_model_name(&mut errors, "face_identification", &identification.model_name);
+        check_image_size(&mut errors, "face_identification", identification.image_size);
+        check_batch_size(&mut errors, "face_identification.batch_size", identification.batch_size);
+        errors.extend(identification.normalization.errors("face_identification.normalization"));
+
+        errors.extend(self.face_quality.normalization.errors("face_quality.normalization"));
+
+        let anti_spoofing = &self.face_anti_spoofing;
+        if anti_spoofing.model_name.is_empty() {
+            errors.push("face_anti_spoofing.model_name must list at least one model".to_string());
+        }
+        if anti_spoofing.scale.len() != anti_spoofing.model_name.len() || anti_spoofing.image_size.len() != anti_spoofing.model_name.len() {
+            errors.push(format!(
+                "face_anti_spoofing.model_name, scale and image_size must have the same length, got {}, {} and {}",
+                anti_spoofing.model_name.len(), anti_spoofing.scale.len(), anti_spoofing.image_size.len()
+            ));
+        }
+        for model_name in anti_spoofing.model_name.iter() {
+            check_model_name(&mut errors, "face_anti_spoofing", model_name);
+        }
+        for scale in anti_spoofing.scale.iter() {
+            if *scale <= 0.0 {
+                errors.push(format!("face_anti_spoofing.scale must be positive, got {scale}"));
+            }
+        }
+        for image_size in anti_spoofing.image_size.iter() {
+            check_image_size(&mut errors, "face_anti_spoofing", *image_size);
+        }
+        check_batch_size(&mut errors, "face_anti_spoofing.batch_size", anti_spoofing.batch_size);
+        check_unit_interval(&mut errors, "face_anti_spoofing.threshold", anti_spoofing.threshold);
+        errors.extend(anti_spoofing.calibration.errors("face_anti_spoofing.calibration"));
+        errors.extend(anti_spoofing.normalization.errors("face_anti_spoofing.normalization"));
+
+        let quality_assessment = &self.face_quality_assessment;
+        check_model_name(&mut errors, "face_quality_assessment", &quality_assessment.model_name);
+        check_image_size(&mut errors, "face_quality_assessment", quality_assessment.image_size);
+        check_batch_size(&mut errors, "face_quality_assessment.batch_size", quality_assessment.batch_size);
+        errors.extend(quality_assessment.normalization.errors("face_quality_assessment.normalization"));
+
+        if !errors.is_empty() {
+            return Err(Error::msg(format!("invalid models config: {}", errors.join("; "))))
+        }
+        Ok(())
+    }
+}
+
+fn check_model_name(errors: &mut Vec<String>, section: &str, model_name: &str) {
+    if model_name.trim().is_empty() {
+        errors.push(format!("{section}.model_name must not be empty"));
+    }
+}
+
+fn check_image_size(errors: &mut Vec<String>, section: &str, image_size: (i32, i32)) {
+    if image_size.0 <= 0 || image_size.1 <= 0 {
+        errors.push(format!("{section}.image_size must be positive, got {:?}", image_size));
+    }
+}
+
+fn check_batch_size(errors: &mut Vec<String>, key: &str, batch_size: i32) {
+    if batch_size < 1 {
+        errors.push(format!("{key} must be at least 1, got {batch_size}"));
+    }
+}
+
+fn check_unit_interval(errors: &mut Vec<String>, key: &str, value: f32) {
+    if !(0.0..=1.0).contains(&value) {
+        errors.push(format!("{key} must be within [0, 1], got {value}"));
+    }
+}
+
+/// The `normalization` section of a model config, pixels become `(pixel - mean[c]) / std[c]` in
+/// `channel_order`. Unset fields keep the values built in for the model.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NormalizationConfig {
+    pub mean: Option<[f32; 3]>,
+    pub std: Option<[f32; 3]>,
+    pub channel_order: Option<ChannelOrder>,
+}
+
+impl NormalizationConfig {
+    /// `default` with the configured fields replaced.
+    pub fn resolve(&self, default: Normalization) -> Normalization {
+        Normalization {
+            channel_order: self.channel_order.unwrap_or(default.channel_order),
+            mean: self.mean.unwrap_or(default.mean),
+            scale: match self.std {
+                None => default.scale,
+                Some(std) => std.map(|std| 1.0 / std),
+            },
+        }
+    }
+
+    /// Problems with the configured values, prefixed with `key`.
+    pub fn errors(&self, key: &str) -> Vec<String> {
+        let mut errors: Vec<String> = vec![];
+        if let Some(mean) = self.mean {
+            if mean.iter().any(|mean| !mean.is_finite()) {
+                errors.push(format!("{key}.mean must be finite, got {:?}", mean));
+            }
+        }
+        if let Some(std) = self.std {
+            if std.iter().any(|std| !(std.is_finite() && *std > 0.0)) {
+                errors.push(format!("{key}.std must be positive, got {:?}", std));
+            }
+        }
+        errors
+    }
+}
+
+/// Face detection model family, each decodes its own output layout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FaceDetectorKind {
+    #[default]
+    RetinaFace,
+    Scrfd,
+    YoloFace,
+}
+
+/// Where the resized image is placed in the detector input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaddingMode {
+    /// Image in the top-left corner, padding on the right and bottom
+    #[default]
+    TopLeft,
+    /// Image centred, padding split evenly on both sides
+    Center,
+}
+
+/// Detection on overlapping tiles of large images, on top of the whole-image pass.
+///
+/// Each entry of `tile_sizes` is the side in original pixels of one grid of tiles, several entries
+/// give a multi-scale search. Tiles are letterboxed into the detector input like the whole image.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DetectionTilingConfig {
+    pub enabled: bool,
+    pub tile_sizes: Vec<i32>,
+    /// Fraction of a tile shared with its neighbour
+    pub overlap: f32,
+    /// Images whose longer side is at most this many pixels are only detected whole
+    pub min_image_size: i32,
+}
+
+impl Default for DetectionTilingConfig {
+    fn default() -> Self {
+        DetectionTilingConfig {
+            enabled: false,
+            tile_sizes: vec![640],
+            overlap: 0.2,
+            min_image_size: 1280,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct FaceDetectionConfig {
+    #[serde(default)]
+    pub detector: FaceDetectorKind,
     pub model_name: String,
     pub timeout: i32,
     pub image_size: (i32, i32),
     pub max_batch_size: i32,
     pub confidence_threshold: f32,
     pub iou_threshold: f32,
+    #[serde(default)]
+    pub padding: PaddingMode,
+    /// BGR colour of the letterbox padding
+    #[serde(default)]
+    pub pad_color: [f64; 3],
+    #[serde(default)]
+    pub tiling: DetectionTilingConfig,
+    #[serde(default)]
+    pub nms: NmsConfig,
+    #[serde(default)]
+    pub normalization: NormalizationConfig,
 }
 
-impl FaceDetectionConfig {
-    pub(crate) fn new() -> Self {
+impl Default for FaceDetectionConfig {
+    fn default() -> Self {
         FaceDetectionConfig {
+            detector: FaceDetectorKind::RetinaFace,
             model_name: "face_detection_retina".to_string(),
             timeout: 20,
             image_size: (640, 640),
             max_batch_size: 1,
             confidence_threshold: 0.7,
             iou_threshold: 0.45,
+            padding: PaddingMode::TopLeft,
+            pad_color: [0.0, 0.0, 0.0],
+            tiling: DetectionTilingConfig::default(),
+            nms: NmsConfig::default(),
+            normalization: NormalizationConfig::default(),
         }
     }
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct FaceAlignmentConfig {
     pub image_size: (i32, i32),
-    pub standard_landmarks: Array2<f32>,
+    pub standard_landmarks: Vec<[f32; 2]>,
 }
 
-impl FaceAlignmentConfig {
-    pub fn new() -> Self {
+impl Default for FaceAlignmentConfig {
+    fn default() -> Self {
         FaceAlignmentConfig {
             image_size: (112, 112),
-            standard_landmarks: Array2::from(vec![
+            standard_landmarks: vec![
                 [38.2946, 51.6963],
                 [73.5318, 51.5014],
                 [56.0252, 71.7366],
                 [41.5493, 92.3655],
                 [70.7299, 92.2041],
-            ]),
+            ],
+        }
+    }
+}
+
+impl FaceAlignmentConfig {
+    pub fn standard_landmarks(&self) -> Array2<f32> {
+        Array2::from(self.standard_landmarks.clone())
+    }
+}
+
+/// Largest head rotation in degrees, either way, a face may have to pass the head pose rules.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HeadPoseConfig {
+    pub max_yaw: f32,
+    pub max_pitch: f32,
+    pub max_roll: f32,
+}
+
+impl Default for HeadPoseConfig {
+    fn default() -> Self {
+        HeadPoseConfig {
+            max_yaw: 30.0,
+            max_pitch: 25.0,
+            max_roll: 25.0,
+        }
+    }
+}
+
+/// Pass/fail limits of the classical quality metrics measured on the face crop.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImageQualityConfig {
+    /// Lowest variance of the Laplacian
+    pub min_sharpness: f32,
+    pub min_brightness: f32,
+    pub max_brightness: f32,
+    /// Lowest standard deviation of the grey levels
+    pub min_contrast: f32,
+    /// Grey level from which a pixel counts as overexposed
+    pub overexposed_level: u8,
+    pub max_overexposed_ratio: f32,
+    /// Grey level up to which a pixel counts as underexposed
+    pub underexposed_level: u8,
+    pub max_underexposed_ratio: f32,
+    /// Fewest pixels between the eyes
+    pub min_inter_eye_distance: f32,
+    pub max_blockiness: f32,
+}
+
+impl Default for ImageQualityConfig {
+    fn default() -> Self {
+        ImageQualityConfig {
+            min_sharpness: 100.0,
+            min_brightness: 60.0,
+            max_brightness: 200.0,
+            min_contrast: 25.0,
+            overexposed_level: 250,
+            max_overexposed_ratio: 0.05,
+            underexposed_level: 5,
+            max_underexposed_ratio: 0.05,
+            min_inter_eye_distance: 60.0,
+            max_blockiness: 1.8,
+        }
+    }
+}
+
+/// Limits of the ICAO / ISO 19794-5 style portrait checks.
+///
+/// Ratios are fractions of the image width or height. The head height is measured on the detector
+/// box, which runs from the brows to the chin and so is shorter than the crown to chin height of the standard.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PortraitComplianceConfig {
+    /// Largest horizontal distance between the face and image centres
+    pub max_center_offset_ratio: f32,
+    /// Lowest height of the eye line above the bottom of the image
+    pub min_eye_line_ratio: f32,
+    pub max_eye_line_ratio: f32,
+    pub min_head_height_ratio: f32,
+    pub max_head_height_ratio: f32,
+    /// Largest head rotation in degrees, either way, of a frontal portrait
+    pub max_yaw: f32,
+    pub max_pitch: f32,
+    pub max_roll: f32,
+    /// Largest standard deviation of the grey levels around the head
+    pub max_background_std: f32,
+    /// Smallest fraction of the image left around the head to judge the background on
+    pub min_background_ratio: f32,
+    /// Largest difference between the mean grey levels of the two face halves, over the brighter one
+    pub max_lighting_imbalance: f32,
+}
+
+impl Default for PortraitComplianceConfig {
+    fn default() -> Self {
+        PortraitComplianceConfig {
+            max_center_offset_ratio: 0.05,
+            min_eye_line_ratio: 0.5,
+            max_eye_line_ratio: 0.7,
+            min_head_height_ratio: 0.4,
+            max_head_height_ratio: 0.7,
+            max_yaw: 8.0,
+            max_pitch: 8.0,
+            max_roll: 8.0,
+            max_background_std: 20.0,
+            min_background_ratio: 0.05,
+            max_lighting_imbalance: 0.2,
+        }
+    }
+}
+
+/// Multi-frame liveness, fusing the anti-spoofing scores of a tracked face with its motion.
+///
+/// Each cue is scored in `[0, 1]` and the weighted mean is the liveness confidence.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LivenessConfig {
+    /// Fewest frames the face must be tracked in
+    pub min_frames: usize,
+    /// Frames beyond this many are ignored
+    pub max_frames: usize,
+    /// Lowest IoU with the previous box for a face to continue the track
+    pub min_track_iou: f32,
+    /// Landmark motion relative to the face box that scores the micro-motion cue 1
+    pub target_micro_motion: f32,
+    /// Eyes count as closed when their openness falls below this fraction of the median
+    pub blink_ratio: f32,
+    pub anti_spoofing_weight: f32,
+    pub micro_motion_weight: f32,
+    pub blink_weight: f32,
+    pub bbox_consistency_weight: f32,
+    /// Lowest confidence of a live face
+    pub threshold: f32,
+}
+
+impl Default for LivenessConfig {
+    fn default() -> Self {
+        LivenessConfig {
+            min_frames: 3,
+            max_frames: 30,
+            min_track_iou: 0.3,
+            target_micro_motion: 0.01,
+            blink_ratio: 0.7,
+            anti_spoofing_weight: 0.5,
+            micro_motion_weight: 0.2,
+            blink_weight: 0.15,
+            bbox_consistency_weight: 0.15,
+            threshold: 0.6,
         }
     }
 }
 
-#[derive(Debug)]
+/// Active liveness, the face must perform a random challenge across the uploaded frames.
+///
+/// Blinks and face tracking reuse `blink_ratio` and `min_track_iou` of the liveness config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LivenessChallengeConfig {
+    /// Fewest frames the challenge is checked on, the face must be found in each
+    pub min_frames: usize,
+    /// Frames beyond this many are ignored
+    pub max_frames: usize,
+    /// Largest yaw of the first frame in degrees, a head turn starts from a frontal face
+    pub max_start_angle: f32,
+    /// Smallest yaw change in degrees that completes a head turn
+    pub min_turn_angle: f32,
+    /// Smallest growth of the mouth width over the eye distance, against the first frame, that completes a smile
+    pub min_smile_widening: f32,
+}
+
+impl Default for LivenessChallengeConfig {
+    fn default() -> Self {
+        LivenessChallengeConfig {
+            min_frames: 3,
+            max_frames: 30,
+            max_start_angle: 10.0,
+            min_turn_angle: 20.0,
+            min_smile_widening: 1.15,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct FaceIdentificationConfig {
     pub model_name: String,
     pub timeout: i32,
     pub image_size: (i32, i32),
     pub batch_size: i32,
+    #[serde(default)]
+    pub normalization: NormalizationConfig,
 }
 
-impl FaceIdentificationConfig {
-    pub fn new() -> Self {
+impl Default for FaceIdentificationConfig {
+    fn default() -> Self {
         FaceIdentificationConfig {
             model_name: "face_identification".to_string(),
             timeout: 20,
             image_size: (112, 112),
             batch_size: 1,
+            normalization: NormalizationConfig::default(),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct FaceQualityConfig {
     pub model_name: String,
     pub timeout: i32,
     pub image_size: (i32, i32),
     pub batch_size: i32,
     pub threshold: f32,
+    #[serde(default)]
+    pub normalization: NormalizationConfig,
 }
 
-impl FaceQualityConfig {
-    pub fn new() -> Self {
+impl Default for FaceQualityConfig {
+    fn default() -> Self {
         FaceQualityConfig {
             model_name: "face_quality".to_string(),
             timeout: 20,
             image_size: (112, 112),
             batch_size: 1,
             threshold: 0.5,
+            normalization: NormalizationConfig::default(),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct FaceSelectionConfig {
     pub margin_center_left_ratio: f32,
     pub margin_center_right_ratio: f32,
@@ -129,8 +648,8 @@ pub struct FaceSelectionConfig {
     pub maximum_width_height_ratio: f32,
 }
 
-impl FaceSelectionConfig {
-    pub fn new() -> Self {
+impl Default for FaceSelectionConfig {
+    fn default() -> Self {
         FaceSelectionConfig {
             margin_center_left_ratio: 0.3,
             margin_center_right_ratio: 0.3,
@@ -142,18 +661,25 @@ impl FaceSelectionConfig {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct FaceAntiSpoofingConfig {
     pub model_name: Vec<String>,
     pub scale: Vec<f32>,
     pub image_size: Vec<(i32, i32)>,
+    /// Lowest calibrated live score of a real face
     pub threshold: f32,
     pub timeout: i32,
     pub batch_size: i32,
+    /// Maps the fused live score to a probability of a real face
+    #[serde(default)]
+    pub calibration: CalibrationConfig,
+    /// Shared by the models of every scale
+    #[serde(default)]
+    pub normalization: NormalizationConfig,
 }
 
-impl FaceAntiSpoofingConfig{
-    pub fn new() -> Self {
+impl Default for FaceAntiSpoofingConfig {
+    fn default() -> Self {
         FaceAntiSpoofingConfig {
             model_name: vec![
                 "miniFAS_4".to_string(),
@@ -171,28 +697,86 @@ impl FaceAntiSpoofingConfig{
             threshold: 0.55,
             timeout: 20,
             batch_size: 1,
+            calibration: CalibrationConfig::default(),
+            normalization: NormalizationConfig::default(),
         }
     }
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct FaceQualityAssessmentConfig {
     pub model_name: String,
     pub timeout: i32,
     pub image_size: (i32, i32),
     pub batch_size: i32,
     pub threshold: f32,
+    #[serde(default)]
+    pub normalization: NormalizationConfig,
 }
 
-impl FaceQualityAssessmentConfig {
-    pub fn new() -> Self {
+impl Default for FaceQualityAssessmentConfig {
+    fn default() -> Self {
         FaceQualityAssessmentConfig {
             model_name: "face_quality_assetment".to_string(),
             timeout: 20,
             image_size: (112, 112),
             batch_size: 1,
             threshold: 55.0,
+            normalization: NormalizationConfig::default(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::model_config::config::{ModelsConfig, NormalizationConfig};
+    use crate::pipeline::processing::calibration::CalibrationMethod;
+    use crate::pipeline::processing::preprocess::{ChannelOrder, Normalization};
+
+    #[test]
+    fn test_validate() {
+        assert!(ModelsConfig::default().validate().is_ok());
+
+        let mut models_cfg = ModelsConfig::default();
+        models_cfg.face_anti_spoofing.scale.pop();
+        models_cfg.face_anti_spoofing.calibration.method = CalibrationMethod::Isotonic;
+        models_cfg.face_detection.iou_threshold = 1.5;
+        models_cfg.face_detection.tiling.enabled = true;
+        models_cfg.face_detection.tiling.overlap = 1.0;
+        models_cfg.face_detection.nms.top_k = Some(0);
+        models_cfg.head_pose.max_yaw = 0.0;
+        models_cfg.image_quality.min_brightness = 220.0;
+        models_cfg.portrait_compliance.min_eye_line_ratio = 0.8;
+        models_cfg.liveness.min_frames = 1;
+        models_cfg.liveness_challenge.min_smile_widening = 0.9;
+        models_cfg.face_identification.normalization.std = Some([127.5, 0.0, 127.5]);
+        let err = models_cfg.validate().unwrap_err().to_string();
+        assert!(err.contains("face_anti_spoofing.model_name, scale and image_size must have the same length"));
+        assert!(err.contains("face_anti_spoofing.calibration.isotonic_scores"));
+        assert!(err.contains("face_detection.iou_threshold"));
+        assert!(err.contains("face_detection.tiling.overlap"));
+        assert!(err.contains("face_detection.nms.top_k"));
+        assert!(err.contains("head_pose.max_yaw"));
+        assert!(err.contains("image_quality.min_brightness"));
+        assert!(err.contains("portrait_compliance.min_eye_line_ratio"));
+        assert!(err.contains("liveness.min_frames"));
+        assert!(err.contains("liveness_challenge.min_smile_widening"));
+        assert!(err.contains("face_identification.normalization.std"));
+    }
+
+    #[test]
+    fn test_normalization_resolve() {
+        assert_eq!(NormalizationConfig::default().resolve(Normalization::FACE_IDENTIFICATION), Normalization::FACE_IDENTIFICATION);
+
+        let normalization_cfg = NormalizationConfig {
+            mean: None,
+            std: Some([255.0, 255.0, 255.0]),
+            channel_order: Some(ChannelOrder::Bgr),
+        };
+        let normalization = normalization_cfg.resolve(Normalization::FACE_IDENTIFICATION);
+        assert_eq!(normalization.channel_order, ChannelOrder::Bgr);
+        assert_eq!(normalization.mean, Normalization::FACE_IDENTIFICATION.mean);
+        assert_eq!(normalization.scale, [1.0 / 255.0; 3]);
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use crate::pipeline::model_config::config::FaceQualityClass;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageKind {
+    Detection,
+    Selection,
+    HeadPose,
+    ImageQuality,
+    Alignment,
+    Quality,
+    QualityAssessment,
+    AntiSpoofing,
+    #[default]
+    Extraction,
+}
+
+impl StageKind {
+    /// Stages that work on each face rather than on the whole image.
+    pub fn is_per_face(&self) -> bool {
+        !matches!(self, StageKind::Detection | StageKind::Selection)
+    }
+}
+
+/// Stage a client can ask for on a single request.
+///
+/// Detection and selection always run, alignment runs whenever a stage that needs an aligned face is asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestedStage {
+    Detect,
+    HeadPose,
+    ImageQuality,
+    Quality,
+    QualityAssessment,
+    Liveness,
+    Embedding,
+}
+
+impl FromStr for RequestedStage {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "detect" => Ok(RequestedStage::Detect),
+            "head_pose" => Ok(RequestedStage::HeadPose),
+            "image_quality" => Ok(RequestedStage::ImageQuality),
+            "quality" => Ok(RequestedStage::Quality),
+            "quality_assessment" => Ok(RequestedStage::QualityAssessment),
+            "liveness" => Ok(RequestedStage::Liveness),
+            "embedding" => Ok(RequestedStage::Embedding),
+            _ => Err(Error::msg(format!("unknown stage {s}, expected one of detect, head_pose, image_quality, quality, quality_assessment, liveness, embedding"))),
+        }
+    }
+}
+
+impl RequestedStage {
+    /// Parses a comma separated list such as `detect,quality`.
+    pub fn parse_list(s: &str) -> Result<Vec<RequestedStage>, Error> {
+        let mut requested: Vec<RequestedStage> = vec![];
+        for value in s.split(',').filter(|value| !value.trim().is_empty()) {
+            match value.parse::<RequestedStage>() {
+                Ok(stage) => requested.push(stage),
+                Err(e) => return Err(e)
+            };
+        }
+        Ok(requested)
+    }
+
+    /// Pipeline stages needed to serve `requested`.
+    pub fn stage_kinds(requested: &[RequestedStage]) -> Vec<StageKind> {
+        let mut kinds = vec![StageKind::Detection, StageKind::Selection];
+        for stage in requested.iter() {
+            let needed: &[StageKind] = match stage {
+                RequestedStage::Detect => &[],
+                RequestedStage::HeadPose => &[StageKind::HeadPose],
+                RequestedStage::ImageQuality => &[StageKind::ImageQuality],
+                RequestedStage::Quality => &[StageKind::Alignment, StageKind::Quality],
+                RequestedStage::QualityAssessment => &[StageKind::Alignment, StageKind::QualityAssessment],
+                RequestedStage::Liveness => &[StageKind::AntiSpoofing],
+                RequestedStage::Embedding => &[StageKind::Alignment, StageKind::Extraction],
+            };
+            for kind in needed.iter() {
+                if !kinds.contains(kind) {
+                    kinds.push(*kind);
+                }
+            }
+        }
+        kinds
+    }
+}
+
+/// Skips a stage when every condition that is set holds.
+///
+/// `enroll` and `spoofing_check` match the request flags. The `face_quality_*`,
+/// `quality_assessment_*`, `head_pose_accepted` and `image_quality_passed` conditions look at the
+/// face the stage is about to process and never match a face whose value has not been computed yet.
+/// `set_face_quality` is reported as the face quality of every face the rule skips.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct StageRule {
+    pub skip: StageKind,
+    pub enroll: Option<bool>,
+    pub spoofing_check: Option<bool>,
+    pub face_quality_in: Option<Vec<FaceQualityClass>>,
+    pub face_quality_not_in: Option<Vec<FaceQualityClass>>,
+    pub quality_assessment_in: Option<Vec<FaceQualityClass>>,
+    pub quality_assessment_not_in: Option<Vec<FaceQualityClass>>,
+    /// Whether the head pose is within the `[models.head_pose]` limits
+    pub head_pose_accepted: Option<bool>,
+    /// Whether every classical image quality metric is within the `[models.image_quality]` limits
+    pub image_quality_passed: Option<bool>,
+    pub set_face_quality: Option<FaceQualityClass>,
+}
+
+impl StageRule {
+    pub fn has_face_conditions(&self) -> bool {
+        self.face_quality_in.is_some()
+            || self.face_quality_not_in.is_some()
+            || self.quality_assessment_in.is_some()
+            || self.quality_assessment_not_in.is_some()
+            || self.head_pose_accepted.is_some()
+            || self.image_quality_passed.is_some()
+    }
+
+    /// Skips extraction on enroll for a face turned beyond the head pose limits, reporting it as `Bad`.
+    pub fn head_pose_enroll_gate() -> Self {
+        StageRule {
+            skip: StageKind::Extraction,
+            enroll: Some(true),
+            head_pose_accepted: Some(false),
+            set_face_quality: Some(FaceQualityClass::Bad),
+            ..Default::default()
+        }
+    }
+
+    /// Stages whose results the face conditions read.
+    pub fn input_stages(&self) -> Vec<StageKind> {
+        let mut stages: Vec<StageKind> = vec![];
+        if self.face_quality_in.is_some() || self.face_quality_not_in.is_some() {
+            stages.push(StageKind::Quality);
+        }
+        if self.quality_assessment_in.is_some() || self.quality_assessment_not_in.is_some() {
+            stages.push(StageKind::QualityAssessment);
+        }
+        if self.head_pose_accepted.is_some() {
+            stages.push(StageKind::HeadPose);
+        }
+        if self.image_quality_passed.is_some() {
+            stages.push(StageKind::ImageQuality);
+        }
+        stages
+    }
+}
+
+/// Ordered stages of one product flow and the rules that skip some of them.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PipelineDefinition {
+    pub stages: Vec<StageKind>,
+    #[serde(default)]
+    pub rules: Vec<StageRule>,
+}
+
+impl PipelineDefinition {
+    /// Detection, selection, head pose, image quality, alignment, quality and extraction, the flow of `GeneralPipeline`.
+    ///
+    /// Enrollment skips extraction for a face turned beyond the head pose limits.
+    pub fn general() -> Self {
+        PipelineDefinition {
+            stages: vec![
+                StageKind::Detection,
+                StageKind::Selection,
+                StageKind::HeadPose,
+                StageKind::ImageQuality,
+                StageKind::Alignment,
+                StageKind::Quality,
+                StageKind::Extraction,
+            ],
+            rules: vec![
+                StageRule::head_pose_enroll_gate(),
+            ],
+        }
+    }
+
+    /// Adds the anti-spoofing check and the enroll quality gate on top of the general flow.
+    pub fn antispoofing() -> Self {
+        PipelineDefinition {
+            stages: vec![
+                StageKind::Detection,
+                StageKind::Selection,
+                StageKind::HeadPose,
+                StageKind::ImageQuality,
+                StageKind::AntiSpoofing,
+                StageKind::Alignment,
+                StageKind::Quality,
+                StageKind::QualityAssessment,
+                StageKind::Extraction,
+            ],
+            rules: vec![
+                StageRule {
+                    skip: StageKind::AntiSpoofing,
+                    spoofing_check: Some(false),
+                    ..Default::default()
+                },
+                StageRule {
+                    skip: StageKind::Extraction,
+                    enroll: Some(false),
+                    face_quality_in: Some(vec![FaceQualityClass::WearingMask]),
+                    set_face_quality: Some(FaceQualityClass::WearingMask),
+                    ..Default::default()
+                },
+                StageRule {
+                    skip: StageKind::Extraction,
+                    enroll: Some(true),
+                    face_quality_not_in: Some(vec![FaceQualityClass::Good]),
+                    set_face_quality: Some(FaceQualityClass::Bad),
+                    ..Default::default()
+                },
+                StageRule {
+                    skip: StageKind::Extraction,
+                    enroll: Some(true),
+                    quality_assessment_not_in: Some(vec![FaceQualityClass::Good]),
+                    set_face_quality: Some(FaceQualityClass::Bad),
+                    ..Default::default()
+                },
+                StageRule::head_pose_enroll_gate(),
+            ],
+        }
+    }
+
+    pub fn validate(&self, pipeline_name: &str) -> Result<(), Error> {
+        let mut errors: Vec<String> = vec![];
+
+        if self.stages.first() != Some(&StageKind::Detection) {
+            errors.push("the first stage must be detection".to_string());
+        }
+        let mut seen: HashSet<StageKind> = HashSet::new();
+        for stage in self.stages.iter() {
+            if !seen.insert(*stage) {
+                errors.push(format!("stage {:?} is listed more than once", stage));
+            }
+            let needs_alignment = matches!(stage, StageKind::Quality | StageKind::QualityAssessment | StageKind::Extraction);
+            if needs_alignment && !seen.contains(&StageKind::Alignment) {
+                errors.push(format!("stage {:?} must come after alignment", stage));
+            }
+        }
+
+        for rule in self.rules.iter() {
+            if !self.stages.contains(&rule.skip) {
+                errors.push(format!("rule skips stage {:?} which is not in the pipeline", rule.skip));
+            }
+            if rule.has_face_conditions() && !rule.skip.is_per_face() {
+                errors.push(format!("rule on stage {:?} cannot use face quality conditions", rule.skip));
+            }
+            let uses_quality = rule.face_quality_in.is_some() || rule.face_quality_not_in.is_some();
+            if uses_quality && !self.runs_before(StageKind::Quality, rule.skip) {
+                errors.push(format!("rule on stage {:?} uses face quality which is not computed before it", rule.skip));
+            }
+            let uses_assessment = rule.quality_assessment_in.is_some() || rule.quality_assessment_not_in.is_some();
+            if uses_assessment && !self.runs_before(StageKind::QualityAssessment, rule.skip) {
+                errors.push(format!("rule on stage {:?} uses quality assessment which is not computed before it", rule.skip));
+            }
+            if rule.head_pose_accepted.is_some() && !self.runs_before(StageKind::HeadPose, rule.skip) {
+                errors.push(format!("rule on stage {:?} uses head pose which is not computed before it", rule.skip));
+            }
+            if rule.image_quality_passed.is_some() && !self.runs_before(StageKind::ImageQuality, rule.skip) {
+                errors.push(format!("rule on stage {:?} uses image quality which is not computed before it", rule.skip));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(Error::msg(format!("invalid {pipeline_name} pipeline: {}", errors.join("; "))))
+        }
+        Ok(())
+    }
+
+    fn runs_before(&self, first: StageKind, second: StageKind) -> bool {
+        let first_idx = self.stages.iter().position(|stage| *stage == first);
+        let second_idx = self.stages.iter().position(|stage| *stage == second);
+        match (first_idx, second_idx) {
+            (Some(first_idx), Some(second_idx)) => first_idx < second_idx,
+            _ => false,
+        }
+    }
+}
+
+/// The `[pipelines.*]` sections of the config file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PipelinesConfig {
+    pub general: PipelineDefinition,
+    pub antispoofing: PipelineDefinition,
+}
+
+impl Default for PipelinesConfig {
+    fn default() -> Self {
+        PipelinesConfig {
+            general: PipelineDefinition::general(),
+            antispoofing: PipelineDefinition::antispoofing(),
+        }
+    }
+}
+
+impl PipelinesConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        match self.general.validate("general") {
+            Ok(_) => {}
+            Err(e) => return Err(e)
+        };
+        self.antispoofing.validate("antispoofing")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::model_config::pipeline_config::{PipelineDefinition, PipelinesConfig, RequestedStage, StageKind, StageRule};
+
+    #[test]
+    fn test_validate() {
+        assert!(PipelinesConfig::default().validate().is_ok());
+
+        let mut definition = PipelineDefinition::general();
+        definition.stages.swap(4, 5);
+        definition.stages.retain(|stage| *stage != StageKind::HeadPose);
+        definition.rules.push(StageRule {
+            skip: StageKind::Selection,
+            face_quality_in: Some(vec![]),
+            ..Default::default()
+        });
+        let err = definition.validate("general").unwrap_err().to_string();
+        assert!(err.contains("stage Quality must come after alignment"));
+        assert!(err.contains("cannot use face quality conditions"));
+        assert!(err.contains("uses head pose which is not computed before it"));
+    }
+
+    #[test]
+    fn test_requested_stages() {
+        let requested = RequestedStage::parse_list("detect, quality").unwrap();
+        assert_eq!(requested, vec![RequestedStage::Detect, RequestedStage::Quality]);
+        assert_eq!(RequestedStage::stage_kinds(&requested), vec![
+            StageKind::Detection, StageKind::Selection, StageKind::Alignment, StageKind::Quality,
+        ]);
+        assert_eq!(RequestedStage::stage_kinds(&RequestedStage::parse_list("head_pose").unwrap()), vec![
+            StageKind::Detection, StageKind::Selection, StageKind::HeadPose,
+        ]);
+        assert!(RequestedStage::parse_list("detect,landmarks").is_err());
+    }
+}
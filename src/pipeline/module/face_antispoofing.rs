@@ -1,22 +1,39 @@
 use std::iter::zip;
+use std::sync::Arc;
 use anyhow::Error;
-use ndarray::{Array1, Array2, Array3, Array4, IntoDimension, s};
+use ndarray::{Array1, Array2, Array4, Ix2};
 use opencv::core::{Mat, MatTraitConst, Rect, Size};
-use opencv::imgproc::{COLOR_RGB2BGR, cvt_color, INTER_LINEAR, resize};
-use crate::pipeline::triton_client::client::triton::model_infer_request::InferInputTensor;
-use crate::pipeline::triton_client::client::triton::{InferTensorContents, ModelConfigResponse, ModelInferRequest};
-use crate::pipeline::triton_client::client::TritonInferenceClient;
-use crate::pipeline::utils::utils::u8_to_f32_vec;
+use opencv::imgproc::{INTER_LINEAR, resize};
+use serde::{Deserialize, Serialize};
+use crate::pipeline::inference_backend::inference_backend::{InferenceBackend, ModelSpec, take_output, Tensor};
+use crate::pipeline::model_config::config::{FaceAntiSpoofingClass, NormalizationConfig};
+use crate::pipeline::processing::calibration::CalibrationConfig;
+use crate::pipeline::processing::preprocess::{Normalization, preprocess_batch};
 
 #[derive(Debug, Clone)]
 pub(crate) struct FaceAntiSpoofing {
-    triton_infer_client: TritonInferenceClient,
-    triton_model_config: Vec<ModelConfigResponse>,
+    inference_backend: Arc<dyn InferenceBackend>,
+    model_specs: Vec<ModelSpec>,
     model_name: Vec<String>,
     scales: Vec<f32>,
     image_sizes: Vec<(i32, i32)>,
     threshold: f32,
     batch_size: i32,
+    calibration: CalibrationConfig,
+    normalization: Normalization,
+}
+
+/// Anti-spoofing result of one face, for clients that combine it with other signals.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AntiSpoofingScore {
+    /// Calibrated probability that the face is real, the configured threshold applies to it
+    pub live_score: f32,
+    /// Weighted mean of `scale_scores` before calibration
+    pub raw_score: f32,
+    /// Live class probability of each scale model, in the configured model order
+    pub scale_scores: Vec<f32>,
+    /// Fraction of each scaled crop that fits in the image
+    pub scale_weights: Vec<f32>,
 }
 
 struct CropParams {
@@ -31,123 +48,86 @@ struct CropParams {
 
 
 impl FaceAntiSpoofing {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
-        triton_infer_client: TritonInferenceClient,
-        triton_model_config: Vec<ModelConfigResponse>,
+        inference_backend: Arc<dyn InferenceBackend>,
+        model_specs: Vec<ModelSpec>,
         model_name: Vec<String>,
         image_sizes: Vec<(i32, i32)>,
         scales: Vec<f32>,
         batch_size: i32,
         threshold: f32,
+        calibration: CalibrationConfig,
     ) -> Result<Self, Error> {
         Ok(FaceAntiSpoofing {
-            triton_infer_client,
-            triton_model_config,
+            inference_backend,
+            model_specs,
             model_name,
             scales,
             image_sizes,
             threshold,
             batch_size,
+            calibration,
+            normalization: Normalization::MINI_FAS,
         })
     }
 
-    pub async fn call(&self, imgs: Mat, face_boxes: Array1<f32>) -> Result< Vec<Array1<i32>>, Error> {
-
-        let img_arr: Vec<Mat> = vec![imgs; 1];
-        let face_boxes_arr: Vec<Array1<f32>> = vec![face_boxes];
+    /// Overrides the built-in input normalisation with the configured fields.
+    pub fn with_normalization(mut self, normalization: &NormalizationConfig) -> Self {
+        self.normalization = normalization.resolve(self.normalization);
+        self
+    }
 
-        let list_image_scales: &mut Vec<Vec<Mat>> = &mut Vec::with_capacity(face_boxes_arr.len());
-        let list_weight_scales: &mut Vec<Vec<f32>> = &mut Vec::with_capacity(face_boxes_arr.len());
+    /// Live score of the face in `bbox`, fused over the scales weighted by how much of each crop
+    /// fits the image, then calibrated.
+    pub async fn call(&self, img: Mat, bbox: Array1<f32>) -> Result<AntiSpoofingScore, Error> {
+        // Crops keep the BGR order, the swap to RGB happens in preprocessing
+        let (crops, weights) = match self._get_scale_image(img, bbox) {
+            Ok((crops, weights)) => {(crops, weights)}
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
 
-        for (image, face_box) in img_arr.into_iter().zip(face_boxes_arr) {
-            let mut converted_image = Mat::default();
-            match cvt_color(&image, &mut converted_image, COLOR_RGB2BGR, 0) {
-                Ok(_) => {}
+        let mut outputs: Vec<Array2<f32>> = Vec::with_capacity(self.scales.len());
+        for (idx, crop) in crops.into_iter().enumerate() {
+            let preprocessed_images = match self._preprocess(&vec![crop], idx) {
+                Ok(preprocessed_images) => {preprocessed_images}
                 Err(e) => {
                     return Err(Error::from(e))
                 }
             };
-
-            drop(image);
-
-            let (tmps, weights) = match self._get_scale_image(converted_image, face_box) {
-                Ok((tmps, weights)) => {(tmps, weights)}
-                Err(e) => {
-                    return Err(Error::from(e))
+            match self.infer(idx, &preprocessed_images).await {
+                Ok(output_tensor) => {
+                    outputs.extend(output_tensor);
                 }
-            };
-
-            for (i, _) in self.scales.clone().into_iter().enumerate() {
-                list_image_scales.insert(i, vec![tmps.clone()[i].clone()]).to_owned();
-                list_weight_scales.insert(i, vec![weights.clone()[i]]).to_owned();
-            }
-        }
-
-
-        let mut outputs: Vec<Vec<Array2<f32>>> = Vec::with_capacity(4);
-
-        for (idx, _) in self.scales.iter().enumerate() {
-            let preprocessed_images = match self._preprocess(&list_image_scales[idx], idx) {
-                Ok(preprocessed_images) => {preprocessed_images}
                 Err(e) => {
                     return Err(Error::from(e))
                 }
             };
-
-            for i in (0..preprocessed_images.shape()[0]).step_by(self.batch_size as usize) {
-                let tensors = preprocessed_images.slice(s![i..i + self.batch_size as usize, .., .., ..]);
-                match self.infer(idx, &tensors.to_owned()).await {
-                    Ok(output_tensor) => {
-                        outputs.push(output_tensor);
-                    }
-                    Err(e) => {
-                        return Err(Error::from(e))
-                    }
-                };
-            }
-            drop(preprocessed_images);
         }
 
-        let result = self._postprocess(&outputs, list_weight_scales);
-        Ok(result)
+        fuse_scale_scores(&outputs, weights, &self.calibration)
     }
 
     async fn infer(&self, idx: usize, tensors: &Array4<f32>) -> Result<Vec<Array2<f32>>, Error>{
-        let flattened_vec: Vec<f32> = tensors.iter().cloned().collect();
-
-        let model_cfg = match &self.triton_model_config[idx].config {
+        let model_spec = match self.model_specs.get(idx) {
             None => {
-                return Err(Error::msg("face_anti_spoofing - face anti-spoofing model config is empty"))
+                return Err(Error::msg("face_anti_spoofing - face anti-spoofing model spec is missing"))
             }
-            Some(model_cfg) => {model_cfg}
+            Some(model_spec) => {model_spec}
         };
-
-        let model_request = ModelInferRequest{
-            model_name: self.model_name[idx].to_owned(),
-            model_version: "".to_string(),
-            id: "".to_string(),
-            parameters: Default::default(),
-            inputs: vec![InferInputTensor {
-                name: model_cfg.input[0].name.to_string(),
-                datatype: model_cfg.input[0].data_type().as_str_name()[5..].to_uppercase(),
-                shape: model_cfg.input[0].dims.to_owned(),
-                parameters: Default::default(),
-                contents: Option::from(InferTensorContents {
-                    bool_contents: vec![],
-                    int_contents: vec![],
-                    int64_contents: vec![],
-                    uint_contents: vec![],
-                    uint64_contents: vec![],
-                    fp32_contents: flattened_vec,
-                    fp64_contents: vec![],
-                    bytes_contents: vec![],
-                }),
-            }; 1],
-            outputs: Default::default(),
-            raw_input_contents: vec![],
+        let input_spec = match model_spec.input(0) {
+            Ok(input_spec) => {input_spec}
+            Err(e) => return Err(e)
         };
+        let output_spec = match model_spec.output(0) {
+            Ok(output_spec) => {output_spec}
+            Err(e) => return Err(e)
+        };
+        let input = Tensor::from_array(&input_spec.name, tensors.to_owned());
 
-        let mut model_out = match self.triton_infer_client.model_infer(model_request).await {
+        let mut model_out = match self.inference_backend.infer(&self.model_name[idx], vec![input]).await {
             Ok(model_out) => model_out,
             Err(e) => {
                 return Err(Error::from(e))
@@ -157,23 +137,13 @@ impl FaceAntiSpoofing {
 
         let mut net_out: Vec<Array2<f32>> = vec![];
 
-        for (idx, output) in &mut model_out.outputs.iter_mut().enumerate() {
-            let dims = &output.shape;
-            let dimensions: [usize; 2] = [
-                dims[0] as usize,
-                dims[1] as usize,
-            ];
-            let u8_array: &[u8] = &model_out.raw_output_contents[idx];
-            let f_array = u8_to_f32_vec(u8_array);
-
-            let array2_f32: Array2<f32> = match Array2::from_shape_vec(dimensions.into_dimension(), f_array) {
-                Ok(array2_f32) => {array2_f32}
-                Err(e) => {
-                    return Err(Error::from(e))
-                }
-            };
-            net_out.push(array2_f32);
-        }
+        let array2_f32: Array2<f32> = match take_output(&mut model_out, &output_spec.name).and_then(|output| output.into_array::<Ix2>()) {
+            Ok(array2_f32) => {array2_f32}
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+        net_out.push(array2_f32);
 
         drop(model_out);
 
@@ -186,64 +156,16 @@ impl FaceAntiSpoofing {
             f32::max((images.len() as f32 / self.batch_size as f32).ceil(), 1.0) * self.batch_size as f32
             ) as i32;
 
-        let mut preprocessed_images = Array4::<f32>::zeros((batch_input_size as usize, 3, self.image_sizes[index].1 as usize, self.image_sizes[index].0 as usize));
-
-        for (i, img) in images.into_iter().enumerate() {
-            let mut img_brg = Mat::default();
-
-            match cvt_color(&img, &mut img_brg, COLOR_RGB2BGR, 0) {
-                Ok(_) => {}
-                Err(e) => return Err(Error::from(e))
-            };
-
-            let im_info = match img_brg.size() {
-                Ok(im_info) => {im_info}
-                Err(e) => return Err(Error::from(e))
-            };
-            let rows = im_info.height;
-            let cols = im_info.width;
-
-            let mut im_tensor = Array3::<f32>::zeros((rows as usize, cols as usize, 3));
-            for i in 0..3 {
-                for y in 0..rows {
-                    for x in 0..cols {
-                        let pixel_value = img_brg.at_2d::<opencv::core::Vec3b>(y, x).unwrap()[i];
-                        im_tensor[[y as usize, x as usize, 2-i]] = pixel_value as f32;
-                    }
-                }
-            }
-
-            let transposed_tensors = im_tensor.permuted_axes([2, 0, 1]);
-            preprocessed_images.slice_mut(s![i,..,..,..]).assign(&transposed_tensors);
-        }
-
-        Ok(preprocessed_images)
+        preprocess_batch(images, self.image_sizes[index], &self.normalization, batch_input_size as usize)
     }
 
-    fn _postprocess(&self, outputs: &Vec<Vec<Array2<f32>>>, list_weight_scales: &Vec<Vec<f32>>) -> Vec<Array1<i32>> {
-
-        let mut results = Vec::new();
-
-        // Transpose the outer Vec<Vec<>> to Vec<Vec<>> for parallel iteration
-        let transposed_outputs: Vec<Vec<&Array2<f32>>> = (0..outputs[0].len())
-            .map(|i| outputs.iter().map(|output| &output[i]).collect())
-            .collect();
-
-        for (output, weights) in zip(transposed_outputs, list_weight_scales.iter()) {
-            let mut live_score: Array1<f32> = Array1::zeros(output[0].dim().0);
-            let mut total_weight = 0.0;
-
-            for (o, &w) in zip(output, weights) {
-                live_score = live_score + o.column(1).to_owned() * w;
-                total_weight += w;
-            }
-
-            live_score /= total_weight;
-            let liveness = live_score.mapv(|score| if score > 0.55 { 1 } else { 0 });
-            results.push(liveness);
+    /// Real when the calibrated live score is above the configured threshold.
+    pub fn classify(&self, score: &AntiSpoofingScore) -> FaceAntiSpoofingClass {
+        if score.live_score > self.threshold {
+            FaceAntiSpoofingClass::Real
+        } else {
+            FaceAntiSpoofingClass::Fake
         }
-
-        results
     }
 
     fn _get_scale_image(&self, image: Mat, face_box: Array1<f32>) -> Result<(Vec<Mat>, Vec<f32>), Error> {
@@ -375,118 +297,78 @@ impl FaceAntiSpoofing {
     }
 }
 
+/// Live score fused over the scales, `outputs` holds the model output of each scale with the face in the first row.
+fn fuse_scale_scores(outputs: &[Array2<f32>], scale_weights: Vec<f32>, calibration: &CalibrationConfig) -> Result<AntiSpoofingScore, Error> {
+    let mut scale_scores: Vec<f32> = Vec::with_capacity(outputs.len());
+    for output in outputs.iter() {
+        if output.nrows() == 0 || output.ncols() < 2 {
+            return Err(Error::msg(format!("face_anti_spoofing - expected a live class column, got an output of shape {:?}", output.dim())))
+        }
+        scale_scores.push(output[[0, 1]]);
+    }
+
+    let total_weight: f32 = scale_weights.iter().sum();
+    let raw_score = if total_weight > 0.0 {
+        zip(&scale_scores, &scale_weights).map(|(score, weight)| score * weight).sum::<f32>() / total_weight
+    } else {
+        scale_scores.iter().sum::<f32>() / scale_scores.len().max(1) as f32
+    };
+
+    Ok(AntiSpoofingScore {
+        live_score: calibration.apply(raw_score),
+        raw_score,
+        scale_scores,
+        scale_weights,
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use ndarray::{Array1, s};
-    use crate::pipeline::module::face_antispoofing::FaceAntiSpoofing;
-    use crate::pipeline::module::face_detection::RetinaFaceDetection;
-    use crate::pipeline::module::face_selection::FaceSelection;
-    use crate::pipeline::triton_client::client::triton::{ModelConfigRequest, ModelConfigResponse};
-    use crate::pipeline::triton_client::client::TritonInferenceClient;
-    use crate::pipeline::utils::utils::byte_data_to_opencv;
-
-    // #[tokio::test]
-    // async fn test_face_antispoofing() {
-    //     let triton_host = "";
-    //     let triton_port = "";
-    //     let im_bytes: &[u8] = include_bytes!("");
-    //     let image = byte_data_to_opencv(im_bytes).unwrap();
-    //
-    //     let triton_infer_client = match TritonInferenceClient::new(triton_host, triton_port).await {
-    //         Ok(triton_infer_client) => triton_infer_client,
-    //         Err(e) => {
-    //             println!("{:?}", e);
-    //             return
-    //         }
-    //     };
-    //
-    //     let model_name = "face_detection_retina".to_string();
-    //
-    //     let face_detection_model_config = match triton_infer_client
-    //         .model_config(ModelConfigRequest {
-    //             name: model_name.to_owned(),
-    //             version: "".to_string(),
-    //         }).await {
-    //         Ok(model_config_resp) => {model_config_resp}
-    //         Err(e) => {
-    //             println!("{:?}", e);
-    //             return
-    //         }
-    //     };
-    //
-    //     let retina_face_detection = match RetinaFaceDetection::new(
-    //         triton_infer_client.clone(),
-    //         face_detection_model_config,
-    //         model_name,
-    //         (640, 640),
-    //         1,
-    //         0.7,
-    //         0.45,
-    //     ).await
-    //     {
-    //         Ok(retina_face_detection)  => retina_face_detection,
-    //         Err(e) => {
-    //             println!("{:?}", e);
-    //             return
-    //         }
-    //     };
-    //     let (preprocessed_img, scale) = retina_face_detection.call(&image, Some(false)).await.unwrap();
-    //     let face_selection = FaceSelection::new(0.3, 0.3, 0.1, 0.0075).await;
-    //     let (selected_face_box, selected_face_point) = face_selection.call(&image, preprocessed_img, scale, Some(false), None).unwrap();
-    //
-    //
-    //     let model_names: Vec<String> = vec![
-    //         "miniFAS_4".to_string(),
-    //         "miniFAS_2_7".to_string(),
-    //         "miniFAS_2".to_string(),
-    //         "miniFAS_1".to_string(),
-    //     ];
-    //
-    //
-    //     let mut model_antispoofing_config: Vec<ModelConfigResponse> = vec![];
-    //
-    //     for model_name in &model_names {
-    //         let face_antispoofing_model_config = match triton_infer_client
-    //             .model_config(ModelConfigRequest {
-    //                 name: model_name.to_owned(),
-    //                 version: "".to_string(),
-    //             }).await {
-    //             Ok(model_config_resp) => {model_config_resp}
-    //             Err(e) => {
-    //                 println!("{:?}", e);
-    //                 return
-    //             }
-    //         };
-    //         model_antispoofing_config.push(face_antispoofing_model_config)
-    //     }
-    //
-    //     let face_antispoofing = match FaceAntiSpoofing::new(
-    //         triton_infer_client.clone(),
-    //         model_antispoofing_config.clone(),
-    //         model_names.clone(),
-    //         vec![
-    //             (80, 80),
-    //             (80, 80),
-    //             (256, 256),
-    //             (128, 128),
-    //         ],
-    //         vec![4.0, 2.7, 2.0, 1.0],
-    //         1,
-    //         0.55).await {
-    //         Ok(face_antispoofing) => {face_antispoofing}
-    //         Err(e) => {
-    //             println!("{:?}", e);
-    //             return
-    //         }
-    //     };
-    //
-    //     if let Some(_selected_face_box) = selected_face_box {
-    //
-    //         let sliced_boxes = _selected_face_box.slice(s![..4]);
-    //         let boxes: Array1<f32> = sliced_boxes.to_owned();
-    //
-    //         face_antispoofing.call(vec![&image],vec![&boxes], None).await;
-    //     }
-    //
-    // }
+    use std::sync::Arc;
+    use ndarray::array;
+    use crate::pipeline::inference_backend::onnx_backend::OnnxBackend;
+    use crate::pipeline::model_config::config::FaceAntiSpoofingClass;
+    use crate::pipeline::module::face_antispoofing::{AntiSpoofingScore, FaceAntiSpoofing, fuse_scale_scores};
+    use crate::pipeline::processing::calibration::{CalibrationConfig, CalibrationMethod};
+
+    #[test]
+    fn test_fuse_scale_scores() {
+        let calibration = CalibrationConfig {
+            method: CalibrationMethod::Platt,
+            platt_a: -10.0,
+            platt_b: 5.0,
+            ..Default::default()
+        };
+
+        // Every scale counts by its weight, (0.8 * 1.0 + 0.2 * 0.5) / 1.5
+        let outputs = vec![array![[0.1, 0.8, 0.1]], array![[0.6, 0.2, 0.2]]];
+        let score = fuse_scale_scores(&outputs, vec![1.0, 0.5], &calibration).unwrap();
+        assert!((score.raw_score - 0.6).abs() < 1e-6);
+        assert_eq!(score.scale_scores, vec![0.8, 0.2]);
+        assert_eq!(score.scale_weights, vec![1.0, 0.5]);
+        assert!((score.live_score - 1.0 / (1.0 + (-1.0f32).exp())).abs() < 1e-6);
+
+        // Platt with a = -10 and b = 5 maps 0.45 below 0.5
+        let score = fuse_scale_scores(&[array![[0.5, 0.45, 0.05]]], vec![1.0], &calibration).unwrap();
+        assert!(score.live_score < 0.5);
+        assert!(fuse_scale_scores(&[array![[0.5]]], vec![1.0], &calibration).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_classify() {
+        // classify never reaches the backend, an empty model directory is enough
+        let inference_backend = Arc::new(OnnxBackend::new(std::env::temp_dir(), 1).unwrap());
+        let face_anti_spoofing = FaceAntiSpoofing::new(inference_backend, vec![], vec![], vec![], vec![], 1, 0.7, CalibrationConfig::default()).await.unwrap();
+        let score = |live_score: f32| AntiSpoofingScore {
+            live_score,
+            raw_score: live_score,
+            scale_scores: vec![live_score],
+            scale_weights: vec![1.0],
+        };
+
+        assert_eq!(face_anti_spoofing.classify(&score(0.75)), FaceAntiSpoofingClass::Real);
+        // Real under the old hardcoded 0.55, the configured threshold decides
+        assert_eq!(face_anti_spoofing.classify(&score(0.65)), FaceAntiSpoofingClass::Fake);
+        assert_eq!(face_anti_spoofing.classify(&score(0.7)), FaceAntiSpoofingClass::Fake);
+    }
 }
@@ -1,27 +1,26 @@
-use opencv::core::{self, Mat, MatTraitConst, Scalar, Size};
-use opencv::imgproc::{INTER_LINEAR, resize};
+use opencv::core::{Mat, MatTraitConst};
 use std::collections::HashMap;
 use std::ops::{MulAssign};
 use crate::pipeline::processing::generate_anchors::{AnchorConfig, Config, generate_anchors_fpn2};
-use crate::pipeline::triton_client::client::TritonInferenceClient;
+use std::sync::Arc;
 use anyhow::{Error, Result};
-use ndarray::{Array, Array2, Array3, Array4, ArrayBase, Axis, concatenate, Dim, IntoDimension, Ix, Ix2, Ix3, OwnedRepr, s};
+use async_trait::async_trait;
+use ndarray::{Array, Array2, Array3, Array4, ArrayBase, Axis, concatenate, Dim, IntoDimension, Ix, Ix2, Ix3, Ix4, OwnedRepr, s};
 use opencv::imgcodecs::imwrite;
 use crate::pipeline::processing::bbox_transform::clip_boxes;
-use crate::pipeline::processing::nms::nms;
+use crate::pipeline::processing::preprocess::{Normalization, preprocess};
+use crate::pipeline::module::face_detector::{DetectionInput, FaceDetections, FaceDetector};
 use crate::pipeline::rcnn::anchors::anchors;
-use crate::pipeline::triton_client::client::triton::model_infer_request::{InferInputTensor, InferRequestedOutputTensor};
-use crate::pipeline::triton_client::client::triton::{InferParameter, InferTensorContents, ModelConfigRequest, ModelConfigResponse, ModelInferRequest};
-use crate::pipeline::triton_client::client::triton::infer_parameter::ParameterChoice;
-use crate::pipeline::utils::utils::{argsort_descending, reorder_2d, reorder_3d, u8_to_f32_vec, vstack_2d, vstack_3d};
+use crate::pipeline::inference_backend::inference_backend::{InferenceBackend, ModelSpec, take_output, Tensor};
+use crate::pipeline::utils::utils::{argsort_descending, reorder_2d, reorder_3d, vstack_2d, vstack_3d};
 
 #[derive(Debug, Clone)]
 pub struct RetinaFaceDetection {
-    triton_infer_client: TritonInferenceClient,
-    triton_model_config: ModelConfigResponse,
+    inference_backend: Arc<dyn InferenceBackend>,
+    model_spec: ModelSpec,
     model_name: String,
     max_batch_size: i32,
-    image_size: (i32, i32),
+    input: DetectionInput,
     use_landmarks: bool,
     confidence_threshold: f32,
     iou_threshold: f32,
@@ -30,19 +29,17 @@ pub struct RetinaFaceDetection {
     anchor_cfg: HashMap<String, AnchorConfig>,
     _anchors_fpn: HashMap<String, Array2<f32>>,
     _num_anchors: HashMap<String, usize>,
-    pixel_means: Vec<f32>,
-    pixel_stds: Vec<f32>,
-    pixel_scale: f32,
+    normalization: Normalization,
     bbox_stds: Vec<f32>,
     landmark_std: f32,
 }
 
 impl RetinaFaceDetection {
     pub async fn new(
-        triton_infer_client: TritonInferenceClient,
-        triton_model_config: ModelConfigResponse,
+        inference_backend: Arc<dyn InferenceBackend>,
+        model_spec: ModelSpec,
         model_name: String,
-        image_size: (i32, i32),
+        input: DetectionInput,
         max_batch_size: i32,
         confidence_threshold: f32,
         iou_threshold: f32,
@@ -87,7 +84,8 @@ impl RetinaFaceDetection {
         }
 
         let dense_anchor = false;
-        let use_landmarks= true;
+        // Score, box and landmark outputs per stride, or only score and box
+        let use_landmarks = model_spec.outputs.len() == 3 * _feat_stride_fpn.len();
         let bbox_stds= vec![1.0, 1.0, 1.0, 1.0];
         let landmark_std = 1.0;
 
@@ -102,15 +100,12 @@ impl RetinaFaceDetection {
             .map(|(k, v)| (k.clone(), v.clone().shape()[0]))
             .collect::<HashMap<_, _>>();
 
-        let pixel_means = vec![0.0, 0.0, 0.0];
-        let pixel_stds = vec![1.0, 1.0, 1.0];
-        let pixel_scale = 1.0;
-
         Ok(RetinaFaceDetection {
-            triton_infer_client,
-            triton_model_config,
+            inference_backend,
+            model_spec,
             model_name,
-            image_size,
+            normalization: input.normalization.resolve(Normalization::RETINA_FACE),
+            input,
             use_landmarks,
             confidence_threshold,
             iou_threshold,
@@ -119,170 +114,48 @@ impl RetinaFaceDetection {
             anchor_cfg,
             _anchors_fpn,
             _num_anchors,
-            pixel_means,
-            pixel_stds,
-            pixel_scale,
             bbox_stds,
             landmark_std,
             max_batch_size,
         })
     }
 
-    fn _preprocess(&self, img: Mat) -> Result<(Mat, f32), Error> {
-
-        let img_shape = match img.size() {
-            Ok(img_shape) => img_shape,
-            Err(e) => return Err(Error::from(e))
-        };
-
-        let im_ratio = img_shape.height as f32 / img_shape.width as f32;
-        let model_ratio = self.image_size.1 as f32 / self.image_size.0 as f32;
-
-        let (new_width, new_height) = if im_ratio > model_ratio {
-            let new_height = self.image_size.1;
-            let new_width = (new_height as f32 / im_ratio) as i32;
-            (new_width, new_height)
-        } else {
-            let new_width = self.image_size.0;
-            let new_height = (new_width as f32 * im_ratio) as i32;
-            (new_width, new_height)
-        };
-
-        let det_scale = new_height as f32 / img_shape.height as f32;
-        let mut resized_img = Mat::default();
-
-        match resize(&img, &mut resized_img, Size::new(new_width, new_height), 0.0, 0.0, INTER_LINEAR) {
-            Ok(_) => {},
-            Err(e) => return Err(Error::from(e))
-        }
-
-
-        let mut det_img = match Mat::new_rows_cols_with_default(self.image_size.1, self.image_size.0, core::CV_8UC3, Scalar::all(0.0)) {
-            Ok(det_img) => det_img,
-            Err(e) => {
-                return Err(Error::from(e))
-            }
-        };
-
-        let  mut roi = match Mat::roi_mut(&mut det_img, core::Rect::new(0, 0, new_width, new_height)) {
-            Ok(roi) => roi,
-            Err(e) => {
-                return Err(Error::from(e))
-            }
-        };
-
-        match  resized_img.copy_to(&mut roi) {
-            Ok(_) => {},
-            Err(e) => {
-                return Err(Error::from(e))
-            }
-        };
-
-        drop(resized_img);
-        drop(img);
-
-        Ok((det_img, det_scale))
-    }
-
-
     async fn _forward(&self, img: Mat) -> Result<(Array2<f32>, Option<Array3<f32>>), Error> {
 
         let im_info = match img.size() {
             Ok(im_info) => {im_info}
             Err(e) => return Err(Error::from(e))
         };
-        let rows = im_info.height;
-        let cols = im_info.width;
-
-        let mut im_tensor = Array4::<f32>::zeros((1, 3, rows as usize, cols as usize));
 
-        // Convert the image to float and normalize it
-        for i in 0..3 {
-            for y in 0..rows {
-                for x in 0..cols {
-                    let pixel_value = img.at_2d::<core::Vec3b>(y, x).unwrap()[2 - i];
-                    im_tensor[[0, i, y as usize, x as usize]] = (pixel_value as f32 / self.pixel_scale - self.pixel_means[2 - i]) / self.pixel_stds[2 - i];
-                }
-            }
-        }
-
-        let vec = im_tensor.into_raw_vec();
-
-        let model_cfg = match &self.triton_model_config.config {
-            None => {
-                return Err(Error::msg("face_detection - face detection model config is empty"))
-            }
-            Some(model_cfg) => {model_cfg}
+        let im_tensor = match preprocess(&img, None, &self.normalization) {
+            Ok(im_tensor) => {im_tensor}
+            Err(e) => return Err(e)
         };
 
-        let mut cfg_outputs = Vec::<InferRequestedOutputTensor>::new();
-
-        for out_cfg in model_cfg.output.iter() {
-            cfg_outputs.push(InferRequestedOutputTensor {
-                name: out_cfg.name.to_string(),
-                parameters: Default::default(),
-            })
-        }
-
-
-        let model_request = ModelInferRequest{
-            model_name: self.model_name.to_owned(),
-            model_version: "".to_string(),
-            id: "".to_string(),
-            parameters: Default::default(),
-            inputs: vec![InferInputTensor {
-                name: model_cfg.input[0].name.to_string(),
-                datatype: model_cfg.input[0].data_type().as_str_name()[5..].to_uppercase(),
-                shape: model_cfg.input[0].dims.to_owned(),
-                parameters: Default::default(),
-                contents: Some(InferTensorContents {
-                    bool_contents: vec![],
-                    int_contents: vec![],
-                    int64_contents: vec![],
-                    uint_contents: vec![],
-                    uint64_contents: vec![],
-                    fp32_contents: vec,
-                    fp64_contents: vec![],
-                    bytes_contents: vec![],
-                }),
-            }],
-            outputs: Default::default(),
-            raw_input_contents: vec![],
+        let input_spec = match self.model_spec.input(0) {
+            Ok(input_spec) => {input_spec}
+            Err(e) => return Err(e)
         };
+        let input = Tensor::from_array(&input_spec.name, im_tensor);
 
-        let mut model_out = match self.triton_infer_client.model_infer(model_request).await {
+        let mut model_out = match self.inference_backend.infer(&self.model_name, vec![input]).await {
             Ok(model_out) => model_out,
             Err(e) => {
                 return Err(Error::from(e))
             }
         };
 
-        let mut net_out: Vec<Array4<f32>> = vec![Array4::zeros([1,1,1,1]); cfg_outputs.len()];
-
-        for (idx, output) in &mut model_out.outputs.iter_mut().enumerate() {
-            let dims = &output.shape;
-            let dimensions: [usize; 4] = [
-                dims[0] as usize,
-                dims[1] as usize,
-                dims[2] as usize,
-                dims[3] as usize,
-            ];
-            let u8_array: &[u8] = &model_out.raw_output_contents[idx];
-            let f_array = u8_to_f32_vec(u8_array);
+        // Outputs are consumed per stride in the order declared by the model
+        let mut net_out: Vec<Array4<f32>> = Vec::with_capacity(self.model_spec.outputs.len());
 
-            let array4_f32: Array4<f32> = match Array4::from_shape_vec(dimensions.into_dimension(), f_array) {
+        for out_spec in self.model_spec.outputs.iter() {
+            let array4_f32: Array4<f32> = match take_output(&mut model_out, &out_spec.name).and_then(|output| output.into_array::<Ix4>()) {
                 Ok(array4_f32) => {array4_f32}
                 Err(e) => {
                     return Err(Error::from(e))
                 }
             };
-            let result_index = match cfg_outputs.iter().position(|r| *r.name == output.name) {
-                None => {
-                    return Err(Error::msg("face_detection - no matched model index"))
-                }
-                Some(result_index) => {result_index}
-            };
-            net_out[result_index] =  array4_f32;
+            net_out.push(array4_f32);
         }
 
         drop(model_out);
@@ -399,11 +272,11 @@ impl RetinaFaceDetection {
         let mut landmarks: Option<Array3<f32>> = None;
 
         if proposals.dim().0 == 0 {
+            let det: Array2<f32> = Array2::zeros((0, 5));
             if self.use_landmarks {
-                let det: Array2<f32> = Array2::zeros((0, 5));
                 landmarks = Some(Array3::zeros((0, 5, 2)));
-                return Ok((det, landmarks));
             }
+            return Ok((det, landmarks));
         }
 
         let score_stack = vstack_2d(scores_list);
@@ -419,80 +292,12 @@ impl RetinaFaceDetection {
         }
         drop(order);
 
+        // Overlaps are suppressed by `detect_input` with the configured NMS
         let pre_det = concatenate![Axis(1), selected_proposals.slice(s![.., 0..4]).to_owned(), selected_score];
-        let keep = nms(&pre_det, self.iou_threshold);
         let det = concatenate![Axis(1), pre_det, selected_proposals.slice(s![.., 4..]).to_owned()];
-        let selected_rows = &keep.iter()
-            .map(|&i| det.slice(s![i, ..]).to_owned())
-            .collect::<Vec<_>>();
-        let new_det_shape = (selected_rows.len(), det.shape()[1]);
-        let det = match Array2::from_shape_vec(
-            new_det_shape,
-            selected_rows.into_iter().flat_map(| row| row.iter().cloned()).collect()
-        ) {
-            Ok(det) => {det}
-            Err(e) => {
-                return Err(Error::from(e))
-            }
-        };
-
-        if self.use_landmarks {
-            let selected_landmarks = &keep.iter()
-                .map(|&i| landmarks.as_mut().unwrap().slice(s![i, .., ..]).to_owned())
-                .collect::<Vec<_>>();
-
-            let new_landmarks_shape = (selected_landmarks.len(), landmarks.as_mut().unwrap().shape()[1], landmarks.as_mut().unwrap().shape()[2]);
-
-            landmarks = match Array3::from_shape_vec(
-                new_landmarks_shape,
-                selected_landmarks.into_iter().flat_map(|array| array.iter().cloned()).collect()
-            ) {
-                Ok(landmarks) => {
-                    Some(landmarks)
-                }
-                Err(e) => {
-                    return Err(Error::from(e))
-                }
-            };
-        }
-        Ok((det, landmarks))
-    }
-
-    async fn _postprocess(&self, predicted_output: (Array2<f32>, Option<Array3<f32>>), preprocess_param: f32) -> (Array2<f32>, Option<Array3<f32>>)  {
-        let (mut det, mut kpss) = predicted_output;
-        for mut row in det.axis_iter_mut(Axis(0)) {
-            for elem in row.iter_mut().take(4) {
-                *elem /= preprocess_param;
-            }
-        }
-        if !kpss.is_none() {
-            kpss.as_mut().unwrap().map_inplace(|x| *x /= preprocess_param);
-        }
-        (det, kpss)
-    }
-
-
-    pub async fn call(&self, image: Mat) -> Result<(Array2<f32>, Option<Array3<f32>>), Error> {
-
-        let (preprocessed_img, scale) = match self._preprocess(image) {
-            Ok((preprocessed_img, scale)) => {(preprocessed_img, scale)}
-            Err(e) => {
-                return Err(Error::from(e))
-            }
-        };
-
-        let predicted_output = match self._forward(preprocessed_img).await {
-            Ok(predicted_output) => {predicted_output}
-            Err(e) => {
-                return Err(Error::from(e))
-            }
-        };
-        let (det, landmarks) = self._postprocess(predicted_output, scale).await;
-
         Ok((det, landmarks))
     }
 
-
     fn bbox_pred(&self, boxes: ArrayBase<OwnedRepr<f32>, Ix2>, box_deltas: ArrayBase<OwnedRepr<f32>, Ix2>) -> Array2<f32> {
         if boxes.shape()[0] == 0 {
             return Array2::zeros((0, box_deltas.shape()[1]));
@@ -555,61 +360,129 @@ impl RetinaFaceDetection {
     }
 }
 
+#[async_trait]
+impl FaceDetector for RetinaFaceDetection {
+    fn input(&self) -> &DetectionInput {
+        &self.input
+    }
+
+    fn iou_threshold(&self) -> f32 {
+        self.iou_threshold
+    }
+
+    async fn detect_input(&self, input_img: Mat) -> Result<FaceDetections, Error> {
+        let (det, landmarks) = match self._forward(input_img).await {
+            Ok((det, landmarks)) => {(det, landmarks)}
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+        Ok(FaceDetections::from_rows(&det, landmarks).filter(self.confidence_threshold, self.iou_threshold, &self.input.nms))
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use opencv::core::Scalar;
+    use crate::pipeline::inference_backend::inference_backend::InferenceBackend;
+    use crate::pipeline::inference_backend::triton_backend::TritonBackend;
+    use crate::pipeline::model_config::config::{DetectionTilingConfig, PaddingMode};
     use crate::pipeline::module::face_detection::RetinaFaceDetection;
-    use crate::pipeline::triton_client::client::triton::ModelConfigRequest;
-    use crate::pipeline::triton_client::client::TritonInferenceClient;
+    use crate::pipeline::module::face_detector::{DetectionInput, FaceDetector};
+    use crate::pipeline::triton_client::mock_server::{MockTritonServer, synthetic_image_bytes};
     use crate::pipeline::utils::utils::byte_data_to_opencv;
 
     #[tokio::test]
     async fn test_retina_face_detection() {
-        // let triton_host = "";
-        // let triton_port = "";
-        // let im_bytes: &[u8] = include_bytes!("");
-        // let image = byte_data_to_opencv(im_bytes).unwrap();
-        //
-        // let triton_infer_client = match TritonInferenceClient::new(triton_host, triton_port).await {
-        //     Ok(triton_infer_client) => triton_infer_client,
-        //     Err(e) => {
-        //         println!("{:?}", e);
-        //         return
-        //     }
-        // };
-        //
-        // let model_name = "face_detection_retina".to_string();
-        //
-        // let face_detection_model_config = match triton_infer_client
-        //     .model_config(ModelConfigRequest {
-        //         name: model_name.to_owned(),
-        //         version: "".to_string(),
-        //     }).await {
-        //     Ok(model_config_resp) => {model_config_resp}
-        //     Err(e) => {
-        //         println!("{:?}", e);
-        //         return
-        //     }
-        // };
-        //
-        // let retina_face_detection = match RetinaFaceDetection::new(
-        //     triton_infer_client,
-        //     face_detection_model_config,
-        //     model_name,
-        //     (640, 640),
-        //     1,
-        //     0.7,
-        //     0.45,
-        // ).await
-        // {
-        //     Ok(retina_face_detection)  => retina_face_detection,
-        //     Err(e) => {
-        //         println!("{:?}", e);
-        //         return
-        //     }
-        // };
-        //
-        // let (preprocessed_img, scale) = retina_face_detection._preprocess(&image, Some(true)).unwrap();
-        // let predicted_output = retina_face_detection._forward(&preprocessed_img, None).await.unwrap();
-        // retina_face_detection._postprocess(predicted_output, scale, Some(true)).await;
+        let server = MockTritonServer::new()
+            .with_face_models()
+            .start().await.unwrap();
+        let inference_backend: Arc<dyn InferenceBackend> = Arc::new(TritonBackend::new(&server.host(), &server.port()).await.unwrap());
+
+        let model_name = "face_detection_retina".to_string();
+        let model_spec = inference_backend.model_spec(&model_name).await.unwrap();
+        let retina_face_detection = RetinaFaceDetection::new(
+            inference_backend,
+            model_spec,
+            model_name,
+            DetectionInput::new((640, 640)),
+            1,
+            0.7,
+            0.45,
+        ).await.unwrap();
+
+        let image = byte_data_to_opencv(&synthetic_image_bytes(640, 480).unwrap()).unwrap();
+        let detections = retina_face_detection.detect(image).await.unwrap();
+        let det = detections.rows();
+        let landmarks = detections.landmarks;
+
+        // The scripted face is the first stride 16 anchor at cell (20, 20), scaled 1.5 times
+        assert_eq!(det.dim(), (1, 5));
+        assert!((det[[0, 4]] - 0.99).abs() < 1e-6);
+        for (coord, expected) in det.row(0).iter().take(4).zip([232.0, 232.0, 423.0, 423.0]) {
+            assert!((coord - expected).abs() < 1.0, "{coord} != {expected}");
+        }
+
+        let landmarks = landmarks.unwrap();
+        assert_eq!(landmarks.dim(), (1, 5, 2));
+        assert!(landmarks[[0, 0, 0]] < landmarks[[0, 1, 0]]);
+        assert!(landmarks[[0, 2, 1]] < landmarks[[0, 3, 1]]);
+
+        let requests = server.infer_requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].inputs[0].shape, vec![1, 3, 640, 640]);
+    }
+
+    #[tokio::test]
+    async fn test_letterbox_and_tiling() {
+        let server = MockTritonServer::new()
+            .with_face_models()
+            .start().await.unwrap();
+        let inference_backend: Arc<dyn InferenceBackend> = Arc::new(TritonBackend::new(&server.host(), &server.port()).await.unwrap());
+
+        let model_name = "face_detection_retina".to_string();
+        let model_spec = inference_backend.model_spec(&model_name).await.unwrap();
+        let mut input = DetectionInput::new((640, 640));
+        input.padding = PaddingMode::Center;
+        input.pad_color = Scalar::all(114.0);
+        input.tiling = DetectionTilingConfig {
+            enabled: true,
+            tile_sizes: vec![640],
+            overlap: 0.2,
+            min_image_size: 1280,
+        };
+        let retina_face_detection = RetinaFaceDetection::new(
+            inference_backend,
+            model_spec,
+            model_name,
+            input,
+            1,
+            0.7,
+            0.45,
+        ).await.unwrap();
+
+        // Centred padding moves the canvas face 80 pixels down, mapping it back undoes that
+        let image = byte_data_to_opencv(&synthetic_image_bytes(640, 480).unwrap()).unwrap();
+        let det = retina_face_detection.detect(image).await.unwrap().rows();
+        assert_eq!(det.dim(), (1, 5));
+        for (coord, expected) in det.row(0).iter().take(4).zip([232.0, 152.0, 423.0, 343.0]) {
+            assert!((coord - expected).abs() < 1.0, "{coord} != {expected}");
+        }
+
+        // 1600x1200 is covered by a 3x3 grid of 640 tiles, the scripted face shows up in every tile
+        let image = byte_data_to_opencv(&synthetic_image_bytes(1600, 1200).unwrap()).unwrap();
+        let detections = retina_face_detection.detect(image).await.unwrap();
+        let det = detections.rows();
+        let landmarks = detections.landmarks;
+        assert_eq!(server.infer_requests().len(), 1 + 1 + 9);
+
+        // The tiles at y 512 and 560 see the same face and are merged, the whole-image face is kept
+        assert_eq!(det.dim(), (7, 5));
+        assert_eq!(landmarks.unwrap().dim(), (7, 5, 2));
+        let has_box = |x_min: f32, y_min: f32| det.rows().into_iter().any(|row| (row[0] - x_min).abs() < 1.0 && (row[1] - y_min).abs() < 1.0);
+        assert!(has_box(232.0, 232.0));
+        assert!(has_box(1192.0, 232.0));
+        assert!(has_box(580.0, 380.0));
     }
 }
@@ -0,0 +1,261 @@
+use std::sync::Arc;
+use anyhow::Error;
+use async_trait::async_trait;
+use ndarray::{Array1, Array2, Array3};
+use opencv::core::Mat;
+use crate::pipeline::inference_backend::inference_backend::{InferenceBackend, ModelSpec, take_output, Tensor};
+use crate::pipeline::module::face_detector::{DetectionInput, FaceDetections, FaceDetector};
+use crate::pipeline::processing::preprocess::{Normalization, preprocess};
+
+/// Score, box and optional landmark outputs of one feature level.
+#[derive(Debug, Clone)]
+struct ScrfdLevel {
+    score: String,
+    bbox: String,
+    landmark: Option<String>,
+}
+
+/// Anchor-free SCRFD detector.
+///
+/// Every anchor centre predicts its distances to the four box sides and the offsets of five landmarks,
+/// both in units of the level stride. Outputs are told apart by their last dimension, 1 for scores,
+/// 4 for boxes and 10 for landmarks, and paired in the order the model config lists them.
+#[derive(Debug, Clone)]
+pub struct ScrfdDetection {
+    inference_backend: Arc<dyn InferenceBackend>,
+    model_spec: ModelSpec,
+    model_name: String,
+    input: DetectionInput,
+    confidence_threshold: f32,
+    iou_threshold: f32,
+    levels: Vec<ScrfdLevel>,
+    normalization: Normalization,
+}
+
+impl ScrfdDetection {
+    pub async fn new(
+        inference_backend: Arc<dyn InferenceBackend>,
+        model_spec: ModelSpec,
+        model_name: String,
+        input: DetectionInput,
+        confidence_threshold: f32,
+        iou_threshold: f32,
+    ) -> Result<Self, Error> {
+        let mut scores: Vec<String> = vec![];
+        let mut bboxes: Vec<String> = vec![];
+        let mut landmarks: Vec<String> = vec![];
+        for output in model_spec.outputs.iter() {
+            match output.dims.last() {
+                Some(1) => scores.push(output.name.to_string()),
+                Some(4) => bboxes.push(output.name.to_string()),
+                Some(10) => landmarks.push(output.name.to_string()),
+                _ => return Err(Error::msg(format!("face_detection_scrfd - unexpected output {} with dims {:?}", output.name, output.dims))),
+            }
+        }
+        if scores.is_empty() || scores.len() != bboxes.len() || !(landmarks.is_empty() || landmarks.len() == scores.len()) {
+            return Err(Error::msg(format!(
+                "face_detection_scrfd - model {model_name} has {} score, {} box and {} landmark outputs",
+                scores.len(), bboxes.len(), landmarks.len()
+            )))
+        }
+
+        let levels = scores.into_iter().zip(bboxes).enumerate().map(|(idx, (score, bbox))| ScrfdLevel {
+            score,
+            bbox,
+            landmark: landmarks.get(idx).cloned(),
+        }).collect();
+
+        Ok(ScrfdDetection {
+            inference_backend,
+            model_spec,
+            model_name,
+            normalization: input.normalization.resolve(Normalization::SCRFD),
+            input,
+            confidence_threshold,
+            iou_threshold,
+            levels,
+        })
+    }
+
+    /// Strides of the levels, 8, 16, 32 and on, finest level first.
+    fn strides(&self) -> Vec<i32> {
+        (0..self.levels.len()).map(|idx| 8 << idx).collect()
+    }
+}
+
+#[async_trait]
+impl FaceDetector for ScrfdDetection {
+    fn input(&self) -> &DetectionInput {
+        &self.input
+    }
+
+    fn iou_threshold(&self) -> f32 {
+        self.iou_threshold
+    }
+
+    async fn detect_input(&self, input_img: Mat) -> Result<FaceDetections, Error> {
+        let im_tensor = match preprocess(&input_img, None, &self.normalization) {
+            Ok(im_tensor) => {im_tensor}
+            Err(e) => return Err(e)
+        };
+        drop(input_img);
+
+        let input_spec = match self.model_spec.input(0) {
+            Ok(input_spec) => {input_spec}
+            Err(e) => return Err(e)
+        };
+        let input = Tensor::from_array(&input_spec.name, im_tensor);
+
+        let mut model_out = match self.inference_backend.infer(&self.model_name, vec![input]).await {
+            Ok(model_out) => model_out,
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+
+        let mut level_outputs: Vec<(Tensor, Tensor, Option<Tensor>)> = Vec::with_capacity(self.levels.len());
+        for level in self.levels.iter() {
+            let score = match take_output(&mut model_out, &level.score) {
+                Ok(score) => {score}
+                Err(e) => return Err(e)
+            };
+            let bbox = match take_output(&mut model_out, &level.bbox) {
+                Ok(bbox) => {bbox}
+                Err(e) => return Err(e)
+            };
+            let landmark = match &level.landmark {
+                None => None,
+                Some(landmark) => match take_output(&mut model_out, landmark) {
+                    Ok(landmark) => Some(landmark),
+                    Err(e) => return Err(e)
+                },
+            };
+            level_outputs.push((score, bbox, landmark));
+        }
+        drop(model_out);
+
+        // Finer levels have more anchor centres, which gives the level order whatever the output names
+        level_outputs.sort_by(|a, b| b.0.data.len().cmp(&a.0.data.len()));
+
+        let with_landmarks = self.levels.iter().all(|level| level.landmark.is_some());
+        let (input_w, input_h) = self.input.image_size;
+        let mut boxes: Vec<f32> = vec![];
+        let mut scores: Vec<f32> = vec![];
+        let mut landmarks: Vec<f32> = vec![];
+        for ((score, bbox, landmark), stride) in level_outputs.into_iter().zip(self.strides()) {
+            let width = (input_w / stride) as usize;
+            let cells = width * (input_h / stride) as usize;
+            let num_anchors = score.data.len() / cells.max(1);
+            let landmark_mismatch = landmark.as_ref().is_some_and(|landmark| landmark.data.len() != 10 * score.data.len());
+            if num_anchors == 0 || num_anchors * cells != score.data.len() || bbox.data.len() != 4 * score.data.len() || landmark_mismatch {
+                return Err(Error::msg(format!(
+                    "face_detection_scrfd - {} scores do not match the {cells} cells of stride {stride} or the box and landmark outputs", score.data.len()
+                )))
+            }
+
+            for (idx, confidence) in score.data.iter().enumerate() {
+                if *confidence < self.confidence_threshold {
+                    continue
+                }
+                let cell = idx / num_anchors;
+                let center_x = ((cell % width) as i32 * stride) as f32;
+                let center_y = ((cell / width) as i32 * stride) as f32;
+                let distances = &bbox.data[idx * 4..idx * 4 + 4];
+                let step = stride as f32;
+                boxes.extend([
+                    center_x - distances[0] * step,
+                    center_y - distances[1] * step,
+                    center_x + distances[2] * step,
+                    center_y + distances[3] * step,
+                ]);
+                scores.push(*confidence);
+                if let Some(landmark) = &landmark {
+                    for offsets in landmark.data[idx * 10..idx * 10 + 10].chunks_exact(2) {
+                        landmarks.extend([center_x + offsets[0] * step, center_y + offsets[1] * step]);
+                    }
+                }
+            }
+        }
+
+        let num_faces = scores.len();
+        let detections = FaceDetections {
+            boxes: match Array2::from_shape_vec((num_faces, 4), boxes) {
+                Ok(boxes) => {boxes}
+                Err(e) => return Err(Error::from(e))
+            },
+            scores: Array1::from(scores),
+            landmarks: if with_landmarks {
+                match Array3::from_shape_vec((num_faces, 5, 2), landmarks) {
+                    Ok(landmarks) => Some(landmarks),
+                    Err(e) => return Err(Error::from(e))
+                }
+            } else {
+                None
+            },
+        };
+        Ok(detections.filter(self.confidence_threshold, self.iou_threshold, &self.input.nms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::pipeline::inference_backend::inference_backend::InferenceBackend;
+    use crate::pipeline::inference_backend::triton_backend::TritonBackend;
+    use crate::pipeline::module::face_detection_scrfd::ScrfdDetection;
+    use crate::pipeline::module::face_detector::{DetectionInput, FaceDetector};
+    use crate::pipeline::triton_client::mock_server::{fp32_infer_response, fp32_model_config, MockTritonServer, synthetic_image_bytes};
+    use crate::pipeline::utils::utils::byte_data_to_opencv;
+
+    #[tokio::test]
+    async fn test_scrfd_detection() {
+        let levels = [(8, 12800i64), (16, 3200), (32, 800)];
+        let mut outputs: Vec<(String, Vec<i64>)> = vec![];
+        for (kind, last_dim) in [("score", 1), ("bbox", 4), ("kps", 10)] {
+            for (stride, rows) in levels {
+                outputs.push((format!("{kind}_{stride}"), vec![rows, last_dim]));
+            }
+        }
+        let response_outputs = outputs.clone();
+        let server = MockTritonServer::new()
+            .with_model_config(fp32_model_config(
+                "face_detection_scrfd", 0, vec![("input.1", vec![1, 3, 640, 640])],
+                outputs.iter().map(|(name, dims)| (name.as_str(), dims.clone())).collect(),
+            ))
+            .with_infer_handler("face_detection_scrfd", move |request| {
+                // One face on the first anchor of stride 16 cell (row 10, column 20), centred at (320, 160)
+                let idx = (10 * 40 + 20) * 2;
+                let tensors = response_outputs.iter().map(|(name, dims)| {
+                    let mut data = vec![0.0; (dims[0] * dims[1]) as usize];
+                    match name.as_str() {
+                        "score_16" => data[idx] = 0.9,
+                        "bbox_16" => data[idx * 4..idx * 4 + 4].copy_from_slice(&[2.0, 3.0, 2.0, 3.0]),
+                        "kps_16" => data[idx * 10..idx * 10 + 10].copy_from_slice(&[-1.0, -1.0, 1.0, -1.0, 0.0, 0.0, -1.0, 1.5, 1.0, 1.5]),
+                        _ => {}
+                    }
+                    (name.as_str(), dims.clone(), data)
+                }).collect();
+                Ok(fp32_infer_response(&request.model_name, tensors))
+            })
+            .start().await.unwrap();
+        let inference_backend: Arc<dyn InferenceBackend> = Arc::new(TritonBackend::new(&server.host(), &server.port()).await.unwrap());
+        let model_spec = inference_backend.model_spec("face_detection_scrfd").await.unwrap();
+
+        let scrfd_detection = ScrfdDetection::new(
+            inference_backend,
+            model_spec,
+            "face_detection_scrfd".to_string(),
+            DetectionInput::new((640, 640)),
+            0.5,
+            0.45,
+        ).await.unwrap();
+
+        let image = byte_data_to_opencv(&synthetic_image_bytes(640, 640).unwrap()).unwrap();
+        let detections = scrfd_detection.detect(image).await.unwrap();
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections.rows().row(0).to_vec(), vec![288.0, 112.0, 352.0, 208.0, 0.9]);
+        let landmarks = detections.landmarks.unwrap();
+        assert_eq!((landmarks[[0, 0, 0]], landmarks[[0, 0, 1]]), (304.0, 144.0));
+        assert_eq!((landmarks[[0, 4, 0]], landmarks[[0, 4, 1]]), (336.0, 184.0));
+    }
+}
@@ -0,0 +1,248 @@
+use std::sync::Arc;
+use anyhow::Error;
+use async_trait::async_trait;
+use ndarray::{Array1, Array2, Array3};
+use opencv::core::Mat;
+use crate::pipeline::inference_backend::inference_backend::{InferenceBackend, ModelSpec, take_output, Tensor};
+use crate::pipeline::module::face_detector::{DetectionInput, FaceDetections, FaceDetector};
+use crate::pipeline::processing::preprocess::{Normalization, preprocess};
+
+/// Values predicted for each candidate face.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum YoloFaceLayout {
+    /// YOLOv5-face, `[cx, cy, w, h, objectness, 5 x (x, y), face class]`
+    V5,
+    /// YOLOv8-face, `[cx, cy, w, h, score, 5 x (x, y, visibility)]`
+    V8,
+}
+
+impl YoloFaceLayout {
+    fn from_channels(channels: i64) -> Option<Self> {
+        match channels {
+            16 => Some(YoloFaceLayout::V5),
+            20 => Some(YoloFaceLayout::V8),
+            _ => None,
+        }
+    }
+
+    fn channels(&self) -> usize {
+        match self {
+            YoloFaceLayout::V5 => 16,
+            YoloFaceLayout::V8 => 20,
+        }
+    }
+}
+
+/// YOLO-face detector decoding boxes centred on the predicted point, in detector input pixels.
+///
+/// The single output is `[candidates, channels]` or, channels first, `[channels, candidates]`, the
+/// model config tells which through the 16 or 20 channel dimension.
+#[derive(Debug, Clone)]
+pub struct YoloFaceDetection {
+    inference_backend: Arc<dyn InferenceBackend>,
+    model_spec: ModelSpec,
+    model_name: String,
+    input: DetectionInput,
+    confidence_threshold: f32,
+    iou_threshold: f32,
+    layout: YoloFaceLayout,
+    channels_first: bool,
+    normalization: Normalization,
+}
+
+impl YoloFaceDetection {
+    pub async fn new(
+        inference_backend: Arc<dyn InferenceBackend>,
+        model_spec: ModelSpec,
+        model_name: String,
+        input: DetectionInput,
+        confidence_threshold: f32,
+        iou_threshold: f32,
+    ) -> Result<Self, Error> {
+        let output_spec = match model_spec.output(0) {
+            Ok(output_spec) => {output_spec}
+            Err(e) => return Err(e)
+        };
+        let dims = &output_spec.dims;
+        let last = dims.last().and_then(|channels| YoloFaceLayout::from_channels(*channels));
+        let second_last = dims.len().checked_sub(2).and_then(|idx| YoloFaceLayout::from_channels(dims[idx]));
+        let (layout, channels_first) = match (last, second_last) {
+            (Some(layout), _) => (layout, false),
+            (None, Some(layout)) => (layout, true),
+            (None, None) => return Err(Error::msg(format!(
+                "face_detection_yolo - output {} with dims {:?} has no 16 or 20 channel dimension", output_spec.name, dims
+            ))),
+        };
+
+        Ok(YoloFaceDetection {
+            inference_backend,
+            model_spec,
+            model_name,
+            normalization: input.normalization.resolve(Normalization::YOLO_FACE),
+            input,
+            confidence_threshold,
+            iou_threshold,
+            layout,
+            channels_first,
+        })
+    }
+
+    fn _decode(&self, output: &Tensor) -> Result<FaceDetections, Error> {
+        let channels = self.layout.channels();
+        if output.data.len() % channels != 0 {
+            return Err(Error::msg(format!(
+                "face_detection_yolo - {} values are not a whole number of {channels} channel candidates", output.data.len()
+            )))
+        }
+        let candidates = output.data.len() / channels;
+        let value = |idx: usize, channel: usize| {
+            if self.channels_first {
+                output.data[channel * candidates + idx]
+            } else {
+                output.data[idx * channels + channel]
+            }
+        };
+
+        let mut boxes: Vec<f32> = vec![];
+        let mut scores: Vec<f32> = vec![];
+        let mut landmarks: Vec<f32> = vec![];
+        for idx in 0..candidates {
+            let (score, point_stride) = match self.layout {
+                YoloFaceLayout::V5 => (value(idx, 4) * value(idx, 15), 2),
+                YoloFaceLayout::V8 => (value(idx, 4), 3),
+            };
+            if score < self.confidence_threshold {
+                continue
+            }
+            let (center_x, center_y) = (value(idx, 0), value(idx, 1));
+            let (half_w, half_h) = (value(idx, 2) / 2.0, value(idx, 3) / 2.0);
+            boxes.extend([center_x - half_w, center_y - half_h, center_x + half_w, center_y + half_h]);
+            scores.push(score);
+            for point in 0..5 {
+                landmarks.extend([value(idx, 5 + point * point_stride), value(idx, 6 + point * point_stride)]);
+            }
+        }
+
+        let num_faces = scores.len();
+        let boxes = match Array2::from_shape_vec((num_faces, 4), boxes) {
+            Ok(boxes) => {boxes}
+            Err(e) => return Err(Error::from(e))
+        };
+        let landmarks = match Array3::from_shape_vec((num_faces, 5, 2), landmarks) {
+            Ok(landmarks) => {landmarks}
+            Err(e) => return Err(Error::from(e))
+        };
+        Ok(FaceDetections {
+            boxes,
+            scores: Array1::from(scores),
+            landmarks: Some(landmarks),
+        })
+    }
+}
+
+#[async_trait]
+impl FaceDetector for YoloFaceDetection {
+    fn input(&self) -> &DetectionInput {
+        &self.input
+    }
+
+    fn iou_threshold(&self) -> f32 {
+        self.iou_threshold
+    }
+
+    async fn detect_input(&self, input_img: Mat) -> Result<FaceDetections, Error> {
+        let im_tensor = match preprocess(&input_img, None, &self.normalization) {
+            Ok(im_tensor) => {im_tensor}
+            Err(e) => return Err(e)
+        };
+        drop(input_img);
+
+        let input_spec = match self.model_spec.input(0) {
+            Ok(input_spec) => {input_spec}
+            Err(e) => return Err(e)
+        };
+        let output_spec = match self.model_spec.output(0) {
+            Ok(output_spec) => {output_spec}
+            Err(e) => return Err(e)
+        };
+        let input = Tensor::from_array(&input_spec.name, im_tensor);
+
+        let mut model_out = match self.inference_backend.infer(&self.model_name, vec![input]).await {
+            Ok(model_out) => model_out,
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+        let output = match take_output(&mut model_out, &output_spec.name) {
+            Ok(output) => {output}
+            Err(e) => return Err(e)
+        };
+        drop(model_out);
+
+        let detections = match self._decode(&output) {
+            Ok(detections) => {detections}
+            Err(e) => return Err(e)
+        };
+        Ok(detections.filter(self.confidence_threshold, self.iou_threshold, &self.input.nms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::pipeline::inference_backend::inference_backend::InferenceBackend;
+    use crate::pipeline::inference_backend::triton_backend::TritonBackend;
+    use crate::pipeline::module::face_detection_yolo::YoloFaceDetection;
+    use crate::pipeline::module::face_detector::{DetectionInput, FaceDetector};
+    use crate::pipeline::triton_client::mock_server::{fp32_infer_response, fp32_model_config, MockTritonServer, synthetic_image_bytes};
+    use crate::pipeline::utils::utils::byte_data_to_opencv;
+
+    #[tokio::test]
+    async fn test_yolo_face_detection() {
+        let landmarks = [290.0, 220.0, 350.0, 220.0, 320.0, 250.0, 295.0, 280.0, 345.0, 280.0];
+        // A face, a weaker duplicate of it and a low objectness candidate
+        let mut v5_rows: Vec<f32> = vec![];
+        for (shift, objectness) in [(0.0, 0.9), (5.0, 0.6), (200.0, 0.2)] {
+            v5_rows.extend([320.0 + shift, 240.0, 100.0, 120.0, objectness]);
+            v5_rows.extend(landmarks);
+            v5_rows.push(0.9);
+        }
+        // The same face for YOLOv8-face, channels first with a visibility per point
+        let mut v8_row: Vec<f32> = vec![320.0, 240.0, 100.0, 120.0, 0.8];
+        for point in landmarks.chunks_exact(2) {
+            v8_row.extend([point[0], point[1], 1.0]);
+        }
+
+        let server = MockTritonServer::new()
+            .with_model_config(fp32_model_config("yolov5_face", 1, vec![("images", vec![3, 640, 640])], vec![("output", vec![-1, 16])]))
+            .with_model_config(fp32_model_config("yolov8_face", 1, vec![("images", vec![3, 640, 640])], vec![("output0", vec![20, -1])]))
+            .with_infer_handler("yolov5_face", move |request| {
+                Ok(fp32_infer_response(&request.model_name, vec![("output", vec![1, 3, 16], v5_rows.clone())]))
+            })
+            .with_infer_handler("yolov8_face", move |request| {
+                Ok(fp32_infer_response(&request.model_name, vec![("output0", vec![1, 20, 1], v8_row.clone())]))
+            })
+            .start().await.unwrap();
+        let inference_backend: Arc<dyn InferenceBackend> = Arc::new(TritonBackend::new(&server.host(), &server.port()).await.unwrap());
+
+        for (model_name, score) in [("yolov5_face", 0.81), ("yolov8_face", 0.8)] {
+            let model_spec = inference_backend.model_spec(model_name).await.unwrap();
+            let yolo_face_detection = YoloFaceDetection::new(
+                Arc::clone(&inference_backend),
+                model_spec,
+                model_name.to_string(),
+                DetectionInput::new((640, 640)),
+                0.5,
+                0.45,
+            ).await.unwrap();
+
+            let image = byte_data_to_opencv(&synthetic_image_bytes(640, 480).unwrap()).unwrap();
+            let detections = yolo_face_detection.detect(image).await.unwrap();
+            assert_eq!(detections.len(), 1, "{model_name}");
+            assert!((detections.scores[0] - score).abs() < 1e-6);
+            assert_eq!(detections.boxes.row(0).to_vec(), vec![270.0, 180.0, 370.0, 300.0]);
+            let face_landmarks = detections.landmarks.unwrap();
+            assert_eq!((face_landmarks[[0, 2, 0]], face_landmarks[[0, 2, 1]]), (320.0, 250.0));
+        }
+    }
+}
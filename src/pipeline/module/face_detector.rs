@@ -0,0 +1,385 @@
+use std::fmt::Debug;
+use anyhow::Error;
+use async_trait::async_trait;
+use ndarray::{Array1, Array2, Array3, Axis, concatenate};
+use opencv::core::{self, Mat, MatTraitConst, Rect, Scalar, Size};
+use opencv::imgproc::{INTER_LINEAR, resize};
+use crate::pipeline::model_config::config::{DetectionTilingConfig, FaceDetectionConfig, NormalizationConfig, PaddingMode};
+use crate::pipeline::processing::nms::NmsConfig;
+use crate::pipeline::utils::utils::{vstack_2d, vstack_3d};
+
+/// Faces found in one image.
+#[derive(Debug, Clone)]
+pub struct FaceDetections {
+    /// `[x_min, y_min, x_max, y_max]` per face
+    pub boxes: Array2<f32>,
+    pub scores: Array1<f32>,
+    /// Five `[x, y]` points per face, `None` when the model has no landmark head
+    pub landmarks: Option<Array3<f32>>,
+}
+
+impl FaceDetections {
+    pub fn empty(with_landmarks: bool) -> Self {
+        FaceDetections {
+            boxes: Array2::zeros((0, 4)),
+            scores: Array1::zeros(0),
+            landmarks: if with_landmarks { Some(Array3::zeros((0, 5, 2))) } else { None },
+        }
+    }
+
+    /// Splits `[x_min, y_min, x_max, y_max, score]` rows.
+    pub fn from_rows(det: &Array2<f32>, landmarks: Option<Array3<f32>>) -> Self {
+        FaceDetections {
+            boxes: det.slice(ndarray::s![.., 0..4]).to_owned(),
+            scores: det.column(4).to_owned(),
+            landmarks,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// `[x_min, y_min, x_max, y_max, score]` rows, the layout selection and alignment work on.
+    pub fn rows(&self) -> Array2<f32> {
+        concatenate![Axis(1), self.boxes, self.scores.clone().insert_axis(Axis(1))]
+    }
+
+    pub fn select(&self, keep: &[usize]) -> Self {
+        FaceDetections {
+            boxes: self.boxes.select(Axis(0), keep),
+            scores: self.scores.select(Axis(0), keep),
+            landmarks: self.landmarks.as_ref().map(|landmarks| landmarks.select(Axis(0), keep)),
+        }
+    }
+
+    /// Keeps the faces scoring at least `confidence_threshold` and suppresses overlaps above `iou_threshold`
+    /// with `nms`, best score first. Faces whose Soft-NMS score decays below `confidence_threshold` are dropped.
+    pub fn filter(&self, confidence_threshold: f32, iou_threshold: f32, nms: &NmsConfig) -> Self {
+        let confident: Vec<usize> = (0..self.len()).filter(|idx| self.scores[*idx] >= confidence_threshold).collect();
+        let confident = self.select(&confident).suppress(iou_threshold, nms);
+        let kept: Vec<usize> = (0..confident.len()).filter(|idx| confident.scores[*idx] >= confidence_threshold).collect();
+        confident.select(&kept)
+    }
+
+    /// Suppresses overlaps above `iou_threshold` with `nms`, scores are replaced by their decayed values.
+    pub fn suppress(&self, iou_threshold: f32, nms: &NmsConfig) -> Self {
+        let kept = nms.suppress(&self.rows(), iou_threshold);
+        let keep: Vec<usize> = kept.iter().map(|(idx, _)| *idx).collect();
+        let mut selected = self.select(&keep);
+        selected.scores = kept.into_iter().map(|(_, score)| score).collect();
+        selected
+    }
+
+    /// Stacks the faces of several views, landmarks are kept only when every view has them.
+    pub fn concat(list: Vec<FaceDetections>) -> Self {
+        let with_landmarks = list.iter().all(|detections| detections.landmarks.is_some());
+        let mut boxes: Vec<Array2<f32>> = Vec::with_capacity(list.len());
+        let mut scores: Vec<f32> = vec![];
+        let mut landmarks: Vec<Array3<f32>> = vec![];
+        for detections in list {
+            boxes.push(detections.boxes);
+            scores.extend(detections.scores);
+            if let Some(view_landmarks) = detections.landmarks {
+                landmarks.push(view_landmarks);
+            }
+        }
+        FaceDetections {
+            boxes: if boxes.is_empty() { Array2::zeros((0, 4)) } else { vstack_2d(boxes) },
+            scores: Array1::from(scores),
+            landmarks: if !with_landmarks {
+                None
+            } else if landmarks.is_empty() {
+                Some(Array3::zeros((0, 5, 2)))
+            } else {
+                Some(vstack_3d(landmarks))
+            },
+        }
+    }
+
+    /// Maps detector input coordinates back to the image, `offset` is the origin of the detected view.
+    pub fn unmap(mut self, letterbox: Letterbox, offset: (f32, f32)) -> Self {
+        for mut row in self.boxes.axis_iter_mut(Axis(0)) {
+            for (idx, elem) in row.iter_mut().enumerate() {
+                *elem = if idx % 2 == 0 {
+                    letterbox.unmap_x(*elem) + offset.0
+                } else {
+                    letterbox.unmap_y(*elem) + offset.1
+                };
+            }
+        }
+        if let Some(landmarks) = self.landmarks.as_mut() {
+            for mut point in landmarks.lanes_mut(Axis(2)) {
+                point[0] = letterbox.unmap_x(point[0]) + offset.0;
+                point[1] = letterbox.unmap_y(point[1]) + offset.1;
+            }
+        }
+        self
+    }
+}
+
+/// Where the resized image sits in the detector input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Letterbox {
+    pub scale: f32,
+    pub pad_x: f32,
+    pub pad_y: f32,
+}
+
+impl Letterbox {
+    pub fn unmap_x(&self, x: f32) -> f32 {
+        (x - self.pad_x) / self.scale
+    }
+
+    pub fn unmap_y(&self, y: f32) -> f32 {
+        (y - self.pad_y) / self.scale
+    }
+}
+
+/// How images are fitted into the fixed detector input and overlapping faces suppressed, shared by every detector.
+#[derive(Debug, Clone)]
+pub struct DetectionInput {
+    /// Detector input width and height
+    pub image_size: (i32, i32),
+    pub padding: PaddingMode,
+    pub pad_color: Scalar,
+    pub tiling: DetectionTilingConfig,
+    /// How overlapping faces are suppressed
+    pub nms: NmsConfig,
+    /// Overrides of the built-in normalisation of the detector
+    pub normalization: NormalizationConfig,
+}
+
+impl DetectionInput {
+    /// Top-left letterbox on black, no tiling, hard NMS and the built-in normalisation.
+    pub fn new(image_size: (i32, i32)) -> Self {
+        DetectionInput {
+            image_size,
+            padding: PaddingMode::TopLeft,
+            pad_color: Scalar::all(0.0),
+            tiling: DetectionTilingConfig::default(),
+            nms: NmsConfig::default(),
+            normalization: NormalizationConfig::default(),
+        }
+    }
+
+    /// The input of `models.face_detection`, whichever detector it configures.
+    pub fn from_config(face_detection_cfg: &FaceDetectionConfig) -> Self {
+        let pad_color = face_detection_cfg.pad_color;
+        DetectionInput {
+            image_size: face_detection_cfg.image_size,
+            padding: face_detection_cfg.padding,
+            pad_color: Scalar::new(pad_color[0], pad_color[1], pad_color[2], 0.0),
+            tiling: face_detection_cfg.tiling.clone(),
+            nms: face_detection_cfg.nms.clone(),
+            normalization: face_detection_cfg.normalization.clone(),
+        }
+    }
+
+    /// Resizes `img` to fit the detector input keeping its aspect ratio and pads the rest.
+    pub fn letterbox(&self, img: &Mat) -> Result<(Mat, Letterbox), Error> {
+
+        let img_shape = match img.size() {
+            Ok(img_shape) => img_shape,
+            Err(e) => return Err(Error::from(e))
+        };
+
+        let im_ratio = img_shape.height as f32 / img_shape.width as f32;
+        let model_ratio = self.image_size.1 as f32 / self.image_size.0 as f32;
+
+        let (new_width, new_height) = if im_ratio > model_ratio {
+            let new_height = self.image_size.1;
+            let new_width = (new_height as f32 / im_ratio) as i32;
+            (new_width, new_height)
+        } else {
+            let new_width = self.image_size.0;
+            let new_height = (new_width as f32 * im_ratio) as i32;
+            (new_width, new_height)
+        };
+        let new_width = new_width.max(1);
+        let new_height = new_height.max(1);
+
+        let det_scale = new_height as f32 / img_shape.height as f32;
+        let (pad_x, pad_y) = match self.padding {
+            PaddingMode::TopLeft => (0, 0),
+            PaddingMode::Center => ((self.image_size.0 - new_width) / 2, (self.image_size.1 - new_height) / 2),
+        };
+        let mut resized_img = Mat::default();
+
+        match resize(img, &mut resized_img, Size::new(new_width, new_height), 0.0, 0.0, INTER_LINEAR) {
+            Ok(_) => {},
+            Err(e) => return Err(Error::from(e))
+        }
+
+
+        let mut det_img = match Mat::new_rows_cols_with_default(self.image_size.1, self.image_size.0, core::CV_8UC3, self.pad_color) {
+            Ok(det_img) => det_img,
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+
+        let  mut roi = match Mat::roi_mut(&mut det_img, Rect::new(pad_x, pad_y, new_width, new_height)) {
+            Ok(roi) => roi,
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+
+        match  resized_img.copy_to(&mut roi) {
+            Ok(_) => {},
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+
+        drop(resized_img);
+
+        Ok((det_img, Letterbox {
+            scale: det_scale,
+            pad_x: pad_x as f32,
+            pad_y: pad_y as f32,
+        }))
+    }
+
+    /// Tiles of every configured size covering the image, empty when tiling does not apply.
+    pub fn tiles(&self, width: i32, height: i32) -> Vec<Rect> {
+        let mut tiles: Vec<Rect> = vec![];
+        if !self.tiling.enabled || width.max(height) <= self.tiling.min_image_size {
+            return tiles
+        }
+        for tile_size in self.tiling.tile_sizes.iter().copied() {
+            if tile_size >= width.max(height) {
+                continue
+            }
+            let stride = ((tile_size as f32 * (1.0 - self.tiling.overlap)) as i32).max(1);
+            let tile_width = tile_size.min(width);
+            let tile_height = tile_size.min(height);
+            for y in tile_origins(height, tile_height, stride) {
+                for x in tile_origins(width, tile_width, stride) {
+                    tiles.push(Rect::new(x, y, tile_width, tile_height));
+                }
+            }
+        }
+        tiles
+    }
+}
+
+/// Starts of `tile`-long windows `stride` apart, the last one flush with the end of `length`.
+fn tile_origins(length: i32, tile: i32, stride: i32) -> Vec<i32> {
+    let mut origins: Vec<i32> = (0..=(length - tile).max(0)).step_by(stride as usize).collect();
+    if origins.last().copied() != Some((length - tile).max(0)) {
+        origins.push(length - tile);
+    }
+    origins
+}
+
+/// A face detection model.
+///
+/// Implementations decode one letterboxed detector input, letterboxing, tiling and mapping the
+/// faces back to the image are shared by `detect`.
+#[async_trait]
+pub trait FaceDetector: Send + Sync + Debug {
+    fn input(&self) -> &DetectionInput;
+
+    fn iou_threshold(&self) -> f32;
+
+    /// Faces in one detector input, in input coordinates, already filtered and suppressed.
+    async fn detect_input(&self, input_img: Mat) -> Result<FaceDetections, Error>;
+
+    /// Faces in the whole image, in image coordinates, best score first.
+    async fn detect(&self, image: Mat) -> Result<FaceDetections, Error> {
+        let mut views: Vec<(Mat, (f32, f32))> = vec![];
+        for tile in self.input().tiles(image.cols(), image.rows()) {
+            let tile_img = match Mat::roi(&image, tile).and_then(|roi| roi.try_clone()) {
+                Ok(tile_img) => {tile_img}
+                Err(e) => return Err(Error::from(e))
+            };
+            views.push((tile_img, (tile.x as f32, tile.y as f32)));
+        }
+        let tiled = !views.is_empty();
+        views.insert(0, (image, (0.0, 0.0)));
+
+        let mut detections_list: Vec<FaceDetections> = Vec::with_capacity(views.len());
+        for (view, offset) in views {
+            let (input_img, letterbox) = match self.input().letterbox(&view) {
+                Ok((input_img, letterbox)) => {(input_img, letterbox)}
+                Err(e) => return Err(e)
+            };
+            drop(view);
+            let detections = match self.detect_input(input_img).await {
+                Ok(detections) => {detections}
+                Err(e) => return Err(e)
+            };
+            detections_list.push(detections.unmap(letterbox, offset));
+        }
+
+        if !tiled {
+            return Ok(detections_list.remove(0))
+        }
+        // A face seen by several views keeps its best scoring box
+        let detections = FaceDetections::concat(detections_list);
+        Ok(detections.suppress(self.iou_threshold(), &self.input().nms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array1, Array2, Array3};
+    use crate::pipeline::model_config::config::{DetectionTilingConfig, PaddingMode};
+    use crate::pipeline::module::face_detector::{DetectionInput, FaceDetections, Letterbox};
+    use crate::pipeline::processing::nms::{NmsConfig, NmsMethod};
+
+    #[test]
+    fn test_detections() {
+        let detections = FaceDetections {
+            boxes: Array2::from(vec![[0.0, 0.0, 10.0, 10.0], [1.0, 1.0, 11.0, 11.0], [50.0, 50.0, 60.0, 60.0]]),
+            scores: Array1::from(vec![0.8, 0.9, 0.3]),
+            landmarks: Some(Array3::from_elem((3, 5, 2), 5.0)),
+        };
+        let filtered = detections.filter(0.5, 0.45, &NmsConfig::default());
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered.scores[0], 0.9);
+        assert_eq!(filtered.rows().row(0).to_vec(), vec![1.0, 1.0, 11.0, 11.0, 0.9]);
+
+        let letterbox = Letterbox { scale: 0.5, pad_x: 0.0, pad_y: 80.0 };
+        let unmapped = filtered.unmap(letterbox, (100.0, 0.0));
+        assert_eq!(unmapped.boxes.row(0).to_vec(), vec![102.0, -158.0, 122.0, -138.0]);
+        assert_eq!(unmapped.landmarks.as_ref().unwrap()[[0, 0, 0]], 110.0);
+        assert_eq!(unmapped.landmarks.as_ref().unwrap()[[0, 0, 1]], -150.0);
+
+        let concat = FaceDetections::concat(vec![unmapped, FaceDetections::empty(false)]);
+        assert_eq!(concat.len(), 1);
+        assert!(concat.landmarks.is_none());
+
+        // A wide Gaussian Soft-NMS keeps the overlapping face while its decayed score stays confident
+        let gaussian = NmsConfig { method: NmsMethod::Gaussian, sigma: 2.0, ..Default::default() };
+        let filtered = detections.filter(0.5, 0.45, &gaussian);
+        assert_eq!(filtered.len(), 2);
+        assert_eq!(filtered.scores[0], 0.9);
+        assert!(filtered.scores[1] < 0.8);
+    }
+
+    #[test]
+    fn test_tiles() {
+        let mut input = DetectionInput::new((640, 640));
+        input.padding = PaddingMode::Center;
+        assert!(input.tiles(1600, 1200).is_empty());
+
+        input.tiling = DetectionTilingConfig {
+            enabled: true,
+            tile_sizes: vec![640, 2000],
+            overlap: 0.2,
+            min_image_size: 1280,
+        };
+        let tiles = input.tiles(1600, 1200);
+        assert_eq!(tiles.len(), 9);
+        assert_eq!((tiles[2].x, tiles[2].y), (960, 0));
+        assert_eq!((tiles[8].x, tiles[8].y), (960, 560));
+        assert!(input.tiles(1280, 720).is_empty());
+    }
+}
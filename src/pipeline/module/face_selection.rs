@@ -1,5 +1,5 @@
 use anyhow::Error;
-use ndarray::{Array1, Array2, Array3};
+use ndarray::{Array1, Array2, Array3, ArrayView1};
 use opencv::core::{Mat, MatTraitConst};
 
 #[derive(Debug, Clone)]
@@ -69,6 +69,23 @@ impl FaceSelection {
         Ok(face_width / image_width > 0.25)
     }
 
+    /// Whether the centre of `face_box` is at least the edge margin, capped at 50 pixels, away from every image side.
+    pub fn is_away_from_edges(&self, image_width: f32, image_height: f32, face_box: ArrayView1<f32>) -> bool {
+        let margin_edge = f32::min(50.0, self.margin_edge_ratio * image_width);
+        let box_center_width = (face_box[0] + face_box[2]) / 2.0;
+        let box_center_height = (face_box[1] + face_box[3]) / 2.0;
+        (box_center_width >= margin_edge)
+            && (box_center_width <= image_width - margin_edge)
+            && (box_center_height >= margin_edge)
+            && (box_center_height <= image_height - margin_edge)
+    }
+
+    /// Whether the horizontal centre of `face_box` is within the left and right centre margins of the image centre.
+    pub fn is_centered(&self, image_width: f32, face_box: ArrayView1<f32>) -> bool {
+        let offset = (face_box[0] + face_box[2]) / 2.0 - image_width / 2.0;
+        -self.margin_center_left_ratio * image_width <= offset && offset <= self.margin_center_right_ratio * image_width
+    }
+
     pub fn call(&self, img: &Mat, face_boxes: Array2<f32>, key_points: Option<Array3<f32>>, is_enroll: Option<bool>) -> Result<(Option<Array1<f32>>, Option<Array2<f32>>), Error> {
 
         let enroll = is_enroll.unwrap_or(false);
@@ -96,28 +113,16 @@ impl FaceSelection {
             return Ok((biggest_bbox, biggest_key_point));
         }
 
-        let margin_center_left = self.margin_center_left_ratio * img_shape.width as f32;
-        let margin_center_right = self.margin_center_right_ratio * img_shape.width as f32;
-        let mut margin_edge = self.margin_edge_ratio * img_shape.width as f32;
-        margin_edge = f32::min(50.0, margin_edge);
-        let y_cen = img_shape.height as f32 / 2.0;
-        let x_cen = img_shape.width as f32 / 2.0;
+        let (image_width, image_height) = (img_shape.width as f32, img_shape.height as f32);
         let mut valid_boxes: Vec<Vec<f32>> = Vec::with_capacity(1); //Vec::new();
 
 
         for detection in face_boxes.outer_iter() {
             let x_min = detection[0];
-            let y_min = detection[1];
             let x_max = detection[2];
-            let y_max = detection[3];
             let area = (x_max - x_min) * (x_max - x_min);
-            let box_center_width = (x_min + x_max) / 2.0;
-            let box_center_height = (y_min + y_max) / 2.0;
-            if (box_center_width >= margin_edge)
-                && (box_center_width <= img_shape.width as f32 - margin_edge)
-                && (box_center_height >= margin_edge)
-                && (box_center_height <= img_shape.height as f32 - margin_edge)
-                && (area / (img_shape.height as f32 * img_shape.width as f32) >= self.minimum_face_ratio)
+            if self.is_away_from_edges(image_width, image_height, detection)
+                && (area / (image_height * image_width) >= self.minimum_face_ratio)
             {
                 valid_boxes.push(detection.to_vec());
             }
@@ -125,8 +130,7 @@ impl FaceSelection {
 
         let mut center_boxes: Vec<Vec<f32>> = Vec::with_capacity(1); //Vec::new();
         for result in valid_boxes.iter() {
-            let box_center_width = (result[0] + result[2]) / 2.0;
-            if -margin_center_left <= box_center_width - x_cen && box_center_width - x_cen <= margin_center_right {
+            if self.is_centered(image_width, ArrayView1::from(result.as_slice())) {
                 center_boxes.push(result.clone());
             }
         }
@@ -0,0 +1,161 @@
+use anyhow::Error;
+use ndarray::Array2;
+use opencv::calib3d::{rodrigues_def, solve_pnp, SOLVEPNP_EPNP, SOLVEPNP_ITERATIVE};
+use opencv::core::{Mat, MatTraitConst};
+use serde::{Deserialize, Serialize};
+use crate::pipeline::utils::utils::array2_to_mat;
+
+/// Reference face in millimetres, origin at the nose tip, x to the image right, y down and z away from the camera.
+///
+/// The points follow the detector landmark order, left eye, right eye, nose, left and right mouth corners,
+/// and are spaced like the ArcFace alignment template.
+const REFERENCE_FACE: [[f32; 3]; 5] = [
+    [-32.0, -36.5, 30.0],
+    [32.0, -36.5, 30.0],
+    [0.0, 0.0, 0.0],
+    [-26.5, 37.5, 25.0],
+    [26.5, 37.5, 25.0],
+];
+
+/// Head rotation in degrees, all zero for a face looking straight at the camera.
+///
+/// `yaw` is positive when the nose turns to the image left, `pitch` when it tips down and `roll`
+/// when the face turns clockwise in the image.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HeadPose {
+    pub yaw: f32,
+    pub pitch: f32,
+    pub roll: f32,
+}
+
+impl HeadPose {
+    /// Whether every angle is within its limit, in degrees either way.
+    pub fn is_within(&self, max_yaw: f32, max_pitch: f32, max_roll: f32) -> bool {
+        self.yaw.abs() <= max_yaw && self.pitch.abs() <= max_pitch && self.roll.abs() <= max_roll
+    }
+}
+
+/// Estimates the head pose from the five detector landmarks with `solvePnP`.
+///
+/// The camera is a pinhole with the focal length of the image width centred on the image, which is
+/// close enough for the frontal check enrollment needs without calibration.
+#[derive(Debug, Clone)]
+pub(crate) struct HeadPoseEstimation {
+    reference_face: Array2<f32>,
+}
+
+impl HeadPoseEstimation {
+    pub fn new() -> Self {
+        HeadPoseEstimation {
+            reference_face: Array2::from(REFERENCE_FACE.to_vec()),
+        }
+    }
+
+    pub fn call(&self, img: &Mat, landmarks: &Array2<f32>) -> Result<HeadPose, Error> {
+        if landmarks.dim() != (5, 2) {
+            return Err(Error::msg(format!("head_pose - expected 5 landmarks, got {:?}", landmarks.dim())))
+        }
+        let object_points = match array2_to_mat(&self.reference_face) {
+            Ok(object_points) => {object_points}
+            Err(e) => return Err(Error::from(e))
+        };
+        let image_points = match array2_to_mat(landmarks) {
+            Ok(image_points) => {image_points}
+            Err(e) => return Err(Error::from(e))
+        };
+
+        let img_shape = match img.size() {
+            Ok(img_shape) => img_shape,
+            Err(e) => return Err(Error::from(e))
+        };
+        let focal_length = img_shape.width as f64;
+        let camera_matrix = match Mat::from_slice_2d(&[
+            [focal_length, 0.0, img_shape.width as f64 / 2.0],
+            [0.0, focal_length, img_shape.height as f64 / 2.0],
+            [0.0, 0.0, 1.0],
+        ]) {
+            Ok(camera_matrix) => {camera_matrix}
+            Err(e) => return Err(Error::from(e))
+        };
+        let dist_coeffs = Mat::default();
+
+        // EPnP works from the five points alone, the iterative solver then refines its estimate
+        let mut rvec = Mat::default();
+        let mut tvec = Mat::default();
+        for (use_extrinsic_guess, flags) in [(false, SOLVEPNP_EPNP), (true, SOLVEPNP_ITERATIVE)] {
+            match solve_pnp(&object_points, &image_points, &camera_matrix, &dist_coeffs, &mut rvec, &mut tvec, use_extrinsic_guess, flags) {
+                Ok(true) => {}
+                Ok(false) => return Err(Error::msg("head_pose - solvePnP found no pose for the landmarks")),
+                Err(e) => return Err(Error::from(e))
+            };
+        }
+
+        let mut rotation = Mat::default();
+        match rodrigues_def(&rvec, &mut rotation) {
+            Ok(_) => {}
+            Err(e) => return Err(Error::from(e))
+        };
+        let mut r = [[0.0f64; 3]; 3];
+        for (row, values) in r.iter_mut().enumerate() {
+            for (col, value) in values.iter_mut().enumerate() {
+                *value = match rotation.at_2d::<f64>(row as i32, col as i32) {
+                    Ok(value) => {*value}
+                    Err(e) => return Err(Error::from(e))
+                };
+            }
+        }
+        Ok(rotation_to_head_pose(&r))
+    }
+}
+
+/// Euler angles of `rotation = Rz(roll) * Ry(yaw) * Rx(pitch)`.
+fn rotation_to_head_pose(r: &[[f64; 3]; 3]) -> HeadPose {
+    let sy = (r[0][0] * r[0][0] + r[1][0] * r[1][0]).sqrt();
+    let (pitch, yaw, roll) = if sy > 1e-6 {
+        (r[2][1].atan2(r[2][2]), (-r[2][0]).atan2(sy), r[1][0].atan2(r[0][0]))
+    } else {
+        // Looking straight up or down, roll cannot be told apart from pitch
+        ((-r[1][2]).atan2(r[1][1]), (-r[2][0]).atan2(sy), 0.0)
+    };
+    HeadPose {
+        yaw: yaw.to_degrees() as f32,
+        pitch: pitch.to_degrees() as f32,
+        roll: roll.to_degrees() as f32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array2;
+    use opencv::core::{CV_8UC3, Mat, Scalar};
+    use crate::pipeline::module::head_pose::{HeadPoseEstimation, REFERENCE_FACE};
+
+    /// Landmarks of the reference face turned by `yaw` degrees, 600 mm in front of a 640x480 camera.
+    fn project(yaw: f32) -> Array2<f32> {
+        let (sin, cos) = yaw.to_radians().sin_cos();
+        let mut landmarks = Array2::zeros((5, 2));
+        for (idx, point) in REFERENCE_FACE.iter().enumerate() {
+            let x = cos * point[0] + sin * point[2];
+            let z = -sin * point[0] + cos * point[2] + 600.0;
+            landmarks[[idx, 0]] = 640.0 * x / z + 320.0;
+            landmarks[[idx, 1]] = 640.0 * point[1] / z + 240.0;
+        }
+        landmarks
+    }
+
+    #[test]
+    fn test_head_pose() {
+        let img = Mat::new_rows_cols_with_default(480, 640, CV_8UC3, Scalar::all(0.0)).unwrap();
+        let head_pose_estimation = HeadPoseEstimation::new();
+
+        let frontal = head_pose_estimation.call(&img, &project(0.0)).unwrap();
+        assert!(frontal.yaw.abs() < 1.0 && frontal.pitch.abs() < 1.0 && frontal.roll.abs() < 1.0, "{frontal:?}");
+        assert!(frontal.is_within(30.0, 25.0, 25.0));
+
+        let turned = head_pose_estimation.call(&img, &project(40.0)).unwrap();
+        assert!((turned.yaw - 40.0).abs() < 2.0, "{turned:?}");
+        assert!(!turned.is_within(30.0, 25.0, 25.0));
+
+        assert!(head_pose_estimation.call(&img, &Array2::zeros((4, 2))).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use anyhow::Error;
+use ndarray::{Array1, Array2};
+use opencv::core::{CV_64F, Mat, MatTraitConst, mean_std_dev_def, Rect};
+use opencv::imgproc::{COLOR_BGR2GRAY, cvt_color, laplacian_def};
+use serde::{Deserialize, Serialize};
+use crate::pipeline::model_config::config::ImageQualityConfig;
+
+/// Side of the JPEG coding blocks.
+const JPEG_BLOCK: usize = 8;
+
+/// Interpretable measurements of one face crop.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ImageQualityMetrics {
+    /// Variance of the Laplacian, low for blurry crops
+    pub sharpness: f32,
+    /// Mean grey level in `[0, 255]`
+    pub brightness: f32,
+    /// Standard deviation of the grey levels
+    pub contrast: f32,
+    /// Fraction of pixels at or above the overexposed level
+    pub overexposed_ratio: f32,
+    /// Fraction of pixels at or below the underexposed level
+    pub underexposed_ratio: f32,
+    /// Distance between the eye landmarks in image pixels, `None` without landmarks
+    pub inter_eye_distance: Option<f32>,
+    /// Mean gradient across the 8x8 JPEG block edges over the mean gradient inside the blocks, about 1 for clean images
+    pub blockiness: f32,
+}
+
+/// A metric outside its configured limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageQualityIssue {
+    TooBlurry,
+    TooDark,
+    TooBright,
+    LowContrast,
+    Overexposed,
+    Underexposed,
+    EyesTooClose,
+    TooCompressed,
+}
+
+impl ImageQualityIssue {
+    /// Wording support staff can pass on to the user.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ImageQualityIssue::TooBlurry => "too blurry",
+            ImageQualityIssue::TooDark => "too dark",
+            ImageQualityIssue::TooBright => "too bright",
+            ImageQualityIssue::LowContrast => "contrast too low",
+            ImageQualityIssue::Overexposed => "overexposed",
+            ImageQualityIssue::Underexposed => "underexposed",
+            ImageQualityIssue::EyesTooClose => "face too small",
+            ImageQualityIssue::TooCompressed => "too compressed",
+        }
+    }
+}
+
+/// Metrics of one face and the limits they break, `passed` when there are none.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageQualityReport {
+    pub metrics: ImageQualityMetrics,
+    pub issues: Vec<ImageQualityIssue>,
+    pub passed: bool,
+}
+
+/// Classical quality metrics on the face crop of the original image, next to the learned quality models.
+#[derive(Debug, Clone)]
+pub(crate) struct ImageQuality {
+    config: ImageQualityConfig,
+}
+
+impl ImageQuality {
+    pub fn new(config: ImageQualityConfig) -> Self {
+        ImageQuality {
+            config,
+        }
+    }
+
+    /// Report of the face in `bbox`, `None` when too little of it lies in the image to be measured.
+    pub fn call(&self, img: &Mat, bbox: &Array1<f32>, landmarks: Option<&Array2<f32>>) -> Result<Option<ImageQualityReport>, Error> {
+        let crop = match self.face_crop(img, bbox) {
+            Ok(Some(crop)) => {crop}
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(e)
+        };
+        let mut gray = Mat::default();
+        match cvt_color(&crop, &mut gray, COLOR_BGR2GRAY, 0) {
+            Ok(_) => {}
+            Err(e) => return Err(Error::from(e))
+        };
+        drop(crop);
+
+        let mut laplacian = Mat::default();
+        match laplacian_def(&gray, &mut laplacian, CV_64F) {
+            Ok(_) => {}
+            Err(e) => return Err(Error::from(e))
+        };
+        let (_, sharpness_std) = match mean_and_std(&laplacian) {
+            Ok(stats) => {stats}
+            Err(e) => return Err(e)
+        };
+        let (brightness, contrast) = match mean_and_std(&gray) {
+            Ok(stats) => {stats}
+            Err(e) => return Err(e)
+        };
+
+        let pixels = match gray.data_bytes() {
+            Ok(pixels) => {pixels}
+            Err(e) => return Err(Error::from(e))
+        };
+        let num_pixels = pixels.len().max(1) as f32;
+        let overexposed = pixels.iter().filter(|pixel| **pixel >= self.config.overexposed_level).count();
+        let underexposed = pixels.iter().filter(|pixel| **pixel <= self.config.underexposed_level).count();
+
+        let inter_eye_distance = landmarks.map(|points| {
+            (points[[1, 0]] - points[[0, 0]]).hypot(points[[1, 1]] - points[[0, 1]])
+        });
+
+        let metrics = ImageQualityMetrics {
+            sharpness: (sharpness_std * sharpness_std) as f32,
+            brightness: brightness as f32,
+            contrast: contrast as f32,
+            overexposed_ratio: overexposed as f32 / num_pixels,
+            underexposed_ratio: underexposed as f32 / num_pixels,
+            inter_eye_distance,
+            blockiness: blockiness(pixels, gray.cols() as usize),
+        };
+        Ok(Some(self.report(metrics)))
+    }
+
+    /// Checks `metrics` against the configured limits.
+    pub fn report(&self, metrics: ImageQualityMetrics) -> ImageQualityReport {
+        let config = &self.config;
+        let mut issues: Vec<ImageQualityIssue> = vec![];
+        if metrics.sharpness < config.min_sharpness {
+            issues.push(ImageQualityIssue::TooBlurry);
+        }
+        if metrics.brightness < config.min_brightness {
+            issues.push(ImageQualityIssue::TooDark);
+        }
+        if metrics.brightness > config.max_brightness {
+            issues.push(ImageQualityIssue::TooBright);
+        }
+        if metrics.contrast < config.min_contrast {
+            issues.push(ImageQualityIssue::LowContrast);
+        }
+        if metrics.overexposed_ratio > config.max_overexposed_ratio {
+            issues.push(ImageQualityIssue::Overexposed);
+        }
+        if metrics.underexposed_ratio > config.max_underexposed_ratio {
+            issues.push(ImageQualityIssue::Underexposed);
+        }
+        if metrics.inter_eye_distance.is_some_and(|distance| distance < config.min_inter_eye_distance) {
+            issues.push(ImageQualityIssue::EyesTooClose);
+        }
+        if metrics.blockiness > config.max_blockiness {
+            issues.push(ImageQualityIssue::TooCompressed);
+        }
+        ImageQualityReport {
+            metrics,
+            passed: issues.is_empty(),
+            issues,
+        }
+    }
+
+    /// The face box clipped to the image, its origin moved back onto the JPEG block grid. `None` when
+    /// fewer than 3 pixels of it are inside the image on either side.
+    fn face_crop(&self, img: &Mat, bbox: &Array1<f32>) -> Result<Option<Mat>, Error> {
+        let block = JPEG_BLOCK as i32;
+        let x_min = ((bbox[0].max(0.0) as i32) / block) * block;
+        let y_min = ((bbox[1].max(0.0) as i32) / block) * block;
+        let x_max = (bbox[2].ceil() as i32).min(img.cols());
+        let y_max = (bbox[3].ceil() as i32).min(img.rows());
+        if x_max - x_min < 3 || y_max - y_min < 3 {
+            return Ok(None)
+        }
+        match Mat::roi(img, Rect::new(x_min, y_min, x_max - x_min, y_max - y_min)).and_then(|roi| roi.try_clone()) {
+            Ok(crop) => Ok(Some(crop)),
+            Err(e) => Err(Error::from(e))
+        }
+    }
+}
+
+fn mean_and_std(img: &Mat) -> Result<(f64, f64), Error> {
+    let mut mean = Mat::default();
+    let mut std_dev = Mat::default();
+    match mean_std_dev_def(img, &mut mean, &mut std_dev) {
+        Ok(_) => {}
+        Err(e) => return Err(Error::from(e))
+    };
+    match (mean.at::<f64>(0), std_dev.at::<f64>(0)) {
+        (Ok(mean), Ok(std_dev)) => Ok((*mean, *std_dev)),
+        (Err(e), _) | (_, Err(e)) => Err(Error::from(e)),
+    }
+}
+
+/// Ratio of the mean absolute horizontal and vertical steps across block edges to the steps inside blocks.
+fn blockiness(pixels: &[u8], width: usize) -> f32 {
+    if width == 0 {
+        return 1.0
+    }
+    let height = pixels.len() / width;
+    let pixel = |x: usize, y: usize| pixels[y * width + x] as f32;
+    let (mut edge_sum, mut edge_count, mut inner_sum, mut inner_count) = (0.0f32, 0usize, 0.0f32, 0usize);
+    let mut add = |step: f32, is_edge: bool| {
+        if is_edge {
+            edge_sum += step;
+            edge_count += 1;
+        } else {
+            inner_sum += step;
+            inner_count += 1;
+        }
+    };
+    for y in 0..height {
+        for x in 0..width - 1 {
+            add((pixel(x + 1, y) - pixel(x, y)).abs(), (x + 1) % JPEG_BLOCK == 0);
+        }
+    }
+    for y in 0..height.saturating_sub(1) {
+        for x in 0..width {
+            add((pixel(x, y + 1) - pixel(x, y)).abs(), (y + 1) % JPEG_BLOCK == 0);
+        }
+    }
+    let edge_mean = edge_sum / edge_count.max(1) as f32;
+    let inner_mean = inner_sum / inner_count.max(1) as f32;
+    // One grey level of slack keeps flat crops at 1
+    (edge_mean + 1.0) / (inner_mean + 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+    use opencv::core::{CV_8UC3, Mat, MatTrait, Scalar, Vec3b};
+    use crate::pipeline::model_config::config::ImageQualityConfig;
+    use crate::pipeline::module::image_quality::{ImageQuality, ImageQualityIssue};
+
+    #[test]
+    fn test_image_quality() {
+        let image_quality = ImageQuality::new(ImageQualityConfig::default());
+        let bbox = array![4.0, 4.0, 196.0, 196.0, 0.9];
+        let landmarks = array![[70.0, 80.0], [130.0, 80.0], [100.0, 110.0], [75.0, 140.0], [125.0, 140.0]];
+
+        // A fine checkerboard is sharp and has no block structure
+        let mut sharp = Mat::new_rows_cols_with_default(200, 200, CV_8UC3, Scalar::all(0.0)).unwrap();
+        for y in 0..200 {
+            for x in 0..200 {
+                let level = if (x / 3 + y / 3) % 2 == 0 { 60 } else { 190 };
+                *sharp.at_2d_mut::<Vec3b>(y, x).unwrap() = Vec3b::from([level, level, level]);
+            }
+        }
+        let report = image_quality.call(&sharp, &bbox, Some(&landmarks)).unwrap().unwrap();
+        assert!(report.passed, "{report:?}");
+        assert_eq!(report.metrics.inter_eye_distance, Some(60.0));
+        assert!((report.metrics.brightness - 125.0).abs() < 5.0);
+        assert!(report.metrics.blockiness < 1.5);
+
+        // A flat dark crop is blurry, dark and without contrast
+        let dark = Mat::new_rows_cols_with_default(200, 200, CV_8UC3, Scalar::all(20.0)).unwrap();
+        let report = image_quality.call(&dark, &bbox, None).unwrap().unwrap();
+        assert!(!report.passed);
+        for issue in [ImageQualityIssue::TooBlurry, ImageQualityIssue::TooDark, ImageQualityIssue::LowContrast] {
+            assert!(report.issues.contains(&issue), "{issue:?} missing from {:?}", report.issues);
+        }
+        assert_eq!(ImageQualityIssue::TooDark.reason(), "too dark");
+
+        // Flat 8x8 blocks of different levels only step at the block edges
+        let mut blocky = Mat::new_rows_cols_with_default(200, 200, CV_8UC3, Scalar::all(0.0)).unwrap();
+        for y in 0..200 {
+            for x in 0..200 {
+                let level = (((x / 8) * 37 + (y / 8) * 91) % 200 + 30) as u8;
+                *blocky.at_2d_mut::<Vec3b>(y, x).unwrap() = Vec3b::from([level, level, level]);
+            }
+        }
+        let report = image_quality.call(&blocky, &bbox, None).unwrap().unwrap();
+        assert!(report.issues.contains(&ImageQualityIssue::TooCompressed), "{report:?}");
+
+        // Faces outside the image or too small are not measurable, a face partly outside is measured on what is left
+        assert_eq!(image_quality.call(&dark, &array![300.0, 300.0, 310.0, 310.0, 0.9], None).unwrap(), None);
+        assert_eq!(image_quality.call(&dark, &array![56.0, 56.0, 57.0, 57.0, 0.9], None).unwrap(), None);
+        assert!(image_quality.call(&dark, &array![-20.0, 150.0, 60.0, 260.0, 0.9], None).unwrap().is_some());
+    }
+}
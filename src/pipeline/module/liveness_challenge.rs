@@ -0,0 +1,252 @@
+use anyhow::Error;
+use ndarray::Array2;
+use opencv::core::Mat;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::pipeline::model_config::config::{FaceAntiSpoofingClass, LivenessChallengeConfig, LivenessConfig};
+use crate::pipeline::module::temporal_liveness::{blink_detected, track_face, TrackedFace};
+use crate::pipeline::stage_pipeline::pipeline_stage::FaceContext;
+
+/// Action the user is asked to perform in front of the camera.
+///
+/// Left and right are the user's own, so in an unmirrored camera image turning left moves the nose
+/// to the image right.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LivenessChallenge {
+    TurnHeadLeft,
+    TurnHeadRight,
+    Blink,
+    Smile,
+}
+
+const CHALLENGES: [LivenessChallenge; 4] = [
+    LivenessChallenge::TurnHeadLeft,
+    LivenessChallenge::TurnHeadRight,
+    LivenessChallenge::Blink,
+    LivenessChallenge::Smile,
+];
+
+impl LivenessChallenge {
+    /// Draws a challenge from the random bits of a v4 UUID.
+    pub fn random() -> Self {
+        let index = Uuid::new_v4().as_u128() % CHALLENGES.len() as u128;
+        CHALLENGES[index as usize]
+    }
+
+    /// Instruction the client shows to the user.
+    pub fn instruction(&self) -> &'static str {
+        match self {
+            LivenessChallenge::TurnHeadLeft => "turn your head to the left",
+            LivenessChallenge::TurnHeadRight => "turn your head to the right",
+            LivenessChallenge::Blink => "blink",
+            LivenessChallenge::Smile => "smile",
+        }
+    }
+}
+
+/// Outcome of a challenge, passed only when a live face was followed through every frame and performed it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LivenessChallengeReport {
+    pub challenge: LivenessChallenge,
+    pub passed: bool,
+    pub challenge_completed: bool,
+    /// Strength of the action, the yaw change in degrees of a head turn, the mouth widening of a
+    /// smile or the lowest eye openness over the median for a blink. `None` when it could not be measured
+    pub response: Option<f32>,
+    pub frame_count: usize,
+    /// Frames in which the followed face was found
+    pub tracked_frames: usize,
+    /// Whether every frame with an anti-spoofing result was classified real
+    pub spoofing_passed: bool,
+    /// Mean anti-spoofing live score of the followed face, `None` when no frame was scored
+    pub anti_spoofing_score: Option<f32>,
+}
+
+/// Checks a challenge on the landmarks and head poses of the face followed across the frames, next
+/// to the anti-spoofing result of each frame.
+#[derive(Debug, Clone)]
+pub(crate) struct LivenessChallengeCheck {
+    config: LivenessChallengeConfig,
+    min_track_iou: f32,
+    blink_ratio: f32,
+}
+
+impl LivenessChallengeCheck {
+    pub fn new(config: LivenessChallengeConfig, liveness_config: &LivenessConfig) -> Self {
+        LivenessChallengeCheck {
+            config,
+            min_track_iou: liveness_config.min_track_iou,
+            blink_ratio: liveness_config.blink_ratio,
+        }
+    }
+
+    pub fn max_frames(&self) -> usize {
+        self.config.max_frames
+    }
+
+    /// `frames` holds each image with the faces the pipeline found in it, in capture order.
+    pub fn call(&self, challenge: LivenessChallenge, frames: &[(Mat, Vec<FaceContext>)]) -> Result<LivenessChallengeReport, Error> {
+        let track = match track_face(frames, self.min_track_iou) {
+            Ok(track) => {track}
+            Err(e) => return Err(e)
+        };
+        let tracked: Vec<&TrackedFace> = track.iter().flatten().collect();
+
+        let (challenge_completed, response) = match challenge {
+            // A turn to the user's right moves the nose to the image left, a positive yaw
+            LivenessChallenge::TurnHeadLeft => self.head_turn(&tracked, -1.0),
+            LivenessChallenge::TurnHeadRight => self.head_turn(&tracked, 1.0),
+            LivenessChallenge::Blink => self.blink(&tracked),
+            LivenessChallenge::Smile => self.smile(&tracked),
+        };
+
+        let spoofing_checks: Vec<&FaceAntiSpoofingClass> = tracked.iter().filter_map(|face| face.spoofing_check.as_ref()).collect();
+        let spoofing_passed = !spoofing_checks.is_empty() && spoofing_checks.iter().all(|class| **class == FaceAntiSpoofingClass::Real);
+        let spoofing_scores: Vec<f32> = tracked.iter().filter_map(|face| face.spoofing_score).collect();
+        let anti_spoofing_score = if spoofing_scores.is_empty() {
+            None
+        } else {
+            Some(spoofing_scores.iter().sum::<f32>() / spoofing_scores.len() as f32)
+        };
+
+        // Losing the face mid-challenge could hide a swap to another face
+        let fully_tracked = tracked.len() == track.len() && tracked.len() >= self.config.min_frames;
+        Ok(LivenessChallengeReport {
+            challenge,
+            passed: fully_tracked && challenge_completed && spoofing_passed,
+            challenge_completed,
+            response,
+            frame_count: track.len(),
+            tracked_frames: tracked.len(),
+            spoofing_passed,
+            anti_spoofing_score,
+        })
+    }
+
+    /// Largest yaw change from a frontal first frame, signed by `direction`.
+    fn head_turn(&self, tracked: &[&TrackedFace], direction: f32) -> (bool, Option<f32>) {
+        let yaws: Vec<f32> = tracked.iter().filter_map(|face| face.head_pose.map(|pose| pose.yaw)).collect();
+        let start = match yaws.first() {
+            None => return (false, None),
+            Some(start) => *start,
+        };
+        let turn = yaws.iter().map(|yaw| (yaw - start) * direction).fold(0.0f32, f32::max);
+        (start.abs() <= self.config.max_start_angle && turn >= self.config.min_turn_angle, Some(turn))
+    }
+
+    fn blink(&self, tracked: &[&TrackedFace]) -> (bool, Option<f32>) {
+        let openness: Vec<f32> = tracked.iter().filter_map(|face| face.eye_openness).collect();
+        if openness.is_empty() {
+            return (false, None)
+        }
+        let mut sorted = openness.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let median = sorted[sorted.len() / 2];
+        let response = if median > 0.0 { Some(sorted[0] / median) } else { None };
+        (blink_detected(&openness, self.blink_ratio), response)
+    }
+
+    /// Largest mouth width over eye distance against the first frame, so that moving closer to the
+    /// camera does not count as a smile.
+    fn smile(&self, tracked: &[&TrackedFace]) -> (bool, Option<f32>) {
+        let ratios: Vec<f32> = tracked.iter().filter_map(|face| face.landmarks.as_ref().and_then(mouth_ratio)).collect();
+        let start = match ratios.first() {
+            None => return (false, None),
+            Some(start) => *start,
+        };
+        // Mouth corners that coincide in the first frame give no width to compare against
+        if start <= f32::EPSILON {
+            return (false, None)
+        }
+        let widening = ratios.iter().fold(0.0f32, |widest, ratio| widest.max(*ratio)) / start;
+        (widening >= self.config.min_smile_widening, Some(widening))
+    }
+}
+
+/// Distance between the mouth corners over the distance between the eyes.
+fn mouth_ratio(landmarks: &Array2<f32>) -> Option<f32> {
+    let eye_distance = (landmarks[[1, 0]] - landmarks[[0, 0]]).hypot(landmarks[[1, 1]] - landmarks[[0, 1]]);
+    if eye_distance <= f32::EPSILON {
+        return None
+    }
+    let mouth_width = (landmarks[[4, 0]] - landmarks[[3, 0]]).hypot(landmarks[[4, 1]] - landmarks[[3, 1]]);
+    Some(mouth_width / eye_distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array1};
+    use opencv::core::{CV_8UC3, Mat, Point, Scalar};
+    use opencv::imgproc::{circle, LINE_8};
+    use crate::pipeline::model_config::config::{FaceAntiSpoofingClass, LivenessChallengeConfig, LivenessConfig};
+    use crate::pipeline::module::head_pose::HeadPose;
+    use crate::pipeline::module::liveness_challenge::{LivenessChallenge, LivenessChallengeCheck};
+    use crate::pipeline::stage_pipeline::pipeline_stage::FaceContext;
+
+    /// A face with dark irises unless `closed`, its mouth corners `mouth_width` apart.
+    fn frame(yaw: f32, closed: bool, mouth_width: f32, spoofing_check: FaceAntiSpoofingClass) -> (Mat, Vec<FaceContext>) {
+        let mut image = Mat::new_rows_cols_with_default(480, 640, CV_8UC3, Scalar::all(170.0)).unwrap();
+        let landmarks = array![
+            [280.0, 220.0], [360.0, 220.0], [320.0, 260.0], [320.0 - mouth_width / 2.0, 300.0], [320.0 + mouth_width / 2.0, 300.0]
+        ];
+        if !closed {
+            for eye in 0..2 {
+                let center = Point::new(landmarks[[eye, 0]] as i32, landmarks[[eye, 1]] as i32);
+                circle(&mut image, center, 8, Scalar::all(30.0), -1, LINE_8, 0).unwrap();
+            }
+        }
+        let mut face = FaceContext::new(Array1::from(vec![240.0, 160.0, 400.0, 360.0, 0.99]), Some(landmarks));
+        face.head_pose = Some(HeadPose { yaw, pitch: 0.0, roll: 0.0 });
+        face.spoofing_score = Some(if spoofing_check == FaceAntiSpoofingClass::Real { 0.9 } else { 0.2 });
+        face.spoofing_check = Some(spoofing_check);
+        (image, vec![face])
+    }
+
+    #[test]
+    fn test_liveness_challenge() {
+        let check = LivenessChallengeCheck::new(LivenessChallengeConfig::default(), &LivenessConfig::default());
+        let real = FaceAntiSpoofingClass::Real;
+
+        // Turning left drives the yaw negative
+        let turn: Vec<_> = [0.0, -10.0, -25.0, -5.0].into_iter().map(|yaw| frame(yaw, false, 60.0, real.clone())).collect();
+        let report = check.call(LivenessChallenge::TurnHeadLeft, &turn).unwrap();
+        assert!(report.passed, "{report:?}");
+        assert_eq!(report.response, Some(25.0));
+        assert!((report.anti_spoofing_score.unwrap() - 0.9).abs() < 1e-6);
+        let report = check.call(LivenessChallenge::TurnHeadRight, &turn).unwrap();
+        assert!(!report.challenge_completed);
+        assert!(!report.passed);
+
+        let blink: Vec<_> = [false, false, true, false].into_iter().map(|closed| frame(0.0, closed, 60.0, real.clone())).collect();
+        let report = check.call(LivenessChallenge::Blink, &blink).unwrap();
+        assert!(report.passed, "{report:?}");
+        assert!(report.response.unwrap() < 0.7);
+        assert!(!check.call(LivenessChallenge::Smile, &blink).unwrap().passed);
+
+        let smile: Vec<_> = [60.0, 64.0, 75.0].into_iter().map(|mouth_width| frame(0.0, false, mouth_width, real.clone())).collect();
+        let report = check.call(LivenessChallenge::Smile, &smile).unwrap();
+        assert!(report.passed, "{report:?}");
+        assert!((report.response.unwrap() - 1.25).abs() < 1e-5);
+
+        // A first frame without mouth width is not a smile rather than an infinite widening
+        let degenerate: Vec<_> = [0.0, 64.0, 75.0].into_iter().map(|mouth_width| frame(0.0, false, mouth_width, real.clone())).collect();
+        let report = check.call(LivenessChallenge::Smile, &degenerate).unwrap();
+        assert!(!report.challenge_completed);
+        assert!(!report.passed);
+        assert_eq!(report.response, None);
+
+        // A spoofed frame fails the challenge even when it was performed
+        let spoofed = vec![frame(0.0, false, 60.0, real.clone()), frame(0.0, false, 64.0, FaceAntiSpoofingClass::Fake), frame(0.0, false, 75.0, real.clone())];
+        let report = check.call(LivenessChallenge::Smile, &spoofed).unwrap();
+        assert!(report.challenge_completed);
+        assert!(!report.spoofing_passed);
+        assert!(!report.passed);
+
+        // Too few frames
+        let short = vec![frame(0.0, false, 60.0, real.clone()), frame(0.0, false, 75.0, real.clone())];
+        let report = check.call(LivenessChallenge::Smile, &short).unwrap();
+        assert!(report.challenge_completed);
+        assert!(!report.passed);
+    }
+}
@@ -1,7 +1,15 @@
 pub mod face_alignment;
 pub mod face_detection;
+pub mod face_detection_scrfd;
+pub mod face_detection_yolo;
+pub mod face_detector;
 pub mod face_selection;
 pub mod face_quality;
 pub mod face_extraction;
 pub mod face_antispoofing;
-pub mod face_quality_assessment;
\ No newline at end of file
+pub mod face_quality_assessment;
+pub mod head_pose;
+pub mod image_quality;
+pub mod portrait_compliance;
+pub mod temporal_liveness;
+pub mod liveness_challenge;
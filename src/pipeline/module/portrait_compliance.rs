@@ -0,0 +1,358 @@
+use anyhow::Error;
+use opencv::core::{Mat, MatTraitConst};
+use opencv::imgproc::{COLOR_BGR2GRAY, cvt_color};
+use serde::{Deserialize, Serialize};
+use crate::pipeline::model_config::config::{FaceQualityClass, FaceSelectionConfig, PortraitComplianceConfig};
+use crate::pipeline::module::face_selection::FaceSelection;
+use crate::pipeline::module::image_quality::ImageQualityIssue;
+use crate::pipeline::stage_pipeline::pipeline_stage::FaceContext;
+
+/// One ICAO / ISO 19794-5 style requirement on an ID portrait.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComplianceRule {
+    SingleFace,
+    Centered,
+    EyeLine,
+    HeadSize,
+    FrontalPose,
+    NoSunglasses,
+    NoMask,
+    UniformBackground,
+    Lighting,
+}
+
+/// Outcome of one rule, `value` is the measurement compared against the configured limits.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComplianceCheck {
+    pub rule: ComplianceRule,
+    pub passed: bool,
+    pub value: Option<f32>,
+    /// Why the rule failed, `None` when it passed
+    pub reason: Option<String>,
+}
+
+impl ComplianceCheck {
+    fn new(rule: ComplianceRule, passed: bool, value: Option<f32>, reason: impl FnOnce() -> String) -> Self {
+        ComplianceCheck {
+            rule,
+            passed,
+            value,
+            reason: if passed { None } else { Some(reason()) },
+        }
+    }
+
+    fn failed(rule: ComplianceRule, reason: &str) -> Self {
+        ComplianceCheck::new(rule, false, None, || reason.to_string())
+    }
+}
+
+/// Every rule checked on a portrait, `compliant` when all of them passed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PortraitComplianceReport {
+    pub compliant: bool,
+    pub face_count: i32,
+    pub checks: Vec<ComplianceCheck>,
+}
+
+/// Image quality issues that come from the lighting rather than from the camera.
+const LIGHTING_ISSUES: [ImageQualityIssue; 5] = [
+    ImageQualityIssue::TooDark,
+    ImageQualityIssue::TooBright,
+    ImageQualityIssue::LowContrast,
+    ImageQualityIssue::Overexposed,
+    ImageQualityIssue::Underexposed,
+];
+
+/// Checks a portrait against the ICAO / ISO 19794-5 photo rules.
+///
+/// The face rules are judged on the largest detected face, using the head pose, quality class and
+/// image quality the pipeline stages computed for it. Centring reuses the `FaceSelection` margins,
+/// with the centre margin narrowed to `max_center_offset_ratio`.
+#[derive(Debug, Clone)]
+pub(crate) struct PortraitCompliance {
+    config: PortraitComplianceConfig,
+    face_selection: FaceSelection,
+}
+
+impl PortraitCompliance {
+    pub async fn new(config: &PortraitComplianceConfig, face_selection_cfg: &FaceSelectionConfig) -> Self {
+        let face_selection = FaceSelection::new(
+            config.max_center_offset_ratio,
+            config.max_center_offset_ratio,
+            face_selection_cfg.margin_edge_ratio,
+            face_selection_cfg.minimum_face_ratio,
+        ).await;
+
+        PortraitCompliance {
+            config: config.clone(),
+            face_selection,
+        }
+    }
+
+    pub fn call(&self, img: &Mat, face_count: i32, faces: &[FaceContext]) -> Result<PortraitComplianceReport, Error> {
+        let mut checks = vec![ComplianceCheck::new(ComplianceRule::SingleFace, face_count == 1, Some(face_count as f32), || {
+            format!("expected exactly one face, found {face_count}")
+        })];
+
+        let face = faces.iter().max_by(|a, b| box_area(a).partial_cmp(&box_area(b)).unwrap_or(std::cmp::Ordering::Equal));
+        match face {
+            None => {
+                for rule in [
+                    ComplianceRule::Centered, ComplianceRule::EyeLine, ComplianceRule::HeadSize, ComplianceRule::FrontalPose,
+                    ComplianceRule::NoSunglasses, ComplianceRule::NoMask, ComplianceRule::UniformBackground, ComplianceRule::Lighting,
+                ] {
+                    checks.push(ComplianceCheck::failed(rule, "no face detected"));
+                }
+            }
+            Some(face) => {
+                let mut gray = Mat::default();
+                match cvt_color(img, &mut gray, COLOR_BGR2GRAY, 0) {
+                    Ok(_) => {}
+                    Err(e) => return Err(Error::from(e))
+                };
+                let face_checks = match self.check_face(&gray, face) {
+                    Ok(face_checks) => {face_checks}
+                    Err(e) => return Err(e)
+                };
+                checks.extend(face_checks);
+            }
+        }
+
+        Ok(PortraitComplianceReport {
+            compliant: checks.iter().all(|check| check.passed),
+            face_count,
+            checks,
+        })
+    }
+
+    fn check_face(&self, gray: &Mat, face: &FaceContext) -> Result<Vec<ComplianceCheck>, Error> {
+        let config = &self.config;
+        let (image_width, image_height) = (gray.cols() as f32, gray.rows() as f32);
+        let bbox = face.detection.view();
+        let mut checks: Vec<ComplianceCheck> = Vec::with_capacity(8);
+
+        let center_offset = ((bbox[0] + bbox[2]) / 2.0 - image_width / 2.0).abs() / image_width;
+        let centered = self.face_selection.is_centered(image_width, bbox)
+            && self.face_selection.is_away_from_edges(image_width, image_height, bbox);
+        checks.push(ComplianceCheck::new(ComplianceRule::Centered, centered, Some(center_offset), || {
+            format!("face centre is {:.0}% of the image width off centre", center_offset * 100.0)
+        }));
+
+        checks.push(match &face.landmarks {
+            None => ComplianceCheck::failed(ComplianceRule::EyeLine, "no landmarks to locate the eyes"),
+            Some(landmarks) => {
+                let eye_y = (landmarks[[0, 1]] + landmarks[[1, 1]]) / 2.0;
+                let eye_line = (image_height - eye_y) / image_height;
+                let passed = (config.min_eye_line_ratio..=config.max_eye_line_ratio).contains(&eye_line);
+                ComplianceCheck::new(ComplianceRule::EyeLine, passed, Some(eye_line), || {
+                    format!("eye line is at {:.0}% of the image height", eye_line * 100.0)
+                })
+            }
+        });
+
+        let head_height = (bbox[3] - bbox[1]) / image_height;
+        let head_size_passed = (config.min_head_height_ratio..=config.max_head_height_ratio).contains(&head_height);
+        checks.push(ComplianceCheck::new(ComplianceRule::HeadSize, head_size_passed, Some(head_height), || {
+            let size = if head_height < config.min_head_height_ratio { "too small" } else { "too large" };
+            format!("head is {size}, {:.0}% of the image height", head_height * 100.0)
+        }));
+
+        checks.push(match &face.head_pose {
+            None => ComplianceCheck::failed(ComplianceRule::FrontalPose, "head pose not estimated"),
+            Some(head_pose) => {
+                let largest_angle = head_pose.yaw.abs().max(head_pose.pitch.abs()).max(head_pose.roll.abs());
+                let passed = head_pose.is_within(config.max_yaw, config.max_pitch, config.max_roll);
+                ComplianceCheck::new(ComplianceRule::FrontalPose, passed, Some(largest_angle), || {
+                    format!("head turned by yaw {:.0}, pitch {:.0} and roll {:.0} degrees", head_pose.yaw, head_pose.pitch, head_pose.roll)
+                })
+            }
+        });
+
+        for (rule, class, reason) in [
+            (ComplianceRule::NoSunglasses, FaceQualityClass::WearingSunGlasses, "wearing sunglasses"),
+            (ComplianceRule::NoMask, FaceQualityClass::WearingMask, "wearing a mask"),
+        ] {
+            checks.push(match &face.face_quality {
+                None => ComplianceCheck::failed(rule, "face quality not assessed"),
+                Some(face_quality) => ComplianceCheck::new(rule, *face_quality != class, None, || reason.to_string()),
+            });
+        }
+
+        let pixels = match gray.data_bytes() {
+            Ok(pixels) => {pixels}
+            Err(e) => return Err(Error::from(e))
+        };
+        let width = gray.cols() as usize;
+
+        checks.push(match self.background_std(pixels, width, bbox[0], bbox[1], bbox[2], bbox[3]) {
+            None => ComplianceCheck::failed(ComplianceRule::UniformBackground, "too little background around the head"),
+            Some(background_std) => {
+                ComplianceCheck::new(ComplianceRule::UniformBackground, background_std <= config.max_background_std, Some(background_std), || {
+                    "background is not uniform".to_string()
+                })
+            }
+        });
+
+        // The nose splits the face into the halves compared for side lighting
+        let split_x = match &face.landmarks {
+            Some(landmarks) => landmarks[[2, 0]],
+            None => (bbox[0] + bbox[2]) / 2.0,
+        };
+        let left = region_mean(pixels, width, bbox[0], split_x, bbox[1], bbox[3]);
+        let right = region_mean(pixels, width, split_x, bbox[2], bbox[1], bbox[3]);
+        let imbalance = match (left, right) {
+            (Some(left), Some(right)) => (left - right).abs() / left.max(right).max(1.0),
+            _ => 0.0,
+        };
+        checks.push(match &face.image_quality {
+            None => ComplianceCheck::failed(ComplianceRule::Lighting, "image quality not measured"),
+            Some(image_quality) => {
+                let issues: Vec<&str> = image_quality.issues.iter()
+                    .filter(|issue| LIGHTING_ISSUES.contains(issue))
+                    .map(|issue| issue.reason())
+                    .collect();
+                let even = imbalance <= config.max_lighting_imbalance;
+                ComplianceCheck::new(ComplianceRule::Lighting, issues.is_empty() && even, Some(imbalance), || {
+                    let mut reasons = issues.clone();
+                    if !even {
+                        reasons.push("uneven lighting across the face");
+                    }
+                    reasons.join(", ")
+                })
+            }
+        });
+
+        Ok(checks)
+    }
+
+    /// Standard deviation of the grey levels beside and above the head, `None` when too little of the image is left.
+    ///
+    /// The face box is widened by half its width on each side and raised by half its height to leave out
+    /// hair and ears, and rows below the chin are left out for the shoulders.
+    fn background_std(&self, pixels: &[u8], width: usize, x_min: f32, y_min: f32, x_max: f32, y_max: f32) -> Option<f32> {
+        let height = pixels.len() / width.max(1);
+        let (face_w, face_h) = (x_max - x_min, y_max - y_min);
+        let (head_x_min, head_x_max) = (x_min - face_w / 2.0, x_max + face_w / 2.0);
+        let head_y_min = y_min - face_h / 2.0;
+        let rows = (y_max.max(0.0) as usize).min(height);
+
+        let (mut sum, mut sum_sq, mut count) = (0.0f64, 0.0f64, 0usize);
+        for y in 0..rows {
+            for x in 0..width {
+                let (xf, yf) = (x as f32, y as f32);
+                if yf >= head_y_min && xf >= head_x_min && xf <= head_x_max {
+                    continue
+                }
+                let level = pixels[y * width + x] as f64;
+                sum += level;
+                sum_sq += level * level;
+                count += 1;
+            }
+        }
+        if (count as f32) < self.config.min_background_ratio * pixels.len() as f32 || count == 0 {
+            return None
+        }
+        let mean = sum / count as f64;
+        Some((sum_sq / count as f64 - mean * mean).max(0.0).sqrt() as f32)
+    }
+}
+
+fn box_area(face: &FaceContext) -> f32 {
+    (face.detection[2] - face.detection[0]) * (face.detection[3] - face.detection[1])
+}
+
+/// Mean grey level of a region clipped to the image, `None` when nothing of it is inside.
+fn region_mean(pixels: &[u8], width: usize, x_min: f32, x_max: f32, y_min: f32, y_max: f32) -> Option<f32> {
+    let height = pixels.len() / width.max(1);
+    let (x_min, x_max) = (x_min.max(0.0) as usize, (x_max.max(0.0) as usize).min(width));
+    let (y_min, y_max) = (y_min.max(0.0) as usize, (y_max.max(0.0) as usize).min(height));
+    if x_min >= x_max || y_min >= y_max {
+        return None
+    }
+    let mut sum = 0u64;
+    for y in y_min..y_max {
+        sum += pixels[y * width + x_min..y * width + x_max].iter().map(|level| *level as u64).sum::<u64>();
+    }
+    Some(sum as f32 / ((x_max - x_min) * (y_max - y_min)) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+    use opencv::core::{CV_8UC3, Mat, MatTrait, Rect, Scalar, Vec3b};
+    use opencv::imgproc::{rectangle, LINE_8};
+    use crate::pipeline::model_config::config::{FaceQualityClass, FaceSelectionConfig, PortraitComplianceConfig};
+    use crate::pipeline::module::head_pose::HeadPose;
+    use crate::pipeline::module::image_quality::{ImageQualityMetrics, ImageQualityReport};
+    use crate::pipeline::module::portrait_compliance::{ComplianceRule, PortraitCompliance, PortraitComplianceReport};
+    use crate::pipeline::stage_pipeline::pipeline_stage::FaceContext;
+
+    /// A centred 140x250 face on a 400x500 portrait, frontal and of good quality.
+    fn portrait_face() -> FaceContext {
+        let mut face = FaceContext::new(
+            array![130.0, 130.0, 270.0, 380.0, 0.9],
+            Some(array![[170.0, 220.0], [230.0, 220.0], [200.0, 270.0], [175.0, 320.0], [225.0, 320.0]]),
+        );
+        face.face_quality = Some(FaceQualityClass::Good);
+        face.head_pose = Some(HeadPose { yaw: 2.0, pitch: -1.0, roll: 0.5 });
+        face.image_quality = Some(ImageQualityReport {
+            metrics: ImageQualityMetrics {
+                sharpness: 300.0,
+                brightness: 120.0,
+                contrast: 40.0,
+                overexposed_ratio: 0.0,
+                underexposed_ratio: 0.0,
+                inter_eye_distance: Some(60.0),
+                blockiness: 1.0,
+            },
+            issues: vec![],
+            passed: true,
+        });
+        face
+    }
+
+    fn failed_rules(report: &PortraitComplianceReport) -> Vec<ComplianceRule> {
+        report.checks.iter().filter(|check| !check.passed).map(|check| check.rule).collect()
+    }
+
+    #[tokio::test]
+    async fn test_portrait_compliance() {
+        let portrait_compliance = PortraitCompliance::new(&PortraitComplianceConfig::default(), &FaceSelectionConfig::default()).await;
+        let mut img = Mat::new_rows_cols_with_default(500, 400, CV_8UC3, Scalar::all(200.0)).unwrap();
+        rectangle(&mut img, Rect::new(130, 130, 140, 250), Scalar::all(120.0), -1, LINE_8, 0).unwrap();
+
+        let report = portrait_compliance.call(&img, 1, &[portrait_face()]).unwrap();
+        assert!(report.compliant, "{report:?}");
+        assert_eq!(report.checks.len(), 9);
+        assert!(report.checks.iter().all(|check| check.reason.is_none()));
+
+        // Sunglasses, a turned head and a second face each break their own rule
+        let mut face = portrait_face();
+        face.face_quality = Some(FaceQualityClass::WearingSunGlasses);
+        face.head_pose = Some(HeadPose { yaw: 20.0, pitch: 0.0, roll: 0.0 });
+        let report = portrait_compliance.call(&img, 2, &[face]).unwrap();
+        assert!(!report.compliant);
+        assert_eq!(failed_rules(&report), vec![ComplianceRule::SingleFace, ComplianceRule::FrontalPose, ComplianceRule::NoSunglasses]);
+
+        // A face off to the side, lit from one side, in front of a striped background
+        let mut busy = Mat::new_rows_cols_with_default(500, 400, CV_8UC3, Scalar::all(0.0)).unwrap();
+        for y in 0..500 {
+            for x in 0..400 {
+                let level = if (x / 10) % 2 == 0 { 40 } else { 220 };
+                *busy.at_2d_mut::<Vec3b>(y, x).unwrap() = Vec3b::from([level, level, level]);
+            }
+        }
+        rectangle(&mut busy, Rect::new(180, 130, 70, 250), Scalar::all(180.0), -1, LINE_8, 0).unwrap();
+        rectangle(&mut busy, Rect::new(250, 130, 70, 250), Scalar::all(60.0), -1, LINE_8, 0).unwrap();
+        let mut face = portrait_face();
+        face.detection = array![180.0, 130.0, 320.0, 380.0, 0.9];
+        face.landmarks = Some(array![[220.0, 220.0], [280.0, 220.0], [250.0, 270.0], [225.0, 320.0], [275.0, 320.0]]);
+        let report = portrait_compliance.call(&busy, 1, &[face]).unwrap();
+        assert_eq!(failed_rules(&report), vec![ComplianceRule::Centered, ComplianceRule::UniformBackground, ComplianceRule::Lighting]);
+
+        let report = portrait_compliance.call(&img, 0, &[]).unwrap();
+        assert_eq!(report.checks.len(), 9);
+        assert!(report.checks.iter().all(|check| !check.passed));
+    }
+}
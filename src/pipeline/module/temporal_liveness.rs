@@ -0,0 +1,306 @@
+use anyhow::Error;
+use ndarray::{Array1, Array2};
+use opencv::core::{Mat, MatTraitConst, mean_std_dev_def, Rect};
+use opencv::imgproc::{COLOR_BGR2GRAY, cvt_color};
+use serde::{Deserialize, Serialize};
+use crate::pipeline::model_config::config::{FaceAntiSpoofingClass, LivenessConfig};
+use crate::pipeline::module::head_pose::HeadPose;
+use crate::pipeline::processing::nms::iou;
+use crate::pipeline::stage_pipeline::pipeline_stage::FaceContext;
+
+/// Liveness decision for a burst of frames and the cues it was fused from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemporalLivenessReport {
+    pub is_live: bool,
+    /// Weighted mean of the cue scores, in `[0, 1]`
+    pub confidence: f32,
+    pub frame_count: usize,
+    /// Frames in which the followed face was found
+    pub tracked_frames: usize,
+    /// Mean anti-spoofing live score of the followed face, `None` when no frame was scored
+    pub anti_spoofing_score: Option<f32>,
+    /// Mean standard deviation of the landmarks across frames, relative to the face box
+    pub micro_motion: f32,
+    pub blink_detected: bool,
+    /// Mean IoU of the face box between consecutive frames, a lost frame counts as 0
+    pub bbox_consistency: f32,
+}
+
+/// One frame of the followed face.
+#[derive(Debug, Clone)]
+pub(crate) struct TrackedFace {
+    pub detection: Array1<f32>,
+    pub landmarks: Option<Array2<f32>>,
+    pub head_pose: Option<HeadPose>,
+    pub spoofing_check: Option<FaceAntiSpoofingClass>,
+    pub spoofing_score: Option<f32>,
+    pub eye_openness: Option<f32>,
+}
+
+/// Fuses per-frame anti-spoofing scores with temporal cues a still print or screen lacks.
+///
+/// The largest face of the first frame that has one is followed from frame to frame by IoU. Its
+/// landmarks should move slightly against the face box, its eyes should blink, and its box should
+/// not jump between frames.
+#[derive(Debug, Clone)]
+pub(crate) struct TemporalLiveness {
+    config: LivenessConfig,
+}
+
+impl TemporalLiveness {
+    pub fn new(config: LivenessConfig) -> Self {
+        TemporalLiveness {
+            config,
+        }
+    }
+
+    pub fn max_frames(&self) -> usize {
+        self.config.max_frames
+    }
+
+    /// `frames` holds each image with the faces the pipeline found in it.
+    pub fn call(&self, frames: &[(Mat, Vec<FaceContext>)]) -> Result<TemporalLivenessReport, Error> {
+        let track = match track_face(frames, self.config.min_track_iou) {
+            Ok(track) => {track}
+            Err(e) => return Err(e)
+        };
+        Ok(self.fuse(&track))
+    }
+
+    fn fuse(&self, track: &[Option<TrackedFace>]) -> TemporalLivenessReport {
+        let config = &self.config;
+        let tracked: Vec<&TrackedFace> = track.iter().flatten().collect();
+
+        let spoofing_scores: Vec<f32> = tracked.iter().filter_map(|face| face.spoofing_score).collect();
+        let anti_spoofing_score = if spoofing_scores.is_empty() {
+            None
+        } else {
+            Some(spoofing_scores.iter().sum::<f32>() / spoofing_scores.len() as f32)
+        };
+        let micro_motion = micro_motion(&tracked);
+        let openness: Vec<f32> = tracked.iter().filter_map(|face| face.eye_openness).collect();
+        let blink_detected = blink_detected(&openness, config.blink_ratio);
+        let bbox_consistency = if track.len() < 2 {
+            0.0
+        } else {
+            let overlaps: f32 = track.windows(2).map(|pair| match (&pair[0], &pair[1]) {
+                (Some(previous), Some(current)) => iou(previous.detection.view(), current.detection.view()),
+                _ => 0.0,
+            }).sum();
+            overlaps / (track.len() - 1) as f32
+        };
+
+        let cues = [
+            (config.anti_spoofing_weight, anti_spoofing_score.unwrap_or(0.0)),
+            (config.micro_motion_weight, (micro_motion / config.target_micro_motion).min(1.0)),
+            (config.blink_weight, if blink_detected { 1.0 } else { 0.0 }),
+            (config.bbox_consistency_weight, bbox_consistency),
+        ];
+        let total_weight: f32 = cues.iter().map(|(weight, _)| weight).sum();
+        let confidence = cues.iter().map(|(weight, score)| weight * score).sum::<f32>() / total_weight.max(f32::EPSILON);
+
+        TemporalLivenessReport {
+            is_live: tracked.len() >= config.min_frames && confidence >= config.threshold,
+            confidence,
+            frame_count: track.len(),
+            tracked_frames: tracked.len(),
+            anti_spoofing_score,
+            micro_motion,
+            blink_detected,
+            bbox_consistency,
+        }
+    }
+}
+
+/// Follows the largest face of the first frame that has one from frame to frame by IoU, `None`
+/// where the face was lost.
+pub(crate) fn track_face(frames: &[(Mat, Vec<FaceContext>)], min_track_iou: f32) -> Result<Vec<Option<TrackedFace>>, Error> {
+    let mut track: Vec<Option<TrackedFace>> = Vec::with_capacity(frames.len());
+    let mut last_box: Option<Array1<f32>> = None;
+    for (image, faces) in frames.iter() {
+        let face = match &last_box {
+            None => faces.iter().max_by(|a, b| box_area(&a.detection).partial_cmp(&box_area(&b.detection)).unwrap_or(std::cmp::Ordering::Equal)),
+            Some(last_box) => faces.iter()
+                .map(|face| (face, iou(last_box.view(), face.detection.view())))
+                .filter(|(_, overlap)| *overlap >= min_track_iou)
+                .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+                .map(|(face, _)| face),
+        };
+        let face = match face {
+            None => {
+                track.push(None);
+                continue
+            }
+            Some(face) => face,
+        };
+        let eye_openness = match &face.landmarks {
+            None => None,
+            Some(landmarks) => match eye_openness(image, landmarks) {
+                Ok(eye_openness) => eye_openness,
+                Err(e) => return Err(e)
+            },
+        };
+        last_box = Some(face.detection.clone());
+        track.push(Some(TrackedFace {
+            detection: face.detection.clone(),
+            landmarks: face.landmarks.clone(),
+            head_pose: face.head_pose,
+            spoofing_check: face.spoofing_check.clone(),
+            spoofing_score: face.spoofing_score,
+            eye_openness,
+        }));
+    }
+    Ok(track)
+}
+
+fn box_area(detection: &Array1<f32>) -> f32 {
+    (detection[2] - detection[0]) * (detection[3] - detection[1])
+}
+
+/// Mean over the landmark coordinates of their standard deviation across frames, each frame
+/// measured from its own face box so that moving the whole head or a photo does not count.
+fn micro_motion(tracked: &[&TrackedFace]) -> f32 {
+    let normalised: Vec<Array2<f32>> = tracked.iter().filter_map(|face| {
+        let (x_min, y_min) = (face.detection[0], face.detection[1]);
+        let width = (face.detection[2] - x_min).max(1.0);
+        let height = (face.detection[3] - y_min).max(1.0);
+        face.landmarks.as_ref().map(|landmarks| {
+            let mut points = landmarks.clone();
+            points.column_mut(0).mapv_inplace(|x| (x - x_min) / width);
+            points.column_mut(1).mapv_inplace(|y| (y - y_min) / height);
+            points
+        })
+    }).collect();
+    if normalised.len() < 2 {
+        return 0.0
+    }
+
+    let count = normalised.len() as f32;
+    let mean = normalised.iter().fold(Array2::<f32>::zeros(normalised[0].dim()), |sum, points| sum + points) / count;
+    let variance = normalised.iter().fold(Array2::<f32>::zeros(mean.dim()), |sum, points| {
+        let diff = points - &mean;
+        sum + &diff * &diff
+    }) / count;
+    variance.mapv(f32::sqrt).mean().unwrap_or(0.0)
+}
+
+/// Whether the eyes go from open to closed and back, closed meaning an openness below `blink_ratio`
+/// of the median.
+pub(crate) fn blink_detected(openness: &[f32], blink_ratio: f32) -> bool {
+    if openness.len() < 3 {
+        return false
+    }
+    let mut sorted = openness.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let closed_below = sorted[sorted.len() / 2] * blink_ratio;
+
+    // Open, then closed, then open again
+    let mut state = 0;
+    for value in openness.iter() {
+        let closed = *value < closed_below;
+        state = match (state, closed) {
+            (0, false) => 1,
+            (1, true) => 2,
+            (2, false) => return true,
+            (state, _) => state,
+        };
+    }
+    false
+}
+
+/// Contrast of the patches around both eye centres, the detector gives no eyelid points so a
+/// closing eye is seen as skin covering the dark iris. `None` when a patch falls off the image.
+fn eye_openness(image: &Mat, landmarks: &Array2<f32>) -> Result<Option<f32>, Error> {
+    let eye_distance = (landmarks[[1, 0]] - landmarks[[0, 0]]).hypot(landmarks[[1, 1]] - landmarks[[0, 1]]);
+    let side = (eye_distance * 0.4).round() as i32;
+    if side < 4 {
+        return Ok(None)
+    }
+
+    let mut openness = 0.0;
+    for eye in 0..2 {
+        let x = (landmarks[[eye, 0]] - side as f32 / 2.0).round() as i32;
+        let y = (landmarks[[eye, 1]] - side as f32 / 2.0).round() as i32;
+        if x < 0 || y < 0 || x + side > image.cols() || y + side > image.rows() {
+            return Ok(None)
+        }
+        let patch = match Mat::roi(image, Rect::new(x, y, side, side)) {
+            Ok(patch) => {patch}
+            Err(e) => return Err(Error::from(e))
+        };
+        let mut gray = Mat::default();
+        match cvt_color(&patch, &mut gray, COLOR_BGR2GRAY, 0) {
+            Ok(_) => {}
+            Err(e) => return Err(Error::from(e))
+        };
+        let mut mean = Mat::default();
+        let mut std_dev = Mat::default();
+        match mean_std_dev_def(&gray, &mut mean, &mut std_dev) {
+            Ok(_) => {}
+            Err(e) => return Err(Error::from(e))
+        };
+        openness += match std_dev.at::<f64>(0) {
+            Ok(std_dev) => {*std_dev as f32}
+            Err(e) => return Err(Error::from(e))
+        };
+    }
+    Ok(Some(openness / 2.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array1};
+    use opencv::core::{CV_8UC3, Mat, Point, Scalar};
+    use opencv::imgproc::{circle, LINE_8};
+    use crate::pipeline::model_config::config::LivenessConfig;
+    use crate::pipeline::module::temporal_liveness::TemporalLiveness;
+    use crate::pipeline::stage_pipeline::pipeline_stage::FaceContext;
+
+    /// A skin coloured frame with dark irises at the eye landmarks unless `closed`, the face shifted by `dx`.
+    fn frame(dx: f32, closed: bool, wiggle: f32, spoofing_score: f32) -> (Mat, Vec<FaceContext>) {
+        let mut image = Mat::new_rows_cols_with_default(480, 640, CV_8UC3, Scalar::all(170.0)).unwrap();
+        let landmarks = array![
+            [280.0 + dx, 220.0 + wiggle], [360.0 + dx, 220.0], [320.0 + dx, 260.0], [290.0 + dx, 300.0 - wiggle], [350.0 + dx, 300.0]
+        ];
+        if !closed {
+            for eye in 0..2 {
+                let center = Point::new(landmarks[[eye, 0]] as i32, landmarks[[eye, 1]] as i32);
+                circle(&mut image, center, 8, Scalar::all(30.0), -1, LINE_8, 0).unwrap();
+            }
+        }
+        let mut face = FaceContext::new(Array1::from(vec![240.0 + dx, 160.0, 400.0 + dx, 360.0, 0.99]), Some(landmarks));
+        face.spoofing_score = Some(spoofing_score);
+        (image, vec![face])
+    }
+
+    #[test]
+    fn test_temporal_liveness() {
+        let temporal_liveness = TemporalLiveness::new(LivenessConfig::default());
+
+        // A face that moves a little, blinks once and scores live in every frame
+        let live: Vec<_> = [(0.0, false, 0.0), (2.0, false, 2.0), (4.0, true, -2.0), (5.0, false, 1.0), (6.0, false, -1.0)]
+            .into_iter()
+            .map(|(dx, closed, wiggle)| frame(dx, closed, wiggle, 0.9))
+            .collect();
+        let report = temporal_liveness.call(&live).unwrap();
+        assert_eq!((report.frame_count, report.tracked_frames), (5, 5));
+        assert!(report.blink_detected);
+        assert!(report.micro_motion > 0.001, "{report:?}");
+        assert!(report.bbox_consistency > 0.9);
+        assert!(report.is_live, "{report:?}");
+
+        // A rigid photo shows no motion and no blink, its scores alone stay under the threshold
+        let photo: Vec<_> = (0..5).map(|_| frame(0.0, false, 0.0, 0.7)).collect();
+        let report = temporal_liveness.call(&photo).unwrap();
+        assert!(!report.blink_detected);
+        assert_eq!(report.micro_motion, 0.0);
+        assert!(!report.is_live, "{report:?}");
+
+        // A face jumping across the frame is not followed
+        let jumping = vec![frame(0.0, false, 0.0, 0.9), frame(200.0, false, 0.0, 0.9)];
+        let report = temporal_liveness.call(&jumping).unwrap();
+        assert_eq!(report.tracked_frames, 1);
+        assert_eq!(report.bbox_consistency, 0.0);
+        assert!(!report.is_live);
+    }
+}
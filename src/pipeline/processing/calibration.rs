@@ -0,0 +1,130 @@
+use serde::Deserialize;
+
+/// How a raw model score is mapped to a probability.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CalibrationMethod {
+    /// The raw score is passed through
+    #[default]
+    None,
+    /// Platt scaling, `1 / (1 + exp(a * score + b))`
+    Platt,
+    /// Isotonic regression, linear between the fitted points and flat beyond them
+    Isotonic,
+}
+
+/// The `calibration` section of a scoring model config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CalibrationConfig {
+    pub method: CalibrationMethod,
+    /// Platt slope, negative so that a higher score gives a higher probability
+    pub platt_a: f32,
+    pub platt_b: f32,
+    /// Raw scores of the isotonic points, increasing
+    pub isotonic_scores: Vec<f32>,
+    /// Probabilities of the isotonic points, non-decreasing
+    pub isotonic_probabilities: Vec<f32>,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        CalibrationConfig {
+            method: CalibrationMethod::None,
+            platt_a: -1.0,
+            platt_b: 0.0,
+            isotonic_scores: vec![],
+            isotonic_probabilities: vec![],
+        }
+    }
+}
+
+impl CalibrationConfig {
+    pub fn apply(&self, score: f32) -> f32 {
+        match self.method {
+            CalibrationMethod::None => score,
+            CalibrationMethod::Platt => 1.0 / (1.0 + (self.platt_a * score + self.platt_b).exp()),
+            CalibrationMethod::Isotonic => {
+                let (xs, ys) = (&self.isotonic_scores, &self.isotonic_probabilities);
+                if xs.is_empty() {
+                    return score
+                }
+                let upper = xs.partition_point(|x| *x < score);
+                if upper == 0 {
+                    return ys[0]
+                }
+                if upper == xs.len() {
+                    return ys[xs.len() - 1]
+                }
+                let (x0, x1, y0, y1) = (xs[upper - 1], xs[upper], ys[upper - 1], ys[upper]);
+                y0 + (y1 - y0) * (score - x0) / (x1 - x0)
+            }
+        }
+    }
+
+    /// Problems with the parameters of the selected method, prefixed with `key`.
+    pub fn errors(&self, key: &str) -> Vec<String> {
+        let mut errors: Vec<String> = vec![];
+        match self.method {
+            CalibrationMethod::None => {}
+            CalibrationMethod::Platt => {
+                let increasing = self.platt_a < 0.0 && self.platt_b.is_finite();
+                if !increasing {
+                    errors.push(format!("{key}.platt_a must be negative and platt_b finite, got {} and {}", self.platt_a, self.platt_b));
+                }
+            }
+            CalibrationMethod::Isotonic => {
+                let (xs, ys) = (&self.isotonic_scores, &self.isotonic_probabilities);
+                if xs.is_empty() || xs.len() != ys.len() {
+                    errors.push(format!(
+                        "{key}.isotonic_scores and isotonic_probabilities must have the same, non-zero length, got {} and {}",
+                        xs.len(), ys.len()
+                    ));
+                } else if xs.windows(2).any(|pair| pair[0] >= pair[1])
+                    || ys.windows(2).any(|pair| pair[0] > pair[1])
+                    || ys.iter().any(|y| !(0.0..=1.0).contains(y)) {
+                    errors.push(format!("{key}.isotonic_scores must increase and isotonic_probabilities must be non-decreasing within [0, 1]"));
+                }
+            }
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::processing::calibration::{CalibrationConfig, CalibrationMethod};
+
+    #[test]
+    fn test_calibration() {
+        assert_eq!(CalibrationConfig::default().apply(0.3), 0.3);
+
+        let platt = CalibrationConfig {
+            method: CalibrationMethod::Platt,
+            platt_a: -10.0,
+            platt_b: 5.0,
+            ..Default::default()
+        };
+        assert!((platt.apply(0.5) - 0.5).abs() < 1e-6);
+        assert!(platt.apply(0.9) > 0.98);
+        assert!(platt.errors("calibration").is_empty());
+
+        let isotonic = CalibrationConfig {
+            method: CalibrationMethod::Isotonic,
+            isotonic_scores: vec![0.2, 0.6, 0.8],
+            isotonic_probabilities: vec![0.0, 0.5, 0.9],
+            ..Default::default()
+        };
+        assert_eq!(isotonic.apply(0.1), 0.0);
+        assert!((isotonic.apply(0.4) - 0.25).abs() < 1e-6);
+        assert!((isotonic.apply(0.7) - 0.7).abs() < 1e-6);
+        assert_eq!(isotonic.apply(0.95), 0.9);
+        assert!(isotonic.errors("calibration").is_empty());
+
+        let unsorted = CalibrationConfig {
+            isotonic_scores: vec![0.6, 0.2],
+            isotonic_probabilities: vec![0.5, 0.7],
+            ..isotonic
+        };
+        assert_eq!(unsorted.errors("calibration").len(), 1);
+    }
+}
@@ -1,4 +1,5 @@
-
 pub mod bbox_transform;
 pub mod generate_anchors;
-pub mod nms;
\ No newline at end of file
+pub mod nms;
+pub mod preprocess;
+pub mod calibration;
@@ -1,67 +1,112 @@
-use ndarray::{Array1, Array2};
+use ndarray::{Array2, ArrayView1};
+use serde::Deserialize;
+
+/// How overlapping boxes are suppressed, boxes of every class compete with each other.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NmsMethod {
+    /// Drops every box overlapping a better one by more than the IoU threshold
+    #[default]
+    Hard,
+    /// Soft-NMS, scales the score of an overlapping box by `1 - IoU` above the threshold
+    Linear,
+    /// Soft-NMS, scales the score of every box by `exp(-IoU^2 / sigma)`
+    Gaussian,
+    /// Drops boxes whose IoU minus the normalised distance between centres exceeds the threshold,
+    /// keeping neighbouring faces whose boxes overlap but whose centres are apart
+    Diou,
+}
 
-pub fn nms(dets: &Array2<f32>, thresh: f32) -> Vec<usize> {
-    let scores = dets.column(4);
-    let mut order: Vec<usize> = (0..dets.nrows()).collect();
-    order.sort_by(|&a, &b| scores[b].partial_cmp(&scores[a]).unwrap_or(std::cmp::Ordering::Equal));
-
-    let mut keep: Vec<usize> = Vec::new();
-
-    while !order.is_empty() {
-        let i = order[0];
-        keep.push(i);
-
-        let xx1 = Array1::from(
-            order[1..]
-                .iter()
-                .map(|&j| f32::max(dets[[i, 0]], dets[[j, 0]]))
-                .collect::<Vec<f32>>(),
-        );
-        let yy1 = Array1::from(
-            order[1..]
-                .iter()
-                .map(|&j| f32::max(dets[[i, 1]], dets[[j, 1]]))
-                .collect::<Vec<f32>>(),
-        );
-        let xx2 = Array1::from(
-            order[1..]
-                .iter()
-                .map(|&j| f32::min(dets[[i, 2]], dets[[j, 2]]))
-                .collect::<Vec<f32>>(),
-        );
-        let yy2 = Array1::from(
-            order[1..]
-                .iter()
-                .map(|&j| f32::min(dets[[i, 3]], dets[[j, 3]]))
-                .collect::<Vec<f32>>(),
-        );
-
-        let w = &xx2 - &xx1 + 1.0;
-        let h = &yy2 - &yy1 + 1.0;
-
-        let w = w.mapv(|val| f32::max(0.0, val));
-        let h = h.mapv(|val| f32::max(0.0, val));
-
-        let inter = &w * &h;
-        let area_i = (dets[[i, 2]] - dets[[i, 0]] + 1.0) * (dets[[i, 3]] - dets[[i, 1]] + 1.0);
-        let area_order = Array1::from(
-            order[1..]
-                .iter()
-                .map(|&j| (dets[[j, 2]] - dets[[j, 0]] + 1.0) * (dets[[j, 3]] - dets[[j, 1]] + 1.0))
-                .collect::<Vec<f32>>(),
-        );
-
-        let ovr = inter.clone() / (area_i + &area_order - inter);
-
-        let inds: Vec<usize> = ovr
-            .indexed_iter()
-            .filter_map(|(idx, &val)| if val <= thresh { Some(idx) } else { None })
-            .collect();
-
-        order = inds.iter().map(|&idx| order[idx + 1]).collect();
+/// The `nms` section of a detector config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NmsConfig {
+    pub method: NmsMethod,
+    /// Spread of the Gaussian decay
+    pub sigma: f32,
+    /// Boxes whose decayed score falls below this are dropped
+    pub min_score: f32,
+    /// At most this many boxes are kept
+    pub top_k: Option<usize>,
+}
+
+impl Default for NmsConfig {
+    fn default() -> Self {
+        NmsConfig {
+            method: NmsMethod::Hard,
+            sigma: 0.5,
+            min_score: 0.001,
+            top_k: None,
+        }
     }
+}
+
+impl NmsConfig {
+    /// Kept rows of `dets` (`[x_min, y_min, x_max, y_max, score]`) with their possibly decayed scores,
+    /// best score first.
+    pub fn suppress(&self, dets: &Array2<f32>, iou_threshold: f32) -> Vec<(usize, f32)> {
+        let mut scores: Vec<f32> = dets.column(4).to_vec();
+        let mut remaining: Vec<usize> = (0..dets.nrows()).collect();
+        let mut keep: Vec<(usize, f32)> = Vec::new();
+
+        while !remaining.is_empty() {
+            if self.top_k.is_some_and(|top_k| keep.len() >= top_k) {
+                break
+            }
+            let (pos, best) = remaining.iter().copied().enumerate()
+                .max_by(|(_, a), (_, b)| scores[*a].partial_cmp(&scores[*b]).unwrap_or(std::cmp::Ordering::Equal))
+                .unwrap();
+            remaining.swap_remove(pos);
+            if scores[best] < self.min_score && self.method != NmsMethod::Hard {
+                break
+            }
+            keep.push((best, scores[best]));
+
+            let best_box = dets.row(best);
+            remaining.retain(|&idx| {
+                let iou = iou(best_box, dets.row(idx));
+                match self.method {
+                    NmsMethod::Hard => iou <= iou_threshold,
+                    NmsMethod::Diou => iou - center_distance_penalty(best_box, dets.row(idx)) <= iou_threshold,
+                    NmsMethod::Linear => {
+                        if iou > iou_threshold {
+                            scores[idx] *= 1.0 - iou;
+                        }
+                        scores[idx] >= self.min_score
+                    }
+                    NmsMethod::Gaussian => {
+                        scores[idx] *= (-(iou * iou) / self.sigma).exp();
+                        scores[idx] >= self.min_score
+                    }
+                }
+            });
+        }
+
+        keep
+    }
+}
+
+/// Overlap of two boxes, sides are inclusive pixel coordinates.
+pub fn iou(a: ArrayView1<f32>, b: ArrayView1<f32>) -> f32 {
+    let w = (f32::min(a[2], b[2]) - f32::max(a[0], b[0]) + 1.0).max(0.0);
+    let h = (f32::min(a[3], b[3]) - f32::max(a[1], b[1]) + 1.0).max(0.0);
+    let inter = w * h;
+    let area_a = (a[2] - a[0] + 1.0) * (a[3] - a[1] + 1.0);
+    let area_b = (b[2] - b[0] + 1.0) * (b[3] - b[1] + 1.0);
+    inter / (area_a + area_b - inter)
+}
+
+/// Squared distance between the box centres over the squared diagonal of the box enclosing both.
+fn center_distance_penalty(a: ArrayView1<f32>, b: ArrayView1<f32>) -> f32 {
+    let dx = (a[0] + a[2] - b[0] - b[2]) / 2.0;
+    let dy = (a[1] + a[3] - b[1] - b[3]) / 2.0;
+    let enclosing_w = f32::max(a[2], b[2]) - f32::min(a[0], b[0]) + 1.0;
+    let enclosing_h = f32::max(a[3], b[3]) - f32::min(a[1], b[1]) + 1.0;
+    (dx * dx + dy * dy) / (enclosing_w * enclosing_w + enclosing_h * enclosing_h)
+}
 
-    keep
+/// Hard NMS, indices of the kept rows of `dets` best score first.
+pub fn nms(dets: &Array2<f32>, thresh: f32) -> Vec<usize> {
+    NmsConfig::default().suppress(dets, thresh).into_iter().map(|(idx, _)| idx).collect()
 }
 
 
@@ -69,7 +114,7 @@ pub fn nms(dets: &Array2<f32>, thresh: f32) -> Vec<usize> {
 #[cfg(test)]
 mod tests {
     use ndarray::array;
-    use crate::processing::nms::nms;
+    use crate::pipeline::processing::nms::{nms, NmsConfig, NmsMethod};
 
     #[test]
     fn test_nms() {
@@ -82,8 +127,38 @@ mod tests {
 
         let thresh = 0.4;
         let keep = nms(&dets, thresh);
+        assert_eq!(keep, vec![2, 1, 0]);
+
+        let top_k = NmsConfig { top_k: Some(2), ..Default::default() };
+        assert_eq!(top_k.suppress(&dets, thresh).len(), 2);
 
-        println!("Kept indices: {:?}", keep);
+        // Soft-NMS drops the exact duplicate of row 0 only once its score is decayed to nothing
+        let linear = NmsConfig { method: NmsMethod::Linear, ..Default::default() };
+        assert_eq!(linear.suppress(&dets, thresh).len(), 3);
+        let gaussian = NmsConfig { method: NmsMethod::Gaussian, ..Default::default() };
+        let kept = gaussian.suppress(&dets, thresh);
+        assert_eq!(kept.len(), 4);
+        assert!((kept[3].1 - 0.6 * (-1.0f32 / 0.5).exp()).abs() < 1e-6);
+
+        // A taller box overlapping row 0 survives linear Soft-NMS with a decayed score
+        let crowded = array![
+            [100.0, 100.0, 210.0, 210.0, 0.72],
+            [100.0, 100.0, 210.0, 240.0, 0.6]
+        ];
+        let kept = linear.suppress(&crowded, thresh);
+        assert_eq!(kept.iter().map(|(idx, _)| *idx).collect::<Vec<_>>(), vec![0, 1]);
+        assert!((kept[1].1 - 0.6 * (1.0 - 111.0 / 141.0)).abs() < 1e-6);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_diou_nms() {
+        // Side by side faces overlapping at IoU 0.46, their centres are far enough apart for DIoU
+        let dets = array![
+            [0.0, 0.0, 99.0, 99.0, 0.9],
+            [37.0, 0.0, 136.0, 99.0, 0.8]
+        ];
+        assert_eq!(nms(&dets, 0.45), vec![0]);
+        let diou = NmsConfig { method: NmsMethod::Diou, ..Default::default() };
+        assert_eq!(diou.suppress(&dets, 0.45).len(), 2);
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::Error;
+use ndarray::{Array4, ArrayViewMut3, Axis};
+use opencv::core::{CV_8UC3, Mat, MatTraitConst, Size};
+use opencv::imgproc::{COLOR_BGR2RGB, cvt_color, INTER_LINEAR, resize};
+use serde::Deserialize;
+
+/// Channel order a model expects, images are decoded as BGR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelOrder {
+    Rgb,
+    Bgr,
+}
+
+/// How a model wants its pixels, every value becomes `(pixel - mean[c]) * scale[c]`.
+///
+/// `mean` and `scale` follow the model's channel order. The constants are the defaults of each
+/// model, the `normalization` section of its config overrides them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Normalization {
+    pub channel_order: ChannelOrder,
+    pub mean: [f32; 3],
+    pub scale: [f32; 3],
+}
+
+impl Normalization {
+    /// RetinaFace detection, raw RGB pixels.
+    pub const RETINA_FACE: Normalization = Normalization {
+        channel_order: ChannelOrder::Rgb,
+        mean: [0.0, 0.0, 0.0],
+        scale: [1.0, 1.0, 1.0],
+    };
+
+    /// SCRFD detection, pixels mapped to about `[-1, 1]`.
+    pub const SCRFD: Normalization = Normalization {
+        channel_order: ChannelOrder::Rgb,
+        mean: [127.5, 127.5, 127.5],
+        scale: [0.0078125, 0.0078125, 0.0078125],
+    };
+
+    /// YOLO-face detection, pixels mapped to `[0, 1]`.
+    pub const YOLO_FACE: Normalization = Normalization {
+        channel_order: ChannelOrder::Rgb,
+        mean: [0.0, 0.0, 0.0],
+        scale: [0.003921569, 0.003921569, 0.003921569],
+    };
+
+    /// Face quality classifier, ImageNet mean and std.
+    pub const FACE_QUALITY: Normalization = Normalization {
+        channel_order: ChannelOrder::Rgb,
+        mean: [123.675, 116.28, 103.53],
+        scale: [0.01712475, 0.017507, 0.01742919],
+    };
+
+    /// Face quality assessment regressor, pixels mapped to `[-1, 1]`.
+    pub const FACE_QUALITY_ASSESSMENT: Normalization = Normalization {
+        channel_order: ChannelOrder::Rgb,
+        mean: [127.5, 127.5, 127.5],
+        scale: [0.00784313725, 0.00784313725, 0.00784313725],
+    };
+
+    /// ArcFace feature extraction.
+    pub const FACE_IDENTIFICATION: Normalization = Normalization {
+        channel_order: ChannelOrder::Rgb,
+        mean: [127.5, 127.5, 127.5],
+        scale: [0.0078125, 0.0078125, 0.0078125],
+    };
+
+    /// MiniFASNet anti-spoofing, raw RGB pixels.
+    pub const MINI_FAS: Normalization = Normalization {
+        channel_order: ChannelOrder::Rgb,
+        mean: [0.0, 0.0, 0.0],
+        scale: [1.0, 1.0, 1.0],
+    };
+}
+
+/// Normalises `image` into `out`, a `(3, height, width)` CHW view, after resizing it to `size` (width, height) when given.
+///
+/// The interleaved pixels are read as one contiguous slice and each channel plane is written in a single pass.
+pub fn preprocess_into(image: &Mat, size: Option<(i32, i32)>, normalization: &Normalization, mut out: ArrayViewMut3<f32>) -> Result<(), Error> {
+    if image.typ() != CV_8UC3 {
+        return Err(Error::msg("preprocess - expected an 8-bit 3-channel image"))
+    }
+
+    let mut resized_img = Mat::default();
+    let mut prepared = image;
+    if let Some((width, height)) = size {
+        if image.cols() != width || image.rows() != height {
+            match resize(image, &mut resized_img, Size::new(width, height), 0.0, 0.0, INTER_LINEAR) {
+                Ok(_) => {},
+                Err(e) => return Err(Error::from(e))
+            }
+            prepared = &resized_img;
+        }
+    }
+
+    let mut converted_img = Mat::default();
+    if normalization.channel_order == ChannelOrder::Rgb {
+        match cvt_color(prepared, &mut converted_img, COLOR_BGR2RGB, 0) {
+            Ok(_) => {}
+            Err(e) => return Err(Error::from(e))
+        };
+        prepared = &converted_img;
+    }
+
+    // Views into a larger image are not contiguous, their copy is
+    let continuous_img: Mat;
+    if !prepared.is_continuous() {
+        continuous_img = match prepared.try_clone() {
+            Ok(continuous_img) => {continuous_img}
+            Err(e) => return Err(Error::from(e))
+        };
+        prepared = &continuous_img;
+    }
+
+    let rows = prepared.rows() as usize;
+    let cols = prepared.cols() as usize;
+    if out.dim() != (3, rows, cols) {
+        return Err(Error::msg(format!("preprocess - a {rows}x{cols} image does not fit a {:?} tensor", out.dim())))
+    }
+
+    let pixels = match prepared.data_bytes() {
+        Ok(pixels) => {pixels}
+        Err(e) => return Err(Error::from(e))
+    };
+    for c in 0..3 {
+        let mean = normalization.mean[c];
+        let scale = normalization.scale[c];
+        let mut plane = out.index_axis_mut(Axis(0), c);
+        for (value, pixel) in plane.iter_mut().zip(pixels.chunks_exact(3)) {
+            *value = (pixel[c] as f32 - mean) * scale;
+        }
+    }
+    Ok(())
+}
+
+/// One image as a `(1, 3, height, width)` NCHW tensor, `size` of `None` keeps the image size.
+pub fn preprocess(image: &Mat, size: Option<(i32, i32)>, normalization: &Normalization) -> Result<Array4<f32>, Error> {
+    let (width, height) = match size {
+        Some(size) => size,
+        None => (image.cols(), image.rows()),
+    };
+    let mut tensor = Array4::<f32>::zeros((1, 3, height as usize, width as usize));
+    match preprocess_into(image, size, normalization, tensor.index_axis_mut(Axis(0), 0)) {
+        Ok(_) => {}
+        Err(e) => return Err(e)
+    };
+    Ok(tensor)
+}
+
+/// Images resized to `size` in an NCHW tensor of `num_rows` rows, rows past the images stay zero as batch padding.
+pub fn preprocess_batch(images: &[Mat], size: (i32, i32), normalization: &Normalization, num_rows: usize) -> Result<Array4<f32>, Error> {
+    if images.len() > num_rows {
+        return Err(Error::msg(format!("preprocess - {} images do not fit {num_rows} rows", images.len())))
+    }
+    let mut tensor = Array4::<f32>::zeros((num_rows, 3, size.1 as usize, size.0 as usize));
+    for (idx, image) in images.iter().enumerate() {
+        match preprocess_into(image, Some(size), normalization, tensor.index_axis_mut(Axis(0), idx)) {
+            Ok(_) => {}
+            Err(e) => return Err(e)
+        };
+    }
+    Ok(tensor)
+}
+
+#[cfg(test)]
+mod tests {
+    use opencv::core::{CV_8UC3, Mat, Scalar};
+    use crate::pipeline::processing::preprocess::{ChannelOrder, Normalization, preprocess, preprocess_batch};
+
+    #[test]
+    fn test_preprocess() {
+        // B = 10, G = 20, R = 30
+        let image = Mat::new_rows_cols_with_default(4, 6, CV_8UC3, Scalar::new(10.0, 20.0, 30.0, 0.0)).unwrap();
+
+        let tensor = preprocess(&image, None, &Normalization::RETINA_FACE).unwrap();
+        assert_eq!(tensor.dim(), (1, 3, 4, 6));
+        assert_eq!(tensor[[0, 0, 3, 5]], 30.0);
+        assert_eq!(tensor[[0, 2, 0, 0]], 10.0);
+
+        let bgr = Normalization { channel_order: ChannelOrder::Bgr, ..Normalization::MINI_FAS };
+        let tensor = preprocess(&image, None, &bgr).unwrap();
+        assert_eq!(tensor[[0, 0, 1, 1]], 10.0);
+
+        let tensor = preprocess(&image, Some((2, 2)), &Normalization::FACE_QUALITY).unwrap();
+        assert_eq!(tensor.dim(), (1, 3, 2, 2));
+        assert!((tensor[[0, 0, 1, 1]] - (30.0 - 123.675) * 0.01712475).abs() < 1e-6);
+        assert!((tensor[[0, 2, 0, 1]] - (10.0 - 103.53) * 0.01742919).abs() < 1e-6);
+
+        let small = Mat::new_rows_cols_with_default(2, 3, CV_8UC3, Scalar::new(10.0, 20.0, 30.0, 0.0)).unwrap();
+        let tensor = preprocess_batch(&[small, image], (3, 2), &Normalization::FACE_IDENTIFICATION, 4).unwrap();
+        assert_eq!(tensor.dim(), (4, 3, 2, 3));
+        assert_eq!(tensor[[0, 1, 1, 2]], (20.0 - 127.5) * 0.0078125);
+        assert_eq!(tensor[[1, 0, 0, 0]], (30.0 - 127.5) * 0.0078125);
+        assert!(tensor.slice(ndarray::s![2.., .., .., ..]).iter().all(|v| *v == 0.0));
+
+        let gray = Mat::new_rows_cols_with_default(2, 2, opencv::core::CV_8UC1, Scalar::all(0.0)).unwrap();
+        assert!(preprocess(&gray, None, &Normalization::RETINA_FACE).is_err());
+    }
+}
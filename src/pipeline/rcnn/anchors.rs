@@ -23,7 +23,7 @@ pub fn anchors(height: usize, width: usize, stride: usize, base_anchors: &Array2
 #[cfg(test)]
 mod tests {
     use ndarray::array;
-    use crate::rcnn::anchors::anchors;
+    use crate::pipeline::rcnn::anchors::anchors;
 
     #[test]
     fn test_anchors() {
@@ -33,7 +33,7 @@ pub(crate) fn bbox_overlaps(boxes: &Array2<f32>, query_boxes: &Array2<f32>) -> A
 #[cfg(test)]
 mod tests {
     use ndarray::array;
-    use crate::rcnn::bbox::bbox_overlaps;
+    use crate::pipeline::rcnn::bbox::bbox_overlaps;
 
     #[test]
     fn test_bbox_overlaps() {
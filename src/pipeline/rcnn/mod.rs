@@ -1,4 +1,2 @@
 pub mod bbox;
-pub mod cpu_nms;
-pub mod gpu_nms;
 pub mod anchors;
\ No newline at end of file
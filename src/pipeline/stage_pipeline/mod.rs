@@ -0,0 +1,2 @@
+pub mod pipeline_stage;
+pub mod stage_pipeline;
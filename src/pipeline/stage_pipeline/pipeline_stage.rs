@@ -0,0 +1,388 @@
+use std::sync::Arc;
+use anyhow::Error;
+use async_trait::async_trait;
+use ndarray::{Array1, Array2, Array3, s};
+use opencv::core::Mat;
+use crate::pipeline::model_config::config::{FaceAntiSpoofingClass, FaceQualityClass, HeadPoseConfig, match_face_quality};
+use crate::pipeline::model_config::pipeline_config::StageKind;
+use crate::pipeline::module::face_alignment::FaceAlignment;
+use crate::pipeline::module::face_antispoofing::{AntiSpoofingScore, FaceAntiSpoofing};
+use crate::pipeline::module::face_detector::FaceDetector;
+use crate::pipeline::module::face_extraction::FaceExtraction;
+use crate::pipeline::module::face_quality::FaceQuality;
+use crate::pipeline::module::face_quality_assessment::FaceQualityAssessment;
+use crate::pipeline::module::face_selection::FaceSelection;
+use crate::pipeline::module::head_pose::{HeadPose, HeadPoseEstimation};
+use crate::pipeline::module::image_quality::{ImageQuality, ImageQualityReport};
+
+/// Request flags the stage rules can match on.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineOptions {
+    pub enroll: bool,
+    pub spoofing_check: bool,
+    /// Keep every detected face, selection becomes a no-op
+    pub all_faces: bool,
+    /// Stages the request asked for, `None` runs every stage of the pipeline
+    pub stages: Option<Vec<StageKind>>,
+}
+
+/// Everything the stages learn about one face.
+#[derive(Debug, Clone)]
+pub struct FaceContext {
+    /// Detection row, `[x_min, y_min, x_max, y_max, score]`
+    pub detection: Array1<f32>,
+    pub landmarks: Option<Array2<f32>>,
+    pub head_pose: Option<HeadPose>,
+    /// Whether `head_pose` is within the configured limits
+    pub head_pose_accepted: Option<bool>,
+    pub image_quality: Option<ImageQualityReport>,
+    pub aligned_face: Option<Mat>,
+    pub face_quality: Option<FaceQualityClass>,
+    pub quality_score: Option<f32>,
+    pub quality_assessment: Option<FaceQualityClass>,
+    pub quality_assessment_score: Option<f32>,
+    pub spoofing_check: Option<FaceAntiSpoofingClass>,
+    /// Calibrated live score behind `spoofing_check`
+    pub spoofing_score: Option<f32>,
+    /// `spoofing_score` with the per-scale scores it was fused from
+    pub anti_spoofing_score: Option<AntiSpoofingScore>,
+    pub facial_feature: Option<Array1<f32>>,
+    /// Face quality set by the rule that skipped a stage for this face
+    pub face_quality_verdict: Option<FaceQualityClass>,
+    pub skipped_stages: Vec<StageKind>,
+}
+
+impl FaceContext {
+    pub fn new(detection: Array1<f32>, landmarks: Option<Array2<f32>>) -> Self {
+        FaceContext {
+            detection,
+            landmarks,
+            head_pose: None,
+            head_pose_accepted: None,
+            image_quality: None,
+            aligned_face: None,
+            face_quality: None,
+            quality_score: None,
+            quality_assessment: None,
+            quality_assessment_score: None,
+            spoofing_check: None,
+            spoofing_score: None,
+            anti_spoofing_score: None,
+            facial_feature: None,
+            face_quality_verdict: None,
+            skipped_stages: vec![],
+        }
+    }
+
+    pub fn is_skipped(&self, stage: StageKind) -> bool {
+        self.skipped_stages.contains(&stage)
+    }
+}
+
+/// State threaded through the stages of one `StagePipeline::run`.
+#[derive(Debug, Clone)]
+pub struct PipelineContext {
+    pub options: PipelineOptions,
+    pub image: Mat,
+    pub face_count: i32,
+    pub detections: Array2<f32>,
+    pub key_points: Option<Array3<f32>>,
+    pub faces: Vec<FaceContext>,
+    pub stages_run: Vec<StageKind>,
+    pub stages_skipped: Vec<StageKind>,
+}
+
+impl PipelineContext {
+    pub fn new(image: Mat, options: PipelineOptions) -> Self {
+        PipelineContext {
+            options,
+            image,
+            face_count: 0,
+            detections: Array2::zeros((0, 5)),
+            key_points: None,
+            faces: vec![],
+            stages_run: vec![],
+            stages_skipped: vec![],
+        }
+    }
+}
+
+/// One step of a `StagePipeline`.
+///
+/// Per-face stages must leave alone the faces whose `skipped_stages` contain their kind.
+#[async_trait]
+pub trait PipelineStage: Send + Sync {
+    fn kind(&self) -> StageKind;
+
+    async fn run(&self, ctx: &mut PipelineContext) -> Result<(), Error>;
+}
+
+pub(crate) struct DetectionStage {
+    pub(crate) face_detector: Arc<dyn FaceDetector>,
+}
+
+#[async_trait]
+impl PipelineStage for DetectionStage {
+    fn kind(&self) -> StageKind {
+        StageKind::Detection
+    }
+
+    async fn run(&self, ctx: &mut PipelineContext) -> Result<(), Error> {
+        let face_detections = match self.face_detector.detect(ctx.image.clone()).await {
+            Ok(face_detections) => {face_detections}
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+        let detections = face_detections.rows();
+        let key_points = face_detections.landmarks;
+
+        ctx.face_count = detections.dim().0 as i32;
+        ctx.faces = (0..detections.dim().0).map(|idx| {
+            let face_point: Option<Array2<f32>> = key_points.as_ref().map(|kps| kps.slice(s![idx, .., ..]).to_owned());
+            FaceContext::new(detections.row(idx).to_owned(), face_point)
+        }).collect();
+        ctx.detections = detections;
+        ctx.key_points = key_points;
+        Ok(())
+    }
+}
+
+pub(crate) struct SelectionStage {
+    pub(crate) face_selection: FaceSelection,
+}
+
+#[async_trait]
+impl PipelineStage for SelectionStage {
+    fn kind(&self) -> StageKind {
+        StageKind::Selection
+    }
+
+    async fn run(&self, ctx: &mut PipelineContext) -> Result<(), Error> {
+        if ctx.options.all_faces || ctx.faces.is_empty() {
+            return Ok(())
+        }
+
+        let (selected_face_box, selected_face_point) = match self.face_selection.call(&ctx.image, ctx.detections.clone(), ctx.key_points.clone(), Some(ctx.options.enroll)) {
+            Ok((selected_face_box, selected_face_point)) => {(selected_face_box, selected_face_point)}
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+
+        ctx.faces = match selected_face_box {
+            None => vec![],
+            Some(selected_face_box) => vec![FaceContext::new(selected_face_box, selected_face_point)],
+        };
+        Ok(())
+    }
+}
+
+pub(crate) struct HeadPoseStage {
+    pub(crate) head_pose_estimation: HeadPoseEstimation,
+    pub(crate) head_pose_cfg: HeadPoseConfig,
+}
+
+#[async_trait]
+impl PipelineStage for HeadPoseStage {
+    fn kind(&self) -> StageKind {
+        StageKind::HeadPose
+    }
+
+    async fn run(&self, ctx: &mut PipelineContext) -> Result<(), Error> {
+        for face in ctx.faces.iter_mut().filter(|face| !face.is_skipped(StageKind::HeadPose)) {
+            let landmarks = match &face.landmarks {
+                None => continue,
+                Some(landmarks) => landmarks,
+            };
+            let head_pose = match self.head_pose_estimation.call(&ctx.image, landmarks) {
+                Ok(head_pose) => {head_pose}
+                Err(e) => {
+                    return Err(Error::from(e))
+                }
+            };
+            face.head_pose_accepted = Some(head_pose.is_within(
+                self.head_pose_cfg.max_yaw,
+                self.head_pose_cfg.max_pitch,
+                self.head_pose_cfg.max_roll,
+            ));
+            face.head_pose = Some(head_pose);
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct ImageQualityStage {
+    pub(crate) image_quality: ImageQuality,
+}
+
+#[async_trait]
+impl PipelineStage for ImageQualityStage {
+    fn kind(&self) -> StageKind {
+        StageKind::ImageQuality
+    }
+
+    async fn run(&self, ctx: &mut PipelineContext) -> Result<(), Error> {
+        for face in ctx.faces.iter_mut().filter(|face| !face.is_skipped(StageKind::ImageQuality)) {
+            let image_quality = match self.image_quality.call(&ctx.image, &face.detection, face.landmarks.as_ref()) {
+                Ok(image_quality) => {image_quality}
+                Err(e) => {
+                    return Err(Error::from(e))
+                }
+            };
+            // A face too small or too far outside the image stays unmeasured instead of failing the request
+            face.image_quality = image_quality;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct AlignmentStage {
+    pub(crate) face_alignment: FaceAlignment,
+}
+
+#[async_trait]
+impl PipelineStage for AlignmentStage {
+    fn kind(&self) -> StageKind {
+        StageKind::Alignment
+    }
+
+    async fn run(&self, ctx: &mut PipelineContext) -> Result<(), Error> {
+        for face in ctx.faces.iter_mut().filter(|face| !face.is_skipped(StageKind::Alignment)) {
+            let aligned_face_image = match self.face_alignment.call(&ctx.image, Some(face.detection.clone()), face.landmarks.clone()) {
+                Ok(aligned_face_image) => {aligned_face_image}
+                Err(e) => {
+                    return Err(Error::from(e))
+                }
+            };
+            face.aligned_face = Some(aligned_face_image);
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct QualityStage {
+    pub(crate) face_quality: FaceQuality,
+}
+
+#[async_trait]
+impl PipelineStage for QualityStage {
+    fn kind(&self) -> StageKind {
+        StageKind::Quality
+    }
+
+    async fn run(&self, ctx: &mut PipelineContext) -> Result<(), Error> {
+        for face in ctx.faces.iter_mut().filter(|face| !face.is_skipped(StageKind::Quality)) {
+            let aligned_face_image = match &face.aligned_face {
+                None => continue,
+                Some(aligned_face_image) => aligned_face_image.clone(),
+            };
+            let (quality_score, quality_class) = match self.face_quality.call(aligned_face_image).await {
+                Ok((quality_score, quality_class)) => {(quality_score, quality_class)}
+                Err(e) => {
+                    return Err(Error::from(e))
+                }
+            };
+            face.face_quality = Some(match_face_quality(quality_class[0].to_owned()));
+            face.quality_score = Some(quality_score[0]);
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct QualityAssessmentStage {
+    pub(crate) face_quality_assessment: FaceQualityAssessment,
+}
+
+#[async_trait]
+impl PipelineStage for QualityAssessmentStage {
+    fn kind(&self) -> StageKind {
+        StageKind::QualityAssessment
+    }
+
+    async fn run(&self, ctx: &mut PipelineContext) -> Result<(), Error> {
+        for face in ctx.faces.iter_mut().filter(|face| !face.is_skipped(StageKind::QualityAssessment)) {
+            let aligned_face_image = match &face.aligned_face {
+                None => continue,
+                Some(aligned_face_image) => aligned_face_image.clone(),
+            };
+            let (quality_assessment_score, quality_assessment_class) = match self.face_quality_assessment.call(aligned_face_image).await {
+                Ok((quality_assessment_score, quality_assessment_class)) => {(quality_assessment_score[0], quality_assessment_class[0])}
+                Err(e) => {
+                    return Err(Error::from(e))
+                }
+            };
+            face.quality_assessment = Some(match_face_quality(quality_assessment_class as usize));
+            face.quality_assessment_score = Some(quality_assessment_score);
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct AntiSpoofingStage {
+    pub(crate) face_anti_spoofing: FaceAntiSpoofing,
+}
+
+#[async_trait]
+impl PipelineStage for AntiSpoofingStage {
+    fn kind(&self) -> StageKind {
+        StageKind::AntiSpoofing
+    }
+
+    async fn run(&self, ctx: &mut PipelineContext) -> Result<(), Error> {
+        for face in ctx.faces.iter_mut().filter(|face| !face.is_skipped(StageKind::AntiSpoofing)) {
+            let score = match self.face_anti_spoofing.call(ctx.image.clone(), face.detection.clone()).await {
+                Ok(score) => {score}
+                Err(e) => {
+                    return Err(Error::from(e))
+                }
+            };
+            face.spoofing_check = Some(self.face_anti_spoofing.classify(&score));
+            face.spoofing_score = Some(score.live_score);
+            face.anti_spoofing_score = Some(score);
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct ExtractionStage {
+    pub(crate) face_extraction: FaceExtraction,
+}
+
+#[async_trait]
+impl PipelineStage for ExtractionStage {
+    fn kind(&self) -> StageKind {
+        StageKind::Extraction
+    }
+
+    async fn run(&self, ctx: &mut PipelineContext) -> Result<(), Error> {
+        let mut face_idxs: Vec<usize> = vec![];
+        let mut aligned_faces: Vec<Mat> = vec![];
+        for (idx, face) in ctx.faces.iter().enumerate() {
+            if face.is_skipped(StageKind::Extraction) {
+                continue
+            }
+            if let Some(aligned_face_image) = &face.aligned_face {
+                face_idxs.push(idx);
+                aligned_faces.push(aligned_face_image.clone());
+            }
+        }
+        if aligned_faces.is_empty() {
+            return Ok(())
+        }
+
+        // All aligned crops go through the extraction model together
+        let facial_features = match self.face_extraction.call_batch(aligned_faces).await {
+            Ok(facial_features) => {facial_features}
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+
+        for (idx, facial_feature) in face_idxs.into_iter().zip(facial_features.into_iter()) {
+            let feature_len = facial_feature.len();
+            ctx.faces[idx].facial_feature = Some(facial_feature.into_shape((feature_len,)).unwrap());
+        }
+        Ok(())
+    }
+}
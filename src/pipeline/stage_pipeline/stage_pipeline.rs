@@ -0,0 +1,498 @@
+use std::sync::Arc;
+use anyhow::Error;
+use opencv::core::Mat;
+use crate::pipeline::inference_backend::inference_backend::{InferenceBackend, ModelSpec};
+use crate::pipeline::model_config::config::{FaceDetectorKind, ModelsConfig};
+use crate::pipeline::model_config::pipeline_config::{PipelineDefinition, StageKind, StageRule};
+use crate::pipeline::module::face_alignment::FaceAlignment;
+use crate::pipeline::module::face_antispoofing::FaceAntiSpoofing;
+use crate::pipeline::module::face_detection::RetinaFaceDetection;
+use crate::pipeline::module::face_detection_scrfd::ScrfdDetection;
+use crate::pipeline::module::face_detection_yolo::YoloFaceDetection;
+use crate::pipeline::module::face_detector::{DetectionInput, FaceDetector};
+use crate::pipeline::module::face_extraction::FaceExtraction;
+use crate::pipeline::module::face_quality::FaceQuality;
+use crate::pipeline::module::face_quality_assessment::FaceQualityAssessment;
+use crate::pipeline::module::face_selection::FaceSelection;
+use crate::pipeline::module::head_pose::HeadPoseEstimation;
+use crate::pipeline::module::image_quality::ImageQuality;
+use crate::pipeline::stage_pipeline::pipeline_stage::{AlignmentStage, AntiSpoofingStage, DetectionStage, ExtractionStage,
+                                                      FaceContext, HeadPoseStage, ImageQualityStage, PipelineContext, PipelineOptions,
+                                                      PipelineStage, QualityAssessmentStage, QualityStage, SelectionStage};
+use crate::pipeline::utils::utils::byte_data_to_opencv;
+
+/// Runs the stages of a `PipelineDefinition` in order, applying its skip rules before each stage.
+#[derive(Clone)]
+pub struct StagePipeline {
+    stages: Vec<Arc<dyn PipelineStage>>,
+    rules: Vec<StageRule>,
+}
+
+impl StagePipeline {
+    pub fn stages(&self) -> Vec<StageKind> {
+        self.stages.iter().map(|stage| stage.kind()).collect()
+    }
+
+    pub async fn run(&self, im_bytes: &[u8], options: PipelineOptions) -> Result<PipelineContext, Error> {
+        let image = match byte_data_to_opencv(im_bytes) {
+            Ok(image) => {image}
+            Err(e) => {
+                return Err(Error::from(e))
+            }
+        };
+        self.run_image(image, options).await
+    }
+
+    /// Like `run` on an image that is already decoded.
+    pub async fn run_image(&self, image: Mat, options: PipelineOptions) -> Result<PipelineContext, Error> {
+        let selected = self.selected_stages(&options);
+        let mut ctx = PipelineContext::new(image, options);
+        for stage in self.stages.iter() {
+            let kind = stage.kind();
+            if let Some(selected) = &selected {
+                if !selected.contains(&kind) {
+                    ctx.stages_skipped.push(kind);
+                    continue
+                }
+            }
+            if !self.apply_rules(kind, &mut ctx) {
+                ctx.stages_skipped.push(kind);
+                continue
+            }
+            match stage.run(&mut ctx).await {
+                Ok(_) => {}
+                Err(e) => return Err(e)
+            };
+            ctx.stages_run.push(kind);
+        }
+        Ok(ctx)
+    }
+
+    /// Stages the request asked for plus the ones the matching rules read, `None` when every stage runs.
+    fn selected_stages(&self, options: &PipelineOptions) -> Option<Vec<StageKind>> {
+        let mut selected = match &options.stages {
+            None => return None,
+            Some(stages) => stages.clone(),
+        };
+        let rules = self.rules.iter()
+            .filter(|rule| selected.contains(&rule.skip) && rule_matches_options(rule, options));
+        let mut rule_inputs: Vec<StageKind> = vec![];
+        for rule in rules {
+            rule_inputs.extend(rule.input_stages());
+        }
+        // A rule gating a requested stage still gets the quality it needs, which requires an aligned face
+        if rule_inputs.iter().any(|kind| matches!(kind, StageKind::Quality | StageKind::QualityAssessment)) {
+            rule_inputs.push(StageKind::Alignment);
+        }
+        for kind in rule_inputs.into_iter() {
+            if !selected.contains(&kind) {
+                selected.push(kind);
+            }
+        }
+        Some(selected)
+    }
+
+    /// Marks the faces the rules take out of `kind`, returns whether the stage still has work to do.
+    fn apply_rules(&self, kind: StageKind, ctx: &mut PipelineContext) -> bool {
+        let mut skip_stage = false;
+        for rule in self.rules.iter().filter(|rule| rule.skip == kind) {
+            if !rule_matches_options(rule, &ctx.options) {
+                continue
+            }
+            if rule.has_face_conditions() {
+                for face in ctx.faces.iter_mut().filter(|face| rule_matches_face(rule, face)) {
+                    skip_face(face, kind, rule);
+                }
+            } else {
+                skip_stage = true;
+                for face in ctx.faces.iter_mut() {
+                    skip_face(face, kind, rule);
+                }
+            }
+        }
+
+        if skip_stage {
+            return false
+        }
+        if !kind.is_per_face() {
+            return kind == StageKind::Detection || !ctx.faces.is_empty()
+        }
+        ctx.faces.iter().any(|face| !face.is_skipped(kind))
+    }
+}
+
+fn skip_face(face: &mut FaceContext, kind: StageKind, rule: &StageRule) {
+    if !face.is_skipped(kind) {
+        face.skipped_stages.push(kind);
+    }
+    if rule.set_face_quality.is_some() {
+        face.face_quality_verdict = rule.set_face_quality.clone();
+    }
+}
+
+fn rule_matches_options(rule: &StageRule, options: &PipelineOptions) -> bool {
+    let enroll = match rule.enroll {
+        None => true,
+        Some(enroll) => enroll == options.enroll,
+    };
+    let spoofing_check = match rule.spoofing_check {
+        None => true,
+        Some(spoofing_check) => spoofing_check == options.spoofing_check,
+    };
+    enroll && spoofing_check
+}
+
+fn rule_matches_face(rule: &StageRule, face: &FaceContext) -> bool {
+    let quality_in = match (&rule.face_quality_in, &face.face_quality) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(classes), Some(face_quality)) => classes.contains(face_quality),
+    };
+    let quality_not_in = match (&rule.face_quality_not_in, &face.face_quality) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(classes), Some(face_quality)) => !classes.contains(face_quality),
+    };
+    let assessment_in = match (&rule.quality_assessment_in, &face.quality_assessment) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(classes), Some(quality_assessment)) => classes.contains(quality_assessment),
+    };
+    let assessment_not_in = match (&rule.quality_assessment_not_in, &face.quality_assessment) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(classes), Some(quality_assessment)) => !classes.contains(quality_assessment),
+    };
+    let head_pose_accepted = match (rule.head_pose_accepted, face.head_pose_accepted) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(accepted), Some(face_accepted)) => accepted == face_accepted,
+    };
+    let image_quality_passed = match (rule.image_quality_passed, &face.image_quality) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(passed), Some(image_quality)) => passed == image_quality.passed,
+    };
+    quality_in && quality_not_in && assessment_in && assessment_not_in && head_pose_accepted && image_quality_passed
+}
+
+/// Instantiates the modules a `PipelineDefinition` needs, querying the model spec of each one.
+pub struct StagePipelineBuilder {
+    inference_backend: Arc<dyn InferenceBackend>,
+    models_cfg: ModelsConfig,
+}
+
+impl StagePipelineBuilder {
+    pub fn new(inference_backend: Arc<dyn InferenceBackend>, models_cfg: &ModelsConfig) -> Self {
+        StagePipelineBuilder {
+            inference_backend,
+            models_cfg: models_cfg.clone(),
+        }
+    }
+
+    async fn model_spec(&self, model_name: &str) -> Result<ModelSpec, Error> {
+        match self.inference_backend.model_spec(model_name).await {
+            Ok(model_spec) => Ok(model_spec),
+            Err(e) => Err(Error::from(e))
+        }
+    }
+
+    pub async fn build(&self, definition: &PipelineDefinition) -> Result<StagePipeline, Error> {
+        let mut stages: Vec<Arc<dyn PipelineStage>> = Vec::with_capacity(definition.stages.len());
+        for kind in definition.stages.iter() {
+            let stage = match self.build_stage(*kind).await {
+                Ok(stage) => {stage}
+                Err(e) => return Err(e)
+            };
+            stages.push(stage);
+        }
+
+        Ok(StagePipeline {
+            stages,
+            rules: definition.rules.clone(),
+        })
+    }
+
+    /// Detector of the family set in `models.face_detection.detector`.
+    async fn face_detector(&self) -> Result<Arc<dyn FaceDetector>, Error> {
+        let face_detection_cfg = &self.models_cfg.face_detection;
+        let model_spec = match self.model_spec(&face_detection_cfg.model_name).await {
+            Ok(model_spec) => {model_spec}
+            Err(e) => return Err(e)
+        };
+        let input = DetectionInput::from_config(face_detection_cfg);
+        let face_detector: Arc<dyn FaceDetector> = match face_detection_cfg.detector {
+            FaceDetectorKind::RetinaFace => match RetinaFaceDetection::new(
+                Arc::clone(&self.inference_backend),
+                model_spec,
+                face_detection_cfg.model_name.to_string(),
+                input,
+                face_detection_cfg.max_batch_size,
+                face_detection_cfg.confidence_threshold,
+                face_detection_cfg.iou_threshold,
+            ).await {
+                Ok(face_detection) => Arc::new(face_detection),
+                Err(e) => return Err(e)
+            },
+            FaceDetectorKind::Scrfd => match ScrfdDetection::new(
+                Arc::clone(&self.inference_backend),
+                model_spec,
+                face_detection_cfg.model_name.to_string(),
+                input,
+                face_detection_cfg.confidence_threshold,
+                face_detection_cfg.iou_threshold,
+            ).await {
+                Ok(face_detection) => Arc::new(face_detection),
+                Err(e) => return Err(e)
+            },
+            FaceDetectorKind::YoloFace => match YoloFaceDetection::new(
+                Arc::clone(&self.inference_backend),
+                model_spec,
+                face_detection_cfg.model_name.to_string(),
+                input,
+                face_detection_cfg.confidence_threshold,
+                face_detection_cfg.iou_threshold,
+            ).await {
+                Ok(face_detection) => Arc::new(face_detection),
+                Err(e) => return Err(e)
+            },
+        };
+        Ok(face_detector)
+    }
+
+    async fn build_stage(&self, kind: StageKind) -> Result<Arc<dyn PipelineStage>, Error> {
+        let stage: Arc<dyn PipelineStage> = match kind {
+            StageKind::Detection => {
+                let face_detector = match self.face_detector().await {
+                    Ok(face_detector) => {face_detector}
+                    Err(e) => return Err(e)
+                };
+                Arc::new(DetectionStage { face_detector })
+            }
+            StageKind::Selection => {
+                let face_selection_cfg = &self.models_cfg.face_selection;
+                let face_selection = FaceSelection::new(
+                    face_selection_cfg.margin_center_left_ratio,
+                    face_selection_cfg.margin_center_right_ratio,
+                    face_selection_cfg.margin_edge_ratio,
+                    face_selection_cfg.minimum_face_ratio,
+                ).await;
+                Arc::new(SelectionStage { face_selection })
+            }
+            StageKind::HeadPose => {
+                Arc::new(HeadPoseStage {
+                    head_pose_estimation: HeadPoseEstimation::new(),
+                    head_pose_cfg: self.models_cfg.head_pose.clone(),
+                })
+            }
+            StageKind::ImageQuality => {
+                Arc::new(ImageQualityStage {
+                    image_quality: ImageQuality::new(self.models_cfg.image_quality.clone()),
+                })
+            }
+            StageKind::Alignment => {
+                let face_align_cfg = &self.models_cfg.face_alignment;
+                let face_alignment = FaceAlignment::new(
+                    face_align_cfg.image_size,
+                    face_align_cfg.standard_landmarks(),
+                );
+                Arc::new(AlignmentStage { face_alignment })
+            }
+            StageKind::Quality => {
+                let face_quality_cfg = &self.models_cfg.face_quality;
+                let model_spec = match self.model_spec(&face_quality_cfg.model_name).await {
+                    Ok(model_spec) => {model_spec}
+                    Err(e) => return Err(e)
+                };
+                let face_quality = match FaceQuality::new(
+                    Arc::clone(&self.inference_backend),
+                    model_spec,
+                    face_quality_cfg.model_name.to_string(),
+                    face_quality_cfg.image_size,
+                    face_quality_cfg.threshold,
+                ).await {
+                    Ok(face_quality) => {face_quality.with_normalization(&face_quality_cfg.normalization)}
+                    Err(e) => {
+                        return Err(Error::from(e))
+                    }
+                };
+                Arc::new(QualityStage { face_quality })
+            }
+            StageKind::QualityAssessment => {
+                let face_quality_assessment_cfg = &self.models_cfg.face_quality_assessment;
+                let model_spec = match self.model_spec(&face_quality_assessment_cfg.model_name).await {
+                    Ok(model_spec) => {model_spec}
+                    Err(e) => return Err(e)
+                };
+                let face_quality_assessment = match FaceQualityAssessment::new(
+                    Arc::clone(&self.inference_backend),
+                    model_spec,
+                    face_quality_assessment_cfg.model_name.to_string(),
+                    face_quality_assessment_cfg.image_size,
+                    face_quality_assessment_cfg.batch_size,
+                    face_quality_assessment_cfg.threshold,
+                ).await {
+                    Ok(face_quality_assessment) => {face_quality_assessment.with_normalization(&face_quality_assessment_cfg.normalization)}
+                    Err(e) => {
+                        return Err(Error::from(e))
+                    }
+                };
+                Arc::new(QualityAssessmentStage { face_quality_assessment })
+            }
+            StageKind::AntiSpoofing => {
+                let face_anti_spoofing_cfg = &self.models_cfg.face_anti_spoofing;
+                let mut model_specs: Vec<ModelSpec> = vec![];
+                for model_name in face_anti_spoofing_cfg.model_name.iter() {
+                    let model_spec = match self.model_spec(model_name).await {
+                        Ok(model_spec) => {model_spec}
+                        Err(e) => return Err(e)
+                    };
+                    model_specs.push(model_spec);
+                }
+                let face_anti_spoofing = match FaceAntiSpoofing::new(
+                    Arc::clone(&self.inference_backend),
+                    model_specs,
+                    face_anti_spoofing_cfg.model_name.clone(),
+                    face_anti_spoofing_cfg.image_size.clone(),
+                    face_anti_spoofing_cfg.scale.clone(),
+                    face_anti_spoofing_cfg.batch_size,
+                    face_anti_spoofing_cfg.threshold,
+                    face_anti_spoofing_cfg.calibration.clone(),
+                ).await {
+                    Ok(face_anti_spoofing) => {face_anti_spoofing.with_normalization(&face_anti_spoofing_cfg.normalization)}
+                    Err(e) => {
+                        return Err(Error::from(e))
+                    }
+                };
+                Arc::new(AntiSpoofingStage { face_anti_spoofing })
+            }
+            StageKind::Extraction => {
+                let face_extraction_cfg = &self.models_cfg.face_identification;
+                let model_spec = match self.model_spec(&face_extraction_cfg.model_name).await {
+                    Ok(model_spec) => {model_spec}
+                    Err(e) => return Err(e)
+                };
+                let face_extraction = match FaceExtraction::new(
+                    Arc::clone(&self.inference_backend),
+                    model_spec,
+                    face_extraction_cfg.model_name.to_string(),
+                    face_extraction_cfg.image_size,
+                    face_extraction_cfg.batch_size,
+                ).await {
+                    Ok(face_extraction) => {face_extraction.with_normalization(&face_extraction_cfg.normalization)}
+                    Err(e) => {
+                        return Err(Error::from(e))
+                    }
+                };
+                Arc::new(ExtractionStage { face_extraction })
+            }
+        };
+        Ok(stage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::pipeline::inference_backend::triton_backend::TritonBackend;
+    use crate::pipeline::model_config::config::{FaceQualityClass, ModelsConfig};
+    use crate::pipeline::model_config::pipeline_config::{PipelineDefinition, RequestedStage, StageKind, StageRule};
+    use crate::pipeline::stage_pipeline::pipeline_stage::PipelineOptions;
+    use crate::pipeline::stage_pipeline::stage_pipeline::StagePipelineBuilder;
+    use crate::pipeline::triton_client::mock_server::{MockTritonServer, synthetic_image_bytes};
+
+    #[tokio::test]
+    async fn test_stage_rules() {
+        let server = MockTritonServer::new()
+            .with_face_models()
+            .start().await.unwrap();
+        let inference_backend = Arc::new(TritonBackend::new(&server.host(), &server.port()).await.unwrap());
+        let builder = StagePipelineBuilder::new(inference_backend, &ModelsConfig::default());
+
+        // The scripted face is Good, a rule keyed on Good must skip extraction for it
+        let mut definition = PipelineDefinition::general();
+        definition.rules.push(StageRule {
+            skip: StageKind::Extraction,
+            face_quality_in: Some(vec![FaceQualityClass::Good]),
+            set_face_quality: Some(FaceQualityClass::Bad),
+            ..Default::default()
+        });
+        let pipeline = builder.build(&definition).await.unwrap();
+        let im_bytes = synthetic_image_bytes(640, 480).unwrap();
+
+        let ctx = pipeline.run(&im_bytes, PipelineOptions::default()).await.unwrap();
+        assert_eq!(ctx.face_count, 1);
+        assert_eq!(ctx.faces.len(), 1);
+        assert_eq!(ctx.faces[0].face_quality, Some(FaceQualityClass::Good));
+        assert_eq!(ctx.faces[0].face_quality_verdict, Some(FaceQualityClass::Bad));
+        assert!(ctx.faces[0].facial_feature.is_none());
+        assert_eq!(ctx.stages_run, vec![
+            StageKind::Detection, StageKind::Selection, StageKind::HeadPose, StageKind::ImageQuality,
+            StageKind::Alignment, StageKind::Quality,
+        ]);
+        assert!(ctx.faces[0].image_quality.is_some());
+        assert_eq!(ctx.stages_skipped, vec![StageKind::Extraction]);
+        assert_eq!(ctx.faces[0].head_pose_accepted, Some(true));
+
+        // A head pose rule gates enrollment like the quality rules
+        let mut definition = PipelineDefinition::general();
+        definition.rules = vec![StageRule {
+            head_pose_accepted: Some(true),
+            ..StageRule::head_pose_enroll_gate()
+        }];
+        let pipeline = builder.build(&definition).await.unwrap();
+        let ctx = pipeline.run(&im_bytes, PipelineOptions { enroll: true, ..Default::default() }).await.unwrap();
+        assert!(ctx.faces[0].head_pose.is_some());
+        assert_eq!(ctx.faces[0].face_quality_verdict, Some(FaceQualityClass::Bad));
+        assert!(ctx.faces[0].facial_feature.is_none());
+
+        let antispoofing_pipeline = builder.build(&PipelineDefinition::antispoofing()).await.unwrap();
+        let ctx = antispoofing_pipeline.run(&im_bytes, PipelineOptions {
+            enroll: true,
+            spoofing_check: false,
+            all_faces: false,
+            stages: None,
+        }).await.unwrap();
+        assert_eq!(ctx.stages_skipped, vec![StageKind::AntiSpoofing]);
+        assert!(ctx.faces[0].spoofing_check.is_none());
+        assert!(ctx.faces[0].facial_feature.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_stage_selection() {
+        let server = MockTritonServer::new()
+            .with_face_models()
+            .start().await.unwrap();
+        let inference_backend = Arc::new(TritonBackend::new(&server.host(), &server.port()).await.unwrap());
+        let builder = StagePipelineBuilder::new(inference_backend, &ModelsConfig::default());
+        let im_bytes = synthetic_image_bytes(640, 480).unwrap();
+
+        // A detect-only request never reaches the per-face models
+        let pipeline = builder.build(&PipelineDefinition::general()).await.unwrap();
+        let ctx = pipeline.run(&im_bytes, PipelineOptions {
+            stages: Some(RequestedStage::stage_kinds(&[RequestedStage::Detect])),
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(ctx.face_count, 1);
+        assert_eq!(ctx.stages_run, vec![StageKind::Detection, StageKind::Selection]);
+        assert_eq!(ctx.stages_skipped, vec![
+            StageKind::HeadPose, StageKind::ImageQuality, StageKind::Alignment, StageKind::Quality, StageKind::Extraction,
+        ]);
+        let model_names: Vec<String> = server.infer_requests().into_iter().map(|request| request.model_name).collect();
+        assert_eq!(model_names, vec!["face_detection_retina"]);
+
+        // The enroll gate on extraction still gets the qualities it reads
+        let antispoofing_pipeline = builder.build(&PipelineDefinition::antispoofing()).await.unwrap();
+        let ctx = antispoofing_pipeline.run(&im_bytes, PipelineOptions {
+            enroll: true,
+            stages: Some(RequestedStage::stage_kinds(&[RequestedStage::Embedding])),
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(ctx.stages_run, vec![
+            StageKind::Detection, StageKind::Selection, StageKind::HeadPose, StageKind::Alignment,
+            StageKind::Quality, StageKind::QualityAssessment, StageKind::Extraction,
+        ]);
+        assert_eq!(ctx.stages_skipped, vec![StageKind::ImageQuality, StageKind::AntiSpoofing]);
+        assert!(ctx.faces[0].facial_feature.is_some());
+    }
+}
@@ -1,3 +1,4 @@
+use std::sync::Arc;
 use anyhow::{Error, Result};
 use tonic::transport::Channel;
 
@@ -24,10 +25,12 @@ use crate::pipeline::triton_client::client::triton::{CudaSharedMemoryRegisterReq
                                            SystemSharedMemoryStatusResponse, SystemSharedMemoryUnregisterRequest,
                                            SystemSharedMemoryUnregisterResponse, TraceSettingRequest,
                                            TraceSettingResponse};
+use crate::pipeline::triton_client::recorder::{InferRecorder, RecordMode};
 
 #[derive(Debug, Clone)]
 pub struct TritonInferenceClient {
     c: GrpcInferenceServiceClient<Channel>,
+    recorder: Option<Arc<InferRecorder>>,
 }
 
 macro_rules! wrap_method_with_args {
@@ -65,6 +68,34 @@ impl TritonInferenceClient {
 
         Ok(TritonInferenceClient {
             c: client,
+            recorder: None,
+        })
+    }
+
+    /// Routes `model_config` and `model_infer` through the recorder.
+    ///
+    /// In replay mode the channel connects lazily, so no Triton has to be reachable.
+    pub(crate) async fn new_with_recorder(host: &str, port: &str, recorder: InferRecorder) -> Result<Self, Error> {
+        if recorder.mode() != RecordMode::Replay {
+            let mut client = match TritonInferenceClient::new(host, port).await {
+                Ok(client) => client,
+                Err(e) => return Err(e)
+            };
+            if recorder.mode() == RecordMode::Record {
+                client.recorder = Some(Arc::new(recorder));
+            }
+            return Ok(client)
+        }
+
+        let channel_url = format!("{}:{}", host, port);
+        let channel = match Channel::from_shared(channel_url) {
+            Ok(endpoint) => endpoint.connect_lazy(),
+            Err(e) => return Err(Error::from(e))
+        };
+
+        Ok(TritonInferenceClient {
+            c: GrpcInferenceServiceClient::new(channel),
+            recorder: Some(Arc::new(recorder)),
         })
     }
 
@@ -103,19 +134,46 @@ impl TritonInferenceClient {
         ModelMetadataResponse
     );
 
-    wrap_method_with_args!(
-        "Perform inference using specific model.",
-        model_infer,
-        ModelInferRequest,
-        ModelInferResponse
-    );
+    /// Perform inference using specific model.
+    pub async fn model_infer(&self, req: ModelInferRequest) -> Result<ModelInferResponse, Error> {
+        let recorder = match &self.recorder {
+            None => {
+                let response = self.c.clone().model_infer(tonic::Request::new(req)).await?;
+                return Ok(response.into_inner())
+            }
+            Some(recorder) => recorder,
+        };
 
-    wrap_method_with_args!(
-        "Get model configuration.",
-        model_config,
-        ModelConfigRequest,
-        ModelConfigResponse
-    );
+        if recorder.mode() == RecordMode::Replay {
+            return recorder.replay_infer(&req)
+        }
+        let response = self.c.clone().model_infer(tonic::Request::new(req.clone())).await?.into_inner();
+        match recorder.record_infer(&req, &response) {
+            Ok(_) => Ok(response),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Get model configuration.
+    pub async fn model_config(&self, req: ModelConfigRequest) -> Result<ModelConfigResponse, Error> {
+        let recorder = match &self.recorder {
+            None => {
+                let response = self.c.clone().model_config(tonic::Request::new(req)).await?;
+                return Ok(response.into_inner())
+            }
+            Some(recorder) => recorder,
+        };
+
+        if recorder.mode() == RecordMode::Replay {
+            return recorder.replay_config(&req.name)
+        }
+        let model_name = req.name.to_string();
+        let response = self.c.clone().model_config(tonic::Request::new(req)).await?.into_inner();
+        match recorder.record_config(&model_name, &response) {
+            Ok(_) => Ok(response),
+            Err(e) => Err(e)
+        }
+    }
 
     wrap_method_with_args!(
         "Get the cumulative inference statistics for a model.",
@@ -198,14 +256,19 @@ impl TritonInferenceClient {
 
 #[cfg(test)]
 mod tests {
-    use crate::triton_client::client::{TritonInferenceClient, RepositoryIndexRequest};
-    use crate::triton_client::client::triton::{InferTensorContents, ModelConfigRequest};
-    use crate::triton_client::client::triton::ModelInferRequest;
-    use crate::triton_client::client::triton::model_infer_request::InferInputTensor;
+    use crate::pipeline::triton_client::client::{TritonInferenceClient, RepositoryIndexRequest};
+    use crate::pipeline::triton_client::client::triton::{InferTensorContents, ModelConfigRequest};
+    use crate::pipeline::triton_client::client::triton::ModelInferRequest;
+    use crate::pipeline::triton_client::client::triton::model_infer_request::InferInputTensor;
+    use crate::pipeline::triton_client::mock_server::{fp32_infer_response, fp32_model_config, MockTritonServer};
+    use crate::pipeline::utils::utils::u8_to_f32_vec;
 
     #[tokio::test]
     async fn test_repository_index() {
-        let client = TritonInferenceClient::new("", "").await.unwrap();
+        let server = MockTritonServer::new()
+            .with_face_models()
+            .start().await.unwrap();
+        let client = TritonInferenceClient::new(&server.host(), &server.port()).await.unwrap();
 
         let models = client
             .repository_index(RepositoryIndexRequest {
@@ -213,14 +276,18 @@ mod tests {
                 ready: false,
             }).await.unwrap();
 
-        for model in models.models.iter() {
-            println!("{:?}", model);
-        }
+        let model_names: Vec<&str> = models.models.iter().map(|model| model.name.as_str()).collect();
+        assert_eq!(model_names.len(), 8);
+        assert!(model_names.contains(&"face_detection_retina"));
+        assert!(model_names.contains(&"miniFAS_2_7"));
     }
 
     #[tokio::test]
     async fn test_model_config() {
-        let client = TritonInferenceClient::new("", "").await.unwrap();
+        let server = MockTritonServer::new()
+            .with_face_models()
+            .start().await.unwrap();
+        let client = TritonInferenceClient::new(&server.host(), &server.port()).await.unwrap();
 
         let models = client
             .model_config(ModelConfigRequest {
@@ -229,27 +296,37 @@ mod tests {
             }).await.unwrap();
 
         let cfg_all = models.config.unwrap();
+        assert_eq!(cfg_all.input[0].dims, vec![1, 3, 640, 640]);
+        assert_eq!(cfg_all.output.len(), 9);
 
-        for cfg in cfg_all.output.iter() {
-            println!("{:?}", cfg);
-        }
-
-
+        let missing = client
+            .model_config(ModelConfigRequest {
+                name: "missing_model".to_string(),
+                version: "".to_string(),
+            }).await;
+        assert!(missing.is_err());
     }
 
     #[tokio::test]
     async fn test_model_infer() {
-        let client = TritonInferenceClient::new("", "").await.unwrap();
+        let server = MockTritonServer::new()
+            .with_model_config(fp32_model_config("echo", 1, vec![("input", vec![1, 2])], vec![("output", vec![1, 2])]))
+            .with_infer_responses("echo", vec![
+                fp32_infer_response("echo", vec![("output", vec![1, 2], vec![0.25, 0.75])]),
+                fp32_infer_response("echo", vec![("output", vec![1, 2], vec![1.0, 0.0])]),
+            ])
+            .start().await.unwrap();
+        let client = TritonInferenceClient::new(&server.host(), &server.port()).await.unwrap();
 
         let req = ModelInferRequest {
-            model_name: "face_detection_retina".to_string(),
+            model_name: "echo".to_string(),
             model_version: "".to_string(),
             id: "".to_string(),
             parameters: Default::default(),
             inputs: vec![InferInputTensor {
-                name: "data".to_string(),
-                datatype: "".to_string(),
-                shape: vec![1, 3, 640, 640],
+                name: "input".to_string(),
+                datatype: "FP32".to_string(),
+                shape: vec![1, 2],
                 parameters: Default::default(),
                 contents: Option::from(InferTensorContents {
                     bool_contents: vec![],
@@ -257,7 +334,7 @@ mod tests {
                     int64_contents: vec![],
                     uint_contents: vec![],
                     uint64_contents: vec![],
-                    fp32_contents: vec![],
+                    fp32_contents: vec![3.0, 4.0],
                     fp64_contents: vec![],
                     bytes_contents: vec![],
                 }),
@@ -266,9 +343,18 @@ mod tests {
             raw_input_contents: vec![],
         };
 
-        let models = client
-            .model_infer(req).await.unwrap();
+        let first = client.model_infer(req.clone()).await.unwrap();
+        assert_eq!(first.outputs[0].shape, vec![1, 2]);
+        assert_eq!(u8_to_f32_vec(&first.raw_output_contents[0]), vec![0.25, 0.75]);
+
+        // Scripted responses are replayed in order, the last one repeats
+        let second = client.model_infer(req.clone()).await.unwrap();
+        let third = client.model_infer(req).await.unwrap();
+        assert_eq!(u8_to_f32_vec(&second.raw_output_contents[0]), vec![1.0, 0.0]);
+        assert_eq!(u8_to_f32_vec(&third.raw_output_contents[0]), vec![1.0, 0.0]);
 
-        println!("{:?}", models.raw_output_contents);
+        let requests = server.infer_requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].inputs[0].contents.as_ref().unwrap().fp32_contents, vec![3.0, 4.0]);
     }
 }
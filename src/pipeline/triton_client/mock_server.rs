@@ -0,0 +1,523 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::net::SocketAddr;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use anyhow::Error;
+use ndarray::{Array2, Array4};
+use opencv::core::{Mat, Scalar, Vector, CV_8UC3};
+use opencv::imgcodecs::imencode;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tonic::codegen::BoxStream;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+use crate::pipeline::triton_client::shared_memory::parse_shared_memory_parameters;
+use crate::pipeline::triton_client::tensor_encoding::{decode_raw, TensorDataType};
+use crate::pipeline::triton_client::client::triton::grpc_inference_service_server::{GrpcInferenceService, GrpcInferenceServiceServer};
+use crate::pipeline::triton_client::client::triton::model_infer_response::InferOutputTensor;
+use crate::pipeline::triton_client::client::triton::repository_index_response::ModelIndex;
+use crate::pipeline::triton_client::client::triton::{CudaSharedMemoryRegisterRequest, CudaSharedMemoryRegisterResponse,
+                                                     CudaSharedMemoryStatusRequest, CudaSharedMemoryStatusResponse,
+                                                     CudaSharedMemoryUnregisterRequest, CudaSharedMemoryUnregisterResponse,
+                                                     DataType, LogSettingsRequest, LogSettingsResponse,
+                                                     ModelConfig, ModelConfigRequest, ModelConfigResponse,
+                                                     ModelInferRequest, ModelInferResponse, ModelInput,
+                                                     ModelMetadataRequest, ModelMetadataResponse, ModelOutput,
+                                                     ModelReadyRequest, ModelReadyResponse, ModelStatisticsRequest,
+                                                     ModelStatisticsResponse, ModelStreamInferResponse,
+                                                     RepositoryIndexRequest, RepositoryIndexResponse,
+                                                     RepositoryModelLoadRequest, RepositoryModelLoadResponse,
+                                                     RepositoryModelUnloadRequest, RepositoryModelUnloadResponse,
+                                                     ServerLiveRequest, ServerLiveResponse, ServerMetadataRequest,
+                                                     ServerMetadataResponse, ServerReadyRequest, ServerReadyResponse,
+                                                     SystemSharedMemoryRegisterRequest, SystemSharedMemoryRegisterResponse,
+                                                     SystemSharedMemoryStatusRequest, SystemSharedMemoryStatusResponse,
+                                                     SystemSharedMemoryUnregisterRequest, SystemSharedMemoryUnregisterResponse,
+                                                     TraceSettingRequest, TraceSettingResponse};
+
+pub type InferHandler = Arc<dyn Fn(&ModelInferRequest) -> Result<ModelInferResponse, Status> + Send + Sync>;
+
+/// In-process `GrpcInferenceService` serving canned model configs and scripted inference responses.
+///
+/// Every `ModelInferRequest` is recorded so tests can assert on what the pipelines sent.
+#[derive(Clone, Default)]
+pub struct MockTritonServer {
+    model_configs: HashMap<String, ModelConfig>,
+    infer_handlers: HashMap<String, InferHandler>,
+    infer_requests: Arc<Mutex<Vec<ModelInferRequest>>>,
+    shared_memory_regions: Arc<Mutex<HashMap<String, PathBuf>>>,
+}
+
+/// Running mock server, stopped when dropped.
+pub struct MockTritonHandle {
+    addr: SocketAddr,
+    infer_requests: Arc<Mutex<Vec<ModelInferRequest>>>,
+    shared_memory_regions: Arc<Mutex<HashMap<String, PathBuf>>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockTritonHandle {
+    pub fn host(&self) -> String {
+        format!("http://{}", self.addr.ip())
+    }
+
+    pub fn port(&self) -> String {
+        self.addr.port().to_string()
+    }
+
+    pub fn infer_requests(&self) -> Vec<ModelInferRequest> {
+        self.infer_requests.lock().unwrap().clone()
+    }
+
+    /// Names of the system shared-memory regions currently registered.
+    pub fn shared_memory_regions(&self) -> Vec<String> {
+        self.shared_memory_regions.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+impl Drop for MockTritonHandle {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+impl MockTritonServer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_model_config(mut self, model_config: ModelConfig) -> Self {
+        self.model_configs.insert(model_config.name.to_string(), model_config);
+        self
+    }
+
+    /// Answers every inference of the model with the output of `handler`.
+    pub fn with_infer_handler<F>(mut self, model_name: &str, handler: F) -> Self
+    where
+        F: Fn(&ModelInferRequest) -> Result<ModelInferResponse, Status> + Send + Sync + 'static,
+    {
+        self.infer_handlers.insert(model_name.to_string(), Arc::new(handler));
+        self
+    }
+
+    /// Replays the responses in order, the last one is repeated once the script is exhausted.
+    pub fn with_infer_responses(self, model_name: &str, responses: Vec<ModelInferResponse>) -> Self {
+        let calls = Mutex::new(0usize);
+        self.with_infer_handler(model_name, move |_| {
+            let mut calls = calls.lock().unwrap();
+            let response = match responses.get(usize::min(*calls, responses.len().saturating_sub(1))) {
+                None => return Err(Status::not_found("no scripted response")),
+                Some(response) => response.clone(),
+            };
+            *calls += 1;
+            Ok(response)
+        })
+    }
+
+    /// Registers every model used by `GeneralPipeline` and `AntiSpoofingPipeline`, scripted to
+    /// detect one good quality, live face in any image.
+    pub fn with_face_models(self) -> Self {
+        let mut server = self
+            .with_model_config(fp32_model_config("face_detection_retina", 1, vec![("data", vec![1, 3, 640, 640])], retina_face_outputs()))
+            .with_infer_handler("face_detection_retina", |request| {
+                Ok(fp32_infer_response(&request.model_name, retina_face_response()))
+            })
+            .with_model_config(fp32_model_config("face_quality", 1, vec![("input", vec![1, 3, 112, 112])], vec![("output", vec![1, 4])]))
+            .with_infer_handler("face_quality", |request| {
+                let rows = batch_size(request);
+                let scores = Array2::<f32>::from_shape_fn((rows, 4), |(_, c)| [0.05, 0.9, 0.03, 0.02][c]);
+                Ok(fp32_infer_response(&request.model_name, vec![("output", vec![rows as i64, 4], scores.iter().cloned().collect())]))
+            })
+            .with_model_config(fp32_model_config("face_identification", 4, vec![("data", vec![4, 3, 112, 112])], vec![("fc1", vec![4, 512])]))
+            .with_infer_handler("face_identification", |request| {
+                // The feature follows the pixels, so different faces give different features
+                let input = match input_values(request, 0) {
+                    Ok(input) => {input}
+                    Err(e) => return Err(Status::invalid_argument(e.to_string()))
+                };
+                let rows = batch_size(request);
+                let row_len = input.len() / usize::max(rows, 1);
+                let features = Array2::from_shape_fn((rows, 512), |(r, c)| 1.0 + input[r * row_len + c * 7]);
+                Ok(fp32_infer_response(&request.model_name, vec![("fc1", vec![rows as i64, 512], features.iter().cloned().collect())]))
+            })
+            .with_model_config(fp32_model_config("face_quality_assetment", 1, vec![("input", vec![1, 3, 112, 112])], vec![("output", vec![1, 1])]))
+            .with_infer_handler("face_quality_assetment", |request| {
+                Ok(fp32_infer_response(&request.model_name, vec![("output", vec![1, 1], vec![70.0])]))
+            });
+
+        for (model_name, size) in [("miniFAS_4", 80), ("miniFAS_2_7", 80), ("miniFAS_2", 256), ("miniFAS_1", 128)] {
+            server = server
+                .with_model_config(fp32_model_config(model_name, 1, vec![("input", vec![1, 3, size, size])], vec![("output", vec![1, 3])]))
+                .with_infer_handler(model_name, |request| {
+                    Ok(fp32_infer_response(&request.model_name, vec![("output", vec![1, 3], vec![0.05, 0.9, 0.05])]))
+                });
+        }
+        server
+    }
+
+    /// Serves on an ephemeral localhost port until the returned handle is dropped.
+    pub async fn start(self) -> Result<MockTritonHandle, Error> {
+        let listener = match TcpListener::bind("127.0.0.1:0").await {
+            Ok(listener) => {listener}
+            Err(e) => return Err(Error::from(e))
+        };
+        let addr = match listener.local_addr() {
+            Ok(addr) => {addr}
+            Err(e) => return Err(Error::from(e))
+        };
+        let incoming = match TcpIncoming::from_listener(listener, true, None) {
+            Ok(incoming) => {incoming}
+            Err(e) => return Err(Error::msg(e.to_string()))
+        };
+
+        let infer_requests = Arc::clone(&self.infer_requests);
+        let shared_memory_regions = Arc::clone(&self.shared_memory_regions);
+        let (shutdown, shutdown_signal) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let _ = Server::builder()
+                .add_service(GrpcInferenceServiceServer::new(self))
+                .serve_with_incoming_shutdown(incoming, async {
+                    let _ = shutdown_signal.await;
+                })
+                .await;
+        });
+
+        Ok(MockTritonHandle {
+            addr,
+            infer_requests,
+            shared_memory_regions,
+            shutdown: Some(shutdown),
+        })
+    }
+}
+
+/// Values of the input at `idx`, read from `raw_input_contents` like Triton or from the typed contents.
+pub fn input_values(request: &ModelInferRequest, idx: usize) -> Result<Vec<f32>, Error> {
+    let input = match request.inputs.get(idx) {
+        None => return Err(Error::msg(format!("request has no input {idx}"))),
+        Some(input) => input,
+    };
+    if let Some(raw_input) = request.raw_input_contents.get(idx) {
+        let datatype = match input.datatype.parse::<TensorDataType>() {
+            Ok(datatype) => {datatype}
+            Err(e) => return Err(e)
+        };
+        return decode_raw(raw_input, datatype)
+    }
+    match input.contents.as_ref() {
+        None => Err(Error::msg(format!("input {} has no contents", input.name))),
+        Some(contents) => Ok(contents.fp32_contents.clone()),
+    }
+}
+
+fn batch_size(request: &ModelInferRequest) -> usize {
+    request.inputs.first()
+        .and_then(|input| input.shape.first())
+        .map(|dim| *dim as usize)
+        .unwrap_or(1)
+}
+
+/// Uniform grey JPEG, the scripted models answer the same whatever the pixels are.
+pub fn synthetic_image_bytes(width: i32, height: i32) -> Result<Vec<u8>, Error> {
+    let image = match Mat::new_rows_cols_with_default(height, width, CV_8UC3, Scalar::all(128.0)) {
+        Ok(image) => {image}
+        Err(e) => return Err(Error::from(e))
+    };
+    let mut im_bytes: Vector<u8> = Vector::new();
+    match imencode(".jpg", &image, &mut im_bytes, &Vector::new()) {
+        Ok(_) => {}
+        Err(e) => return Err(Error::from(e))
+    };
+    Ok(im_bytes.to_vec())
+}
+
+pub fn fp32_model_config(model_name: &str, max_batch_size: i32, inputs: Vec<(&str, Vec<i64>)>, outputs: Vec<(&str, Vec<i64>)>) -> ModelConfig {
+    ModelConfig {
+        name: model_name.to_string(),
+        max_batch_size,
+        input: inputs.into_iter().map(|(name, dims)| ModelInput {
+            name: name.to_string(),
+            data_type: DataType::TypeFp32 as i32,
+            dims,
+            ..Default::default()
+        }).collect(),
+        output: outputs.into_iter().map(|(name, dims)| ModelOutput {
+            name: name.to_string(),
+            data_type: DataType::TypeFp32 as i32,
+            dims,
+            ..Default::default()
+        }).collect(),
+        ..Default::default()
+    }
+}
+
+/// Builds a response the way Triton does, with every output in `raw_output_contents`.
+pub fn fp32_infer_response(model_name: &str, outputs: Vec<(&str, Vec<i64>, Vec<f32>)>) -> ModelInferResponse {
+    let mut response = ModelInferResponse {
+        model_name: model_name.to_string(),
+        ..Default::default()
+    };
+    for (name, shape, data) in outputs {
+        response.outputs.push(InferOutputTensor {
+            name: name.to_string(),
+            datatype: "FP32".to_string(),
+            shape,
+            ..Default::default()
+        });
+        response.raw_output_contents.push(data.iter().flat_map(|v| v.to_le_bytes()).collect());
+    }
+    response
+}
+
+const RETINA_FACE_STRIDES: [i64; 3] = [32, 16, 8];
+const RETINA_FACE_NUM_ANCHORS: usize = 2;
+
+fn retina_face_outputs() -> Vec<(&'static str, Vec<i64>)> {
+    let names = [
+        ["face_rpn_cls_prob_reshape_stride32", "face_rpn_bbox_pred_stride32", "face_rpn_landmark_pred_stride32"],
+        ["face_rpn_cls_prob_reshape_stride16", "face_rpn_bbox_pred_stride16", "face_rpn_landmark_pred_stride16"],
+        ["face_rpn_cls_prob_reshape_stride8", "face_rpn_bbox_pred_stride8", "face_rpn_landmark_pred_stride8"],
+    ];
+    let a = RETINA_FACE_NUM_ANCHORS as i64;
+    let mut outputs = vec![];
+    for (stride, [cls, bbox, landmark]) in RETINA_FACE_STRIDES.iter().zip(names) {
+        let size = 640 / stride;
+        outputs.push((cls, vec![1, 2 * a, size, size]));
+        outputs.push((bbox, vec![1, 4 * a, size, size]));
+        outputs.push((landmark, vec![1, 10 * a, size, size]));
+    }
+    outputs
+}
+
+/// One confident face around the image centre on the stride 16 level, 1.5 times its first anchor.
+fn retina_face_response() -> Vec<(&'static str, Vec<i64>, Vec<f32>)> {
+    // Five points relative to the anchor size, eyes, nose and mouth corners of a frontal face
+    let landmark_deltas = [[-0.2, -0.15], [0.2, -0.15], [0.0, 0.08], [-0.165, 0.31], [0.165, 0.31]];
+    let (face_y, face_x) = (20usize, 20usize);
+
+    let mut outputs = vec![];
+    for ((name, shape), stride) in retina_face_outputs().into_iter().zip(RETINA_FACE_STRIDES.iter().flat_map(|s| [*s; 3])) {
+        let dims = (shape[0] as usize, shape[1] as usize, shape[2] as usize, shape[3] as usize);
+        let mut array = Array4::<f32>::zeros(dims);
+        if stride == 16 {
+            if name.contains("cls") {
+                array[[0, RETINA_FACE_NUM_ANCHORS, face_y, face_x]] = 0.99;
+            } else if name.contains("bbox") {
+                array[[0, 2, face_y, face_x]] = 1.5f32.ln();
+                array[[0, 3, face_y, face_x]] = 1.5f32.ln();
+            } else {
+                for (p, [dx, dy]) in landmark_deltas.iter().enumerate() {
+                    array[[0, p * 2, face_y, face_x]] = *dx;
+                    array[[0, p * 2 + 1, face_y, face_x]] = *dy;
+                }
+            }
+        }
+        outputs.push((name, shape, array.iter().cloned().collect()));
+    }
+    outputs
+}
+
+impl MockTritonServer {
+    fn shared_memory_path(&self, region_name: &str) -> Result<PathBuf, Status> {
+        match self.shared_memory_regions.lock().unwrap().get(region_name) {
+            None => Err(Status::invalid_argument(format!("unregistered shared memory region {region_name}"))),
+            Some(path) => Ok(path.clone()),
+        }
+    }
+
+    /// Moves the inputs placed in shared memory into `raw_input_contents`.
+    fn read_shared_memory_inputs(&self, request: &mut ModelInferRequest) -> Result<(), Status> {
+        if request.inputs.iter().all(|input| parse_shared_memory_parameters(&input.parameters).is_none()) {
+            return Ok(())
+        }
+        let mut raw_inputs = std::mem::take(&mut request.raw_input_contents).into_iter();
+        let mut raw_input_contents: Vec<Vec<u8>> = Vec::with_capacity(request.inputs.len());
+        for input in request.inputs.iter() {
+            let raw_input = match parse_shared_memory_parameters(&input.parameters) {
+                None => raw_inputs.next().unwrap_or_default(),
+                Some((region_name, offset, byte_size)) => {
+                    let path = match self.shared_memory_path(&region_name) {
+                        Ok(path) => {path}
+                        Err(status) => return Err(status)
+                    };
+                    let mut bytes = vec![0u8; byte_size];
+                    let read = File::open(path).and_then(|file| file.read_exact_at(&mut bytes, offset as u64));
+                    if let Err(e) = read {
+                        return Err(Status::internal(e.to_string()))
+                    }
+                    bytes
+                }
+            };
+            raw_input_contents.push(raw_input);
+        }
+        request.raw_input_contents = raw_input_contents;
+        Ok(())
+    }
+
+    /// Writes the outputs requested in shared memory to their region and drops them from the response.
+    fn write_shared_memory_outputs(&self, request: &ModelInferRequest, response: &mut ModelInferResponse) -> Result<(), Status> {
+        let mut raw_output_contents: Vec<Vec<u8>> = vec![];
+        for (output, raw_output) in response.outputs.iter().zip(std::mem::take(&mut response.raw_output_contents)) {
+            let shared_memory = request.outputs.iter()
+                .find(|requested| requested.name == output.name)
+                .and_then(|requested| parse_shared_memory_parameters(&requested.parameters));
+            let (region_name, offset, byte_size) = match shared_memory {
+                None => {
+                    raw_output_contents.push(raw_output);
+                    continue
+                }
+                Some(shared_memory) => shared_memory,
+            };
+            if raw_output.len() > byte_size {
+                return Err(Status::invalid_argument(format!("output {} needs {} bytes, got {byte_size}", output.name, raw_output.len())))
+            }
+            let path = match self.shared_memory_path(&region_name) {
+                Ok(path) => {path}
+                Err(status) => return Err(status)
+            };
+            let written = OpenOptions::new().write(true).open(path).and_then(|file| file.write_all_at(&raw_output, offset as u64));
+            if let Err(e) = written {
+                return Err(Status::internal(e.to_string()))
+            }
+        }
+        response.raw_output_contents = raw_output_contents;
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl GrpcInferenceService for MockTritonServer {
+    async fn server_live(&self, _request: Request<ServerLiveRequest>) -> Result<Response<ServerLiveResponse>, Status> {
+        Ok(Response::new(ServerLiveResponse { live: true }))
+    }
+
+    async fn server_ready(&self, _request: Request<ServerReadyRequest>) -> Result<Response<ServerReadyResponse>, Status> {
+        Ok(Response::new(ServerReadyResponse { ready: true }))
+    }
+
+    async fn model_ready(&self, request: Request<ModelReadyRequest>) -> Result<Response<ModelReadyResponse>, Status> {
+        let ready = self.model_configs.contains_key(&request.get_ref().name);
+        Ok(Response::new(ModelReadyResponse { ready }))
+    }
+
+    async fn server_metadata(&self, _request: Request<ServerMetadataRequest>) -> Result<Response<ServerMetadataResponse>, Status> {
+        Ok(Response::new(ServerMetadataResponse {
+            name: "mock_triton".to_string(),
+            ..Default::default()
+        }))
+    }
+
+    async fn model_metadata(&self, _request: Request<ModelMetadataRequest>) -> Result<Response<ModelMetadataResponse>, Status> {
+        Err(Status::unimplemented("model_metadata"))
+    }
+
+    async fn model_infer(&self, request: Request<ModelInferRequest>) -> Result<Response<ModelInferResponse>, Status> {
+        let mut request = request.into_inner();
+        self.infer_requests.lock().unwrap().push(request.clone());
+
+        let handler = match self.infer_handlers.get(&request.model_name) {
+            None => return Err(Status::not_found(format!("no infer handler for model {}", request.model_name))),
+            Some(handler) => handler,
+        };
+        // Handlers always see raw contents, whichever way the inputs came in
+        if let Err(status) = self.read_shared_memory_inputs(&mut request) {
+            return Err(status)
+        }
+        let mut response = match handler(&request) {
+            Ok(response) => {response}
+            Err(status) => return Err(status)
+        };
+        if let Err(status) = self.write_shared_memory_outputs(&request, &mut response) {
+            return Err(status)
+        }
+        Ok(Response::new(response))
+    }
+
+    type ModelStreamInferStream = BoxStream<ModelStreamInferResponse>;
+
+    async fn model_stream_infer(&self, _request: Request<Streaming<ModelInferRequest>>) -> Result<Response<Self::ModelStreamInferStream>, Status> {
+        Err(Status::unimplemented("model_stream_infer"))
+    }
+
+    async fn model_config(&self, request: Request<ModelConfigRequest>) -> Result<Response<ModelConfigResponse>, Status> {
+        match self.model_configs.get(&request.get_ref().name) {
+            None => Err(Status::not_found(format!("no model config for model {}", request.get_ref().name))),
+            Some(model_config) => Ok(Response::new(ModelConfigResponse {
+                config: Some(model_config.clone()),
+            })),
+        }
+    }
+
+    async fn model_statistics(&self, _request: Request<ModelStatisticsRequest>) -> Result<Response<ModelStatisticsResponse>, Status> {
+        Err(Status::unimplemented("model_statistics"))
+    }
+
+    async fn repository_index(&self, _request: Request<RepositoryIndexRequest>) -> Result<Response<RepositoryIndexResponse>, Status> {
+        let mut models: Vec<ModelIndex> = self.model_configs.keys().map(|name| ModelIndex {
+            name: name.to_string(),
+            version: "1".to_string(),
+            state: "READY".to_string(),
+            reason: "".to_string(),
+        }).collect();
+        models.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Response::new(RepositoryIndexResponse { models }))
+    }
+
+    async fn repository_model_load(&self, _request: Request<RepositoryModelLoadRequest>) -> Result<Response<RepositoryModelLoadResponse>, Status> {
+        Err(Status::unimplemented("repository_model_load"))
+    }
+
+    async fn repository_model_unload(&self, _request: Request<RepositoryModelUnloadRequest>) -> Result<Response<RepositoryModelUnloadResponse>, Status> {
+        Err(Status::unimplemented("repository_model_unload"))
+    }
+
+    async fn system_shared_memory_status(&self, _request: Request<SystemSharedMemoryStatusRequest>) -> Result<Response<SystemSharedMemoryStatusResponse>, Status> {
+        Err(Status::unimplemented("system_shared_memory_status"))
+    }
+
+    async fn system_shared_memory_register(&self, request: Request<SystemSharedMemoryRegisterRequest>) -> Result<Response<SystemSharedMemoryRegisterResponse>, Status> {
+        let request = request.into_inner();
+        let path = Path::new("/dev/shm").join(request.key.trim_start_matches('/'));
+        if !path.is_file() {
+            return Err(Status::invalid_argument(format!("unable to open shared memory region {}", request.key)))
+        }
+        self.shared_memory_regions.lock().unwrap().insert(request.name, path);
+        Ok(Response::new(SystemSharedMemoryRegisterResponse {}))
+    }
+
+    async fn system_shared_memory_unregister(&self, request: Request<SystemSharedMemoryUnregisterRequest>) -> Result<Response<SystemSharedMemoryUnregisterResponse>, Status> {
+        let request = request.into_inner();
+        let mut shared_memory_regions = self.shared_memory_regions.lock().unwrap();
+        if request.name.is_empty() {
+            shared_memory_regions.clear();
+        } else {
+            shared_memory_regions.remove(&request.name);
+        }
+        Ok(Response::new(SystemSharedMemoryUnregisterResponse {}))
+    }
+
+    async fn cuda_shared_memory_status(&self, _request: Request<CudaSharedMemoryStatusRequest>) -> Result<Response<CudaSharedMemoryStatusResponse>, Status> {
+        Err(Status::unimplemented("cuda_shared_memory_status"))
+    }
+
+    async fn cuda_shared_memory_register(&self, _request: Request<CudaSharedMemoryRegisterRequest>) -> Result<Response<CudaSharedMemoryRegisterResponse>, Status> {
+        Err(Status::unimplemented("cuda_shared_memory_register"))
+    }
+
+    async fn cuda_shared_memory_unregister(&self, _request: Request<CudaSharedMemoryUnregisterRequest>) -> Result<Response<CudaSharedMemoryUnregisterResponse>, Status> {
+        Err(Status::unimplemented("cuda_shared_memory_unregister"))
+    }
+
+    async fn trace_setting(&self, _request: Request<TraceSettingRequest>) -> Result<Response<TraceSettingResponse>, Status> {
+        Err(Status::unimplemented("trace_setting"))
+    }
+
+    async fn log_settings(&self, _request: Request<LogSettingsRequest>) -> Result<Response<LogSettingsResponse>, Status> {
+        Err(Status::unimplemented("log_settings"))
+    }
+}
@@ -1 +1,6 @@
-pub mod client;
\ No newline at end of file
+pub mod client;
+pub mod recorder;
+pub mod tensor_encoding;
+pub mod shared_memory;
+#[cfg(test)]
+pub mod mock_server;
@@ -0,0 +1,244 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use anyhow::Error;
+use prost::Message;
+use crate::pipeline::triton_client::client::triton::{ModelConfigResponse, ModelInferRequest, ModelInferResponse};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordMode {
+    /// Talk to Triton, nothing is written
+    Off,
+    /// Talk to Triton and write every exchange as a fixture
+    Record,
+    /// Answer from the fixtures only, Triton is never called
+    Replay,
+}
+
+impl FromStr for RecordMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "off" => Ok(RecordMode::Off),
+            "record" => Ok(RecordMode::Record),
+            "replay" => Ok(RecordMode::Replay),
+            _ => Err(Error::msg(format!("unknown triton record mode {s}, expected one of off, record, replay"))),
+        }
+    }
+}
+
+impl fmt::Display for RecordMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordMode::Off => write!(f, "off"),
+            RecordMode::Record => write!(f, "record"),
+            RecordMode::Replay => write!(f, "replay"),
+        }
+    }
+}
+
+/// Stores Triton exchanges as prost-encoded golden fixtures.
+///
+/// Fixtures live under `<fixture_dir>/<model_name>/`: `config.pb` holds the `ModelConfigResponse`,
+/// every inference is kept as `<input_hash>.request.pb` and `<input_hash>.response.pb`.
+#[derive(Debug, Clone)]
+pub struct InferRecorder {
+    mode: RecordMode,
+    fixture_dir: PathBuf,
+}
+
+impl InferRecorder {
+    pub fn new<P: AsRef<Path>>(mode: RecordMode, fixture_dir: P) -> Self {
+        InferRecorder {
+            mode,
+            fixture_dir: fixture_dir.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn mode(&self) -> RecordMode {
+        self.mode
+    }
+
+    pub fn fixture_dir(&self) -> &Path {
+        &self.fixture_dir
+    }
+
+    /// FNV-1a hash of everything that determines the output: model, version and the input tensors.
+    ///
+    /// The request id and parameters are left out so the same inputs always map to the same fixture.
+    pub fn input_hash(request: &ModelInferRequest) -> String {
+        let mut hash = FNV_OFFSET_BASIS;
+        let mut update = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        };
+
+        update(request.model_name.as_bytes());
+        update(request.model_version.as_bytes());
+        for input in request.inputs.iter() {
+            update(input.name.as_bytes());
+            update(input.datatype.as_bytes());
+            for dim in input.shape.iter() {
+                update(&dim.to_le_bytes());
+            }
+            if let Some(contents) = &input.contents {
+                for value in contents.fp32_contents.iter() {
+                    update(&value.to_le_bytes());
+                }
+                for value in contents.int_contents.iter() {
+                    update(&value.to_le_bytes());
+                }
+                for value in contents.int64_contents.iter() {
+                    update(&value.to_le_bytes());
+                }
+                for value in contents.uint_contents.iter() {
+                    update(&value.to_le_bytes());
+                }
+                for value in contents.uint64_contents.iter() {
+                    update(&value.to_le_bytes());
+                }
+                for value in contents.fp64_contents.iter() {
+                    update(&value.to_le_bytes());
+                }
+                for value in contents.bool_contents.iter() {
+                    update(&[*value as u8]);
+                }
+                for value in contents.bytes_contents.iter() {
+                    update(value);
+                }
+            }
+        }
+        for raw_input in request.raw_input_contents.iter() {
+            update(raw_input);
+        }
+        for output in request.outputs.iter() {
+            update(output.name.as_bytes());
+        }
+
+        format!("{hash:016x}")
+    }
+
+    fn model_dir(&self, model_name: &str) -> PathBuf {
+        self.fixture_dir.join(model_name)
+    }
+
+    pub fn record_infer(&self, request: &ModelInferRequest, response: &ModelInferResponse) -> Result<(), Error> {
+        let input_hash = Self::input_hash(request);
+        let model_dir = self.model_dir(&request.model_name);
+        match write_fixture(&model_dir.join(format!("{input_hash}.request.pb")), &request.encode_to_vec()) {
+            Ok(_) => {}
+            Err(e) => return Err(e)
+        };
+        write_fixture(&model_dir.join(format!("{input_hash}.response.pb")), &response.encode_to_vec())
+    }
+
+    pub fn replay_infer(&self, request: &ModelInferRequest) -> Result<ModelInferResponse, Error> {
+        let input_hash = Self::input_hash(request);
+        let path = self.model_dir(&request.model_name).join(format!("{input_hash}.response.pb"));
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => {bytes}
+            Err(e) => {
+                return Err(Error::msg(format!("infer_recorder - no fixture for model {} input {input_hash} at {}: {e}", request.model_name, path.display())))
+            }
+        };
+        match ModelInferResponse::decode(bytes.as_slice()) {
+            Ok(response) => Ok(response),
+            Err(e) => Err(Error::from(e))
+        }
+    }
+
+    pub fn record_config(&self, model_name: &str, response: &ModelConfigResponse) -> Result<(), Error> {
+        write_fixture(&self.model_dir(model_name).join("config.pb"), &response.encode_to_vec())
+    }
+
+    pub fn replay_config(&self, model_name: &str) -> Result<ModelConfigResponse, Error> {
+        let path = self.model_dir(model_name).join("config.pb");
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => {bytes}
+            Err(e) => {
+                return Err(Error::msg(format!("infer_recorder - no model config fixture for model {model_name} at {}: {e}", path.display())))
+            }
+        };
+        match ModelConfigResponse::decode(bytes.as_slice()) {
+            Ok(response) => Ok(response),
+            Err(e) => Err(Error::from(e))
+        }
+    }
+}
+
+fn write_fixture(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            return Err(Error::from(e))
+        }
+    }
+    match fs::write(path, bytes) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::from(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use uuid::Uuid;
+    use crate::pipeline::general_pipeline::general_pipeline::GeneralPipeline;
+    use crate::pipeline::inference_backend::triton_backend::TritonBackend;
+    use crate::pipeline::model_config::config::ModelsConfig;
+    use crate::pipeline::model_config::pipeline_config::PipelineDefinition;
+    use crate::pipeline::triton_client::mock_server::{MockTritonServer, synthetic_image_bytes};
+    use crate::pipeline::triton_client::recorder::{InferRecorder, RecordMode};
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let fixture_dir = std::env::temp_dir().join(format!("triton-fixtures-{}", Uuid::new_v4()));
+        let im_bytes = synthetic_image_bytes(640, 480).unwrap();
+
+        let server = MockTritonServer::new()
+            .with_face_models()
+            .start().await.unwrap();
+        let recording_backend = TritonBackend::new_with_recorder(
+            &server.host(),
+            &server.port(),
+            InferRecorder::new(RecordMode::Record, &fixture_dir),
+        ).await.unwrap();
+        let recorded = GeneralPipeline::new(Arc::new(recording_backend), &ModelsConfig::default(), &PipelineDefinition::general()).await.unwrap()
+            .extract_all(&im_bytes, None).await.unwrap();
+        drop(server);
+
+        assert!(fixture_dir.join("face_detection_retina").join("config.pb").exists());
+
+        // Nothing listens on the port anymore, every answer comes from the fixtures
+        let replay_backend = TritonBackend::new_with_recorder(
+            "http://127.0.0.1",
+            "1",
+            InferRecorder::new(RecordMode::Replay, &fixture_dir),
+        ).await.unwrap();
+        let replayed = GeneralPipeline::new(Arc::new(replay_backend), &ModelsConfig::default(), &PipelineDefinition::general()).await.unwrap()
+            .extract_all(&im_bytes, None).await.unwrap();
+
+        assert_eq!(replayed.face_count, recorded.face_count);
+        assert_eq!(replayed.faces[0].bbox, recorded.faces[0].bbox);
+        assert_eq!(replayed.faces[0].landmarks, recorded.faces[0].landmarks);
+        assert_eq!(replayed.faces[0].facial_feature, recorded.faces[0].facial_feature);
+
+        // An input that was never recorded is an error rather than a silent default
+        let other_bytes = synthetic_image_bytes(480, 480).unwrap();
+        let replay_backend = TritonBackend::new_with_recorder(
+            "http://127.0.0.1",
+            "1",
+            InferRecorder::new(RecordMode::Replay, &fixture_dir),
+        ).await.unwrap();
+        let pipeline = GeneralPipeline::new(Arc::new(replay_backend), &ModelsConfig::default(), &PipelineDefinition::general()).await.unwrap();
+        assert!(pipeline.extract_all(&other_bytes, None).await.is_err());
+
+        let _ = std::fs::remove_dir_all(&fixture_dir);
+    }
+}
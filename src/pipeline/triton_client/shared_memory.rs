@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::ops::Deref;
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use anyhow::Error;
+use log::{info, warn};
+use crate::pipeline::triton_client::client::triton::infer_parameter::ParameterChoice;
+use crate::pipeline::triton_client::client::triton::{InferParameter, SystemSharedMemoryRegisterRequest, SystemSharedMemoryUnregisterRequest};
+use crate::pipeline::triton_client::client::TritonInferenceClient;
+
+/// Where POSIX shared-memory objects live on Linux, `shm_open("/name")` maps `/dev/shm/name`.
+const SHM_DIR: &str = "/dev/shm";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TritonTransport {
+    /// Tensors travel inside the gRPC messages
+    Grpc,
+    /// Tensors travel through system shared-memory regions registered with a co-located Triton
+    SystemSharedMemory,
+}
+
+impl FromStr for TritonTransport {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "grpc" => Ok(TritonTransport::Grpc),
+            "system_shared_memory" | "shm" => Ok(TritonTransport::SystemSharedMemory),
+            _ => Err(Error::msg(format!("unknown triton transport {s}, expected one of grpc, system_shared_memory"))),
+        }
+    }
+}
+
+impl fmt::Display for TritonTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TritonTransport::Grpc => write!(f, "grpc"),
+            TritonTransport::SystemSharedMemory => write!(f, "system_shared_memory"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SharedMemoryOptions {
+    /// Prefix of the region names, the process id is appended so replicas on one host do not collide
+    pub prefix: String,
+    pub pool_size: usize,
+    /// Bytes per region, inputs and outputs of one request share a region
+    pub region_size: usize,
+}
+
+/// One registered region, backed by a file in `/dev/shm`.
+#[derive(Debug)]
+pub struct SharedMemoryRegion {
+    name: String,
+    path: PathBuf,
+    file: File,
+    byte_size: usize,
+}
+
+impl SharedMemoryRegion {
+    fn create(name: &str, byte_size: usize) -> Result<Self, Error> {
+        let path = PathBuf::from(SHM_DIR).join(name);
+        let file = match OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path) {
+            Ok(file) => {file}
+            Err(e) => return Err(Error::msg(format!("shared_memory - failed to create region {}: {e}", path.display())))
+        };
+        if let Err(e) = file.set_len(byte_size as u64) {
+            let _ = fs::remove_file(&path);
+            return Err(Error::from(e))
+        }
+        Ok(SharedMemoryRegion {
+            name: name.to_string(),
+            path,
+            file,
+            byte_size,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Key Triton opens the region with.
+    pub fn key(&self) -> String {
+        format!("/{}", self.name)
+    }
+
+    pub fn byte_size(&self) -> usize {
+        self.byte_size
+    }
+
+    pub fn write(&self, offset: usize, bytes: &[u8]) -> Result<(), Error> {
+        if offset + bytes.len() > self.byte_size {
+            return Err(Error::msg(format!("shared_memory - {} bytes at offset {offset} overflow region {}", bytes.len(), self.name)))
+        }
+        match self.file.write_all_at(bytes, offset as u64) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::from(e))
+        }
+    }
+
+    pub fn read(&self, offset: usize, len: usize) -> Result<Vec<u8>, Error> {
+        if offset + len > self.byte_size {
+            return Err(Error::msg(format!("shared_memory - {len} bytes at offset {offset} overflow region {}", self.name)))
+        }
+        let mut bytes = vec![0u8; len];
+        match self.file.read_exact_at(&mut bytes, offset as u64) {
+            Ok(_) => Ok(bytes),
+            Err(e) => Err(Error::from(e))
+        }
+    }
+}
+
+/// Region borrowed from a `SharedMemoryPool`, handed back when dropped.
+pub struct SharedMemoryLease {
+    region: Option<SharedMemoryRegion>,
+    free: Arc<Mutex<Vec<SharedMemoryRegion>>>,
+}
+
+impl Deref for SharedMemoryLease {
+    type Target = SharedMemoryRegion;
+
+    fn deref(&self) -> &Self::Target {
+        self.region.as_ref().unwrap()
+    }
+}
+
+impl Drop for SharedMemoryLease {
+    fn drop(&mut self) {
+        if let Some(region) = self.region.take() {
+            self.free.lock().unwrap().push(region);
+        }
+    }
+}
+
+/// Fixed set of system shared-memory regions registered with Triton and reused across requests.
+///
+/// `release` unregisters the regions and removes their files, dropping the pool only removes the files.
+#[derive(Debug)]
+pub struct SharedMemoryPool {
+    client: TritonInferenceClient,
+    free: Arc<Mutex<Vec<SharedMemoryRegion>>>,
+    regions: Vec<(String, PathBuf)>,
+    region_size: usize,
+}
+
+impl SharedMemoryPool {
+    /// Creates and registers every region, nothing is left behind when one of them fails.
+    pub async fn new(client: TritonInferenceClient, options: &SharedMemoryOptions) -> Result<Self, Error> {
+        if options.pool_size == 0 || options.region_size == 0 {
+            return Err(Error::msg("shared_memory - pool_size and region_size must be positive"))
+        }
+
+        let mut pool = SharedMemoryPool {
+            client,
+            free: Arc::new(Mutex::new(Vec::with_capacity(options.pool_size))),
+            regions: Vec::with_capacity(options.pool_size),
+            region_size: options.region_size,
+        };
+        for idx in 0..options.pool_size {
+            let name = format!("{}_{}_{idx}", options.prefix, std::process::id());
+            let region = match SharedMemoryRegion::create(&name, options.region_size) {
+                Ok(region) => {region}
+                Err(e) => {
+                    pool.release().await;
+                    return Err(e)
+                }
+            };
+            pool.regions.push((name.to_string(), region.path.clone()));
+
+            let register = pool.client.system_shared_memory_register(SystemSharedMemoryRegisterRequest {
+                name: name.to_string(),
+                key: region.key(),
+                offset: 0,
+                byte_size: options.region_size as u64,
+            }).await;
+            pool.free.lock().unwrap().push(region);
+            if let Err(e) = register {
+                pool.release().await;
+                return Err(Error::msg(format!("shared_memory - failed to register region {name}: {e}")))
+            }
+        }
+
+        info!("registered {} shared memory regions of {} bytes", pool.regions.len(), pool.region_size);
+        Ok(pool)
+    }
+
+    pub fn region_size(&self) -> usize {
+        self.region_size
+    }
+
+    /// Takes a free region, `None` when every region is in use.
+    pub fn acquire(&self) -> Option<SharedMemoryLease> {
+        let region = self.free.lock().unwrap().pop();
+        region.map(|region| SharedMemoryLease {
+            region: Some(region),
+            free: Arc::clone(&self.free),
+        })
+    }
+
+    /// Unregisters the regions from Triton and removes their files.
+    pub async fn release(&self) {
+        for (name, path) in self.regions.iter() {
+            if let Err(e) = self.client.system_shared_memory_unregister(SystemSharedMemoryUnregisterRequest {
+                name: name.to_string(),
+            }).await {
+                warn!("failed to unregister shared memory region {name}: {e}");
+            }
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+impl Drop for SharedMemoryPool {
+    fn drop(&mut self) {
+        for (_, path) in self.regions.iter() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Request parameters pointing an input or output at `byte_size` bytes of a region.
+pub fn shared_memory_parameters(region_name: &str, offset: usize, byte_size: usize) -> HashMap<String, InferParameter> {
+    HashMap::from([
+        ("shared_memory_region".to_string(), InferParameter {
+            parameter_choice: Some(ParameterChoice::StringParam(region_name.to_string())),
+        }),
+        ("shared_memory_offset".to_string(), InferParameter {
+            parameter_choice: Some(ParameterChoice::Int64Param(offset as i64)),
+        }),
+        ("shared_memory_byte_size".to_string(), InferParameter {
+            parameter_choice: Some(ParameterChoice::Int64Param(byte_size as i64)),
+        }),
+    ])
+}
+
+/// Region name, offset and byte size set by `shared_memory_parameters`, `None` for a regular tensor.
+pub fn parse_shared_memory_parameters(parameters: &HashMap<String, InferParameter>) -> Option<(String, usize, usize)> {
+    let region_name = match parameters.get("shared_memory_region").and_then(|p| p.parameter_choice.as_ref()) {
+        Some(ParameterChoice::StringParam(region_name)) => region_name.to_string(),
+        _ => return None,
+    };
+    let offset = match parameters.get("shared_memory_offset").and_then(|p| p.parameter_choice.as_ref()) {
+        Some(ParameterChoice::Int64Param(offset)) => *offset as usize,
+        _ => 0,
+    };
+    let byte_size = match parameters.get("shared_memory_byte_size").and_then(|p| p.parameter_choice.as_ref()) {
+        Some(ParameterChoice::Int64Param(byte_size)) => *byte_size as usize,
+        _ => return None,
+    };
+    Some((region_name, offset, byte_size))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::pipeline::inference_backend::inference_backend::{InferenceBackend, Tensor};
+    use crate::pipeline::inference_backend::triton_backend::TritonBackend;
+    use crate::pipeline::triton_client::mock_server::{fp32_infer_response, fp32_model_config, input_values, MockTritonServer};
+    use crate::pipeline::triton_client::shared_memory::SharedMemoryOptions;
+
+    #[tokio::test]
+    async fn test_shared_memory_transport() {
+        let server = MockTritonServer::new()
+            .with_model_config(fp32_model_config("double", 1, vec![("input", vec![1, 4])], vec![("output", vec![1, 4])]))
+            .with_infer_handler("double", |request| {
+                let values: Vec<f32> = input_values(request, 0).unwrap().iter().map(|v| v * 2.0).collect();
+                let rows = values.len() as i64 / 4;
+                Ok(fp32_infer_response(&request.model_name, vec![("output", vec![rows, 4], values)]))
+            })
+            .start().await.unwrap();
+        let options = SharedMemoryOptions {
+            prefix: "test_shm_transport".to_string(),
+            pool_size: 2,
+            region_size: 64,
+        };
+        let backend = TritonBackend::new(&server.host(), &server.port()).await.unwrap()
+            .with_shared_memory(&options).await;
+        assert_eq!(server.shared_memory_regions().len(), 2);
+
+        let outputs = backend.infer("double", vec![Tensor::new("input", vec![1, 4], vec![1.0, 2.0, 3.0, 4.0])]).await.unwrap();
+        assert_eq!(outputs[0].data, vec![2.0, 4.0, 6.0, 8.0]);
+
+        // 80 input bytes do not fit a 64 byte region, the request falls back to grpc payloads
+        let outputs = backend.infer("double", vec![Tensor::new("input", vec![5, 4], vec![1.0; 20])]).await.unwrap();
+        assert_eq!(outputs[0].data, vec![2.0; 20]);
+
+        let requests = server.infer_requests();
+        assert!(requests[0].raw_input_contents.is_empty());
+        assert!(requests[0].inputs[0].parameters.contains_key("shared_memory_region"));
+        assert_eq!(requests[1].raw_input_contents.len(), 1);
+
+        backend.release_shared_memory().await;
+        assert!(server.shared_memory_regions().is_empty());
+        assert!(!Path::new("/dev/shm").join(format!("test_shm_transport_{}_0", std::process::id())).exists());
+    }
+}
@@ -0,0 +1,154 @@
+use std::str::FromStr;
+use anyhow::Error;
+
+/// Tensor element types the service can exchange with Triton as raw little-endian bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TensorDataType {
+    Fp32,
+    Fp16,
+    Uint8,
+}
+
+impl FromStr for TensorDataType {
+    type Err = Error;
+
+    /// Parses the Triton datatype name, `FP32` or `TYPE_FP32` alike.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().trim_start_matches("TYPE_") {
+            "FP32" => Ok(TensorDataType::Fp32),
+            "FP16" => Ok(TensorDataType::Fp16),
+            "UINT8" => Ok(TensorDataType::Uint8),
+            _ => Err(Error::msg(format!("tensor_encoding - unsupported datatype {s}"))),
+        }
+    }
+}
+
+impl TensorDataType {
+    /// Name used in the `datatype` field of inference requests and responses.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TensorDataType::Fp32 => "FP32",
+            TensorDataType::Fp16 => "FP16",
+            TensorDataType::Uint8 => "UINT8",
+        }
+    }
+
+    pub fn byte_size(&self) -> usize {
+        match self {
+            TensorDataType::Fp32 => 4,
+            TensorDataType::Fp16 => 2,
+            TensorDataType::Uint8 => 1,
+        }
+    }
+}
+
+/// Encodes the values as the little-endian bytes Triton expects in `raw_input_contents`.
+///
+/// UINT8 values are rounded and clamped to `[0, 255]`.
+pub fn encode_raw(data: &[f32], datatype: TensorDataType) -> Vec<u8> {
+    match datatype {
+        TensorDataType::Fp32 => data.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        TensorDataType::Fp16 => data.iter().flat_map(|v| f32_to_f16_bits(*v).to_le_bytes()).collect(),
+        TensorDataType::Uint8 => data.iter().map(|v| v.round().clamp(0.0, 255.0) as u8).collect(),
+    }
+}
+
+/// Decodes `raw_input_contents` or `raw_output_contents` bytes back into FP32 values.
+pub fn decode_raw(bytes: &[u8], datatype: TensorDataType) -> Result<Vec<f32>, Error> {
+    if bytes.len() % datatype.byte_size() != 0 {
+        return Err(Error::msg(format!(
+            "tensor_encoding - {} bytes is not a whole number of {} elements", bytes.len(), datatype.as_str()
+        )))
+    }
+    let values = match datatype {
+        TensorDataType::Fp32 => bytes.chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect(),
+        TensorDataType::Fp16 => bytes.chunks_exact(2)
+            .map(|chunk| f16_bits_to_f32(u16::from_le_bytes([chunk[0], chunk[1]])))
+            .collect(),
+        TensorDataType::Uint8 => bytes.iter().map(|v| *v as f32).collect(),
+    };
+    Ok(values)
+}
+
+/// IEEE 754 half precision bits of `value`, rounded to nearest even.
+fn f32_to_f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x007f_ffff;
+
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x0200 } else { 0 };
+        return sign | 0x7c00 | nan
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00
+    }
+    if half_exponent <= 0 {
+        // Too small for a normal half, keep what fits of the subnormal
+        if half_exponent < -10 {
+            return sign
+        }
+        let mantissa = mantissa | 0x0080_0000;
+        let shift = (14 - half_exponent) as u32;
+        let half_mantissa = mantissa >> shift;
+        let remainder = mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round_up = remainder > halfway || (remainder == halfway && half_mantissa & 1 == 1);
+        return sign | (half_mantissa + round_up as u32) as u16
+    }
+
+    let half_mantissa = mantissa >> 13;
+    let remainder = mantissa & 0x1fff;
+    let round_up = remainder > 0x1000 || (remainder == 0x1000 && half_mantissa & 1 == 1);
+    // A carry out of the mantissa bumps the exponent, up to infinity
+    let half = (((half_exponent as u32) << 10) | half_mantissa) + round_up as u32;
+    sign | half as u16
+}
+
+fn f16_bits_to_f32(bits: u16) -> f32 {
+    let sign = ((bits & 0x8000) as u32) << 16;
+    let exponent = ((bits >> 10) & 0x1f) as u32;
+    let mantissa = (bits & 0x03ff) as u32;
+
+    match (exponent, mantissa) {
+        (0, 0) => f32::from_bits(sign),
+        (0, _) => {
+            let magnitude = mantissa as f32 * 2f32.powi(-24);
+            if sign != 0 { -magnitude } else { magnitude }
+        }
+        (0x1f, _) => f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(sign | ((exponent + 127 - 15) << 23) | (mantissa << 13)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::triton_client::tensor_encoding::{decode_raw, encode_raw, TensorDataType};
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!("TYPE_FP16".parse::<TensorDataType>().unwrap(), TensorDataType::Fp16);
+        assert!("INT64".parse::<TensorDataType>().is_err());
+
+        let values = vec![0.0, 1.0, -2.0, 0.5, 65504.0, 1e-7];
+        let fp32 = encode_raw(&values, TensorDataType::Fp32);
+        assert_eq!(fp32.len(), 24);
+        assert_eq!(decode_raw(&fp32, TensorDataType::Fp32).unwrap(), values);
+
+        let fp16 = encode_raw(&values, TensorDataType::Fp16);
+        assert_eq!(&fp16[2..6], &[0x00, 0x3c, 0x00, 0xc0]);
+        let decoded = decode_raw(&fp16, TensorDataType::Fp16).unwrap();
+        assert_eq!(&decoded[..5], &values[..5]);
+        assert!((decoded[5] - 1e-7).abs() < 6e-8);
+        assert_eq!(decode_raw(&encode_raw(&[1e6, 0.1], TensorDataType::Fp16), TensorDataType::Fp16).unwrap()[0], f32::INFINITY);
+
+        let uint8 = encode_raw(&[-3.0, 12.4, 254.6, 300.0], TensorDataType::Uint8);
+        assert_eq!(uint8, vec![0, 12, 255, 255]);
+        assert!(decode_raw(&fp16[..3], TensorDataType::Fp16).is_err());
+    }
+}
@@ -1,8 +1,8 @@
 use opencv::core::{self, Mat, MatTrait, MatTraitConst, Scalar};
-use opencv::imgcodecs::{imdecode, IMREAD_UNCHANGED};
+use opencv::imgcodecs::{imdecode, imdecodemulti_def, IMREAD_COLOR, IMREAD_UNCHANGED};
 use opencv::imgproc::{cvt_color, COLOR_RGBA2RGB, COLOR_GRAY2RGB};
 use anyhow::{Error, Result};
-use ndarray::{Array2, Array3, ArrayBase, Axis, concatenate, Ix2, Ix3, OwnedRepr, s, stack};
+use ndarray::{Array1, Array2, Array3, ArrayBase, Axis, concatenate, Ix2, Ix3, OwnedRepr, s, stack};
 use ndarray_linalg::Norm;
 
 pub fn byte_data_to_opencv(im_bytes: &[u8]) -> Result<Mat, Error> {
@@ -51,6 +51,27 @@ pub fn byte_data_to_opencv(im_bytes: &[u8]) -> Result<Mat, Error> {
     Ok(opencv_img)
 }
 
+/// Decodes every page of a multi-page image, such as a TIFF burst, into BGR frames. Video
+/// containers are not supported.
+pub fn clip_to_opencv_frames(clip_bytes: &[u8]) -> Result<Vec<Mat>, Error> {
+    let clip_as_mat = match Mat::from_slice(clip_bytes) {
+        Ok(clip_as_mat) => clip_as_mat,
+        Err(e) => {
+            return Err(Error::from(e))
+        }
+    };
+
+    let mut frames: core::Vector<Mat> = core::Vector::new();
+    match imdecodemulti_def(&clip_as_mat, IMREAD_COLOR, &mut frames) {
+        Ok(true) => {}
+        Ok(false) => return Err(Error::msg("utils - clip could not be decoded as a multi-page image")),
+        Err(e) => {
+            return Err(Error::from(e))
+        }
+    };
+    Ok(frames.to_vec())
+}
+
 pub fn vstack_2d(v: Vec<ArrayBase<OwnedRepr<f32>, Ix2>>) -> Array2<f32> {
     // Check if proposals_list is empty
     if v.is_empty() {
@@ -145,7 +166,7 @@ pub fn array2_to_mat(arr: &Array2<f32>) -> opencv::Result<Mat> {
     Ok(mat)
 }
 
-pub fn normalize_outputs(outputs: Vec<Vec<Array2<f32>>>) -> Vec<Array2<f64>> {
+pub fn normalize_outputs(outputs: Vec<Vec<Array2<f32>>>) -> Vec<Array2<f32>> {
     outputs
         .iter()
         .map(|outer| {
@@ -156,16 +177,30 @@ pub fn normalize_outputs(outputs: Vec<Vec<Array2<f32>>>) -> Vec<Array2<f64>> {
         .collect()
 }
 
-pub fn l2_norm(arr: &Array2<f32>) -> Array2<f64> {
+pub fn l2_normalize(v: &Array1<f32>) -> Array1<f32> {
+    let norm = v.iter().map(|&x| (x as f64).powi(2)).sum::<f64>().sqrt();
+    if norm == 0.0 {
+        return v.to_owned();
+    }
+    v.mapv(|x| (x as f64 / norm) as f32)
+}
+
+/// Cosine similarity of two L2-normalised features, as produced by `normalize_outputs`.
+pub fn cosine_similarity(a: &Array1<f32>, b: &Array1<f32>) -> f32 {
+    a.dot(b).clamp(-1.0, 1.0)
+}
+
+pub fn l2_norm(arr: &Array2<f32>) -> Array2<f32> {
     let norm = arr.iter().map(|&x| (x as f64).powi(2)).sum::<f64>().sqrt();
-    arr.mapv(|x| x as f64) / norm
+    arr.mapv(|x| (x as f64 / norm) as f32)
 }
 
 
 
 #[cfg(test)]
 mod tests {
-    use crate::utils::utils::byte_data_to_opencv;
+    use ndarray::array;
+    use crate::pipeline::utils::utils::{byte_data_to_opencv, cosine_similarity, l2_normalize};
 
     #[test]
     fn test_nms() {
@@ -177,4 +212,13 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_cosine_similarity() {
+        let a = l2_normalize(&array![1.0, 0.0, 1.0]);
+        let b = l2_normalize(&array![2.0, 0.0, 2.0]);
+        let c = l2_normalize(&array![0.0, 3.0, 0.0]);
+
+        assert!((cosine_similarity(&a, &b) - 1.0).abs() < 1e-6);
+        assert!(cosine_similarity(&a, &c).abs() < 1e-6);
+    }
 }
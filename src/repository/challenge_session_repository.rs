@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+use crate::pipeline::module::liveness_challenge::LivenessChallenge;
+
+/// An issued challenge waiting for its frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChallengeSession {
+    pub session_id: String,
+    pub challenge: LivenessChallenge,
+    /// Unix time in seconds from which the session is refused
+    pub expires_at: u64,
+}
+
+/// Liveness challenge sessions kept in memory, each can be taken once before it expires.
+///
+/// Sessions do not survive a restart and are not shared between replicas, a client must send its
+/// frames to the instance that issued the challenge.
+pub struct ChallengeSessionRepository {
+    ttl_secs: u64,
+    sessions: Mutex<HashMap<String, ChallengeSession>>,
+}
+
+impl ChallengeSessionRepository {
+    pub fn new(ttl_secs: u64) -> Self {
+        ChallengeSessionRepository {
+            ttl_secs,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Issues a session for `challenge`, dropping the sessions that expired meanwhile.
+    pub fn create(&self, challenge: LivenessChallenge) -> ChallengeSession {
+        let now = unix_timestamp();
+        let session = ChallengeSession {
+            session_id: Uuid::new_v4().to_string(),
+            challenge,
+            expires_at: now + self.ttl_secs,
+        };
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(session.session_id.clone(), session.clone());
+        session
+    }
+
+    /// Removes and returns the session, `None` when it is unknown, already taken or expired.
+    pub fn take(&self, session_id: &str) -> Option<ChallengeSession> {
+        let session = self.sessions.lock().unwrap().remove(session_id);
+        session.filter(|session| session.expires_at > unix_timestamp())
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::module::liveness_challenge::LivenessChallenge;
+    use crate::repository::challenge_session_repository::ChallengeSessionRepository;
+
+    #[test]
+    fn test_single_use_and_expiry() {
+        let repository = ChallengeSessionRepository::new(60);
+        let session = repository.create(LivenessChallenge::Blink);
+        assert_eq!(repository.take("unknown"), None);
+        assert_eq!(repository.take(&session.session_id), Some(session.clone()));
+        assert_eq!(repository.take(&session.session_id), None);
+
+        let expired = ChallengeSessionRepository::new(0);
+        let session = expired.create(LivenessChallenge::Smile);
+        assert_eq!(expired.take(&session.session_id), None);
+    }
+}
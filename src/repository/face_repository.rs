@@ -0,0 +1,71 @@
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, bincode::Encode, bincode::Decode)]
+pub struct Identity {
+    pub identity_id: String,
+    pub embeddings: Vec<Vec<f32>>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentitySummary {
+    pub identity_id: String,
+    pub embedding_count: usize,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl From<&Identity> for IdentitySummary {
+    fn from(identity: &Identity) -> Self {
+        IdentitySummary {
+            identity_id: identity.identity_id.clone(),
+            embedding_count: identity.embeddings.len(),
+            created_at: identity.created_at,
+            updated_at: identity.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityMatch {
+    pub identity_id: String,
+    pub score: f32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchParams {
+    /// Overrides the index candidate list size for this query, trading latency for recall.
+    pub ef_search: Option<usize>,
+    /// Forces an exact linear scan even when an index is available.
+    pub exact: bool,
+}
+
+/// Storage of enrolled identities and their facial features.
+///
+/// Embeddings are L2-normalised on enrollment, so `search` scores are cosine similarities.
+pub trait FaceRepository: Send + Sync {
+    /// Adds the embeddings to the identity, creating it when it does not exist yet.
+    fn enroll(&self, identity_id: &str, embeddings: Vec<Vec<f32>>) -> Result<Identity, Error>;
+
+    /// Removes the identity, returns `false` when it was not enrolled.
+    fn delete(&self, identity_id: &str) -> Result<bool, Error>;
+
+    fn get(&self, identity_id: &str) -> Result<Option<Identity>, Error>;
+
+    /// Lists identities ordered by id, together with the total number of identities.
+    fn list(&self, offset: usize, limit: usize) -> Result<(Vec<IdentitySummary>, usize), Error>;
+
+    /// Returns the `top_k` identities closest to the embedding, best match first.
+    fn search(&self, embedding: &[f32], top_k: usize) -> Result<Vec<IdentityMatch>, Error> {
+        self.search_with_params(embedding, top_k, &SearchParams::default())
+    }
+
+    fn search_with_params(&self, embedding: &[f32], top_k: usize, params: &SearchParams) -> Result<Vec<IdentityMatch>, Error>;
+
+    /// Persists any state kept only in memory, called on shutdown.
+    fn flush(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
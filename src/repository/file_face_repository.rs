@@ -0,0 +1,590 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::Error;
+use log::{error, info, warn};
+use ndarray::Array1;
+use crate::pipeline::utils::utils::{cosine_similarity, l2_normalize};
+use crate::repository::face_repository::{FaceRepository, Identity, IdentityMatch, IdentitySummary, SearchParams};
+use crate::repository::hnsw_index::{HnswIndex, HnswParams};
+
+const GALLERY_FILE_VERSION: u32 = 1;
+
+#[derive(bincode::Encode, bincode::Decode)]
+struct GalleryFile {
+    version: u32,
+    identities: Vec<Identity>,
+}
+
+/// One change appended to the gallery log, replaying it twice gives the same gallery.
+#[derive(bincode::Encode, bincode::Decode)]
+enum GalleryLogEntry {
+    /// The identity as it is after the enrollment
+    Enroll(Identity),
+    Delete(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct FileFaceRepositoryIndexOptions {
+    pub path: PathBuf,
+    pub params: HnswParams,
+}
+
+/// `FaceRepository` kept in memory and persisted to a local gallery file.
+///
+/// Every change is appended to a log next to the gallery file and synced before it is applied.
+/// Every `snapshot_every` changes, and on `flush`, the gallery file is rewritten and the log
+/// emptied. With index options the search goes through an HNSW index instead of a linear scan, its
+/// snapshot is saved at the same cadence.
+pub struct FileFaceRepository {
+    path: PathBuf,
+    identities: RwLock<HashMap<String, Identity>>,
+    log: Mutex<File>,
+    snapshot_every: usize,
+    index: Option<RwLock<HnswIndex>>,
+    index_options: Option<FileFaceRepositoryIndexOptions>,
+    pending_mutations: AtomicUsize,
+}
+
+impl FileFaceRepository {
+    pub fn open(path: impl AsRef<Path>, snapshot_every: usize, index_options: Option<FileFaceRepositoryIndexOptions>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let (identities, log_len) = match Self::load_gallery(&path) {
+            Ok(gallery) => {gallery}
+            Err(e) => return Err(e)
+        };
+        info!("loaded {} identities from gallery {:?}", identities.len(), path);
+
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                if let Err(e) = fs::create_dir_all(parent) {
+                    return Err(Error::from(e))
+                }
+            }
+        }
+        let log = match OpenOptions::new().create(true).append(true).open(log_path(&path)) {
+            Ok(log) => {log}
+            Err(e) => return Err(Error::from(e))
+        };
+        // Drops an entry a crash left half written
+        if let Err(e) = log.set_len(log_len) {
+            return Err(Error::from(e))
+        }
+
+        let index = match &index_options {
+            None => None,
+            Some(index_options) => match Self::open_index(index_options, &identities) {
+                Ok(index) => Some(RwLock::new(index)),
+                Err(e) => return Err(e)
+            },
+        };
+
+        Ok(FileFaceRepository {
+            path,
+            identities: RwLock::new(identities),
+            log: Mutex::new(log),
+            snapshot_every,
+            index,
+            index_options,
+            pending_mutations: AtomicUsize::new(0),
+        })
+    }
+
+    /// Builds an index over every enrolled embedding.
+    pub fn build_index(identities: &[Identity], params: HnswParams) -> Result<HnswIndex, Error> {
+        let mut index = HnswIndex::new(params);
+        for identity in identities.iter() {
+            for embedding in identity.embeddings.iter() {
+                if let Err(e) = index.insert(&identity.identity_id, embedding) {
+                    return Err(e)
+                }
+            }
+        }
+        Ok(index)
+    }
+
+    /// Reuses the index snapshot when it holds exactly the enrolled embeddings, rebuilds it otherwise.
+    fn open_index(index_options: &FileFaceRepositoryIndexOptions, identities: &HashMap<String, Identity>) -> Result<HnswIndex, Error> {
+        if index_options.path.exists() {
+            match HnswIndex::load(&index_options.path) {
+                Ok(index) => {
+                    let expected: HashMap<String, usize> = identities
+                        .iter()
+                        .map(|(identity_id, identity)| (identity_id.clone(), identity.embeddings.len()))
+                        .collect();
+                    if index.key_counts() == expected {
+                        info!("loaded gallery index snapshot with {} vectors", index.len());
+                        return Ok(index)
+                    }
+                    warn!("gallery index snapshot is stale, rebuilding it");
+                }
+                Err(e) => {
+                    warn!("failed to load gallery index snapshot, rebuilding it: {e}");
+                }
+            }
+        }
+
+        let identities: Vec<Identity> = identities.values().cloned().collect();
+        let index = match Self::build_index(&identities, index_options.params.clone()) {
+            Ok(index) => {index}
+            Err(e) => return Err(e)
+        };
+        info!("built gallery index with {} vectors", index.len());
+        if let Err(e) = index.snapshot(&index_options.path) {
+            error!("failed to save gallery index snapshot: {e}");
+        }
+        Ok(index)
+    }
+
+    /// Reads every identity stored in a gallery file and its log, an absent file is an empty gallery.
+    pub fn load_identities(path: &Path) -> Result<Vec<Identity>, Error> {
+        match Self::load_gallery(path) {
+            Ok((identities, _)) => Ok(identities.into_values().collect()),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Reads the gallery file and replays the log over it, together with the length of the log up
+    /// to its last complete entry.
+    fn load_gallery(path: &Path) -> Result<(HashMap<String, Identity>, u64), Error> {
+        let mut identities: HashMap<String, Identity> = HashMap::new();
+        if path.exists() {
+            let bytes = match fs::read(path) {
+                Ok(bytes) => {bytes}
+                Err(e) => return Err(Error::from(e))
+            };
+            let (gallery_file, _): (GalleryFile, usize) = match bincode::decode_from_slice(&bytes, bincode::config::standard()) {
+                Ok(gallery_file) => {gallery_file}
+                Err(e) => return Err(Error::from(e))
+            };
+            if gallery_file.version != GALLERY_FILE_VERSION {
+                return Err(Error::msg(format!("gallery - unsupported gallery file version {}", gallery_file.version)))
+            }
+            identities = gallery_file.identities
+                .into_iter()
+                .map(|identity| (identity.identity_id.clone(), identity))
+                .collect();
+        }
+
+        let log_path = log_path(path);
+        if !log_path.exists() {
+            return Ok((identities, 0))
+        }
+        let bytes = match fs::read(&log_path) {
+            Ok(bytes) => {bytes}
+            Err(e) => return Err(Error::from(e))
+        };
+        // Each entry is its length as a little-endian u32 followed by the encoded entry
+        let mut offset = 0;
+        while bytes.len() - offset >= 4 {
+            let entry_len = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+            if bytes.len() - offset - 4 < entry_len {
+                warn!("ignoring a truncated entry at the end of gallery log {:?}", log_path);
+                break
+            }
+            let entry_bytes = &bytes[offset + 4..offset + 4 + entry_len];
+            let (entry, _): (GalleryLogEntry, usize) = match bincode::decode_from_slice(entry_bytes, bincode::config::standard()) {
+                Ok(entry) => {entry}
+                Err(e) => return Err(Error::from(e))
+            };
+            match entry {
+                GalleryLogEntry::Enroll(identity) => {
+                    identities.insert(identity.identity_id.clone(), identity);
+                }
+                GalleryLogEntry::Delete(identity_id) => {
+                    identities.remove(&identity_id);
+                }
+            }
+            offset += 4 + entry_len;
+        }
+        Ok((identities, offset as u64))
+    }
+
+    /// Appends the entry to the log and syncs it, the change is durable once this returns.
+    fn append_log(&self, entry: &GalleryLogEntry) -> Result<(), Error> {
+        let entry_bytes = match bincode::encode_to_vec(entry, bincode::config::standard()) {
+            Ok(entry_bytes) => {entry_bytes}
+            Err(e) => return Err(Error::from(e))
+        };
+        let mut bytes = Vec::with_capacity(4 + entry_bytes.len());
+        bytes.extend_from_slice(&(entry_bytes.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&entry_bytes);
+
+        let mut log = self.log.lock().unwrap();
+        if let Err(e) = log.write_all(&bytes) {
+            return Err(Error::from(e))
+        }
+        if let Err(e) = log.sync_data() {
+            return Err(Error::from(e))
+        }
+        Ok(())
+    }
+
+    /// Rewrites the gallery file from memory and empties the log. Holding the identities lock keeps
+    /// any change from landing in the log between the two.
+    fn compact(&self) -> Result<(), Error> {
+        let identities = self.identities.read().unwrap();
+        self.pending_mutations.store(0, Ordering::SeqCst);
+        if let Err(e) = self.persist(&identities) {
+            return Err(e)
+        }
+
+        let log = self.log.lock().unwrap();
+        if let Err(e) = log.set_len(0) {
+            return Err(Error::from(e))
+        }
+        if let Err(e) = log.sync_data() {
+            return Err(Error::from(e))
+        }
+        Ok(())
+    }
+
+    fn snapshot_index(&self) -> Result<(), Error> {
+        let (index, index_options) = match (&self.index, &self.index_options) {
+            (Some(index), Some(index_options)) => (index, index_options),
+            _ => return Ok(()),
+        };
+        let mut index = index.write().unwrap();
+        if index.needs_compaction() {
+            index.compact();
+        }
+        index.snapshot(&index_options.path)
+    }
+
+    fn record_mutation(&self) {
+        let pending = self.pending_mutations.fetch_add(1, Ordering::SeqCst) + 1;
+        if self.snapshot_every > 0 && pending >= self.snapshot_every {
+            if let Err(e) = self.compact() {
+                error!("failed to compact gallery: {e}");
+            }
+            if let Err(e) = self.snapshot_index() {
+                error!("failed to save gallery index snapshot: {e}");
+            }
+        }
+    }
+
+    /// Writes the gallery to a temporary file first so a crash never leaves a truncated gallery.
+    fn persist(&self, identities: &HashMap<String, Identity>) -> Result<(), Error> {
+        let gallery_file = GalleryFile {
+            version: GALLERY_FILE_VERSION,
+            identities: identities.values().cloned().collect(),
+        };
+        let bytes = match bincode::encode_to_vec(&gallery_file, bincode::config::standard()) {
+            Ok(bytes) => {bytes}
+            Err(e) => return Err(Error::from(e))
+        };
+
+        let tmp_path = self.path.with_extension("tmp");
+        let mut tmp_file = match File::create(&tmp_path) {
+            Ok(tmp_file) => {tmp_file}
+            Err(e) => return Err(Error::from(e))
+        };
+        if let Err(e) = tmp_file.write_all(&bytes) {
+            return Err(Error::from(e))
+        }
+        if let Err(e) = tmp_file.sync_all() {
+            return Err(Error::from(e))
+        }
+        if let Err(e) = fs::rename(&tmp_path, &self.path) {
+            return Err(Error::from(e))
+        }
+        Ok(())
+    }
+}
+
+/// The log of `gallery.bin` is `gallery.log`.
+fn log_path(path: &Path) -> PathBuf {
+    path.with_extension("log")
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl FaceRepository for FileFaceRepository {
+    fn enroll(&self, identity_id: &str, embeddings: Vec<Vec<f32>>) -> Result<Identity, Error> {
+        if identity_id.is_empty() {
+            return Err(Error::msg("gallery - identity id is empty"))
+        }
+        if embeddings.is_empty() {
+            return Err(Error::msg("gallery - no embedding to enroll"))
+        }
+
+        let mut identities = self.identities.write().unwrap();
+        let now = unix_timestamp();
+        let mut identity = match identities.get(identity_id) {
+            Some(identity) => identity.clone(),
+            None => Identity {
+                identity_id: identity_id.to_string(),
+                embeddings: vec![],
+                created_at: now,
+                updated_at: now,
+            },
+        };
+
+        // Every embedding of the gallery comes from the same model. Everything is checked before the
+        // log is written so the log, the gallery and the index never disagree.
+        let mut dim = identities.values().next().and_then(|enrolled| enrolled.embeddings.first()).map(|first| first.len());
+        if let Some(index) = &self.index {
+            dim = dim.or(index.read().unwrap().dim());
+        }
+        let first_new_embedding = identity.embeddings.len();
+        for embedding in embeddings {
+            match dim {
+                Some(dim) if dim != embedding.len() => {
+                    return Err(Error::msg(format!(
+                        "gallery - embedding length mismatch: {} != {}",
+                        dim,
+                        embedding.len()
+                    )))
+                }
+                _ => dim = Some(embedding.len()),
+            }
+            let norm = embedding.iter().map(|value| value * value).sum::<f32>().sqrt();
+            if !norm.is_finite() || norm <= f32::EPSILON {
+                return Err(Error::msg("gallery - embedding is empty, zero or not finite"))
+            }
+            identity.embeddings.push(l2_normalize(&Array1::from(embedding)).to_vec());
+        }
+        identity.updated_at = now;
+
+        if let Err(e) = self.append_log(&GalleryLogEntry::Enroll(identity.clone())) {
+            return Err(e)
+        }
+        identities.insert(identity_id.to_string(), identity.clone());
+
+        if let Some(index) = &self.index {
+            let mut index = index.write().unwrap();
+            for embedding in identity.embeddings[first_new_embedding..].iter() {
+                if let Err(e) = index.insert(identity_id, embedding) {
+                    return Err(e)
+                }
+            }
+        }
+        drop(identities);
+        self.record_mutation();
+
+        Ok(identity)
+    }
+
+    fn delete(&self, identity_id: &str) -> Result<bool, Error> {
+        let mut identities = self.identities.write().unwrap();
+        if !identities.contains_key(identity_id) {
+            return Ok(false)
+        }
+        if let Err(e) = self.append_log(&GalleryLogEntry::Delete(identity_id.to_string())) {
+            return Err(e)
+        }
+        identities.remove(identity_id);
+
+        if let Some(index) = &self.index {
+            index.write().unwrap().delete(identity_id);
+        }
+        drop(identities);
+        self.record_mutation();
+
+        Ok(true)
+    }
+
+    fn get(&self, identity_id: &str) -> Result<Option<Identity>, Error> {
+        let identities = self.identities.read().unwrap();
+        Ok(identities.get(identity_id).cloned())
+    }
+
+    fn list(&self, offset: usize, limit: usize) -> Result<(Vec<IdentitySummary>, usize), Error> {
+        let identities = self.identities.read().unwrap();
+        let mut identity_ids: Vec<&String> = identities.keys().collect();
+        identity_ids.sort();
+
+        let summaries = identity_ids
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|identity_id| IdentitySummary::from(&identities[identity_id]))
+            .collect();
+        Ok((summaries, identities.len()))
+    }
+
+    fn search_with_params(&self, embedding: &[f32], top_k: usize, params: &SearchParams) -> Result<Vec<IdentityMatch>, Error> {
+        if let Some(index) = &self.index {
+            if !params.exact {
+                let matches = match index.read().unwrap().search(embedding, top_k, params.ef_search) {
+                    Ok(matches) => {matches}
+                    Err(e) => return Err(e)
+                };
+                let matches = matches
+                    .into_iter()
+                    .map(|(identity_id, score)| IdentityMatch {
+                        identity_id,
+                        score,
+                    })
+                    .collect();
+                return Ok(matches)
+            }
+        }
+
+        let query = l2_normalize(&Array1::from(embedding.to_vec()));
+        let identities = self.identities.read().unwrap();
+
+        let mut matches: Vec<IdentityMatch> = Vec::with_capacity(identities.len());
+        for identity in identities.values() {
+            let mut best_score: Option<f32> = None;
+            for enrolled in identity.embeddings.iter() {
+                if enrolled.len() != query.len() {
+                    continue;
+                }
+                let score = cosine_similarity(&query, &Array1::from(enrolled.to_owned()));
+                if best_score.map_or(true, |best| score > best) {
+                    best_score = Some(score);
+                }
+            }
+            if let Some(score) = best_score {
+                matches.push(IdentityMatch {
+                    identity_id: identity.identity_id.clone(),
+                    score,
+                });
+            }
+        }
+
+        matches.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+        matches.truncate(top_k);
+        Ok(matches)
+    }
+
+    fn flush(&self) -> Result<(), Error> {
+        if let Err(e) = self.compact() {
+            return Err(e)
+        }
+        self.snapshot_index()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use uuid::Uuid;
+    use crate::repository::face_repository::{FaceRepository, SearchParams};
+    use crate::repository::file_face_repository::{FileFaceRepository, FileFaceRepositoryIndexOptions};
+    use crate::repository::hnsw_index::HnswParams;
+
+    #[test]
+    fn test_enroll_search_delete() {
+        let dir = std::env::temp_dir().join(format!("gallery-{}", Uuid::new_v4()));
+        let path = dir.join("gallery.bin");
+
+        let repository = FileFaceRepository::open(&path, 100, None).unwrap();
+        repository.enroll("alice", vec![vec![1.0, 0.0, 0.0]]).unwrap();
+        repository.enroll("bob", vec![vec![0.0, 1.0, 0.0], vec![0.0, 0.7, 0.7]]).unwrap();
+
+        let matches = repository.search(&[0.1, 0.9, 0.0], 2).unwrap();
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].identity_id, "bob");
+
+        // Reload from disk
+        let repository = FileFaceRepository::open(&path, 100, None).unwrap();
+        let (identities, total) = repository.list(0, 10).unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(identities[1].embedding_count, 2);
+
+        assert!(repository.delete("alice").unwrap());
+        assert!(!repository.delete("alice").unwrap());
+        assert!(repository.get("alice").unwrap().is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_embeddings() {
+        let dir = std::env::temp_dir().join(format!("gallery-{}", Uuid::new_v4()));
+        let path = dir.join("gallery.bin");
+        let log_path = dir.join("gallery.log");
+
+        // An empty or zero embedding never fixes the gallery dimension
+        let repository = FileFaceRepository::open(&path, 100, None).unwrap();
+        assert!(repository.enroll("alice", vec![vec![]]).is_err());
+        assert!(repository.enroll("alice", vec![vec![0.0, 0.0, 0.0]]).is_err());
+        assert!(repository.enroll("alice", vec![vec![f32::NAN, 0.0, 0.0]]).is_err());
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), 0);
+
+        repository.enroll("alice", vec![vec![1.0, 0.0, 0.0]]).unwrap();
+        let log_len = fs::metadata(&log_path).unwrap().len();
+        // A bad embedding rejects the whole enrollment before the log is written
+        assert!(repository.enroll("bob", vec![vec![0.0, 1.0, 0.0], vec![1.0, 0.0]]).is_err());
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), log_len);
+        assert!(repository.get("bob").unwrap().is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_indexed_search() {
+        let dir = std::env::temp_dir().join(format!("gallery-{}", Uuid::new_v4()));
+        let path = dir.join("gallery.bin");
+        let index_options = FileFaceRepositoryIndexOptions {
+            path: dir.join("gallery.hnsw"),
+            params: HnswParams::default(),
+        };
+
+        let repository = FileFaceRepository::open(&path, 2, Some(index_options.clone())).unwrap();
+        repository.enroll("alice", vec![vec![1.0, 0.0, 0.0]]).unwrap();
+        repository.enroll("bob", vec![vec![0.0, 1.0, 0.0]]).unwrap();
+        repository.enroll("carol", vec![vec![0.0, 0.0, 1.0]]).unwrap();
+        assert!(repository.delete("carol").unwrap());
+
+        let indexed = repository.search(&[0.1, 0.9, 0.0], 1).unwrap();
+        let exact = repository.search_with_params(&[0.1, 0.9, 0.0], 1, &SearchParams { ef_search: None, exact: true }).unwrap();
+        assert_eq!(indexed[0].identity_id, exact[0].identity_id);
+
+        // The snapshot is reused on reload
+        repository.flush().unwrap();
+        let repository = FileFaceRepository::open(&path, 2, Some(index_options)).unwrap();
+        assert_eq!(repository.search(&[0.0, 0.0, 1.0], 3).unwrap().len(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_log_replay_and_compaction() {
+        let dir = std::env::temp_dir().join(format!("gallery-{}", Uuid::new_v4()));
+        let path = dir.join("gallery.bin");
+        let log_path = dir.join("gallery.log");
+
+        // Below the cadence the changes only reach the log
+        let repository = FileFaceRepository::open(&path, 10, None).unwrap();
+        repository.enroll("alice", vec![vec![1.0, 0.0]]).unwrap();
+        repository.enroll("bob", vec![vec![0.0, 1.0]]).unwrap();
+        repository.enroll("alice", vec![vec![0.7, 0.7]]).unwrap();
+        assert!(repository.delete("bob").unwrap());
+        assert!(!path.exists());
+        drop(repository);
+
+        // A crash in the middle of an append leaves a truncated entry
+        let log_len = fs::metadata(&log_path).unwrap().len();
+        OpenOptions::new().append(true).open(&log_path).unwrap().write_all(&[200, 0, 0, 0, 1, 2]).unwrap();
+
+        let repository = FileFaceRepository::open(&path, 10, None).unwrap();
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), log_len);
+        assert_eq!(repository.get("alice").unwrap().unwrap().embeddings.len(), 2);
+        assert!(repository.get("bob").unwrap().is_none());
+
+        repository.flush().unwrap();
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), 0);
+        repository.enroll("carol", vec![vec![0.0, 1.0]]).unwrap();
+        drop(repository);
+
+        let identities = FileFaceRepository::load_identities(&path).unwrap();
+        let mut identity_ids: Vec<String> = identities.into_iter().map(|identity| identity.identity_id).collect();
+        identity_ids.sort();
+        assert_eq!(identity_ids, vec!["alice", "carol"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,572 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::Error;
+use ndarray::Array1;
+use crate::pipeline::utils::utils::l2_normalize;
+
+const HNSW_SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, bincode::Encode, bincode::Decode)]
+pub struct HnswParams {
+    /// Maximum number of links per node on the upper layers, layer 0 keeps twice as many.
+    pub m: usize,
+    pub ef_construction: usize,
+    /// Default size of the candidate list at query time, higher means better recall and slower queries.
+    pub ef_search: usize,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        HnswParams {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, bincode::Encode, bincode::Decode)]
+struct HnswNode {
+    key: String,
+    vector: Vec<f32>,
+    neighbours: Vec<Vec<u32>>,
+    deleted: bool,
+}
+
+#[derive(bincode::Encode, bincode::Decode)]
+struct HnswSnapshot {
+    version: u32,
+    params: HnswParams,
+    nodes: Vec<HnswNode>,
+    entry_point: Option<u32>,
+    max_level: usize,
+    rng_state: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    distance: f32,
+    node: u32,
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance).then(self.node.cmp(&other.node))
+    }
+}
+
+/// In-process HNSW graph over L2-normalised embeddings, searched by cosine similarity.
+///
+/// Several vectors may share a key (one identity with several embeddings), search returns the
+/// best score per key. Deletion only marks nodes so the graph stays connected, `compact` rebuilds
+/// the graph once too many nodes are deleted.
+#[derive(Debug, Clone)]
+pub struct HnswIndex {
+    params: HnswParams,
+    /// Length of the vectors, set by the first insert
+    dim: Option<usize>,
+    nodes: Vec<HnswNode>,
+    key_nodes: HashMap<String, Vec<u32>>,
+    entry_point: Option<u32>,
+    max_level: usize,
+    deleted_count: usize,
+    rng_state: u64,
+}
+
+impl HnswIndex {
+    pub fn new(params: HnswParams) -> Self {
+        HnswIndex {
+            params,
+            dim: None,
+            nodes: vec![],
+            key_nodes: HashMap::new(),
+            entry_point: None,
+            max_level: 0,
+            deleted_count: 0,
+            rng_state: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    pub fn params(&self) -> &HnswParams {
+        &self.params
+    }
+
+    pub fn dim(&self) -> Option<usize> {
+        self.dim
+    }
+
+    /// Number of vectors that are not deleted.
+    pub fn len(&self) -> usize {
+        self.nodes.len() - self.deleted_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of live vectors stored per key.
+    pub fn key_counts(&self) -> HashMap<String, usize> {
+        self.key_nodes
+            .iter()
+            .map(|(key, nodes)| (key.clone(), nodes.len()))
+            .collect()
+    }
+
+    pub fn insert(&mut self, key: &str, vector: &[f32]) -> Result<(), Error> {
+        if let Err(e) = self.check_dim(vector) {
+            return Err(e)
+        }
+        self.dim = Some(vector.len());
+        let vector = l2_normalize(&Array1::from(vector.to_vec())).to_vec();
+        let level = self.random_level();
+        let node_id = self.nodes.len() as u32;
+
+        self.nodes.push(HnswNode {
+            key: key.to_string(),
+            vector,
+            neighbours: vec![vec![]; level + 1],
+            deleted: false,
+        });
+        self.key_nodes.entry(key.to_string()).or_default().push(node_id);
+
+        let entry_point = match self.entry_point {
+            None => {
+                self.entry_point = Some(node_id);
+                self.max_level = level;
+                return Ok(())
+            }
+            Some(entry_point) => entry_point,
+        };
+
+        let query = self.nodes[node_id as usize].vector.clone();
+        let mut entry_points = vec![entry_point];
+
+        // Greedy descent through the layers above the new node
+        let mut current_level = self.max_level;
+        while current_level > level {
+            let nearest = self.search_layer(&query, &entry_points, 1, current_level);
+            entry_points = nearest.iter().map(|c| c.node).collect();
+            current_level -= 1;
+        }
+
+        for layer in (0..=usize::min(level, self.max_level)).rev() {
+            let candidates = self.search_layer(&query, &entry_points, self.params.ef_construction, layer);
+            let max_links = self.max_links(layer);
+            let neighbours = self.select_neighbours(&candidates, max_links);
+            self.nodes[node_id as usize].neighbours[layer] = neighbours.clone();
+
+            for neighbour in neighbours {
+                self.nodes[neighbour as usize].neighbours[layer].push(node_id);
+                if self.nodes[neighbour as usize].neighbours[layer].len() > max_links {
+                    self.prune_links(neighbour, layer, max_links);
+                }
+            }
+            entry_points = candidates.iter().map(|c| c.node).collect();
+        }
+
+        if level > self.max_level {
+            self.max_level = level;
+            self.entry_point = Some(node_id);
+        }
+        Ok(())
+    }
+
+    /// Marks every vector stored under the key as deleted and returns how many there were.
+    pub fn delete(&mut self, key: &str) -> usize {
+        let nodes = match self.key_nodes.remove(key) {
+            None => return 0,
+            Some(nodes) => nodes,
+        };
+        for node in nodes.iter() {
+            self.nodes[*node as usize].deleted = true;
+        }
+        self.deleted_count += nodes.len();
+        nodes.len()
+    }
+
+    pub fn needs_compaction(&self) -> bool {
+        self.deleted_count > 0 && self.deleted_count * 2 > self.nodes.len()
+    }
+
+    /// Rebuilds the graph from the live vectors only.
+    pub fn compact(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        let mut compacted = HnswIndex::new(self.params.clone());
+        compacted.rng_state = self.rng_state;
+        for node in nodes.into_iter().filter(|node| !node.deleted) {
+            // Every stored vector has the index dimension
+            compacted.insert(&node.key, &node.vector).unwrap();
+        }
+        *self = compacted;
+    }
+
+    /// Returns the `top_k` best scoring keys, `ef_search` overrides the configured recall/latency trade-off.
+    ///
+    /// Deleted nodes and the other vectors of a key take candidate slots, so the candidate list is
+    /// widened until `top_k` keys are found or the whole graph was reached.
+    pub fn search(&self, query: &[f32], top_k: usize, ef_search: Option<usize>) -> Result<Vec<(String, f32)>, Error> {
+        let entry_point = match self.entry_point {
+            None => return Ok(vec![]),
+            Some(entry_point) => entry_point,
+        };
+        if let Err(e) = self.check_dim(query) {
+            return Err(e)
+        }
+        if top_k == 0 {
+            return Ok(vec![]);
+        }
+
+        let query = l2_normalize(&Array1::from(query.to_vec())).to_vec();
+        let mut ef = usize::max(ef_search.unwrap_or(self.params.ef_search), top_k);
+
+        let mut entry_points = vec![entry_point];
+        for layer in (1..=self.max_level).rev() {
+            let nearest = self.search_layer(&query, &entry_points, 1, layer);
+            entry_points = nearest.iter().map(|c| c.node).collect();
+        }
+
+        loop {
+            let candidates = self.search_layer(&query, &entry_points, ef, 0);
+            let mut results = self.best_per_key(&candidates);
+            if results.len() >= top_k || candidates.len() < ef || ef >= self.nodes.len() {
+                results.truncate(top_k);
+                return Ok(results)
+            }
+            ef = usize::min(ef * 2, self.nodes.len());
+        }
+    }
+
+    /// Best score of each live key among the candidates, best first.
+    fn best_per_key(&self, candidates: &[Candidate]) -> Vec<(String, f32)> {
+        let mut best_per_key: HashMap<&str, f32> = HashMap::new();
+        for candidate in candidates.iter() {
+            let node = &self.nodes[candidate.node as usize];
+            if node.deleted {
+                continue;
+            }
+            let score = 1.0 - candidate.distance;
+            let best = best_per_key.entry(node.key.as_str()).or_insert(score);
+            if score > *best {
+                *best = score;
+            }
+        }
+
+        let mut results: Vec<(String, f32)> = best_per_key
+            .into_iter()
+            .map(|(key, score)| (key.to_string(), score))
+            .collect();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1));
+        results
+    }
+
+    fn check_dim(&self, vector: &[f32]) -> Result<(), Error> {
+        match self.dim {
+            Some(dim) if dim != vector.len() => Err(Error::msg(format!(
+                "hnsw_index - vector length mismatch: {} != {}",
+                dim,
+                vector.len()
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes the index to a temporary file first so a crash never leaves a truncated snapshot.
+    pub fn snapshot(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let snapshot = HnswSnapshot {
+            version: HNSW_SNAPSHOT_VERSION,
+            params: self.params.clone(),
+            nodes: self.nodes.clone(),
+            entry_point: self.entry_point,
+            max_level: self.max_level,
+            rng_state: self.rng_state,
+        };
+        let bytes = match bincode::encode_to_vec(&snapshot, bincode::config::standard()) {
+            Ok(bytes) => {bytes}
+            Err(e) => return Err(Error::from(e))
+        };
+
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                if let Err(e) = fs::create_dir_all(parent) {
+                    return Err(Error::from(e))
+                }
+            }
+        }
+
+        // Suffix the full file name, the gallery file next to it already uses the `.tmp` extension
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        if let Err(e) = fs::write(&tmp_path, bytes) {
+            return Err(Error::from(e))
+        }
+        if let Err(e) = fs::rename(&tmp_path, path) {
+            return Err(Error::from(e))
+        }
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let bytes = match fs::read(path.as_ref()) {
+            Ok(bytes) => {bytes}
+            Err(e) => return Err(Error::from(e))
+        };
+        let (snapshot, _): (HnswSnapshot, usize) = match bincode::decode_from_slice(&bytes, bincode::config::standard()) {
+            Ok(snapshot) => {snapshot}
+            Err(e) => return Err(Error::from(e))
+        };
+        if snapshot.version != HNSW_SNAPSHOT_VERSION {
+            return Err(Error::msg(format!("hnsw_index - unsupported snapshot version {}", snapshot.version)))
+        }
+
+        let mut key_nodes: HashMap<String, Vec<u32>> = HashMap::new();
+        let mut deleted_count = 0;
+        for (node_id, node) in snapshot.nodes.iter().enumerate() {
+            if node.deleted {
+                deleted_count += 1;
+            } else {
+                key_nodes.entry(node.key.clone()).or_default().push(node_id as u32);
+            }
+        }
+
+        Ok(HnswIndex {
+            params: snapshot.params,
+            dim: snapshot.nodes.first().map(|node| node.vector.len()),
+            nodes: snapshot.nodes,
+            key_nodes,
+            entry_point: snapshot.entry_point,
+            max_level: snapshot.max_level,
+            deleted_count,
+            rng_state: snapshot.rng_state,
+        })
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.params.m * 2
+        } else {
+            self.params.m
+        }
+    }
+
+    fn distance(&self, query: &[f32], node: u32) -> f32 {
+        let vector = &self.nodes[node as usize].vector;
+        let dot: f32 = query.iter().zip(vector.iter()).map(|(a, b)| a * b).sum();
+        1.0 - dot
+    }
+
+    /// xorshift64*, deterministic so that rebuilt indexes are reproducible.
+    fn next_random(&mut self) -> f64 {
+        self.rng_state ^= self.rng_state >> 12;
+        self.rng_state ^= self.rng_state << 25;
+        self.rng_state ^= self.rng_state >> 27;
+        let value = self.rng_state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        ((value >> 11) as f64 + 1.0) / (1u64 << 53) as f64
+    }
+
+    fn random_level(&mut self) -> usize {
+        let level_multiplier = 1.0 / (usize::max(self.params.m, 2) as f64).ln();
+        let level = (-self.next_random().ln() * level_multiplier).floor() as usize;
+        usize::min(level, 16)
+    }
+
+    /// Returns up to `ef` nodes of the layer closest to the query, nearest first.
+    fn search_layer(&self, query: &[f32], entry_points: &[u32], ef: usize, layer: usize) -> Vec<Candidate> {
+        let mut visited: HashSet<u32> = HashSet::new();
+        let mut candidates: BinaryHeap<Reverse<Candidate>> = BinaryHeap::new();
+        let mut results: BinaryHeap<Candidate> = BinaryHeap::new();
+
+        for &entry_point in entry_points {
+            if visited.insert(entry_point) {
+                let candidate = Candidate {
+                    distance: self.distance(query, entry_point),
+                    node: entry_point,
+                };
+                candidates.push(Reverse(candidate));
+                results.push(candidate);
+            }
+        }
+        while results.len() > ef {
+            results.pop();
+        }
+
+        while let Some(Reverse(current)) = candidates.pop() {
+            if let Some(furthest) = results.peek() {
+                if current.distance > furthest.distance && results.len() >= ef {
+                    break;
+                }
+            }
+
+            let node = &self.nodes[current.node as usize];
+            if layer >= node.neighbours.len() {
+                continue;
+            }
+            for &neighbour in node.neighbours[layer].iter() {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let candidate = Candidate {
+                    distance: self.distance(query, neighbour),
+                    node: neighbour,
+                };
+                let is_closer = match results.peek() {
+                    None => true,
+                    Some(furthest) => candidate.distance < furthest.distance,
+                };
+                if results.len() < ef || is_closer {
+                    candidates.push(Reverse(candidate));
+                    results.push(candidate);
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        results.into_sorted_vec()
+    }
+
+    /// Neighbour selection heuristic of the HNSW paper, keeps links spread across clusters.
+    fn select_neighbours(&self, candidates: &[Candidate], max_links: usize) -> Vec<u32> {
+        let mut selected: Vec<u32> = Vec::with_capacity(max_links);
+        let mut pruned: Vec<u32> = vec![];
+
+        for candidate in candidates.iter() {
+            if selected.len() >= max_links {
+                break;
+            }
+            let candidate_vector = &self.nodes[candidate.node as usize].vector;
+            let is_diverse = selected
+                .iter()
+                .all(|&other| self.distance(candidate_vector, other) > candidate.distance);
+            if is_diverse {
+                selected.push(candidate.node);
+            } else {
+                pruned.push(candidate.node);
+            }
+        }
+
+        for node in pruned {
+            if selected.len() >= max_links {
+                break;
+            }
+            selected.push(node);
+        }
+        selected
+    }
+
+    fn prune_links(&mut self, node: u32, layer: usize, max_links: usize) {
+        let vector = self.nodes[node as usize].vector.clone();
+        let mut candidates: Vec<Candidate> = self.nodes[node as usize].neighbours[layer]
+            .iter()
+            .map(|&neighbour| Candidate {
+                distance: self.distance(&vector, neighbour),
+                node: neighbour,
+            })
+            .collect();
+        candidates.sort();
+        let neighbours = self.select_neighbours(&candidates, max_links);
+        self.nodes[node as usize].neighbours[layer] = neighbours;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use uuid::Uuid;
+    use crate::repository::hnsw_index::{HnswIndex, HnswParams};
+
+    fn random_vectors(count: usize, dim: usize) -> Vec<Vec<f32>> {
+        let mut state: u64 = 42;
+        (0..count)
+            .map(|_| {
+                (0..dim)
+                    .map(|_| {
+                        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                        ((state >> 33) as f32 / (1u64 << 31) as f32) - 0.5
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_hnsw_search() {
+        let vectors = random_vectors(500, 32);
+        let mut index = HnswIndex::new(HnswParams::default());
+        for (i, vector) in vectors.iter().enumerate() {
+            index.insert(&format!("id-{i}"), vector).unwrap();
+        }
+
+        let mut hits = 0;
+        for (i, vector) in vectors.iter().enumerate().take(50) {
+            let results = index.search(vector, 1, None).unwrap();
+            if results[0].0 == format!("id-{i}") {
+                hits += 1;
+            }
+        }
+        assert!(hits >= 48, "recall too low: {hits}/50");
+    }
+
+    #[test]
+    fn test_hnsw_delete_and_snapshot() {
+        let vectors = random_vectors(100, 16);
+        let mut index = HnswIndex::new(HnswParams::default());
+        for (i, vector) in vectors.iter().enumerate() {
+            index.insert(&format!("id-{}", i % 50), vector).unwrap();
+        }
+        assert_eq!(index.len(), 100);
+        assert_eq!(index.delete("id-3"), 2);
+        assert!(index.search(&vectors[3], 5, Some(200)).unwrap().iter().all(|(key, _)| key != "id-3"));
+
+        let path = std::env::temp_dir().join(format!("hnsw-{}.bin", Uuid::new_v4()));
+        index.snapshot(&path).unwrap();
+        let reloaded = HnswIndex::load(&path).unwrap();
+        assert_eq!(reloaded.len(), 98);
+        assert_eq!(reloaded.search(&vectors[10], 1, None).unwrap()[0].0, "id-10");
+        assert_eq!(reloaded.dim(), Some(16));
+
+        let mut compacted = reloaded.clone();
+        compacted.compact();
+        assert_eq!(compacted.len(), 98);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_hnsw_dimension_mismatch() {
+        let mut index = HnswIndex::new(HnswParams::default());
+        index.insert("id-0", &[1.0, 0.0, 0.0]).unwrap();
+        assert!(index.insert("id-1", &[1.0, 0.0]).is_err());
+        assert!(index.search(&[1.0, 0.0], 1, None).is_err());
+        assert_eq!(index.len(), 1);
+    }
+
+    #[test]
+    fn test_hnsw_search_after_deletes() {
+        let vectors = random_vectors(200, 16);
+        let mut index = HnswIndex::new(HnswParams::default());
+        for (i, vector) in vectors.iter().enumerate() {
+            index.insert(&format!("id-{i}"), vector).unwrap();
+        }
+        for i in 0..180 {
+            index.delete(&format!("id-{i}"));
+        }
+
+        // Most of the nearest candidates are deleted
+        let results = index.search(&vectors[0], 10, Some(10)).unwrap();
+        assert_eq!(results.len(), 10);
+        assert!(results.iter().all(|(key, _)| key["id-".len()..].parse::<usize>().unwrap() >= 180));
+    }
+}
@@ -0,0 +1,4 @@
+pub mod face_repository;
+pub mod file_face_repository;
+pub mod hnsw_index;
+pub mod challenge_session_repository;
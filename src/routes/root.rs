@@ -23,8 +23,17 @@ use crate::pipeline::antispoofing_pipeline::antispoofing_pipeline::AntiSpoofingP
 use crate::response::common_response::{BaseResponse, GeneralResponseBuilder, GeneralResponseResult};
 use crate::routes::v2::general_extract::new_general_extract_route;
 use crate::routes::v2::antispoofing_extract::new_antispoofing_extract_route;
+use crate::routes::v2::verify::new_verify_route;
+use crate::routes::v2::gallery::new_gallery_route;
+use crate::routes::v2::compliance::new_compliance_route;
+use crate::routes::v2::liveness::new_liveness_route;
+use crate::repository::face_repository::FaceRepository;
 use crate::state::general_state::GeneralState;
 use crate::state::antispoofing_state::AntiSpoofingState;
+use crate::state::verification_state::VerificationState;
+use crate::state::gallery_state::GalleryState;
+use crate::state::compliance_state::ComplianceState;
+use crate::state::liveness_state::LivenessState;
 
 #[derive(Clone, Serialize, Deserialize)]
 struct FallbackResponse {
@@ -35,14 +44,16 @@ struct FallbackResponse {
 #[derive(Clone)]
 pub struct RouterState {
     general_pipeline: Arc<GeneralPipeline>,
-    // antispoofing_pipeline: Arc<AntiSpoofingPipeline>,
+    antispoofing_pipeline: Arc<AntiSpoofingPipeline>,
+    face_repository: Arc<dyn FaceRepository>,
 }
 
 impl RouterState {
-    pub fn new(general_pipeline: GeneralPipeline) -> Self { // , antispoofing_pipeline: AntiSpoofingPipeline
+    pub fn new(general_pipeline: GeneralPipeline, antispoofing_pipeline: AntiSpoofingPipeline, face_repository: Arc<dyn FaceRepository>) -> Self {
          RouterState {
              general_pipeline: Arc::new(general_pipeline),
-             // antispoofing_pipeline: Arc::new(antispoofing_pipeline),
+             antispoofing_pipeline: Arc::new(antispoofing_pipeline),
+             face_repository,
         }
 
     }
@@ -59,18 +70,38 @@ pub fn root_routes(router_state: RouterState) -> IntoMakeService<Router> {
         // let antispoofing_route = new_antispoofing_extract_route()
         //     .with_state(antispoofing_state);
 
+        let verification_state = VerificationState::new(&router_state.general_pipeline);
+        let verify_route = new_verify_route()
+            .with_state(verification_state);
+
+        let gallery_state = GalleryState::new(&router_state.general_pipeline, &router_state.face_repository);
+        let gallery_route = new_gallery_route()
+            .with_state(gallery_state);
+
+        let compliance_state = ComplianceState::new(&router_state.general_pipeline);
+        let compliance_route = new_compliance_route()
+            .with_state(compliance_state);
+
+        let liveness_state = LivenessState::new(&router_state.antispoofing_pipeline);
+        let liveness_route = new_liveness_route()
+            .with_state(liveness_state);
 
         Router::new()
             .nest(
                 "/v2",
-                Router::new().nest(
-                    "/extract",
-                    Router::new()
-                        .merge(general_route)
-                        // .merge(antispoofing_route)
-
-
-                )
+                Router::new()
+                    .nest(
+                        "/extract",
+                        Router::new()
+                            .merge(general_route)
+                            // .merge(antispoofing_route)
+
+
+                    )
+                    .merge(verify_route)
+                    .nest("/gallery", gallery_route)
+                    .merge(compliance_route)
+                    .merge(liveness_route)
             )
     };
 
@@ -0,0 +1,17 @@
+use axum::extract::DefaultBodyLimit;
+use axum::Router;
+use axum::routing::post;
+use tower_http::limit::RequestBodyLimitLayer;
+use crate::handler::compliance_handler::check_compliance;
+use crate::state::compliance_state::ComplianceState;
+
+pub fn new_compliance_route() -> Router<ComplianceState> {
+
+    let router = Router::new()
+        .route("/compliance", post(check_compliance))
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(
+            250 * 1024 * 1024, /* 250mb */
+        ));
+    router
+}
@@ -0,0 +1,19 @@
+use axum::extract::DefaultBodyLimit;
+use axum::Router;
+use axum::routing::{delete, post};
+use tower_http::limit::RequestBodyLimitLayer;
+use crate::handler::gallery_handler::{gallery_delete, gallery_enroll, gallery_list, gallery_search};
+use crate::state::gallery_state::GalleryState;
+
+pub fn new_gallery_route() -> Router<GalleryState> {
+
+    let router = Router::new()
+        .route("/identities", post(gallery_enroll).get(gallery_list))
+        .route("/identities/:identity_id", delete(gallery_delete))
+        .route("/search", post(gallery_search))
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(
+            250 * 1024 * 1024, /* 250mb */
+        ));
+    router
+}
@@ -0,0 +1,19 @@
+use axum::extract::DefaultBodyLimit;
+use axum::Router;
+use axum::routing::post;
+use tower_http::limit::RequestBodyLimitLayer;
+use crate::handler::liveness_handler::{check_liveness, start_liveness_challenge, verify_liveness_challenge};
+use crate::state::liveness_state::LivenessState;
+
+pub fn new_liveness_route() -> Router<LivenessState> {
+
+    let router = Router::new()
+        .route("/liveness", post(check_liveness))
+        .route("/liveness/challenge", post(start_liveness_challenge))
+        .route("/liveness/challenge/:session_id", post(verify_liveness_challenge))
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(
+            250 * 1024 * 1024, /* 250mb */
+        ));
+    router
+}
@@ -1,2 +1,6 @@
 pub mod general_extract;
-pub mod antispoofing_extract;
\ No newline at end of file
+pub mod antispoofing_extract;
+pub mod verify;
+pub mod gallery;
+pub mod compliance;
+pub mod liveness;
@@ -0,0 +1,17 @@
+use axum::extract::DefaultBodyLimit;
+use axum::Router;
+use axum::routing::post;
+use tower_http::limit::RequestBodyLimitLayer;
+use crate::handler::verification_handler::verify;
+use crate::state::verification_state::VerificationState;
+
+pub fn new_verify_route() -> Router<VerificationState> {
+
+    let router = Router::new()
+        .route("/verify", post(verify))
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(
+            250 * 1024 * 1024, /* 250mb */
+        ));
+    router
+}
@@ -19,7 +19,7 @@ impl AntiSpoofingService {
 
     pub async fn extract_antispoofing_image(&self, input: AntiSpoofingExtractionInput) ->  Result<AntiSpoofingExtractionResultOutput, Error> {
 
-        let result = match self.antispoofing_pipeline.extract(&input.im_bytes.to_owned(), input.spoofing_check.to_owned(), input.is_enroll.to_owned()).await {
+        let result = match self.antispoofing_pipeline.extract(&input.im_bytes.to_owned(), input.spoofing_check.to_owned(), input.is_enroll.to_owned(), input.stages.as_deref()).await {
             Ok(result) => {result}
             Err(e) => {
                 error!("failed to extract face: {e}");
@@ -30,7 +30,12 @@ impl AntiSpoofingService {
         let mut facial_feature: Option<Vec<f32>> = None;
         let face_count = result.face_count;
         let spoofing_check = result.spoofing_check;
+        let anti_spoofing_score = result.anti_spoofing_score;
         let face_quality = result.face_quality;
+        let head_pose = result.head_pose;
+        let image_quality = result.image_quality;
+        let stages_run = result.stages_run;
+        let stages_skipped = result.stages_skipped;
 
         if let Some(_feature) = result.facial_feature {
             facial_feature = Some(_feature.to_vec());
@@ -41,6 +46,11 @@ impl AntiSpoofingService {
             face_quality,
             facial_feature,
             spoofing_check,
+            anti_spoofing_score,
+            head_pose,
+            image_quality,
+            stages_run,
+            stages_skipped,
         })
     }
 
@@ -0,0 +1,34 @@
+use std::sync::Arc;
+use anyhow::Error;
+use log::error;
+use crate::models::compliance_model::{ComplianceInput, ComplianceResultOutput};
+use crate::pipeline::general_pipeline::general_pipeline::GeneralPipeline;
+
+#[derive(Clone)]
+pub struct ComplianceService {
+    general_pipeline: Arc<GeneralPipeline>,
+}
+
+impl ComplianceService {
+    pub fn new(general_pipeline: &Arc<GeneralPipeline>) -> Self {
+        ComplianceService {
+            general_pipeline: Arc::clone(general_pipeline),
+        }
+    }
+
+    pub async fn check(&self, input: ComplianceInput) -> Result<ComplianceResultOutput, Error> {
+        let report = match self.general_pipeline.check_compliance(&input.im_bytes).await {
+            Ok(report) => {report}
+            Err(e) => {
+                error!("failed to check portrait compliance: {e}");
+                return Err(e)
+            }
+        };
+
+        Ok(ComplianceResultOutput {
+            compliant: report.compliant,
+            face_count: report.face_count,
+            checks: report.checks,
+        })
+    }
+}
@@ -0,0 +1,150 @@
+use std::sync::Arc;
+use anyhow::Error;
+use log::{error, info};
+use crate::models::gallery_model::{GalleryEnrollInput, GalleryEnrollResultOutput, GalleryListResultOutput, GalleryRejectedImageOutput, GallerySearchInput, GallerySearchResultOutput};
+use crate::pipeline::general_pipeline::general_pipeline::GeneralPipeline;
+use crate::pipeline::model_config::config::FaceQualityClass;
+use crate::repository::face_repository::FaceRepository;
+
+#[derive(Clone)]
+pub struct GalleryService {
+    general_pipeline: Arc<GeneralPipeline>,
+    face_repository: Arc<dyn FaceRepository>,
+}
+
+impl GalleryService {
+    pub fn new(general_pipeline: &Arc<GeneralPipeline>, face_repository: &Arc<dyn FaceRepository>) -> Self {
+        GalleryService {
+            general_pipeline: Arc::clone(general_pipeline),
+            face_repository: Arc::clone(face_repository),
+        }
+    }
+
+    /// Checks an embedding sent in `field`, the error is the message returned to the caller.
+    pub fn check_embedding(&self, field: &str, embedding: &[f32]) -> Result<(), String> {
+        self.general_pipeline.check_embedding(field, embedding)
+    }
+
+    pub async fn enroll(&self, input: GalleryEnrollInput) -> Result<GalleryEnrollResultOutput, Error> {
+        let mut embeddings = input.embeddings;
+        let mut rejected: Vec<GalleryRejectedImageOutput> = vec![];
+
+        for (index, im_bytes) in input.images.iter().enumerate() {
+            // The enroll path selects the biggest face only
+            let result = match self.general_pipeline.extract(im_bytes, Some(true)).await {
+                Ok(result) => {result}
+                Err(e) => {
+                    error!("failed to extract face for enrollment: {e}");
+                    return Err(e)
+                }
+            };
+
+            match (&result.face_quality, result.facial_feature) {
+                (Some(FaceQualityClass::Good), Some(facial_feature)) => {
+                    embeddings.push(facial_feature.to_vec());
+                }
+                (Some(face_quality), _) => {
+                    // The classical metrics say why a photo is Bad in words the user can act on
+                    let issues: Vec<&str> = match &result.image_quality {
+                        None => vec![],
+                        Some(image_quality) => image_quality.issues.iter().map(|issue| issue.reason()).collect(),
+                    };
+                    let reason = if issues.is_empty() {
+                        format!("face quality is {:?}", face_quality)
+                    } else {
+                        format!("face quality is {:?}: {}", face_quality, issues.join(", "))
+                    };
+                    rejected.push(GalleryRejectedImageOutput {
+                        index,
+                        reason,
+                    });
+                }
+                (None, _) => {
+                    rejected.push(GalleryRejectedImageOutput {
+                        index,
+                        reason: "no face detected".to_string(),
+                    });
+                }
+            }
+        }
+
+        let enrolled_count = embeddings.len();
+        if enrolled_count == 0 {
+            return Ok(GalleryEnrollResultOutput {
+                identity_id: input.identity_id,
+                enrolled_count,
+                embedding_count: 0,
+                rejected,
+            })
+        }
+
+        let identity = match self.face_repository.enroll(&input.identity_id, embeddings) {
+            Ok(identity) => {identity}
+            Err(e) => {
+                error!("failed to enroll identity: {e}");
+                return Err(e)
+            }
+        };
+        info!("enrolled {} embeddings for identity {}", enrolled_count, identity.identity_id);
+
+        Ok(GalleryEnrollResultOutput {
+            identity_id: identity.identity_id,
+            enrolled_count,
+            embedding_count: identity.embeddings.len(),
+            rejected,
+        })
+    }
+
+    pub fn delete(&self, identity_id: &str) -> Result<bool, Error> {
+        self.face_repository.delete(identity_id)
+    }
+
+    pub fn list(&self, offset: usize, limit: usize) -> Result<(GalleryListResultOutput, usize), Error> {
+        let (identities, total) = match self.face_repository.list(offset, limit) {
+            Ok((identities, total)) => {(identities, total)}
+            Err(e) => return Err(e)
+        };
+        Ok((GalleryListResultOutput { identities }, total))
+    }
+
+    pub async fn search(&self, input: GallerySearchInput) -> Result<GallerySearchResultOutput, Error> {
+        let mut face_count: Option<i32> = None;
+
+        let embedding = match (input.embedding, input.im_bytes) {
+            (Some(embedding), _) => embedding,
+            (None, Some(im_bytes)) => {
+                let result = match self.general_pipeline.extract(&im_bytes, Some(false)).await {
+                    Ok(result) => {result}
+                    Err(e) => {
+                        error!("failed to extract face for search: {e}");
+                        return Err(e)
+                    }
+                };
+                face_count = Some(result.face_count);
+                match (result.face_quality, result.facial_feature) {
+                    (Some(_), Some(facial_feature)) => facial_feature.to_vec(),
+                    _ => {
+                        return Ok(GallerySearchResultOutput {
+                            face_count,
+                            matches: vec![],
+                        })
+                    }
+                }
+            }
+            (None, None) => return Err(Error::msg("gallery - search requires an image or an embedding")),
+        };
+
+        let matches = match self.face_repository.search_with_params(&embedding, input.top_k, &input.search_params) {
+            Ok(matches) => {matches}
+            Err(e) => {
+                error!("failed to search gallery: {e}");
+                return Err(e)
+            }
+        };
+
+        Ok(GallerySearchResultOutput {
+            face_count,
+            matches,
+        })
+    }
+}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 use anyhow::Error;
 use log::error;
-use crate::models::general_model::{GeneralExtractionInput, GeneralExtractionResultOutput};
+use crate::models::general_model::{GeneralExtractionInput, GeneralExtractionMode, GeneralExtractionOutput, GeneralExtractionResultOutput, GeneralFaceOutput, GeneralMultiFaceExtractionResultOutput};
 use crate::pipeline::general_pipeline::general_pipeline::{GeneralPipeline};
 
 #[derive(Clone)]
@@ -16,9 +16,26 @@ impl GeneralService {
         }
     }
 
+    pub async fn extract(&self, input: GeneralExtractionInput) -> Result<GeneralExtractionOutput, Error> {
+        match input.mode {
+            GeneralExtractionMode::Single => {
+                match self.extract_general_image(input).await {
+                    Ok(result) => Ok(GeneralExtractionOutput::Single(result)),
+                    Err(e) => Err(e),
+                }
+            }
+            GeneralExtractionMode::All => {
+                match self.extract_all_faces(input).await {
+                    Ok(result) => Ok(GeneralExtractionOutput::All(result)),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+
     pub async fn extract_general_image(&self, input: GeneralExtractionInput) ->  Result<GeneralExtractionResultOutput, Error> {
 
-        let result = match self.general_pipeline.extract(&input.im_bytes.to_owned(), input.is_enroll.to_owned()).await {
+        let result = match self.general_pipeline.extract_with_stages(&input.im_bytes.to_owned(), input.is_enroll.to_owned(), input.stages.as_deref()).await {
             Ok(result) => {result}
             Err(e) => {
                 error!("failed to extract face: {e}");
@@ -28,21 +45,59 @@ impl GeneralService {
 
         drop(input.im_bytes);
 
-        let mut facial_feature: Option<Vec<f32>> = Some(Vec::with_capacity(512));
         let face_count = result.face_count;
         let quality_score = result.quality_score;
         let face_quality = result.face_quality;
+        let head_pose = result.head_pose;
+        let image_quality = result.image_quality;
+        let stages_run = result.stages_run;
+        let stages_skipped = result.stages_skipped;
 
-        if let Some(_feature) = result.facial_feature {
-            facial_feature = Some(_feature.to_vec());
-        }
-
+        let facial_feature = result.facial_feature.map(|feature| feature.to_vec());
 
         Ok(GeneralExtractionResultOutput {
             face_count,
             face_quality,
             quality_score,
+            head_pose,
+            image_quality,
             facial_feature,
+            stages_run,
+            stages_skipped,
         })
     }
-}
\ No newline at end of file
+
+    pub async fn extract_all_faces(&self, input: GeneralExtractionInput) -> Result<GeneralMultiFaceExtractionResultOutput, Error> {
+
+        let result = match self.general_pipeline.extract_all(&input.im_bytes.to_owned(), input.stages.as_deref()).await {
+            Ok(result) => {result}
+            Err(e) => {
+                error!("failed to extract faces: {e}");
+                return Err(e)
+            }
+        };
+
+        drop(input.im_bytes);
+
+        let faces = result.faces
+            .into_iter()
+            .map(|face| GeneralFaceOutput {
+                bbox: face.bbox,
+                landmarks: face.landmarks,
+                detection_score: face.detection_score,
+                face_quality: face.face_quality,
+                quality_score: face.quality_score,
+                head_pose: face.head_pose,
+                image_quality: face.image_quality,
+                facial_feature: face.facial_feature.map(|feature| feature.to_vec()),
+            })
+            .collect();
+
+        Ok(GeneralMultiFaceExtractionResultOutput {
+            face_count: result.face_count,
+            faces,
+            stages_run: result.stages_run,
+            stages_skipped: result.stages_skipped,
+        })
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+use anyhow::Error;
+use log::{error, info};
+use opencv::core::Mat;
+use crate::config::settings::SETTINGS;
+use crate::models::liveness_model::{LivenessChallengeInput, LivenessChallengeOutput, LivenessChallengeResultOutput, LivenessInput, LivenessResultOutput, LivenessSource};
+use crate::pipeline::antispoofing_pipeline::antispoofing_pipeline::AntiSpoofingPipeline;
+use crate::pipeline::module::liveness_challenge::LivenessChallenge;
+use crate::pipeline::utils::utils::{byte_data_to_opencv, clip_to_opencv_frames};
+use crate::repository::challenge_session_repository::ChallengeSessionRepository;
+
+const DEFAULT_CHALLENGE_SESSION_TTL_SECS: u64 = 120;
+
+#[derive(Clone)]
+pub struct LivenessService {
+    antispoofing_pipeline: Arc<AntiSpoofingPipeline>,
+    challenge_sessions: Arc<ChallengeSessionRepository>,
+}
+
+impl LivenessService {
+    pub fn new(antispoofing_pipeline: &Arc<AntiSpoofingPipeline>) -> Self {
+        let session_ttl_secs = match &SETTINGS.liveness_challenge {
+            None => DEFAULT_CHALLENGE_SESSION_TTL_SECS,
+            Some(liveness_challenge) => liveness_challenge.session_ttl_secs,
+        };
+
+        LivenessService {
+            antispoofing_pipeline: Arc::clone(antispoofing_pipeline),
+            challenge_sessions: Arc::new(ChallengeSessionRepository::new(session_ttl_secs)),
+        }
+    }
+
+    pub async fn check(&self, input: LivenessInput) -> Result<LivenessResultOutput, Error> {
+        let report = match self.antispoofing_pipeline.check_liveness(input.frames).await {
+            Ok(report) => {report}
+            Err(e) => {
+                error!("failed to check liveness: {e}");
+                return Err(e)
+            }
+        };
+
+        Ok(LivenessResultOutput {
+            is_live: report.is_live,
+            confidence: report.confidence,
+            frame_count: report.frame_count,
+            tracked_frames: report.tracked_frames,
+            anti_spoofing_score: report.anti_spoofing_score,
+            micro_motion: report.micro_motion,
+            blink_detected: report.blink_detected,
+            bbox_consistency: report.bbox_consistency,
+        })
+    }
+
+    /// Issues a random challenge under a new single use session.
+    pub fn start_challenge(&self) -> LivenessChallengeOutput {
+        let session = self.challenge_sessions.create(LivenessChallenge::random());
+        info!("issued liveness challenge {:?} for session {}", session.challenge, session.session_id);
+        LivenessChallengeOutput {
+            session_id: session.session_id,
+            challenge: session.challenge,
+            instruction: session.challenge.instruction().to_string(),
+            expires_at: session.expires_at,
+        }
+    }
+
+    /// Checks the frames against the challenge of the session, `None` when the session is unknown,
+    /// already used or expired. The session is used up whatever the outcome.
+    pub async fn verify_challenge(&self, input: LivenessChallengeInput) -> Result<Option<LivenessChallengeResultOutput>, Error> {
+        let session = match self.challenge_sessions.take(&input.session_id) {
+            Some(session) => {session}
+            None => return Ok(None)
+        };
+        let report = match self.antispoofing_pipeline.verify_challenge(session.challenge, input.frames).await {
+            Ok(report) => {report}
+            Err(e) => {
+                error!("failed to verify liveness challenge: {e}");
+                return Err(e)
+            }
+        };
+
+        Ok(Some(LivenessChallengeResultOutput {
+            session_id: session.session_id,
+            challenge: report.challenge,
+            passed: report.passed,
+            challenge_completed: report.challenge_completed,
+            response: report.response,
+            frame_count: report.frame_count,
+            tracked_frames: report.tracked_frames,
+            spoofing_passed: report.spoofing_passed,
+            anti_spoofing_score: report.anti_spoofing_score,
+        }))
+    }
+
+    /// Decodes the frames or the multi-page clip of a request, the error is the message for the client.
+    pub fn decode_frames(&self, source: &LivenessSource) -> Result<Vec<Mat>, String> {
+        match source {
+            LivenessSource::Frames(frames) => {
+                let mut images = Vec::with_capacity(frames.len());
+                for (index, frame) in frames.iter().enumerate() {
+                    match byte_data_to_opencv(frame) {
+                        Ok(image) => images.push(image),
+                        Err(e) => {
+                            error!("failed to decode liveness frame {index}: {e}");
+                            return Err(format!("frame {index} could not be decoded"))
+                        }
+                    };
+                }
+                Ok(images)
+            }
+            LivenessSource::Clip(clip) => match clip_to_opencv_frames(clip) {
+                Ok(images) => Ok(images),
+                Err(e) => {
+                    error!("failed to decode liveness clip: {e}");
+                    Err("clip could not be decoded, only multi-page images such as TIFF are supported".to_string())
+                }
+            },
+        }
+    }
+}
@@ -1,2 +1,6 @@
-pub(crate) mod general_service;
-pub(crate) mod antispoofing_service;
\ No newline at end of file
+pub mod general_service;
+pub mod antispoofing_service;
+pub mod verification_service;
+pub mod gallery_service;
+pub mod compliance_service;
+pub mod liveness_service;
@@ -0,0 +1,109 @@
+use std::sync::Arc;
+use anyhow::Error;
+use log::error;
+use ndarray::Array1;
+use crate::config::settings::SETTINGS;
+use crate::models::verification_model::{VerificationFaceOutput, VerificationInput, VerificationResultOutput, VerificationTarget};
+use crate::pipeline::general_pipeline::general_pipeline::{GeneralFaceExtractionResult, GeneralPipeline};
+use crate::pipeline::utils::utils::{cosine_similarity, l2_normalize};
+
+#[derive(Clone)]
+pub struct VerificationService {
+    general_pipeline: Arc<GeneralPipeline>,
+    threshold: f32,
+}
+
+impl VerificationService {
+    pub fn new(general_pipeline: &Arc<GeneralPipeline>) -> Self {
+        VerificationService {
+            general_pipeline: Arc::clone(general_pipeline),
+            threshold: SETTINGS.verification.threshold,
+        }
+    }
+
+    /// Checks a caller supplied target embedding, the error is the message returned to the caller.
+    pub fn check_embedding(&self, embedding: &[f32]) -> Result<(), String> {
+        self.general_pipeline.check_embedding("target_embedding", embedding)
+    }
+
+    pub async fn verify(&self, input: VerificationInput) -> Result<VerificationResultOutput, Error> {
+
+        let (source_result, target_result) = match &input.target {
+            VerificationTarget::Image(target_im_bytes) => {
+                let (source_result, target_result) = tokio::join!(
+                    self.general_pipeline.extract(&input.source_im_bytes, Some(false)),
+                    self.general_pipeline.extract(target_im_bytes, Some(false)),
+                );
+                let target_result = match target_result {
+                    Ok(target_result) => {target_result}
+                    Err(e) => {
+                        error!("failed to extract target face: {e}");
+                        return Err(e)
+                    }
+                };
+                (source_result, Some(target_result))
+            }
+            VerificationTarget::Embedding(_) => {
+                (self.general_pipeline.extract(&input.source_im_bytes, Some(false)).await, None)
+            }
+        };
+
+        let source_result = match source_result {
+            Ok(source_result) => {source_result}
+            Err(e) => {
+                error!("failed to extract source face: {e}");
+                return Err(e)
+            }
+        };
+
+        let source_feature = selected_feature(&source_result);
+        let target_feature = match (&input.target, &target_result) {
+            (VerificationTarget::Embedding(embedding), _) => Some(l2_normalize(&Array1::from(embedding.to_owned()))),
+            (VerificationTarget::Image(_), Some(target_result)) => selected_feature(target_result),
+            (VerificationTarget::Image(_), None) => None,
+        };
+
+        let similarity = match (&source_feature, &target_feature) {
+            (Some(source_feature), Some(target_feature)) => {
+                if source_feature.len() != target_feature.len() {
+                    return Err(Error::msg(format!(
+                        "verification - feature length mismatch: {} != {}",
+                        source_feature.len(),
+                        target_feature.len()
+                    )))
+                }
+                Some(cosine_similarity(source_feature, target_feature))
+            }
+            _ => None,
+        };
+
+        let is_match = match similarity {
+            None => false,
+            Some(similarity) => similarity >= self.threshold,
+        };
+
+        Ok(VerificationResultOutput {
+            similarity,
+            threshold: self.threshold,
+            is_match,
+            source: face_output(&source_result),
+            target: target_result.as_ref().map(face_output),
+        })
+    }
+}
+
+/// Returns the feature of the selected face, `None` when the pipeline did not select any face.
+fn selected_feature(result: &GeneralFaceExtractionResult) -> Option<Array1<f32>> {
+    if result.face_quality.is_none() {
+        return None
+    }
+    result.facial_feature.clone()
+}
+
+fn face_output(result: &GeneralFaceExtractionResult) -> VerificationFaceOutput {
+    VerificationFaceOutput {
+        face_count: result.face_count,
+        face_quality: result.face_quality.clone(),
+        quality_score: result.quality_score,
+    }
+}
@@ -0,0 +1,16 @@
+use std::sync::Arc;
+use crate::pipeline::general_pipeline::general_pipeline::GeneralPipeline;
+use crate::service::compliance_service::ComplianceService;
+
+#[derive(Clone)]
+pub struct ComplianceState {
+    pub compliance_service: ComplianceService,
+}
+
+impl ComplianceState {
+    pub fn new(pipeline: &Arc<GeneralPipeline>) -> Self {
+        Self {
+            compliance_service: ComplianceService::new(pipeline),
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use std::sync::Arc;
+use crate::pipeline::general_pipeline::general_pipeline::GeneralPipeline;
+use crate::repository::face_repository::FaceRepository;
+use crate::service::gallery_service::GalleryService;
+
+#[derive(Clone)]
+pub struct GalleryState {
+    pub gallery_service: GalleryService,
+}
+
+impl GalleryState {
+    pub fn new(pipeline: &Arc<GeneralPipeline>, face_repository: &Arc<dyn FaceRepository>) -> Self {
+        Self {
+            gallery_service: GalleryService::new(pipeline, face_repository),
+        }
+    }
+}
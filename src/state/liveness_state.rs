@@ -0,0 +1,16 @@
+use std::sync::Arc;
+use crate::pipeline::antispoofing_pipeline::antispoofing_pipeline::AntiSpoofingPipeline;
+use crate::service::liveness_service::LivenessService;
+
+#[derive(Clone)]
+pub struct LivenessState {
+    pub liveness_service: LivenessService,
+}
+
+impl LivenessState {
+    pub fn new(pipeline: &Arc<AntiSpoofingPipeline>) -> Self {
+        Self {
+            liveness_service: LivenessService::new(pipeline),
+        }
+    }
+}
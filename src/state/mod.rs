@@ -1,2 +1,6 @@
 pub mod general_state;
-pub mod antispoofing_state;
\ No newline at end of file
+pub mod antispoofing_state;
+pub mod verification_state;
+pub mod gallery_state;
+pub mod compliance_state;
+pub mod liveness_state;
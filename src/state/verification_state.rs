@@ -0,0 +1,16 @@
+use std::sync::Arc;
+use crate::pipeline::general_pipeline::general_pipeline::GeneralPipeline;
+use crate::service::verification_service::VerificationService;
+
+#[derive(Clone)]
+pub struct VerificationState {
+    pub verification_service: VerificationService,
+}
+
+impl VerificationState {
+    pub fn new(pipeline: &Arc<GeneralPipeline>) -> Self {
+        Self {
+            verification_service: VerificationService::new(pipeline),
+        }
+    }
+}
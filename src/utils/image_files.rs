@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::Error;
+
+const IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "bmp", "webp"];
+
+/// Whether `path` is a file with an image extension.
+pub fn is_image(path: &Path) -> bool {
+    path.is_file() && path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// Images in `dir` sorted by path, in its sub-directories too when `recursive`.
+pub fn image_files(dir: &Path, recursive: bool) -> Result<Vec<PathBuf>, Error> {
+    let mut files: Vec<PathBuf> = vec![];
+    let mut dirs: Vec<PathBuf> = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => {entries}
+            Err(e) => return Err(Error::from(e))
+        };
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => return Err(Error::from(e))
+            };
+            if path.is_dir() {
+                if recursive {
+                    dirs.push(path);
+                }
+            } else if is_image(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use uuid::Uuid;
+    use crate::utils::image_files::image_files;
+
+    #[test]
+    fn test_image_files() {
+        let dir = std::env::temp_dir().join(format!("image-files-{}", Uuid::new_v4()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        for name in ["b.JPG", "a.png", "notes.txt", "nested/c.jpeg"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+
+        assert_eq!(image_files(&dir, false).unwrap(), vec![dir.join("a.png"), dir.join("b.JPG")]);
+        assert_eq!(image_files(&dir, true).unwrap(), vec![dir.join("a.png"), dir.join("b.JPG"), dir.join("nested").join("c.jpeg")]);
+        assert!(image_files(&dir.join("missing"), false).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,2 @@
+pub mod roc;
+pub mod image_files;
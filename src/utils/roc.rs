@@ -0,0 +1,98 @@
+use serde::Serialize;
+
+/// How a service compares a score with its threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Acceptance {
+    /// `score >= threshold` is accepted, like the verification and face quality thresholds
+    AtOrAbove,
+    /// `score > threshold` is accepted, like the anti-spoofing threshold
+    Above,
+}
+
+/// Error rates when the scores `Acceptance` lets through at `threshold` are accepted.
+///
+/// For anti-spoofing the false accept rate is the APCER and the false reject rate the BPCER.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct OperatingPoint {
+    pub threshold: f32,
+    /// Share of impostor scores accepted
+    pub far: f64,
+    /// Share of genuine scores rejected
+    pub frr: f64,
+}
+
+/// Operating point at every distinct score, lowest threshold first, accepting the scores the way the
+/// service comparing with the threshold does. The ROC curve plots `1 - frr` against `far`, the DET
+/// curve `frr` against `far`.
+pub fn error_rates(genuine: &[f32], impostor: &[f32], acceptance: Acceptance) -> Vec<OperatingPoint> {
+    let mut genuine: Vec<f32> = genuine.iter().copied().filter(|score| score.is_finite()).collect();
+    let mut impostor: Vec<f32> = impostor.iter().copied().filter(|score| score.is_finite()).collect();
+    genuine.sort_by(f32::total_cmp);
+    impostor.sort_by(f32::total_cmp);
+
+    let mut thresholds: Vec<f32> = genuine.iter().chain(impostor.iter()).copied().collect();
+    thresholds.sort_by(f32::total_cmp);
+    thresholds.dedup();
+
+    let genuine_count = genuine.len().max(1) as f64;
+    let impostor_count = impostor.len().max(1) as f64;
+    let rejected = |score: f32, threshold: f32| match acceptance {
+        Acceptance::AtOrAbove => score < threshold,
+        Acceptance::Above => score <= threshold,
+    };
+    thresholds.into_iter().map(|threshold| {
+        let rejected_genuine = genuine.partition_point(|score| rejected(*score, threshold));
+        let accepted_impostor = impostor.len() - impostor.partition_point(|score| rejected(*score, threshold));
+        OperatingPoint {
+            threshold,
+            far: accepted_impostor as f64 / impostor_count,
+            frr: rejected_genuine as f64 / genuine_count,
+        }
+    }).collect()
+}
+
+/// The point where the two error rates are closest, its error being their mean.
+pub fn equal_error_rate(points: &[OperatingPoint]) -> Option<(f64, OperatingPoint)> {
+    points.iter()
+        .min_by(|a, b| (a.far - a.frr).abs().total_cmp(&(b.far - b.frr).abs()))
+        .map(|point| ((point.far + point.frr) / 2.0, *point))
+}
+
+/// The lowest threshold whose false accept rate is at most `target_far`, so the one rejecting the
+/// fewest genuine scores.
+pub fn point_at_far(points: &[OperatingPoint], target_far: f64) -> Option<OperatingPoint> {
+    points.iter().find(|point| point.far <= target_far).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::roc::{equal_error_rate, error_rates, point_at_far, Acceptance};
+
+    #[test]
+    fn test_error_rates() {
+        let genuine = [0.9, 0.8, 0.7, 0.4];
+        let impostor = [0.1, 0.2, 0.3, 0.75];
+        let points = error_rates(&genuine, &impostor, Acceptance::AtOrAbove);
+        assert_eq!(points.len(), 8);
+        assert_eq!((points[0].far, points[0].frr), (1.0, 0.0));
+        assert_eq!((points[7].far, points[7].frr), (0.0, 0.75));
+
+        // At 0.7 one impostor gets in and one genuine score is rejected
+        let (eer, point) = equal_error_rate(&points).unwrap();
+        assert_eq!(eer, 0.25);
+        assert_eq!(point.threshold, 0.7);
+
+        let point = point_at_far(&points, 0.0).unwrap();
+        assert_eq!(point.threshold, 0.8);
+        assert_eq!(point.frr, 0.5);
+        assert!(point_at_far(&error_rates(&[], &[], Acceptance::AtOrAbove), 0.1).is_none());
+
+        // A score equal to the threshold is rejected when only higher scores are accepted
+        let points = error_rates(&genuine, &impostor, Acceptance::Above);
+        assert_eq!((points[0].far, points[0].frr), (0.75, 0.0));
+        assert_eq!((points[7].far, points[7].frr), (0.0, 1.0));
+        let point = point_at_far(&points, 0.0).unwrap();
+        assert_eq!(point.threshold, 0.75);
+        assert_eq!(point.frr, 0.5);
+    }
+}